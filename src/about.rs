@@ -0,0 +1,110 @@
+use crate::app::{App, Context, Transition};
+use crate::battery::BatteryStatus;
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::ui::{Ui, WidgetId};
+use crate::widget::{Label, ProgressBar, StatusBar};
+
+pub struct About {
+    ui: Ui,
+    heap: WidgetId,
+    battery: WidgetId,
+    level: WidgetId,
+    health: WidgetId,
+    usb: WidgetId,
+}
+
+impl About {
+    pub fn launch(ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        ui.add(StatusBar::new("About"));
+        ui.add(Label::new(&format!(
+            "dynatac {}",
+            env!("CARGO_PKG_VERSION")
+        )));
+        let heap = ui.add(Label::new(""));
+        let battery = ui.add(Label::new(""));
+        let level = ui.add(ProgressBar::new());
+        let health = ui.add(Label::new(""));
+        let usb = ui.add(Label::new(""));
+        let mut about = Self {
+            ui,
+            heap,
+            battery,
+            level,
+            health,
+            usb,
+        };
+        about.update_battery(ctx.battery.as_ref());
+        Box::new(about)
+    }
+
+    fn update_heap(&mut self) {
+        let free = unsafe { esp_idf_svc::sys::esp_get_free_heap_size() };
+        let text = format!("free heap: {} KiB", free / 1024);
+        self.ui.get_mut::<Label>(self.heap).unwrap().set_text(&text);
+    }
+
+    // the battery task's last report, blank until there is one
+    fn update_battery(&mut self, status: Option<&BatteryStatus>) {
+        let battery = status.map_or(String::new(), |s| match s {
+            BatteryStatus { present: false, .. } => "battery: not found".to_string(),
+            BatteryStatus { full: true, .. } => format!("battery: full, {} mV", s.voltage_mv),
+            _ => format!("battery: {}%, {} mV", s.percent, s.voltage_mv),
+        });
+        let health = status.and_then(|s| s.health).map_or(String::new(), |h| {
+            format!(
+                "health: {}%, {} cycles, {} mAh",
+                h.percent, h.cycles, h.design_capacity_mah
+            )
+        });
+        let usb = match status {
+            Some(BatteryStatus { usb: Some(usb), .. }) => format!(
+                "USB: {} mV, {} mA max, charging {} mA",
+                usb.vbus_mv, usb.input_limit_ma, usb.charge_current_ma
+            ),
+            Some(_) => "USB: not connected".to_string(),
+            None => String::new(),
+        };
+        let percent = status.map_or(0, |s| s.percent);
+        self.ui
+            .get_mut::<ProgressBar>(self.level)
+            .unwrap()
+            .set_percent(percent);
+        for (id, text) in [
+            (self.battery, battery),
+            (self.health, health),
+            (self.usb, usb),
+        ] {
+            self.ui.get_mut::<Label>(id).unwrap().set_text(&text);
+        }
+    }
+}
+
+impl App for About {
+    fn name(&self) -> &str {
+        "About"
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, _ctx: &mut Context) {
+        self.update_heap();
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Battery(_) => {
+                self.update_battery(ctx.battery.as_ref());
+                Transition::None
+            }
+            Event::Key(Key::Back) | Event::Key(Key::Left) => Transition::Pop,
+            Event::Touch(Gesture::Swipe {
+                direction: Direction::Right,
+                ..
+            }) => Transition::Pop,
+            _ => Transition::None,
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// AES block encryption (FIPS-197) for 128 and 256 bit keys. Only the forward cipher is
+// implemented, which is all CTR mode needs.
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+pub const BLOCK_LEN: usize = 16;
+
+pub struct Aes {
+    round_keys: Vec<[u8; BLOCK_LEN]>,
+}
+
+impl Aes {
+    // None unless the key is 16 or 32 bytes long
+    pub fn new(key: &[u8]) -> Option<Self> {
+        let nk = match key.len() {
+            16 => 4,
+            32 => 8,
+            _ => return None,
+        };
+        let rounds = nk + 6;
+        let mut words: Vec<[u8; 4]> = key
+            .chunks_exact(4)
+            .map(|w| [w[0], w[1], w[2], w[3]])
+            .collect();
+        for i in nk..4 * (rounds + 1) {
+            let mut word = words[i - 1];
+            if i % nk == 0 {
+                word.rotate_left(1);
+                word = word.map(|b| SBOX[b as usize]);
+                word[0] ^= RCON[i / nk - 1];
+            } else if nk > 6 && i % nk == 4 {
+                word = word.map(|b| SBOX[b as usize]);
+            }
+            let previous = words[i - nk];
+            words.push([
+                word[0] ^ previous[0],
+                word[1] ^ previous[1],
+                word[2] ^ previous[2],
+                word[3] ^ previous[3],
+            ]);
+        }
+        let round_keys = words
+            .chunks_exact(4)
+            .map(|w| {
+                let mut key = [0u8; BLOCK_LEN];
+                for (i, word) in w.iter().enumerate() {
+                    key[4 * i..4 * i + 4].copy_from_slice(word);
+                }
+                key
+            })
+            .collect();
+        Some(Self { round_keys })
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; BLOCK_LEN]) {
+        let last = self.round_keys.len() - 1;
+        add_round_key(block, &self.round_keys[0]);
+        for round_key in &self.round_keys[1..last] {
+            sub_bytes(block);
+            shift_rows(block);
+            mix_columns(block);
+            add_round_key(block, round_key);
+        }
+        sub_bytes(block);
+        shift_rows(block);
+        add_round_key(block, &self.round_keys[last]);
+    }
+
+    // CTR mode keystream XOR, the counter is the last four bytes of `iv`, big endian.
+    pub fn ctr(&self, iv: &[u8; BLOCK_LEN], data: &mut [u8]) {
+        let mut counter = *iv;
+        for chunk in data.chunks_mut(BLOCK_LEN) {
+            let mut keystream = counter;
+            self.encrypt_block(&mut keystream);
+            for (byte, k) in chunk.iter_mut().zip(keystream) {
+                *byte ^= k;
+            }
+            let next = u32::from_be_bytes([counter[12], counter[13], counter[14], counter[15]])
+                .wrapping_add(1);
+            counter[12..].copy_from_slice(&next.to_be_bytes());
+        }
+    }
+}
+
+fn add_round_key(block: &mut [u8; BLOCK_LEN], key: &[u8; BLOCK_LEN]) {
+    for (b, k) in block.iter_mut().zip(key) {
+        *b ^= k;
+    }
+}
+
+fn sub_bytes(block: &mut [u8; BLOCK_LEN]) {
+    for b in block.iter_mut() {
+        *b = SBOX[*b as usize];
+    }
+}
+
+// the state is column major: byte r + 4c is row r of column c
+fn shift_rows(block: &mut [u8; BLOCK_LEN]) {
+    let state = *block;
+    for r in 1..4 {
+        for c in 0..4 {
+            block[r + 4 * c] = state[r + 4 * ((c + r) % 4)];
+        }
+    }
+}
+
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn mix_columns(block: &mut [u8; BLOCK_LEN]) {
+    for column in block.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn block(s: &str) -> [u8; BLOCK_LEN] {
+        hex(s).try_into().unwrap()
+    }
+
+    #[test]
+    fn fips197_aes128() {
+        // appendix B
+        let aes = Aes::new(&hex("2b7e151628aed2a6abf7158809cf4f3c")).unwrap();
+        let mut b = block("3243f6a8885a308d313198a2e0370734");
+        aes.encrypt_block(&mut b);
+        assert_eq!(b, block("3925841d02dc09fbdc118597196a0b32"));
+
+        // appendix C.1
+        let aes = Aes::new(&hex("000102030405060708090a0b0c0d0e0f")).unwrap();
+        let mut b = block("00112233445566778899aabbccddeeff");
+        aes.encrypt_block(&mut b);
+        assert_eq!(b, block("69c4e0d86a7b0430d8cdb78070b4c55a"));
+    }
+
+    #[test]
+    fn fips197_aes256() {
+        // appendix C.3
+        let key = hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let aes = Aes::new(&key).unwrap();
+        assert_eq!(aes.round_keys.len(), 15);
+        let mut b = block("00112233445566778899aabbccddeeff");
+        aes.encrypt_block(&mut b);
+        assert_eq!(b, block("8ea2b7ca516745bfeafc49904b496089"));
+    }
+
+    #[test]
+    fn key_lengths() {
+        assert!(Aes::new(&[0; 16]).is_some());
+        assert!(Aes::new(&[0; 32]).is_some());
+        // AES-192 isn't used by Meshtastic
+        assert!(Aes::new(&[0; 24]).is_none());
+        assert!(Aes::new(&[]).is_none());
+    }
+
+    #[test]
+    fn sp800_38a_ctr() {
+        // F.5.1 CTR-AES128.Encrypt
+        let aes = Aes::new(&hex("2b7e151628aed2a6abf7158809cf4f3c")).unwrap();
+        let iv = block("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+        let plain = hex(concat!(
+            "6bc1bee22e409f96e93d7e117393172a",
+            "ae2d8a571e03ac9c9eb76fac45af8e51",
+            "30c81c46a35ce411e5fbc1191a0a52ef",
+            "f69f2445df4f9b17ad2b417be66c3710",
+        ));
+        let cipher = hex(concat!(
+            "874d6191b620e3261bef6864990db6ce",
+            "9806f66b7970fdff8617187bb9fffdff",
+            "5ae4df3edbd5d35e5b4f09020db03eab",
+            "1e031dda2fbe03d1792170a0f3009cee",
+        ));
+        let mut data = plain.clone();
+        aes.ctr(&iv, &mut data);
+        assert_eq!(data, cipher);
+        aes.ctr(&iv, &mut data);
+        assert_eq!(data, plain);
+
+        // a partial last block uses the start of its keystream
+        let mut data = plain[..21].to_vec();
+        aes.ctr(&iv, &mut data);
+        assert_eq!(data, cipher[..21]);
+    }
+
+    #[test]
+    fn ctr_counter_wraps_in_last_word() {
+        let aes = Aes::new(&[7; 16]).unwrap();
+        let iv = block("000102030405060708090a0bffffffff");
+        let mut data = [0u8; 2 * BLOCK_LEN];
+        aes.ctr(&iv, &mut data);
+        let mut first = iv;
+        aes.encrypt_block(&mut first);
+        // only the last four bytes count, the nonce in front is left alone
+        let mut second = block("000102030405060708090a0b00000000");
+        aes.encrypt_block(&mut second);
+        assert_eq!(data[..BLOCK_LEN], first);
+        assert_eq!(data[BLOCK_LEN..], second);
+    }
+}
@@ -0,0 +1,360 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audio::Tone;
+use crate::battery::BatteryStatus;
+use crate::call::{CallState, Calls};
+use crate::canvas::{Canvas, Rect, Refresh};
+use crate::contacts::Contacts;
+use crate::conversations::Conversations;
+use crate::dialer;
+use crate::event::{Event, Gesture, Key};
+use crate::font::FontStack;
+use crate::gnss::Gnss;
+use crate::mesh::Mesh;
+use crate::messages;
+use crate::modem::NetworkStatus;
+use crate::notifications::{self, Notification, Notifications};
+use crate::settings::Settings;
+use crate::sms::Mailbox;
+use crate::storage::{Storage, StorageStatus};
+use crate::ui::Ui;
+use crate::widget::{StatusBar, Toast, Widget};
+
+// how long a notification stays over the open app
+const TOAST_TIME: Duration = Duration::from_secs(5);
+
+pub enum Transition {
+    None,
+    Push(Box<dyn App>),
+    Pop,
+    Replace(Box<dyn App>),
+    // back to the home screen at the bottom of the stack
+    Home,
+}
+
+pub struct AppInfo {
+    pub name: &'static str,
+    pub launch: fn(&mut Context) -> Box<dyn App>,
+}
+
+// State shared by every app on the display task.
+pub struct Context {
+    pub apps: Vec<AppInfo>,
+    // lets apps queue events for themselves (e.g. deferred work after a refresh)
+    pub events: Sender<Event>,
+    // SMS inbox and outbox, serviced by the cellular task
+    pub mailbox: Arc<Mutex<Mailbox>>,
+    // voice call state and commands, serviced by the cellular task
+    pub calls: Arc<Mutex<Calls>>,
+    // Meshtastic messages and nodes, serviced by the LoRa radio task
+    pub mesh: Arc<Mutex<Mesh>>,
+    // position, time and satellites, serviced by the GNSS task
+    pub gnss: Arc<Mutex<Gnss>>,
+    // files on the SD card, mounted by the storage task
+    pub storage: Arc<Mutex<Storage>>,
+    // user settings, changes are announced with `Event::Settings`
+    pub settings: Arc<Mutex<Settings>>,
+    // address book, read from the SD card whenever one is mounted
+    pub contacts: Contacts,
+    // SMS and direct mesh messages, kept on the SD card
+    pub conversations: Conversations,
+    // SMS, calls, mesh messages and battery warnings waiting for the user
+    pub notifications: Notifications,
+    // last report of the battery task, shown in every status bar
+    pub battery: Option<BatteryStatus>,
+    // last report of the cellular task, its signal is shown in every status bar
+    pub network: Option<NetworkStatus>,
+    // tones for the audio task; without one nothing is played
+    pub tones: Option<Sender<Tone>>,
+}
+
+impl Context {
+    pub fn new(
+        events: Sender<Event>,
+        mailbox: Arc<Mutex<Mailbox>>,
+        calls: Arc<Mutex<Calls>>,
+        mesh: Arc<Mutex<Mesh>>,
+        gnss: Arc<Mutex<Gnss>>,
+        storage: Arc<Mutex<Storage>>,
+        settings: Arc<Mutex<Settings>>,
+    ) -> Self {
+        Self {
+            apps: Vec::new(),
+            events,
+            mailbox,
+            calls,
+            mesh,
+            gnss,
+            storage,
+            settings,
+            contacts: Contacts::new(),
+            conversations: Conversations::new(),
+            notifications: Notifications::new(),
+            battery: None,
+            network: None,
+            tones: None,
+        }
+    }
+
+    // Sends a tone to the audio task, if there is one.
+    pub fn play(&self, tone: Tone) {
+        let Some(tones) = self.tones.as_ref() else {
+            return;
+        };
+        if tones.send(tone).is_err() {
+            log::warn!("audio task has gone");
+        }
+    }
+
+    pub fn install(&mut self, name: &'static str, launch: fn(&mut Context) -> Box<dyn App>) {
+        self.apps.push(AppInfo { name, launch });
+    }
+}
+
+// An app owns a page of widgets. `enter` runs every time the app comes to the top of the
+// stack (on launch and when the app above it is popped), `exit` when it leaves the top.
+pub trait App {
+    fn name(&self) -> &str;
+    fn ui(&mut self) -> &mut Ui;
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition;
+
+    fn enter(&mut self, _ctx: &mut Context) {}
+    fn exit(&mut self, _ctx: &mut Context) {}
+
+    fn render(&mut self, canvas: &mut dyn Canvas, fonts: &mut FontStack) -> Refresh {
+        self.ui().render(canvas, fonts)
+    }
+}
+
+// A notification shown over whatever app is open.
+struct ShownToast {
+    widget: Toast,
+    notification: Notification,
+    until: Instant,
+    // where it was last drawn
+    bounds: Rect,
+}
+
+// Stack of screens, the bottom one (the home screen) is never popped.
+pub struct Navigator {
+    stack: Vec<Box<dyn App>>,
+    toast: Option<ShownToast>,
+}
+
+impl Navigator {
+    pub fn new(mut root: Box<dyn App>, ctx: &mut Context) -> Self {
+        root.enter(ctx);
+        Self {
+            stack: vec![root],
+            toast: None,
+        }
+    }
+
+    pub fn current(&self) -> &str {
+        self.stack.last().map_or("", |app| app.name())
+    }
+
+    pub fn handle_event(&mut self, ctx: &mut Context, event: &Event) {
+        if self
+            .toast
+            .as_ref()
+            .is_some_and(|t| Instant::now() >= t.until)
+        {
+            self.close_toast();
+        }
+        match event {
+            Event::Battery(status) => ctx.battery = Some(*status),
+            Event::Network(status) => ctx.network = Some(*status),
+            Event::Storage(StorageStatus::Mounted) => {
+                let storage = ctx.storage.lock().unwrap();
+                if let Err(e) = ctx.contacts.load(&storage) {
+                    log::warn!("reading contacts failed: {e:?}");
+                }
+                match ctx.conversations.load(&storage) {
+                    // what arrived without a card is on it now
+                    Ok(()) => messages::release_sms(ctx),
+                    Err(e) => log::warn!("reading messages failed: {e:?}"),
+                }
+                if let Err(e) = ctx.notifications.load(&storage) {
+                    log::warn!("reading notifications failed: {e:?}");
+                }
+            }
+            Event::Sms(_) | Event::Mesh(_) => messages::record(ctx, event),
+            _ => {}
+        }
+        if let Some(notification) = notifications::record(ctx, event, self.current()) {
+            self.show_toast(notification);
+        }
+        let transition = match event {
+            Event::Key(Key::Home) => Transition::Home,
+            // an incoming call takes over the screen from whatever app is open
+            Event::Call(CallState::Incoming) if self.current() != dialer::NAME => {
+                Transition::Push(dialer::Dialer::launch(ctx))
+            }
+            Event::Touch(Gesture::Tap { x, y })
+                if self
+                    .toast
+                    .as_ref()
+                    .is_some_and(|t| t.bounds.contains(*x, *y)) =>
+            {
+                self.open_toast(ctx)
+            }
+            _ => match self.stack.last_mut() {
+                Some(app) => app.handle_event(ctx, event),
+                None => Transition::None,
+            },
+        };
+        self.apply(ctx, transition);
+        self.update_status_bar(ctx);
+    }
+
+    // card, signal and battery are the same on every page, apps only set their own indicators
+    fn update_status_bar(&mut self, ctx: &Context) {
+        let Some(app) = self.stack.last_mut() else {
+            return;
+        };
+        let card = match ctx.storage.lock().unwrap().status() {
+            StorageStatus::NoCard => None,
+            StorageStatus::Mounted => Some(true),
+            StorageStatus::Unreadable => Some(false),
+        };
+        if let Some(bar) = app.ui().find_mut::<StatusBar>() {
+            bar.set_card(card);
+            bar.set_signal(ctx.network.and_then(|n| n.signal_bars()));
+            bar.set_battery(ctx.battery.map(|b| (b.percent, b.charging())));
+        }
+    }
+
+    fn apply(&mut self, ctx: &mut Context, transition: Transition) {
+        match transition {
+            Transition::None => return,
+            Transition::Push(app) => {
+                self.leave_top(ctx);
+                self.stack.push(app);
+            }
+            Transition::Pop => {
+                if self.stack.len() == 1 {
+                    return;
+                }
+                self.leave_top(ctx);
+                self.stack.pop();
+            }
+            Transition::Replace(app) => {
+                self.leave_top(ctx);
+                // the home screen is kept, the new app opens on top of it instead
+                if self.stack.len() > 1 {
+                    self.stack.pop();
+                }
+                self.stack.push(app);
+            }
+            Transition::Home => {
+                if self.stack.len() == 1 {
+                    return;
+                }
+                self.leave_top(ctx);
+                self.stack.truncate(1);
+            }
+        }
+        let Some(app) = self.stack.last_mut() else {
+            return;
+        };
+        log::info!("entering {}", app.name());
+        app.ui().invalidate();
+        app.enter(ctx);
+        let name = app.name().to_string();
+        notifications::seen(ctx, &name);
+        let shown_here = self
+            .toast
+            .as_ref()
+            .is_some_and(|t| t.notification.kind.app() == Some(name.as_str()));
+        if shown_here {
+            self.close_toast();
+        }
+    }
+
+    fn leave_top(&mut self, ctx: &mut Context) {
+        if let Some(app) = self.stack.last_mut() {
+            app.exit(ctx);
+        }
+    }
+
+    // redraw the whole screen, e.g. after the rotation changed
+    pub fn invalidate(&mut self) {
+        if let Some(app) = self.stack.last_mut() {
+            app.ui().invalidate();
+        }
+    }
+
+    // A more important notification than the one shown replaces it, a lesser one waits for
+    // the launcher's badges. The app it is about shows it already.
+    fn show_toast(&mut self, notification: Notification) {
+        if notification.kind.app() == Some(self.current()) {
+            return;
+        }
+        if let Some(shown) = self.toast.as_ref() {
+            let replaces = shown.notification.id == notification.id
+                || notification.priority >= shown.notification.priority;
+            if !replaces {
+                return;
+            }
+        }
+        // the same size as any it replaces, so it covers that completely
+        let bounds = self.toast.as_ref().map_or(Rect::default(), |t| t.bounds);
+        self.toast = Some(ShownToast {
+            widget: Toast::new(&notification.heading(), &notification.text),
+            notification,
+            until: Instant::now() + TOAST_TIME,
+            bounds,
+        });
+    }
+
+    fn close_toast(&mut self) {
+        let Some(toast) = self.toast.take() else {
+            return;
+        };
+        // the app redraws what was under it
+        if let Some(app) = self.stack.last_mut() {
+            app.ui().damage(toast.bounds);
+        }
+    }
+
+    fn open_toast(&mut self, ctx: &mut Context) -> Transition {
+        let Some(toast) = self.toast.as_ref() else {
+            return Transition::None;
+        };
+        let notification = toast.notification.clone();
+        self.close_toast();
+        if notification.kind.app() == Some(self.current()) {
+            return Transition::None;
+        }
+        notifications::open(ctx, &notification)
+    }
+
+    // The app draws first, the toast goes on top whenever it is new or the app drew under
+    // it.
+    pub fn render(&mut self, canvas: &mut dyn Canvas, fonts: &mut FontStack) -> Refresh {
+        let Some(app) = self.stack.last_mut() else {
+            return Refresh::None;
+        };
+        let mut refresh = app.render(canvas, fonts);
+        let Some(toast) = self.toast.as_mut() else {
+            return refresh;
+        };
+        let screen = Rect::new(0, 0, canvas.width(), canvas.height());
+        let bounds = toast.widget.bounds(fonts, screen);
+        let covered = match refresh {
+            Refresh::Full => true,
+            Refresh::Partial(area) => area.intersects(&bounds),
+            Refresh::None => false,
+        };
+        if covered || toast.widget.is_dirty() {
+            toast.widget.draw(canvas, fonts, bounds);
+            toast.widget.set_dirty(false);
+            toast.bounds = bounds;
+            refresh = refresh.merge(Refresh::Partial(bounds));
+        }
+        refresh
+    }
+}
@@ -0,0 +1,633 @@
+// Audio: tone synthesis, mixing and PCM sinks, plus the call audio settings on the modem.
+//
+// On the T-Deck Pro the speaker, the headset jack and the call microphone are wired to the
+// A7682E's analog audio interface, so call audio never passes through the ESP32; it is
+// routed and levelled with AT commands. Generated tones (the dialer's keys, the ringtone, the
+// busy signal) are mixed by the audio task into any `PcmSink`: the I2S DAC, or a WAV file
+// (which is how the generator is checked on the host). Notification alerts are a beep from the
+// modem's own tone generator and pulses of the vibration motor.
+
+use std::f32::consts::TAU;
+use std::fmt::Debug;
+#[cfg(test)]
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::delay::BLOCK;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::i2s::{I2sDriver, I2sTx};
+
+use crate::call::{CallState, Calls};
+use crate::modem::{Modem, ModemError, Transport, DEFAULT_TIMEOUT};
+
+pub const SAMPLE_RATE: u32 = 16_000;
+const BLOCK_SAMPLES: usize = 256;
+// about a minute of ringing, longer than the network lets a call ring unanswered
+const RING_REPEATS: u32 = 20;
+const BUSY_REPEATS: u32 = 3;
+pub const MAX_VOLUME: u8 = 10;
+// +CLVL range on the A7682E
+const MODEM_MAX_LEVEL: u8 = 5;
+
+#[derive(Debug)]
+pub enum AudioError {
+    Device(String),
+}
+impl AudioError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        AudioError::Device(format!("{:?}", e))
+    }
+}
+
+// Mono 16 bit PCM at SAMPLE_RATE.
+pub trait PcmSource {
+    // fills `out`, returns how many samples were written; fewer than `out.len()` means the
+    // source has finished
+    fn read(&mut self, out: &mut [i16]) -> usize;
+}
+
+pub trait PcmSink {
+    fn write(&mut self, samples: &[i16]) -> Result<(), AudioError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    Dtmf(char),
+    // one cadence of the UK style double ring
+    Ring,
+    Beep,
+    Busy,
+}
+
+// DTMF row and column frequencies
+fn dtmf_pair(digit: char) -> Option<(f32, f32)> {
+    const ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+    const COLS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+    const KEYS: [[char; 4]; 4] = [
+        ['1', '2', '3', 'A'],
+        ['4', '5', '6', 'B'],
+        ['7', '8', '9', 'C'],
+        ['*', '0', '#', 'D'],
+    ];
+    KEYS.iter().enumerate().find_map(|(r, row)| {
+        row.iter()
+            .position(|&k| k == digit)
+            .map(|c| (ROWS[r], COLS[c]))
+    })
+}
+
+// a stretch of one or two sine waves (0 Hz is silence)
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    freqs: (f32, f32),
+    samples: u32,
+}
+
+impl Segment {
+    fn new(freqs: (f32, f32), ms: u32) -> Self {
+        Self {
+            freqs,
+            samples: SAMPLE_RATE * ms / 1000,
+        }
+    }
+}
+
+pub struct ToneGenerator {
+    segments: Vec<Segment>,
+    index: usize,
+    position: u32,
+    phases: (f32, f32),
+    repeats: u32,
+}
+
+impl ToneGenerator {
+    pub fn new(tone: Tone) -> Self {
+        let segments = match tone {
+            Tone::Dtmf(digit) => match dtmf_pair(digit) {
+                Some(pair) => vec![Segment::new(pair, 120), Segment::new((0.0, 0.0), 60)],
+                None => Vec::new(),
+            },
+            Tone::Ring => vec![
+                Segment::new((400.0, 450.0), 400),
+                Segment::new((0.0, 0.0), 200),
+                Segment::new((400.0, 450.0), 400),
+                Segment::new((0.0, 0.0), 2000),
+            ],
+            Tone::Beep => vec![Segment::new((1000.0, 0.0), 150)],
+            Tone::Busy => vec![
+                Segment::new((425.0, 0.0), 375),
+                Segment::new((0.0, 0.0), 375),
+            ],
+        };
+        Self {
+            segments,
+            index: 0,
+            position: 0,
+            phases: (0.0, 0.0),
+            repeats: 0,
+        }
+    }
+
+    // plays the tone `times` more times after the first
+    pub fn repeat(mut self, times: u32) -> Self {
+        self.repeats = times;
+        self
+    }
+}
+
+impl PcmSource for ToneGenerator {
+    fn read(&mut self, out: &mut [i16]) -> usize {
+        let mut written = 0;
+        while written < out.len() {
+            let Some(segment) = self.segments.get(self.index).copied() else {
+                break;
+            };
+            if self.position >= segment.samples {
+                self.index += 1;
+                self.position = 0;
+                if self.index == self.segments.len() && self.repeats > 0 {
+                    self.repeats -= 1;
+                    self.index = 0;
+                }
+                continue;
+            }
+            let (f1, f2) = segment.freqs;
+            let (mut p1, mut p2) = self.phases;
+            let n = ((segment.samples - self.position) as usize).min(out.len() - written);
+            for sample in &mut out[written..written + n] {
+                // two tones at half amplitude each so a pair never clips
+                let mut value = 0.0;
+                if f1 > 0.0 {
+                    value += p1.sin();
+                    p1 = (p1 + TAU * f1 / SAMPLE_RATE as f32) % TAU;
+                }
+                if f2 > 0.0 {
+                    value += p2.sin();
+                    p2 = (p2 + TAU * f2 / SAMPLE_RATE as f32) % TAU;
+                }
+                *sample = (value * 0.5 * i16::MAX as f32) as i16;
+            }
+            self.phases = (p1, p2);
+            self.position += n as u32;
+            written += n;
+        }
+        written
+    }
+}
+
+// 0 (mute) to MAX_VOLUME, about 3 dB per step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Volume(u8);
+
+impl Volume {
+    pub fn new(level: u8) -> Self {
+        Self(level.min(MAX_VOLUME))
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    pub fn up(&self) -> Self {
+        Self::new(self.0 + 1)
+    }
+
+    pub fn down(&self) -> Self {
+        Self(self.0.saturating_sub(1))
+    }
+
+    pub fn gain(&self) -> f32 {
+        if self.0 == 0 {
+            0.0
+        } else {
+            10f32.powf(-3.0 * (MAX_VOLUME - self.0) as f32 / 20.0)
+        }
+    }
+
+    // the same level on the modem's +CLVL scale
+    fn modem_level(&self) -> u8 {
+        (self.0 as u16 * MODEM_MAX_LEVEL as u16).div_ceil(MAX_VOLUME as u16) as u8
+    }
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self(7)
+    }
+}
+
+// Sums any number of sources, each with its own gain, under a master volume. Finished
+// sources are dropped; the mixer finishes with the last of them.
+pub struct Mixer {
+    voices: Vec<(Box<dyn PcmSource + Send>, f32)>,
+    volume: Volume,
+    scratch: Vec<i16>,
+    accumulator: Vec<f32>,
+}
+
+impl Mixer {
+    pub fn new(volume: Volume) -> Self {
+        Self {
+            voices: Vec::new(),
+            volume,
+            scratch: Vec::new(),
+            accumulator: Vec::new(),
+        }
+    }
+
+    pub fn play(&mut self, source: Box<dyn PcmSource + Send>, gain: f32) {
+        self.voices.push((source, gain));
+    }
+
+    pub fn stop_all(&mut self) {
+        self.voices.clear();
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.voices.is_empty()
+    }
+}
+
+impl PcmSource for Mixer {
+    fn read(&mut self, out: &mut [i16]) -> usize {
+        self.accumulator.clear();
+        self.accumulator.resize(out.len(), 0.0);
+        self.scratch.resize(out.len(), 0);
+        let master = self.volume.gain();
+        let (accumulator, scratch) = (&mut self.accumulator, &mut self.scratch);
+        let mut longest = 0;
+        self.voices.retain_mut(|(source, gain)| {
+            let n = source.read(scratch);
+            longest = longest.max(n);
+            for (acc, &s) in accumulator.iter_mut().zip(&scratch[..n]) {
+                *acc += s as f32 * *gain;
+            }
+            n == scratch.len()
+        });
+        for (sample, acc) in out.iter_mut().zip(&self.accumulator) {
+            *sample = (acc * master).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+        if self.voices.is_empty() {
+            longest
+        } else {
+            out.len()
+        }
+    }
+}
+
+// Moves one block from a source to a sink, returns false once the source has finished.
+pub fn render(source: &mut dyn PcmSource, sink: &mut dyn PcmSink) -> Result<bool, AudioError> {
+    let mut block = [0i16; BLOCK_SAMPLES];
+    let n = source.read(&mut block);
+    if n > 0 {
+        sink.write(&block[..n])?;
+    }
+    Ok(n == block.len())
+}
+
+// RIFF/WAVE writer for mono 16 bit PCM. The sizes in the header are filled in by `finish`.
+#[cfg(test)]
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    samples: u32,
+}
+
+#[cfg(test)]
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> Result<Self, AudioError> {
+        let mut header = Vec::with_capacity(44);
+        header.extend(b"RIFF");
+        header.extend(0u32.to_le_bytes());
+        header.extend(b"WAVEfmt ");
+        header.extend(16u32.to_le_bytes());
+        header.extend(1u16.to_le_bytes()); // PCM
+        header.extend(1u16.to_le_bytes()); // mono
+        header.extend(sample_rate.to_le_bytes());
+        header.extend((sample_rate * 2).to_le_bytes()); // byte rate
+        header.extend(2u16.to_le_bytes()); // block align
+        header.extend(16u16.to_le_bytes()); // bits per sample
+        header.extend(b"data");
+        header.extend(0u32.to_le_bytes());
+        writer.write_all(&header).map_err(AudioError::from_debug)?;
+        Ok(Self { writer, samples: 0 })
+    }
+
+    pub fn finish(mut self) -> Result<W, AudioError> {
+        let data_len = self.samples * 2;
+        self.writer
+            .seek(SeekFrom::Start(4))
+            .map_err(AudioError::from_debug)?;
+        self.writer
+            .write_all(&(36 + data_len).to_le_bytes())
+            .map_err(AudioError::from_debug)?;
+        self.writer
+            .seek(SeekFrom::Start(40))
+            .map_err(AudioError::from_debug)?;
+        self.writer
+            .write_all(&data_len.to_le_bytes())
+            .map_err(AudioError::from_debug)?;
+        self.writer
+            .seek(SeekFrom::End(0))
+            .map_err(AudioError::from_debug)?;
+        self.writer.flush().map_err(AudioError::from_debug)?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+impl<W: Write + Seek> PcmSink for WavWriter<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<(), AudioError> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.writer
+            .write_all(&bytes)
+            .map_err(AudioError::from_debug)?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+}
+
+// Standard mode I2S transmitter (e.g. a MAX98357A amplifier), configured by the caller
+// for SAMPLE_RATE, 16 bit mono.
+#[cfg(target_os = "espidf")]
+pub struct I2sSink {
+    driver: I2sDriver<'static, I2sTx>,
+}
+
+#[cfg(target_os = "espidf")]
+impl I2sSink {
+    pub fn new(mut driver: I2sDriver<'static, I2sTx>) -> Result<Self, AudioError> {
+        driver.tx_enable().map_err(AudioError::from_debug)?;
+        Ok(Self { driver })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl PcmSink for I2sSink {
+    fn write(&mut self, samples: &[i16]) -> Result<(), AudioError> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.driver
+            .write_all(&bytes, BLOCK)
+            .map_err(AudioError::from_debug)
+    }
+}
+
+// How a notification makes itself noticed: a tone and pulses of the vibration motor, as
+// (on, off) milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alert {
+    pub tone: Option<Tone>,
+    pub buzz: &'static [(u32, u32)],
+}
+
+// Alert task: plays the alerts the display task sends. Tones go to the modem with the call
+// commands (so the cellular task keeps them out of calls), the motor is driven from here.
+pub fn run_alerts<P: OutputPin, D: DelayNs>(
+    mut motor: P,
+    mut delay: D,
+    alerts: Receiver<Alert>,
+    calls: Arc<Mutex<Calls>>,
+) -> Result<(), AudioError> {
+    for alert in alerts {
+        if let Some(tone) = alert.tone {
+            calls.lock().unwrap().play_tone(tone);
+        }
+        for &(on, off) in alert.buzz {
+            motor.set_high().map_err(AudioError::from_debug)?;
+            delay.delay_ms(on);
+            motor.set_low().map_err(AudioError::from_debug)?;
+            delay.delay_ms(off);
+        }
+    }
+    Ok(())
+}
+
+// Audio task: mixes the tones the display task sends into the sink. The ringtone goes on
+// until the call is answered or gone; nothing else is played over a call.
+pub fn run<S: PcmSink>(
+    mut sink: S,
+    tones: Receiver<Tone>,
+    calls: Arc<Mutex<Calls>>,
+) -> Result<(), AudioError> {
+    let mut mixer = Mixer::new(Volume::default());
+    let mut ringing = false;
+    loop {
+        // nothing to play, wait for something
+        let first = if mixer.is_idle() {
+            match tones.recv() {
+                Ok(tone) => Some(tone),
+                Err(_) => return Ok(()),
+            }
+        } else {
+            None
+        };
+        let state = calls.lock().unwrap().state();
+        for tone in first.into_iter().chain(tones.try_iter()) {
+            match tone {
+                Tone::Ring if state == CallState::Incoming && !ringing => {
+                    ringing = true;
+                    let ring = ToneGenerator::new(tone).repeat(RING_REPEATS);
+                    mixer.play(Box::new(ring), 1.0);
+                }
+                Tone::Ring => {}
+                Tone::Busy if state == CallState::Idle => {
+                    let busy = ToneGenerator::new(tone).repeat(BUSY_REPEATS);
+                    mixer.play(Box::new(busy), 1.0);
+                }
+                _ if state == CallState::Idle => {
+                    mixer.play(Box::new(ToneGenerator::new(tone)), 0.5)
+                }
+                _ => {}
+            }
+        }
+        if ringing && state != CallState::Incoming {
+            mixer.stop_all();
+        }
+        render(&mut mixer, &mut sink)?;
+        ringing &= !mixer.is_idle();
+    }
+}
+
+// Output device for call audio on the modem (+CSDVC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Route {
+    #[default]
+    Handset,
+    Speaker,
+}
+
+// Call audio on the modem: output device, speaker level and microphone mute.
+pub fn set_route<T: Transport>(modem: &mut Modem<T>, route: Route) -> Result<(), ModemError> {
+    let device = match route {
+        Route::Handset => 1,
+        Route::Speaker => 3,
+    };
+    modem
+        .command(&format!("AT+CSDVC={device}"), DEFAULT_TIMEOUT)
+        .map(|_| ())
+}
+
+pub fn set_call_volume<T: Transport>(
+    modem: &mut Modem<T>,
+    volume: Volume,
+) -> Result<(), ModemError> {
+    modem
+        .command(
+            &format!("AT+CLVL={}", volume.modem_level()),
+            DEFAULT_TIMEOUT,
+        )
+        .map(|_| ())
+}
+
+pub fn set_mic_muted<T: Transport>(modem: &mut Modem<T>, muted: bool) -> Result<(), ModemError> {
+    modem
+        .command(&format!("AT+CMUT={}", muted as u8), DEFAULT_TIMEOUT)
+        .map(|_| ())
+}
+
+// One of the modem's built-in tones on its speaker (+CPTONE), outside calls. DTMF digits are
+// sent in calls with +VTS instead.
+pub fn play_modem_tone<T: Transport>(modem: &mut Modem<T>, tone: Tone) -> Result<(), ModemError> {
+    let id = match tone {
+        Tone::Beep => 16,
+        Tone::Ring => 8,
+        Tone::Busy => 2,
+        Tone::Dtmf(_) => return Ok(()),
+    };
+    modem
+        .command(&format!("AT+CPTONE={id}"), DEFAULT_TIMEOUT)
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn samples(wav: &[u8]) -> Vec<i16> {
+        wav[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    // Goertzel power of one frequency, relative to the signal's own power
+    fn power(samples: &[i16], freq: f32) -> f32 {
+        let k = TAU * freq / SAMPLE_RATE as f32;
+        let (mut s1, mut s2) = (0.0f32, 0.0f32);
+        for &x in samples {
+            let s0 = x as f32 + 2.0 * k.cos() * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        let energy: f32 = samples.iter().map(|&x| (x as f32).powi(2)).sum();
+        (s1 * s1 + s2 * s2 - 2.0 * k.cos() * s1 * s2) / (energy * samples.len() as f32)
+    }
+
+    fn render_wav(mixer: &mut Mixer) -> Vec<u8> {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), SAMPLE_RATE).unwrap();
+        while render(mixer, &mut wav).unwrap() {}
+        wav.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn dtmf_to_wav() {
+        let mut mixer = Mixer::new(Volume::new(MAX_VOLUME));
+        mixer.play(Box::new(ToneGenerator::new(Tone::Dtmf('5'))), 1.0);
+        let wav = render_wav(&mut mixer);
+        assert!(mixer.is_idle());
+
+        // 120 ms of tone and 60 ms of silence
+        let count = SAMPLE_RATE * 180 / 1000;
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[4..8], &(36 + count * 2).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[22..24], &1u16.to_le_bytes());
+        assert_eq!(&wav[24..28], &SAMPLE_RATE.to_le_bytes());
+        assert_eq!(&wav[34..36], &16u16.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(&wav[40..44], &(count * 2).to_le_bytes());
+        assert_eq!(wav.len(), 44 + count as usize * 2);
+
+        let samples = samples(&wav);
+        let (tone, silence) = samples.split_at(SAMPLE_RATE as usize * 120 / 1000);
+        assert!(silence.iter().all(|&s| s == 0));
+        // '5' is 770 Hz and 1336 Hz, nothing of the other rows and columns
+        for freq in [770.0, 1336.0] {
+            assert!(power(tone, freq) > 0.2, "{freq} Hz");
+        }
+        for freq in [697.0, 852.0, 1209.0, 1477.0] {
+            assert!(power(tone, freq) < 0.01, "{freq} Hz");
+        }
+        // the pair at half amplitude each never clips
+        assert!(tone.iter().all(|&s| s > i16::MIN && s < i16::MAX));
+    }
+
+    #[test]
+    fn mixed_tones() {
+        let mut mixer = Mixer::new(Volume::new(MAX_VOLUME));
+        mixer.play(Box::new(ToneGenerator::new(Tone::Beep)), 0.5);
+        mixer.play(Box::new(ToneGenerator::new(Tone::Busy).repeat(1)), 0.5);
+        let samples = samples(&render_wav(&mut mixer));
+        // the longer source decides the length: two busy cadences
+        assert_eq!(samples.len(), SAMPLE_RATE as usize * 1500 / 1000);
+
+        let beep = &samples[..SAMPLE_RATE as usize * 150 / 1000];
+        assert!(power(beep, 1000.0) > 0.1);
+        assert!(power(beep, 425.0) > 0.1);
+        let busy = &samples[SAMPLE_RATE as usize * 200 / 1000..SAMPLE_RATE as usize * 375 / 1000];
+        assert!(power(busy, 425.0) > 0.2);
+        assert!(power(busy, 1000.0) < 0.01);
+        let gap = &samples[SAMPLE_RATE as usize * 375 / 1000..SAMPLE_RATE as usize * 750 / 1000];
+        assert!(gap.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn volume() {
+        let peak = |volume| {
+            let mut mixer = Mixer::new(volume);
+            mixer.play(Box::new(ToneGenerator::new(Tone::Beep)), 1.0);
+            let samples = samples(&render_wav(&mut mixer));
+            samples.iter().map(|s| s.unsigned_abs()).max().unwrap()
+        };
+        assert_eq!(peak(Volume::new(0)), 0);
+        let full = peak(Volume::new(MAX_VOLUME)) as f32;
+        let lower = peak(Volume::new(MAX_VOLUME).down()) as f32;
+        // 3 dB down
+        assert!((lower / full - 0.708).abs() < 0.01);
+        assert_eq!(Volume::new(MAX_VOLUME).modem_level(), MODEM_MAX_LEVEL);
+        assert_eq!(Volume::new(1).modem_level(), 1);
+    }
+
+    #[test]
+    fn stop_all() {
+        let mut mixer = Mixer::new(Volume::default());
+        mixer.play(
+            Box::new(ToneGenerator::new(Tone::Ring).repeat(RING_REPEATS)),
+            1.0,
+        );
+        let mut sink = Vec::new();
+        assert!(render(&mut mixer, &mut sink).unwrap());
+        mixer.stop_all();
+        assert!(mixer.is_idle());
+        assert!(!render(&mut mixer, &mut sink).unwrap());
+        assert_eq!(sink.len(), BLOCK_SAMPLES);
+    }
+
+    #[test]
+    fn unknown_dtmf_digit() {
+        let mut mixer = Mixer::new(Volume::default());
+        mixer.play(Box::new(ToneGenerator::new(Tone::Dtmf('x'))), 1.0);
+        assert_eq!(render_wav(&mut mixer).len(), 44);
+    }
+
+    impl PcmSink for Vec<i16> {
+        fn write(&mut self, samples: &[i16]) -> Result<(), AudioError> {
+            self.extend_from_slice(samples);
+            Ok(())
+        }
+    }
+}
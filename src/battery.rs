@@ -0,0 +1,213 @@
+// Battery task: polls the fuel gauge and the charger on the shared I2C bus and reports the
+// battery state to the display task as `Event::Battery` whenever it changes.
+
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::i2c::I2c;
+
+use crate::bq25896::{Bq25896, ChargeStatus, ChargerStatus, VbusStatus};
+use crate::bq27220::{Bq27220, GaugeReading};
+use crate::event::Event;
+
+const POLL_PERIOD: Duration = Duration::from_secs(10);
+// report at least this often so time to empty stays current
+const REPORT_PERIOD: Duration = Duration::from_secs(60);
+pub const LOW_PERCENT: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryStatus {
+    pub percent: u8,
+    pub voltage_mv: u16,
+    // positive while charging
+    pub current_ma: i16,
+    pub time_to_empty_min: Option<u16>,
+    pub time_to_full_min: Option<u16>,
+    pub charge: ChargeStatus,
+    // USB power is connected
+    pub external_power: bool,
+    // as the gauge sees the cell
+    pub present: bool,
+    pub full: bool,
+    pub empty: bool,
+    // read when the status is reported, None if the gauge didn't answer
+    pub health: Option<BatteryHealth>,
+    // None unless the charger sees something plugged in
+    pub usb: Option<UsbInput>,
+}
+
+// What the gauge has learnt about the cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryHealth {
+    pub percent: u8,
+    pub cycles: u16,
+    pub design_capacity_mah: u16,
+}
+
+// The USB input as the charger measures it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbInput {
+    pub vbus_mv: u16,
+    pub input_limit_ma: u16,
+    pub charge_current_ma: u16,
+}
+
+impl BatteryStatus {
+    fn new(gauge: &GaugeReading, charger: Option<&ChargerStatus>) -> Self {
+        let (charge, external_power) = match charger {
+            Some(c) => (
+                c.charge,
+                c.vbus_present && !matches!(c.vbus, VbusStatus::NoInput | VbusStatus::Otg),
+            ),
+            // without the charger, the gauge tells whether it is charging
+            None => {
+                let charging = !gauge.discharging() && gauge.current_ma > 0;
+                let charge = if charging {
+                    ChargeStatus::FastCharge
+                } else {
+                    ChargeStatus::NotCharging
+                };
+                (charge, charging)
+            }
+        };
+        Self {
+            percent: gauge.state_of_charge,
+            voltage_mv: gauge.voltage_mv,
+            current_ma: gauge.current_ma,
+            time_to_empty_min: gauge.time_to_empty_min,
+            time_to_full_min: gauge.time_to_full_min,
+            charge,
+            external_power,
+            present: gauge.battery_present(),
+            full: gauge.fully_charged(),
+            empty: gauge.empty(),
+            health: None,
+            usb: None,
+        }
+    }
+
+    pub fn charging(&self) -> bool {
+        matches!(
+            self.charge,
+            ChargeStatus::PreCharge | ChargeStatus::FastCharge
+        )
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.present && (self.percent <= LOW_PERCENT || self.empty) && !self.external_power
+    }
+
+    // what the status bar and the about screen show and compare, so small current changes
+    // are not reported
+    fn summary(&self) -> (u8, ChargeStatus, bool, bool, bool) {
+        (
+            self.percent,
+            self.charge,
+            self.external_power,
+            self.present,
+            self.full,
+        )
+    }
+}
+
+fn health<I: I2c>(gauge: &mut Bq27220<I>) -> Result<BatteryHealth, I::Error> {
+    Ok(BatteryHealth {
+        percent: gauge.state_of_health()?,
+        cycles: gauge.cycle_count()?,
+        design_capacity_mah: gauge.design_capacity_mah()?,
+    })
+}
+
+fn usb_input<I: I2c>(charger: &mut Bq25896<I>) -> Result<UsbInput, I::Error> {
+    Ok(UsbInput {
+        vbus_mv: charger.vbus_mv()?,
+        input_limit_ma: charger.input_current_limit_ma()?,
+        charge_current_ma: charger.charge_current_ma()?,
+    })
+}
+
+pub fn run<G, C>(
+    mut gauge: Bq27220<G>,
+    mut charger: Bq25896<C>,
+    events: Sender<Event>,
+) -> anyhow::Result<()>
+where
+    G: I2c,
+    C: I2c,
+{
+    // the gauge alone still gives the level, so a missing charger is not fatal
+    let mut has_charger = match charger.init() {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("charger init failed: {e:?}");
+            false
+        }
+    };
+
+    let mut last: Option<(BatteryStatus, Instant)> = None;
+    loop {
+        let charger_status = if has_charger {
+            match charger.status() {
+                Ok(status) => {
+                    if status.faults != 0 {
+                        log::warn!("charger faults {:#04x}", status.faults);
+                    }
+                    Some(status)
+                }
+                Err(e) => {
+                    log::warn!("charger read failed: {e:?}");
+                    has_charger = charger.init().is_ok();
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let usb = match charger_status {
+            Some(status) if status.vbus_present => match usb_input(&mut charger) {
+                Ok(usb) => Some(usb),
+                Err(e) => {
+                    log::warn!("charger ADC read failed: {e:?}");
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        match gauge.reading() {
+            Ok(reading) => {
+                let mut status = BatteryStatus::new(&reading, charger_status.as_ref());
+                status.usb = usb;
+                let due = match last {
+                    Some((previous, at)) => {
+                        previous.summary() != status.summary() || at.elapsed() >= REPORT_PERIOD
+                    }
+                    None => true,
+                };
+                if due {
+                    status.health = match health(&mut gauge) {
+                        Ok(health) => Some(health),
+                        Err(e) => {
+                            log::warn!("fuel gauge health read failed: {e:?}");
+                            None
+                        }
+                    };
+                    log::info!(
+                        "battery {}% {} mV {} mA {:?}",
+                        status.percent,
+                        status.voltage_mv,
+                        status.current_ma,
+                        status.charge
+                    );
+                    if events.send(Event::Battery(status)).is_err() {
+                        return Ok(());
+                    }
+                    last = Some((status, Instant::now()));
+                }
+            }
+            Err(e) => log::warn!("fuel gauge read failed: {e:?}"),
+        }
+        thread::sleep(POLL_PERIOD);
+    }
+}
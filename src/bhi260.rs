@@ -0,0 +1,332 @@
+// BHI260AP smart sensor hub datasheet:
+// https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bhi260ap-ds000.pdf
+//
+// The hub runs its own firmware, uploaded over I2C to program RAM after every power up, and
+// reports "virtual sensors" through FIFOs. Only the accelerometer and the any-motion
+// detector are used here.
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+pub const DEFAULT_ADDRESS: u8 = 0x28;
+
+const REG_CHAN_CMD: u8 = 0x00;
+const REG_CHAN_FIFO_WAKE: u8 = 0x01;
+const REG_CHAN_FIFO_NON_WAKE: u8 = 0x02;
+const REG_CHAN_STATUS: u8 = 0x03;
+const REG_HOST_INTERRUPT_CTRL: u8 = 0x07;
+const REG_RESET_REQ: u8 = 0x14;
+const REG_PRODUCT_ID: u8 = 0x1C;
+const REG_KERNEL_VERSION: u8 = 0x20;
+const REG_BOOT_STATUS: u8 = 0x25;
+const REG_INT_STATUS: u8 = 0x2D;
+
+const PRODUCT_ID: u8 = 0x89;
+
+const CMD_UPLOAD_TO_PROGRAM_RAM: u16 = 0x0002;
+const CMD_BOOT_PROGRAM_RAM: u16 = 0x0003;
+const CMD_CONFIGURE_SENSOR: u16 = 0x000D;
+
+// boot status
+const BOOT_HOST_INTERFACE_READY: u8 = 0x10;
+const BOOT_FW_VERIFY_DONE: u8 = 0x20;
+const BOOT_FW_VERIFY_ERROR: u8 = 0x40;
+
+// host interrupt control: the interrupt line is active low and only the sensor FIFOs and
+// faults raise it
+const INT_DISABLE_STATUS: u8 = 0x04;
+const INT_DISABLE_DEBUG: u8 = 0x08;
+const INT_ACTIVE_LOW: u8 = 0x20;
+
+// interrupt status
+const INT_FIFO_WAKE: u8 = 0x06;
+const INT_FIFO_NON_WAKE: u8 = 0x18;
+const INT_STATUS_FIFO: u8 = 0x20;
+const INT_RESET_FAULT: u8 = 0x80;
+
+// virtual sensor ids
+pub const SENSOR_ACCELEROMETER: u8 = 4;
+// low power any-motion, in the wake-up FIFO so it raises the interrupt on its own
+pub const SENSOR_ANY_MOTION_WAKE: u8 = 142;
+// default range of +-8 g
+pub const ACCEL_LSB_PER_G: f32 = 4096.0;
+
+// FIFO event ids outside the sensor range
+const ID_PADDING: u8 = 0;
+const ID_META_EVENT_WAKE: u8 = 248;
+const ID_META_EVENT: u8 = 254;
+
+// bytes per I2C write while uploading, besides the register address
+const UPLOAD_CHUNK: usize = 256;
+const RESET_TIMEOUT: Duration = Duration::from_millis(500);
+const BOOT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImuError<E> {
+    I2c(E),
+    UnknownPart(u8),
+    Timeout(&'static str),
+    Firmware(String),
+}
+impl<E> ImuError<E> {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        ImuError::Firmware(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoEvent {
+    // raw accelerometer counts, ACCEL_LSB_PER_G per g
+    Accelerometer { x: i16, y: i16, z: i16 },
+    // the device started moving
+    Motion,
+    // sensor ids reported by the firmware: initialized, sensor errors, FIFO overflows
+    Meta { kind: u8, a: u8, b: u8 },
+}
+
+pub struct Bhi260<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Bhi260<I2C>
+where
+    I2C: I2c,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    pub fn product_id(&mut self) -> Result<u8, I2C::Error> {
+        self.read_register(REG_PRODUCT_ID)
+    }
+
+    // non-zero once firmware is running
+    pub fn kernel_version(&mut self) -> Result<u16, I2C::Error> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(self.address, &[REG_KERNEL_VERSION], &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    // Resets the hub and boots `len` bytes of firmware, fed to `read` in chunks by offset.
+    // Uploading the usual ~130 KiB image takes a few seconds, so callers skip this when the
+    // firmware still runs from before an MCU reset.
+    pub fn boot<D, F, E>(
+        &mut self,
+        delay: &mut D,
+        len: u32,
+        mut read: F,
+    ) -> Result<(), ImuError<I2C::Error>>
+    where
+        D: DelayNs,
+        F: FnMut(u32, &mut [u8]) -> Result<(), E>,
+        E: Debug,
+    {
+        let product = self.product_id().map_err(ImuError::I2c)?;
+        if product != PRODUCT_ID {
+            return Err(ImuError::UnknownPart(product));
+        }
+        if len == 0 || len & 3 != 0 || len / 4 > u16::MAX as u32 {
+            return Err(ImuError::Firmware(format!("bad image length {len}")));
+        }
+        self.write_register(REG_RESET_REQ, 0x01)
+            .map_err(ImuError::I2c)?;
+        self.wait_boot_status(delay, BOOT_HOST_INTERFACE_READY, RESET_TIMEOUT, "reset")?;
+
+        // the upload command's length is in 32-bit words, the image follows it
+        let mut header = Vec::with_capacity(5 + UPLOAD_CHUNK);
+        header.push(REG_CHAN_CMD);
+        header.extend_from_slice(&CMD_UPLOAD_TO_PROGRAM_RAM.to_le_bytes());
+        header.extend_from_slice(&((len / 4) as u16).to_le_bytes());
+        let mut chunk = [0u8; UPLOAD_CHUNK];
+        let mut offset = 0;
+        while offset < len {
+            let n = (len - offset).min(UPLOAD_CHUNK as u32) as usize;
+            read(offset, &mut chunk[..n]).map_err(ImuError::from_debug)?;
+            let mut write = if offset == 0 {
+                std::mem::take(&mut header)
+            } else {
+                vec![REG_CHAN_CMD]
+            };
+            write.extend_from_slice(&chunk[..n]);
+            self.i2c
+                .write(self.address, &write)
+                .map_err(ImuError::I2c)?;
+            offset += n as u32;
+        }
+        self.wait_boot_status(delay, BOOT_FW_VERIFY_DONE, BOOT_TIMEOUT, "firmware verify")?;
+        if self.read_register(REG_BOOT_STATUS).map_err(ImuError::I2c)? & BOOT_FW_VERIFY_ERROR != 0 {
+            return Err(ImuError::Firmware("image rejected".to_string()));
+        }
+
+        self.command(CMD_BOOT_PROGRAM_RAM, &[])
+            .map_err(ImuError::I2c)?;
+        let deadline = Instant::now() + BOOT_TIMEOUT;
+        while self.kernel_version().map_err(ImuError::I2c)? == 0 {
+            if Instant::now() > deadline {
+                return Err(ImuError::Timeout("firmware boot"));
+            }
+            delay.delay_ms(10);
+        }
+        Ok(())
+    }
+
+    // Interrupt line active low, raised for sensor data and faults only.
+    pub fn configure_interrupt(&mut self) -> Result<(), I2C::Error> {
+        self.write_register(
+            REG_HOST_INTERRUPT_CTRL,
+            INT_ACTIVE_LOW | INT_DISABLE_STATUS | INT_DISABLE_DEBUG,
+        )
+    }
+
+    // `rate_hz` 0 turns the sensor off. Samples are batched for up to `latency` before the
+    // interrupt fires.
+    pub fn configure_sensor(
+        &mut self,
+        sensor: u8,
+        rate_hz: f32,
+        latency: Duration,
+    ) -> Result<(), I2C::Error> {
+        let latency_ms = (latency.as_millis() as u32).min(0x00FF_FFFF);
+        let mut payload = [0u8; 8];
+        payload[0] = sensor;
+        payload[1..5].copy_from_slice(&rate_hz.to_le_bytes());
+        payload[5..8].copy_from_slice(&latency_ms.to_le_bytes()[..3]);
+        self.command(CMD_CONFIGURE_SENSOR, &payload)
+    }
+
+    // Drains whatever the interrupt status says is pending into `events`. Returns false
+    // after a reset or fault, when the firmware has to be booted again.
+    pub fn read_events(&mut self, events: &mut Vec<FifoEvent>) -> Result<bool, I2C::Error> {
+        let status = self.read_register(REG_INT_STATUS)?;
+        if status & INT_RESET_FAULT != 0 {
+            return Ok(false);
+        }
+        let mut data = Vec::new();
+        for (mask, channel) in [
+            (INT_FIFO_WAKE, REG_CHAN_FIFO_WAKE),
+            (INT_FIFO_NON_WAKE, REG_CHAN_FIFO_NON_WAKE),
+        ] {
+            if status & mask != 0 {
+                data.clear();
+                self.read_fifo(channel, &mut data)?;
+                parse_fifo(&data, events);
+            }
+        }
+        if status & INT_STATUS_FIFO != 0 {
+            // command responses, not used
+            let mut header = [0u8; 4];
+            self.i2c
+                .write_read(self.address, &[REG_CHAN_STATUS], &mut header)?;
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            let mut rest = vec![0u8; len];
+            self.i2c
+                .write_read(self.address, &[REG_CHAN_STATUS], &mut rest)?;
+        }
+        Ok(true)
+    }
+
+    // A transfer starts with its length; later reads of the channel continue it.
+    fn read_fifo(&mut self, channel: u8, data: &mut Vec<u8>) -> Result<(), I2C::Error> {
+        let mut len = [0u8; 2];
+        self.i2c.write_read(self.address, &[channel], &mut len)?;
+        let mut remaining = u16::from_le_bytes(len) as usize;
+        let mut chunk = [0u8; UPLOAD_CHUNK];
+        while remaining > 0 {
+            let n = remaining.min(UPLOAD_CHUNK);
+            self.i2c
+                .write_read(self.address, &[channel], &mut chunk[..n])?;
+            data.extend_from_slice(&chunk[..n]);
+            remaining -= n;
+        }
+        Ok(())
+    }
+
+    // command id and byte length, then the payload padded to a multiple of 4
+    fn command(&mut self, id: u16, payload: &[u8]) -> Result<(), I2C::Error> {
+        let padded = payload.len().div_ceil(4) * 4;
+        let mut write = Vec::with_capacity(5 + padded);
+        write.push(REG_CHAN_CMD);
+        write.extend_from_slice(&id.to_le_bytes());
+        write.extend_from_slice(&(padded as u16).to_le_bytes());
+        write.extend_from_slice(payload);
+        write.resize(5 + padded, 0);
+        self.i2c.write(self.address, &write)
+    }
+
+    fn wait_boot_status<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        bits: u8,
+        timeout: Duration,
+        stage: &'static str,
+    ) -> Result<(), ImuError<I2C::Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let status = self.read_register(REG_BOOT_STATUS).map_err(ImuError::I2c)?;
+            if status & bits == bits {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                return Err(ImuError::Timeout(stage));
+            }
+            delay.delay_ms(10);
+        }
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u8, I2C::Error> {
+        let mut buf = [0u8];
+        self.i2c.write_read(self.address, &[reg], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(self.address, &[reg, value])
+    }
+}
+
+// Splits a FIFO transfer into events. Each event is an id byte and a payload whose size
+// depends on the id; the parse stops at an id it does not know the size of.
+pub fn parse_fifo(data: &[u8], events: &mut Vec<FifoEvent>) {
+    let mut i = 0;
+    while i < data.len() {
+        let id = data[i];
+        let size = match id {
+            ID_PADDING | 249 | 255 | SENSOR_ANY_MOTION_WAKE => 0,
+            1 | 3..=7 => 6,
+            // timestamps: small delta, large delta, full, for wake-up and non wake-up FIFOs
+            245 | 251 => 1,
+            246 | 252 => 2,
+            247 | 253 => 5,
+            ID_META_EVENT_WAKE | ID_META_EVENT => 3,
+            // debug message
+            250 => 17,
+            _ => {
+                log::warn!("BHI260 FIFO: unknown event id {id}, dropping the rest");
+                return;
+            }
+        };
+        let Some(payload) = data.get(i + 1..i + 1 + size) else {
+            return;
+        };
+        match id {
+            1 | 3..=7 => events.push(FifoEvent::Accelerometer {
+                x: i16::from_le_bytes([payload[0], payload[1]]),
+                y: i16::from_le_bytes([payload[2], payload[3]]),
+                z: i16::from_le_bytes([payload[4], payload[5]]),
+            }),
+            SENSOR_ANY_MOTION_WAKE => events.push(FifoEvent::Motion),
+            ID_META_EVENT_WAKE | ID_META_EVENT => events.push(FifoEvent::Meta {
+                kind: payload[0],
+                a: payload[1],
+                b: payload[2],
+            }),
+            _ => {}
+        }
+        i += 1 + size;
+    }
+}
@@ -0,0 +1,166 @@
+// BQ25896 single-cell charger with power path datasheet: https://www.ti.com/lit/ds/symlink/bq25896.pdf
+
+use embedded_hal::i2c::I2c;
+
+pub const DEFAULT_ADDRESS: u8 = 0x6B;
+
+const REG_INPUT: u8 = 0x00;
+const REG_ADC: u8 = 0x02;
+const REG_CHARGE_CONFIG: u8 = 0x03;
+const REG_TIMER: u8 = 0x07;
+const REG_STATUS: u8 = 0x0B;
+const REG_FAULT: u8 = 0x0C;
+const REG_VBUSV: u8 = 0x11;
+const REG_ICHGR: u8 = 0x12;
+const REG_PART: u8 = 0x14;
+
+// REG00
+const INPUT_IINLIM_MASK: u8 = 0x3F;
+const IINLIM_OFFSET_MA: u16 = 100;
+const IINLIM_STEP_MA: u16 = 50;
+// REG02
+const ADC_CONV_START: u8 = 0x80;
+// continuous conversion, once a second
+const ADC_CONV_RATE: u8 = 0x40;
+// REG03
+const CHARGE_WD_RST: u8 = 0x40;
+// REG07
+const TIMER_WATCHDOG_MASK: u8 = 0x30;
+// REG11
+const VBUS_GD: u8 = 0x80;
+// REG14
+const PART_NUMBER_MASK: u8 = 0x38;
+const PART_BQ25896: u8 = 0x00;
+
+// ADC scales
+const VBUS_OFFSET_MV: u16 = 2600;
+const VBUS_STEP_MV: u16 = 100;
+const ICHGR_STEP_MA: u16 = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChargerError<E> {
+    I2c(E),
+    // something else answers at the charger's address
+    UnknownPart(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VbusStatus {
+    NoInput,
+    UsbHost,
+    Adapter,
+    // boost mode, the charger is powering VBUS
+    Otg,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChargeStatus {
+    NotCharging,
+    PreCharge,
+    FastCharge,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChargerStatus {
+    pub vbus: VbusStatus,
+    pub charge: ChargeStatus,
+    pub power_good: bool,
+    // a source is connected to VBUS
+    pub vbus_present: bool,
+    // REG0C, non-zero when there is a fault
+    pub faults: u8,
+}
+
+pub struct Bq25896<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Bq25896<I2C>
+where
+    I2C: I2c,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    // Checks the part, turns the I2C watchdog off (it would reset the settings after 40 s
+    // without host writes) and starts the ADC so voltages and charge current can be read.
+    pub fn init(&mut self) -> Result<(), ChargerError<I2C::Error>> {
+        let part = self.read_register(REG_PART).map_err(ChargerError::I2c)? & PART_NUMBER_MASK;
+        if part != PART_BQ25896 {
+            return Err(ChargerError::UnknownPart(part));
+        }
+        self.update_register(REG_CHARGE_CONFIG, CHARGE_WD_RST, CHARGE_WD_RST)
+            .and_then(|_| self.update_register(REG_TIMER, TIMER_WATCHDOG_MASK, 0))
+            .and_then(|_| {
+                self.update_register(
+                    REG_ADC,
+                    ADC_CONV_START | ADC_CONV_RATE,
+                    ADC_CONV_START | ADC_CONV_RATE,
+                )
+            })
+            .map_err(ChargerError::I2c)
+    }
+
+    pub fn status(&mut self) -> Result<ChargerStatus, I2C::Error> {
+        let status = self.read_register(REG_STATUS)?;
+        let vbus = match status >> 5 {
+            0 => VbusStatus::NoInput,
+            1 => VbusStatus::UsbHost,
+            2 | 3 => VbusStatus::Adapter,
+            7 => VbusStatus::Otg,
+            _ => VbusStatus::Unknown,
+        };
+        let charge = match (status >> 3) & 0x03 {
+            1 => ChargeStatus::PreCharge,
+            2 => ChargeStatus::FastCharge,
+            3 => ChargeStatus::Done,
+            _ => ChargeStatus::NotCharging,
+        };
+        // the fault register latches, read it twice for the current state
+        self.read_register(REG_FAULT)?;
+        let faults = self.read_register(REG_FAULT)?;
+        Ok(ChargerStatus {
+            vbus,
+            charge,
+            power_good: status & 0x04 != 0,
+            vbus_present: self.read_register(REG_VBUSV)? & VBUS_GD != 0,
+            faults,
+        })
+    }
+
+    // set by input source detection when something is plugged in
+    pub fn input_current_limit_ma(&mut self) -> Result<u16, I2C::Error> {
+        let code = self.read_register(REG_INPUT)? & INPUT_IINLIM_MASK;
+        Ok(IINLIM_OFFSET_MA + code as u16 * IINLIM_STEP_MA)
+    }
+
+    // 0 when nothing is connected
+    pub fn vbus_mv(&mut self) -> Result<u16, I2C::Error> {
+        let reg = self.read_register(REG_VBUSV)?;
+        if reg & VBUS_GD == 0 {
+            return Ok(0);
+        }
+        Ok(VBUS_OFFSET_MV + (reg & 0x7F) as u16 * VBUS_STEP_MV)
+    }
+
+    pub fn charge_current_ma(&mut self) -> Result<u16, I2C::Error> {
+        let code = self.read_register(REG_ICHGR)? & 0x7F;
+        Ok(code as u16 * ICHGR_STEP_MA)
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u8, I2C::Error> {
+        let mut buf = [0u8];
+        self.i2c.write_read(self.address, &[reg], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn update_register(&mut self, reg: u8, mask: u8, value: u8) -> Result<(), I2C::Error> {
+        let current = self.read_register(reg)?;
+        let updated = (current & !mask) | (value & mask);
+        self.i2c.write(self.address, &[reg, updated])
+    }
+}
@@ -0,0 +1,150 @@
+// BQ27220 single-cell fuel gauge datasheet: https://www.ti.com/lit/ds/symlink/bq27220.pdf
+// Technical reference (commands): https://www.ti.com/lit/ug/sluubd4a/sluubd4a.pdf
+
+use embedded_hal::i2c::I2c;
+
+pub const DEFAULT_ADDRESS: u8 = 0x55;
+
+// standard commands, each a 16-bit little endian value
+const CMD_TEMPERATURE: u8 = 0x06;
+const CMD_VOLTAGE: u8 = 0x08;
+const CMD_BATTERY_STATUS: u8 = 0x0A;
+const CMD_CURRENT: u8 = 0x0C;
+const CMD_REMAINING_CAPACITY: u8 = 0x10;
+const CMD_FULL_CHARGE_CAPACITY: u8 = 0x12;
+const CMD_AVERAGE_CURRENT: u8 = 0x14;
+const CMD_TIME_TO_EMPTY: u8 = 0x16;
+const CMD_TIME_TO_FULL: u8 = 0x18;
+const CMD_CYCLE_COUNT: u8 = 0x2A;
+const CMD_STATE_OF_CHARGE: u8 = 0x2C;
+const CMD_STATE_OF_HEALTH: u8 = 0x2E;
+const CMD_DESIGN_CAPACITY: u8 = 0x3C;
+
+// BatteryStatus bits
+const STATUS_DSG: u16 = 0x0001; // discharging
+const STATUS_SYSDWN: u16 = 0x0002; // voltage low enough to shut the system down
+const STATUS_BATTPRES: u16 = 0x0008;
+const STATUS_FC: u16 = 0x0200; // full charge
+const STATUS_FD: u16 = 0x8000; // fully discharged
+
+// times read as this while not charging / discharging
+const TIME_UNAVAILABLE: u16 = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GaugeReading {
+    pub state_of_charge: u8,
+    pub voltage_mv: u16,
+    // positive while charging
+    pub current_ma: i16,
+    pub average_current_ma: i16,
+    pub remaining_mah: u16,
+    pub full_charge_mah: u16,
+    // None unless discharging (charging for time to full)
+    pub time_to_empty_min: Option<u16>,
+    pub time_to_full_min: Option<u16>,
+    // tenths of a degree Celsius
+    pub temperature_dc: i16,
+    pub status: u16,
+}
+
+impl GaugeReading {
+    pub fn discharging(&self) -> bool {
+        self.status & STATUS_DSG != 0
+    }
+
+    pub fn battery_present(&self) -> bool {
+        self.status & STATUS_BATTPRES != 0
+    }
+
+    pub fn fully_charged(&self) -> bool {
+        self.status & STATUS_FC != 0
+    }
+
+    // at or below the voltage the system should shut down at
+    pub fn empty(&self) -> bool {
+        self.status & (STATUS_SYSDWN | STATUS_FD) != 0
+    }
+}
+
+pub struct Bq27220<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Bq27220<I2C>
+where
+    I2C: I2c,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    // percent, 0..=100
+    pub fn state_of_charge(&mut self) -> Result<u8, I2C::Error> {
+        Ok(self.read(CMD_STATE_OF_CHARGE)?.min(100) as u8)
+    }
+
+    pub fn voltage_mv(&mut self) -> Result<u16, I2C::Error> {
+        self.read(CMD_VOLTAGE)
+    }
+
+    pub fn current_ma(&mut self) -> Result<i16, I2C::Error> {
+        Ok(self.read(CMD_CURRENT)? as i16)
+    }
+
+    pub fn average_current_ma(&mut self) -> Result<i16, I2C::Error> {
+        Ok(self.read(CMD_AVERAGE_CURRENT)? as i16)
+    }
+
+    pub fn time_to_empty_min(&mut self) -> Result<Option<u16>, I2C::Error> {
+        let minutes = self.read(CMD_TIME_TO_EMPTY)?;
+        Ok((minutes != TIME_UNAVAILABLE).then_some(minutes))
+    }
+
+    pub fn time_to_full_min(&mut self) -> Result<Option<u16>, I2C::Error> {
+        let minutes = self.read(CMD_TIME_TO_FULL)?;
+        Ok((minutes != TIME_UNAVAILABLE).then_some(minutes))
+    }
+
+    pub fn temperature_dc(&mut self) -> Result<i16, I2C::Error> {
+        // reported in 0.1 K
+        Ok(self.read(CMD_TEMPERATURE)? as i16 - 2732)
+    }
+
+    pub fn status(&mut self) -> Result<u16, I2C::Error> {
+        self.read(CMD_BATTERY_STATUS)
+    }
+
+    pub fn state_of_health(&mut self) -> Result<u8, I2C::Error> {
+        Ok(self.read(CMD_STATE_OF_HEALTH)?.min(100) as u8)
+    }
+
+    pub fn cycle_count(&mut self) -> Result<u16, I2C::Error> {
+        self.read(CMD_CYCLE_COUNT)
+    }
+
+    pub fn design_capacity_mah(&mut self) -> Result<u16, I2C::Error> {
+        self.read(CMD_DESIGN_CAPACITY)
+    }
+
+    pub fn reading(&mut self) -> Result<GaugeReading, I2C::Error> {
+        Ok(GaugeReading {
+            state_of_charge: self.state_of_charge()?,
+            voltage_mv: self.voltage_mv()?,
+            current_ma: self.current_ma()?,
+            average_current_ma: self.average_current_ma()?,
+            remaining_mah: self.read(CMD_REMAINING_CAPACITY)?,
+            full_charge_mah: self.read(CMD_FULL_CHARGE_CAPACITY)?,
+            time_to_empty_min: self.time_to_empty_min()?,
+            time_to_full_min: self.time_to_full_min()?,
+            temperature_dc: self.temperature_dc()?,
+            status: self.status()?,
+        })
+    }
+
+    fn read(&mut self, command: u8) -> Result<u16, I2C::Error> {
+        let mut buf = [0u8; 2];
+        self.i2c.write_read(self.address, &[command], &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+}
@@ -0,0 +1,400 @@
+// Voice call control. `Calls` is shared between the cellular task and the apps on the
+// display task like the SMS mailbox: apps queue commands, the cellular task sends them to
+// the modem and feeds URCs and +CLCC listings back into the state machine.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::audio::{self, Route, Tone, Volume};
+use crate::modem::{Modem, ModemError, Transport, DEFAULT_TIMEOUT};
+
+const DIAL_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallState {
+    Idle,
+    // outgoing, before and after the far end starts ringing
+    Dialing,
+    Alerting,
+    Incoming,
+    Active,
+    Held,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EndReason {
+    HungUp,
+    NoCarrier,
+    Busy,
+    NoAnswer,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub state: CallState,
+    pub incoming: bool,
+    // None until +CLIP arrives for an incoming call, or if the caller withheld it
+    pub number: Option<String>,
+    pub answered_at: Option<Instant>,
+}
+
+impl Call {
+    pub fn duration(&self, now: Instant) -> Duration {
+        self.answered_at
+            .map_or(Duration::ZERO, |t| now.saturating_duration_since(t))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallCommand {
+    Dial(String),
+    Answer,
+    HangUp,
+    Dtmf(char),
+    // put the current call on hold (or release it) and answer the waiting one
+    AnswerWaiting { hold: bool },
+    RejectWaiting,
+    Route(Route),
+    Volume(Volume),
+    Mute(bool),
+    // a notification tone, not a call
+    Tone(Tone),
+}
+
+impl CallCommand {
+    fn execute<T: Transport>(&self, modem: &mut Modem<T>) -> Result<(), ModemError> {
+        let command = match self {
+            CallCommand::Dial(number) => {
+                return modem
+                    .command(&format!("ATD{number};"), DIAL_TIMEOUT)
+                    .map(|_| ())
+            }
+            CallCommand::Route(route) => return audio::set_route(modem, *route),
+            CallCommand::Volume(volume) => return audio::set_call_volume(modem, *volume),
+            CallCommand::Mute(muted) => return audio::set_mic_muted(modem, *muted),
+            CallCommand::Tone(tone) => return audio::play_modem_tone(modem, *tone),
+            CallCommand::Answer => "ATA".to_string(),
+            CallCommand::HangUp => "AT+CHUP".to_string(),
+            CallCommand::Dtmf(c) => format!("AT+VTS={c}"),
+            CallCommand::AnswerWaiting { hold: true } => "AT+CHLD=2".to_string(),
+            CallCommand::AnswerWaiting { hold: false } => "AT+CHLD=1".to_string(),
+            CallCommand::RejectWaiting => "AT+CHLD=0".to_string(),
+        };
+        modem.command(&command, DEFAULT_TIMEOUT).map(|_| ())
+    }
+}
+
+// One line of an AT+CLCC listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallEntry {
+    pub incoming: bool,
+    // 0 active, 1 held, 2 dialing, 3 alerting, 4 incoming, 5 waiting
+    pub stat: u8,
+    pub number: Option<String>,
+}
+
+// "+CLCC: 1,1,4,0,0,"+447700900123",145"
+pub fn parse_clcc(line: &str) -> Option<CallEntry> {
+    let mut fields = line.strip_prefix("+CLCC:")?.split(',').map(str::trim);
+    fields.next()?; // call index
+    let incoming = fields.next()? == "1";
+    let stat = fields.next()?.parse().ok()?;
+    let number = fields
+        .nth(2) // skip mode and multiparty
+        .map(|n| n.trim_matches('"').to_string())
+        .filter(|n| !n.is_empty());
+    Some(CallEntry {
+        incoming,
+        stat,
+        number,
+    })
+}
+
+// first quoted field of +CLIP / +CCWA
+fn quoted_number(line: &str) -> Option<String> {
+    let start = line.find('"')? + 1;
+    let end = start + line[start..].find('"')?;
+    Some(line[start..end].to_string()).filter(|n| !n.is_empty())
+}
+
+#[derive(Default)]
+pub struct Calls {
+    call: Option<Call>,
+    // number of a second incoming call announced with +CCWA (None inside if withheld)
+    waiting: Option<Option<String>>,
+    last_end: Option<EndReason>,
+    commands: VecDeque<CallCommand>,
+    route: Route,
+    volume: Volume,
+    muted: bool,
+}
+
+impl Calls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn call(&self) -> Option<&Call> {
+        self.call.as_ref()
+    }
+
+    pub fn state(&self) -> CallState {
+        self.call.as_ref().map_or(CallState::Idle, |c| c.state)
+    }
+
+    pub fn waiting(&self) -> Option<&Option<String>> {
+        self.waiting.as_ref()
+    }
+
+    // why the last call ended, until the next one starts
+    pub fn last_end(&self) -> Option<&EndReason> {
+        self.last_end.as_ref()
+    }
+
+    pub fn dial(&mut self, number: &str) {
+        if self.call.is_some() {
+            return;
+        }
+        self.call = Some(Call {
+            state: CallState::Dialing,
+            incoming: false,
+            number: Some(number.to_string()),
+            answered_at: None,
+        });
+        self.last_end = None;
+        self.commands
+            .push_back(CallCommand::Dial(number.to_string()));
+    }
+
+    pub fn answer(&mut self) {
+        if self.state() == CallState::Incoming {
+            self.commands.push_back(CallCommand::Answer);
+        }
+    }
+
+    pub fn hang_up(&mut self) {
+        if self.call.is_some() {
+            self.commands.push_back(CallCommand::HangUp);
+        }
+    }
+
+    pub fn dtmf(&mut self, digit: char) {
+        if self.state() == CallState::Active && is_dtmf(digit) {
+            self.commands.push_back(CallCommand::Dtmf(digit));
+        }
+    }
+
+    pub fn answer_waiting(&mut self, hold: bool) {
+        if self.waiting.is_some() {
+            self.commands.push_back(CallCommand::AnswerWaiting { hold });
+        }
+    }
+
+    pub fn reject_waiting(&mut self) {
+        if self.waiting.take().is_some() {
+            self.commands.push_back(CallCommand::RejectWaiting);
+        }
+    }
+
+    pub fn route(&self) -> Route {
+        self.route
+    }
+
+    pub fn set_route(&mut self, route: Route) {
+        if route != self.route {
+            self.route = route;
+            self.commands.push_back(CallCommand::Route(route));
+        }
+    }
+
+    pub fn volume(&self) -> Volume {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: Volume) {
+        if volume != self.volume {
+            self.volume = volume;
+            self.commands.push_back(CallCommand::Volume(volume));
+        }
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted != self.muted {
+            self.muted = muted;
+            self.commands.push_back(CallCommand::Mute(muted));
+        }
+    }
+
+    // Beeps through the modem for a notification; nothing is played over a call.
+    pub fn play_tone(&mut self, tone: Tone) {
+        if self.call.is_none() {
+            self.commands.push_back(CallCommand::Tone(tone));
+        }
+    }
+
+    fn take_command(&mut self) -> Option<CallCommand> {
+        self.commands.pop_front()
+    }
+
+    // Applies a call related URC, returns true if the visible state changed.
+    pub fn handle_urc(&mut self, line: &str, now: Instant) -> bool {
+        if line == "RING" || line.starts_with("+CRING:") {
+            if self.call.is_some() {
+                return false;
+            }
+            self.incoming(None);
+        } else if line.starts_with("+CLIP:") {
+            let number = quoted_number(line);
+            match self.call.as_mut() {
+                Some(call) if call.incoming && call.number.is_none() => call.number = number,
+                Some(_) => return false,
+                None => self.incoming(number),
+            }
+        } else if line.starts_with("+CCWA:") {
+            self.waiting = Some(quoted_number(line));
+        } else if line.starts_with("VOICE CALL: BEGIN") {
+            let call = match self.call.as_mut() {
+                Some(call) => call,
+                None => return false,
+            };
+            call.state = CallState::Active;
+            call.answered_at.get_or_insert(now);
+        } else if line.starts_with("VOICE CALL: END") {
+            self.end(EndReason::HungUp);
+        } else if line == "NO CARRIER" {
+            self.end(EndReason::NoCarrier);
+        } else if line == "BUSY" {
+            self.end(EndReason::Busy);
+        } else if line == "NO ANSWER" {
+            self.end(EndReason::NoAnswer);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    // Brings the state machine in line with an AT+CLCC listing, returns true on change.
+    pub fn reconcile(&mut self, entries: &[CallEntry], now: Instant) -> bool {
+        let waiting = entries
+            .iter()
+            .find(|e| e.stat == 5)
+            .map(|e| e.number.clone());
+        let current = entries
+            .iter()
+            .filter(|e| e.stat != 5)
+            // prefer the call in the foreground when one is held
+            .min_by_key(|e| if e.stat == 1 { 1 } else { 0 });
+        let before = (self.call.clone(), self.waiting.clone());
+        self.waiting = waiting;
+        match current {
+            None => {
+                // an outgoing call is not listed until the modem has processed ATD
+                let dialing = self.state() == CallState::Dialing
+                    && self
+                        .commands
+                        .iter()
+                        .any(|c| matches!(c, CallCommand::Dial(_)));
+                if self.call.is_some() && !dialing {
+                    self.end(EndReason::HungUp);
+                }
+            }
+            Some(entry) => {
+                let state = match entry.stat {
+                    0 => CallState::Active,
+                    1 => CallState::Held,
+                    2 => CallState::Dialing,
+                    3 => CallState::Alerting,
+                    _ => CallState::Incoming,
+                };
+                let call = self.call.get_or_insert(Call {
+                    state,
+                    incoming: entry.incoming,
+                    number: None,
+                    answered_at: None,
+                });
+                call.state = state;
+                call.incoming = entry.incoming;
+                if entry.number.is_some() {
+                    call.number = entry.number.clone();
+                }
+                if matches!(state, CallState::Active | CallState::Held) {
+                    call.answered_at.get_or_insert(now);
+                }
+            }
+        }
+        before != (self.call.clone(), self.waiting.clone())
+    }
+
+    fn incoming(&mut self, number: Option<String>) {
+        self.call = Some(Call {
+            state: CallState::Incoming,
+            incoming: true,
+            number,
+            answered_at: None,
+        });
+        self.last_end = None;
+    }
+
+    fn end(&mut self, reason: EndReason) {
+        if self.call.take().is_some() {
+            self.last_end = Some(reason);
+        }
+        self.waiting = None;
+        // the next call starts on the handset with the microphone on
+        self.set_route(Route::Handset);
+        self.set_muted(false);
+    }
+
+    // a command the modem rejected, e.g. ATD without network
+    fn failed(&mut self, command: &CallCommand, error: String) {
+        if matches!(command, CallCommand::Dial(_) | CallCommand::Answer) {
+            self.end(EndReason::Failed(error));
+        }
+    }
+}
+
+pub fn is_dtmf(c: char) -> bool {
+    c.is_ascii_digit() || matches!(c, '*' | '#' | 'A'..='D')
+}
+
+// caller ID and call waiting notifications, default call audio
+pub fn init<T: Transport>(modem: &mut Modem<T>) -> Result<(), ModemError> {
+    modem.command("AT+CLIP=1", DEFAULT_TIMEOUT)?;
+    modem.command("AT+CCWA=1,1", DEFAULT_TIMEOUT)?;
+    audio::set_route(modem, Route::default())?;
+    audio::set_call_volume(modem, Volume::default())?;
+    Ok(())
+}
+
+// Sends queued commands to the modem, returns true if any was sent. The lock is not held
+// while the modem is busy.
+pub fn run_commands<T: Transport>(modem: &mut Modem<T>, calls: &Mutex<Calls>) -> bool {
+    let mut sent = false;
+    loop {
+        let Some(command) = calls.lock().unwrap().take_command() else {
+            break;
+        };
+        if let Err(e) = command.execute(modem) {
+            log::warn!("{command:?} failed: {e:?}");
+            calls.lock().unwrap().failed(&command, format!("{e:?}"));
+        }
+        sent = true;
+    }
+    sent
+}
+
+// Lists the modem's calls and reconciles, returns true on change.
+pub fn refresh<T: Transport>(
+    modem: &mut Modem<T>,
+    calls: &Mutex<Calls>,
+) -> Result<bool, ModemError> {
+    let lines = modem.command("AT+CLCC", DEFAULT_TIMEOUT)?;
+    let entries: Vec<CallEntry> = lines.iter().filter_map(|l| parse_clcc(l)).collect();
+    Ok(calls.lock().unwrap().reconcile(&entries, Instant::now()))
+}
@@ -0,0 +1,110 @@
+use crate::epdisplay::Colour;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i16,
+    pub y: i16,
+    pub w: i16,
+    pub h: i16,
+}
+
+impl Rect {
+    pub const fn new(x: i16, y: i16, w: i16, h: i16) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w <= 0 || self.h <= 0
+    }
+
+    pub fn right(&self) -> i16 {
+        self.x + self.w
+    }
+
+    pub fn bottom(&self) -> i16 {
+        self.y + self.h
+    }
+
+    pub fn contains(&self, x: i16, y: i16) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    // smallest rectangle covering both, empty rectangles are ignored
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+
+    pub fn inset(&self, d: i16) -> Rect {
+        Rect::new(self.x + d, self.y + d, self.w - 2 * d, self.h - 2 * d)
+    }
+}
+
+// What has to be pushed to the panel after drawing into a canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refresh {
+    None,
+    Partial(Rect),
+    Full,
+}
+
+impl Refresh {
+    pub fn merge(self, other: Refresh) -> Refresh {
+        match (self, other) {
+            (Refresh::Full, _) | (_, Refresh::Full) => Refresh::Full,
+            (Refresh::None, r) | (r, Refresh::None) => r,
+            (Refresh::Partial(a), Refresh::Partial(b)) => Refresh::Partial(a.union(&b)),
+        }
+    }
+}
+
+// Anything that can be drawn into in logical (rotated) coordinates.
+pub trait Canvas {
+    fn width(&self) -> i16;
+    fn height(&self) -> i16;
+    fn draw_pixel(&mut self, x: i16, y: i16, colour: Colour);
+
+    fn fill_rect(&mut self, x: i16, y: i16, w: i16, h: i16, colour: Colour) {
+        for j in y..y + h {
+            for i in x..x + w {
+                self.draw_pixel(i, j, colour);
+            }
+        }
+    }
+
+    fn draw_hline(&mut self, x: i16, y: i16, w: i16, colour: Colour) {
+        self.fill_rect(x, y, w, 1, colour);
+    }
+
+    fn draw_vline(&mut self, x: i16, y: i16, h: i16, colour: Colour) {
+        self.fill_rect(x, y, 1, h, colour);
+    }
+
+    fn draw_rect(&mut self, x: i16, y: i16, w: i16, h: i16, colour: Colour) {
+        self.draw_hline(x, y, w, colour);
+        self.draw_hline(x, y + h - 1, w, colour);
+        self.draw_vline(x, y, h, colour);
+        self.draw_vline(x + w - 1, y, h, colour);
+    }
+}
@@ -0,0 +1,276 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+
+use crate::call::{self, Calls};
+use crate::clock::{self, TimeKeeper};
+use crate::event::Event;
+use crate::modem::{Modem, ModemError, NetworkStatus, SimStatus, Transport, DEFAULT_TIMEOUT};
+use crate::pdu::Pdu;
+use crate::sms::{self, Mailbox, SmsEvent};
+use crate::timesource::TimeSource;
+
+// time for the modem supply to settle after POWER_EN before pressing PWRKEY
+const POWER_SETTLE_MS: u32 = 100;
+const STATUS_PERIOD: Duration = Duration::from_secs(30);
+// between reads of the network time while the clock wants it
+const NETWORK_TIME_PERIOD: Duration = Duration::from_secs(10 * 60);
+const POLL_PERIOD: Duration = Duration::from_millis(500);
+// +CLCC listing while a call is up, catches state changes the modem has no URC for
+const CALL_REFRESH_PERIOD: Duration = Duration::from_secs(2);
+const CALL_URCS: [&str; 8] = [
+    "RING",
+    "+CRING:",
+    "+CLIP:",
+    "+CCWA:",
+    "VOICE CALL:",
+    "NO CARRIER",
+    "BUSY",
+    "NO ANSWER",
+];
+
+// Cellular task: powers the modem up (its supply must be enabled already), then dispatches
+// URCs, reports the network status to the display task whenever it changes (and at least
+// every STATUS_PERIOD), sets the clock from the network time and services the SMS mailbox
+// and call control.
+pub fn run<T, PWR, D>(
+    mut modem: Modem<T>,
+    mut pwrkey: PWR,
+    mut delay: D,
+    mailbox: Arc<Mutex<Mailbox>>,
+    calls: Arc<Mutex<Calls>>,
+    clock: Arc<Mutex<TimeKeeper>>,
+    events: Sender<Event>,
+) -> anyhow::Result<()>
+where
+    T: Transport,
+    PWR: OutputPin,
+    D: DelayNs,
+{
+    delay.delay_ms(POWER_SETTLE_MS);
+    // the modem may still be on from before a reset, in which case it answers straight away
+    if modem.wait_ready(Duration::from_secs(1)).is_err() {
+        log::info!("powering on modem");
+        modem
+            .power_on(&mut pwrkey, &mut delay)
+            .map_err(|e| anyhow::anyhow!("modem power on failed: {e:?}"))?;
+    }
+    modem
+        .init()
+        .map_err(|e| anyhow::anyhow!("modem init failed: {e:?}"))?;
+    // registration changes are reported as URCs, which trigger an early status query; RI
+    // pulses low for every URC, not only RING, so a new message wakes the chip from sleep.
+    // The modem sets its clock from NITZ and reports the time zone when that arrives.
+    let setup = [
+        "AT+CREG=1",
+        "AT+CEREG=1",
+        "AT+CFGRI=1",
+        "AT+CTZU=1",
+        "AT+CTZR=1",
+    ];
+    for command in setup {
+        if let Err(e) = modem.command(command, DEFAULT_TIMEOUT) {
+            log::warn!("{command} failed: {e:?}");
+        }
+    }
+    let changed = Arc::new(AtomicBool::new(false));
+    for prefix in ["+CREG:", "+CEREG:", "+CPIN:"] {
+        let changed = changed.clone();
+        modem.subscribe(
+            prefix,
+            Box::new(move |_| changed.store(true, Ordering::Relaxed)),
+        );
+    }
+    let nitz = Arc::new(AtomicBool::new(false));
+    {
+        let nitz = nitz.clone();
+        modem.subscribe(
+            "+CTZV:",
+            Box::new(move |_| nitz.store(true, Ordering::Relaxed)),
+        );
+    }
+    // new messages and delivery reports, as (storage, index)
+    let (stored_tx, stored_rx) = mpsc::channel::<(String, u16)>();
+    for prefix in ["+CMTI:", "+CDSI:"] {
+        let stored_tx = stored_tx.clone();
+        modem.subscribe(
+            prefix,
+            Box::new(move |line| match sms::parse_index_urc(line) {
+                Some(stored) => stored_tx.send(stored).unwrap_or_default(),
+                None => log::warn!("bad SMS notification {line:?}"),
+            }),
+        );
+    }
+    if let Err(e) = call::init(&mut modem) {
+        log::warn!("call notification setup failed: {e:?}");
+    }
+    for prefix in CALL_URCS {
+        let calls = calls.clone();
+        let events = events.clone();
+        modem.subscribe(
+            prefix,
+            Box::new(move |line| {
+                let mut calls = calls.lock().unwrap();
+                if calls.handle_urc(line, Instant::now()) {
+                    events.send(Event::Call(calls.state())).ok();
+                }
+            }),
+        );
+    }
+    log::info!("modem ready");
+
+    // SMS storage is only accessible once the SIM is unlocked
+    let mut sms_ready = false;
+    let mut last_status: Option<NetworkStatus> = None;
+    let mut next_status = Instant::now();
+    let mut next_time = Instant::now();
+    let mut next_call_refresh = Instant::now();
+    loop {
+        if changed.swap(false, Ordering::Relaxed) || Instant::now() >= next_status {
+            next_status = Instant::now() + STATUS_PERIOD;
+            match modem.network_status() {
+                Ok(status) if last_status != Some(status) => {
+                    log::info!("network status: {status:?}");
+                    last_status = Some(status);
+                    if events.send(Event::Network(status)).is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(_) => {}
+                Err(ModemError::Timeout) => log::warn!("modem not responding"),
+                Err(e) => log::warn!("network status error: {e:?}"),
+            }
+        }
+        let registered = last_status.is_some_and(|s| s.registration.is_registered());
+        let nitz_arrived = nitz.swap(false, Ordering::Relaxed);
+        if registered && (nitz_arrived || Instant::now() >= next_time) {
+            next_time = Instant::now() + NETWORK_TIME_PERIOD;
+            set_clock(&mut modem, &clock, nitz_arrived);
+        }
+        if !sms_ready && last_status.is_some_and(|s| s.sim == SimStatus::Ready) {
+            sms_ready = start_sms(&mut modem, &mailbox, &events);
+        }
+        if call::run_commands(&mut modem, &calls) {
+            // pick up the effect of the command straight away
+            next_call_refresh = Instant::now();
+        }
+        let in_call = calls.lock().unwrap().call().is_some();
+        if in_call && Instant::now() >= next_call_refresh {
+            next_call_refresh = Instant::now() + CALL_REFRESH_PERIOD;
+            match call::refresh(&mut modem, &calls) {
+                Ok(true) => {
+                    let state = calls.lock().unwrap().state();
+                    events.send(Event::Call(state)).ok();
+                }
+                Ok(false) => {}
+                Err(e) => log::warn!("call listing failed: {e:?}"),
+            }
+        }
+        if let Err(e) = modem.poll(POLL_PERIOD) {
+            log::warn!("modem read error: {e:?}");
+        }
+        if sms_ready {
+            for (mem, index) in stored_rx.try_iter() {
+                if let Some(event) = file_stored(&mut modem, &mailbox, &mem, index) {
+                    events.send(Event::Sms(event)).ok();
+                }
+            }
+            while let Some(id) = sms::send_queued(&mut modem, &mailbox) {
+                events.send(Event::Sms(SmsEvent::StatusChanged(id))).ok();
+            }
+            sms::purge(&mut modem, &mailbox);
+        }
+    }
+}
+
+// Sets the clock from the network time if it is the best there is, and the time zone from
+// its offset. The modem's clock only holds the network time once NITZ has arrived (which
+// may bring a new time zone), until then it is in the past and is ignored.
+fn set_clock<T: Transport>(modem: &mut Modem<T>, clock: &Mutex<TimeKeeper>, nitz: bool) {
+    if !nitz && !clock.lock().unwrap().wants(TimeSource::Network) {
+        return;
+    }
+    match modem.network_time() {
+        Ok(Some(time)) => {
+            let utc = u64::try_from(time.utc).unwrap_or(0);
+            if !clock::is_plausible(utc) {
+                return;
+            }
+            let mut clock = clock.lock().unwrap();
+            clock.offer(TimeSource::Network, Duration::from_secs(utc));
+            clock.network_offset(time.offset_minutes);
+        }
+        Ok(None) => log::warn!("unreadable network time"),
+        Err(e) => log::warn!("network time query failed: {e:?}"),
+    }
+}
+
+fn start_sms<T: Transport>(
+    modem: &mut Modem<T>,
+    mailbox: &Mutex<Mailbox>,
+    events: &Sender<Event>,
+) -> bool {
+    if let Err(e) = sms::init(modem) {
+        log::warn!("SMS init failed: {e:?}");
+        return false;
+    }
+    match sms::load(modem) {
+        Ok(stored) => {
+            log::info!("{} stored SMS parts", stored.len());
+            let ids: Vec<u32> = {
+                let mut mailbox = mailbox.lock().unwrap();
+                stored
+                    .into_iter()
+                    .filter_map(|(index, deliver, read)| mailbox.receive(index, deliver, read))
+                    .collect()
+            };
+            for id in ids {
+                events.send(Event::Sms(SmsEvent::Received(id))).ok();
+            }
+            true
+        }
+        Err(e) => {
+            log::warn!("loading stored SMS failed: {e:?}");
+            false
+        }
+    }
+}
+
+// Reads a newly stored message or delivery report into the mailbox.
+fn file_stored<T: Transport>(
+    modem: &mut Modem<T>,
+    mailbox: &Mutex<Mailbox>,
+    mem: &str,
+    index: u16,
+) -> Option<SmsEvent> {
+    let pdu = match sms::read(modem, mem, index) {
+        Ok(pdu) => pdu,
+        Err(e) => {
+            log::warn!("reading SMS {mem} {index} failed: {e:?}");
+            return None;
+        }
+    };
+    match pdu {
+        Pdu::Deliver(deliver) => mailbox
+            .lock()
+            .unwrap()
+            .receive(index, deliver, false)
+            .map(SmsEvent::Received),
+        Pdu::StatusReport(report) => {
+            // reports are not kept on the SIM once applied
+            if let Err(e) = sms::delete(modem, mem, index) {
+                log::warn!("deleting status report {index} failed: {e:?}");
+            }
+            mailbox
+                .lock()
+                .unwrap()
+                .status_report(&report)
+                .map(SmsEvent::StatusChanged)
+        }
+        Pdu::Submit { .. } => None,
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Instant;
+
+use crate::app::{App, Context, Transition};
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::mesh::Delivery;
+use crate::meshtastic::BROADCAST;
+use crate::ui::{Ui, WidgetId};
+use crate::widget::{List, StatusBar, TextInput};
+
+pub const NAME: &str = "Mesh";
+const VISIBLE_ROWS: usize = 13;
+// characters per list row, longer messages continue on the next rows
+const LINE_CHARS: usize = 50;
+// what fits in one packet with the protobuf framing
+pub const MAX_TEXT: usize = 200;
+
+// Messages on the primary Meshtastic channel. Typing goes straight into the input, Enter
+// sends to the whole channel, Up/Down scroll.
+pub struct Chat {
+    ui: Ui,
+    status: WidgetId,
+    list: WidgetId,
+    input: WidgetId,
+}
+
+impl Chat {
+    pub fn launch(_ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        let status = ui.add(StatusBar::new(NAME));
+        let list = ui.add(List::new(VISIBLE_ROWS));
+        let mut input = TextInput::new("Message the channel");
+        input.set_focused(true);
+        let input = ui.add(input);
+        Box::new(Self {
+            ui,
+            status,
+            list,
+            input,
+        })
+    }
+
+    fn list(&mut self) -> &mut List {
+        self.ui.get_mut::<List>(self.list).unwrap()
+    }
+
+    fn input(&mut self) -> &mut TextInput {
+        self.ui.get_mut::<TextInput>(self.input).unwrap()
+    }
+
+    // Rebuilds the rows from the shared mesh state and scrolls to the newest message if the
+    // list was at the bottom.
+    fn refresh(&mut self, ctx: &mut Context) {
+        let mut mesh = ctx.mesh.lock().unwrap();
+        mesh.mark_read();
+        let mut rows = Vec::new();
+        for m in mesh.messages() {
+            // nodes in the address book go by the contact's name
+            let from = match ctx.contacts.by_node(m.from) {
+                Some(contact) => contact.name.clone(),
+                None => mesh.name_of(m.from),
+            };
+            let mut line = format!("{from}: {}", m.text);
+            if m.to != BROADCAST {
+                line.insert_str(0, "(direct) ");
+            }
+            match m.delivery {
+                Delivery::Queued => line.push_str(" [sending]"),
+                Delivery::Failed => line.push_str(" [not sent]"),
+                Delivery::Received | Delivery::Sent => {}
+            }
+            wrap(&line, &mut rows);
+        }
+        let nodes = format!("{} nodes", mesh.nodes().len());
+        drop(mesh);
+
+        self.ui
+            .get_mut::<StatusBar>(self.status)
+            .unwrap()
+            .set_indicators(&nodes);
+        let list = self.list();
+        let at_bottom = match list.selected() {
+            Some(i) => i + 1 >= list.items().len(),
+            None => true,
+        };
+        list.set_items(rows);
+        if at_bottom {
+            let last = list.items().len().saturating_sub(1);
+            list.select(last);
+        }
+    }
+
+    fn send(&mut self, ctx: &mut Context) {
+        let text = self.input().take();
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let result = ctx
+            .mesh
+            .lock()
+            .unwrap()
+            .send_text(BROADCAST, text, Instant::now());
+        if let Err(e) = result {
+            log::warn!("mesh message not queued: {e:?}");
+        }
+    }
+}
+
+// splits a message over rows of LINE_CHARS characters, continuation rows are indented
+fn wrap(line: &str, rows: &mut Vec<String>) {
+    let chars: Vec<char> = line.chars().collect();
+    for (i, chunk) in chars.chunks(LINE_CHARS).enumerate() {
+        let chunk: String = chunk.iter().collect();
+        rows.push(if i == 0 { chunk } else { format!("  {chunk}") });
+    }
+}
+
+impl App for Chat {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Key(Key::Char(c)) if self.input().text().len() + c.len_utf8() <= MAX_TEXT => {
+                self.input().insert(*c);
+            }
+            Event::Key(Key::Backspace) => self.input().backspace(),
+            Event::Key(Key::Enter) => self.send(ctx),
+            Event::Key(Key::Up) => self.list().select_previous(),
+            Event::Key(Key::Down) => self.list().select_next(),
+            Event::Key(Key::Back) => return Transition::Pop,
+            Event::Touch(Gesture::Swipe { direction, .. }) => match direction {
+                Direction::Up => self.list().select_next(),
+                Direction::Down => self.list().select_previous(),
+                Direction::Right => return Transition::Pop,
+                Direction::Left => {}
+            },
+            _ => {}
+        }
+        // sending queues a message and the radio task reports progress with Event::Mesh
+        if matches!(event, Event::Mesh(_) | Event::Key(Key::Enter)) {
+            self.refresh(ctx);
+        }
+        Transition::None
+    }
+}
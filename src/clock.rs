@@ -0,0 +1,260 @@
+// Wall clock time: the system clock, set from the best of the network (NITZ via +CCLK), GNSS
+// and SNTP (which of them to believe is up to `timesource`), and the time zone, kept in NVS.
+// ESP-IDF keeps the system clock running on the RTC timer through deep sleep; which source set
+// it is kept in RTC memory alongside.
+
+use std::ptr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys;
+
+use crate::timesource::{self, Reading, SourceSelector, TimeSource};
+
+// before this (2024-01-01) the system clock has not been set since power up
+const VALID_AFTER: u64 = 1_704_067_200;
+const NAMESPACE: &str = "clock";
+const TZ_KEY: &str = "tz";
+// POSIX TZ
+const DEFAULT_TZ: &str = "UTC0";
+const MAX_TZ_LEN: usize = 64;
+
+pub const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+pub const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i32,
+    // 1 to 12
+    pub month: u8,
+    // 1 to 31
+    pub day: u8,
+    // 0 is Sunday
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl LocalTime {
+    // "14:05"
+    pub fn time_text(&self) -> String {
+        format!("{:02}:{:02}", self.hour, self.minute)
+    }
+
+    // "Monday 19 October"
+    pub fn date_text(&self) -> String {
+        format!(
+            "{} {} {}",
+            WEEKDAYS[self.weekday as usize % 7],
+            self.day,
+            MONTHS[(self.month as usize + 11) % 12]
+        )
+    }
+}
+
+// whether a time in seconds since the Unix epoch can be a real reading
+pub fn is_plausible(secs: u64) -> bool {
+    secs >= VALID_AFTER
+}
+
+fn unix_time() -> Option<Duration> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .filter(|t| is_plausible(t.as_secs()))
+}
+
+fn local_tm(secs: u64) -> Option<sys::tm> {
+    let t = secs as sys::time_t;
+    let mut tm: sys::tm = unsafe { std::mem::zeroed() };
+    if unsafe { sys::localtime_r(&t, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(tm)
+}
+
+// None until the clock has been set
+pub fn now() -> Option<LocalTime> {
+    local_time(unix_time()?.as_secs())
+}
+
+// seconds since the Unix epoch, None until the clock has been set
+pub fn unix_now() -> Option<u64> {
+    unix_time().map(|t| t.as_secs())
+}
+
+// a time in seconds since the Unix epoch in the current time zone
+pub fn local_time(secs: u64) -> Option<LocalTime> {
+    let tm = local_tm(secs)?;
+    Some(LocalTime {
+        year: tm.tm_year + 1900,
+        month: tm.tm_mon as u8 + 1,
+        day: tm.tm_mday as u8,
+        weekday: tm.tm_wday as u8,
+        hour: tm.tm_hour as u8,
+        minute: tm.tm_min as u8,
+        second: tm.tm_sec as u8,
+    })
+}
+
+// Time left in the current minute, for waking up just after the clock has moved on. Time
+// zones are whole minutes off UTC, so this is the same for local time.
+pub fn until_next_minute() -> Duration {
+    let t = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let into_minute = Duration::new(t.as_secs() % 60, t.subsec_nanos());
+    Duration::from_secs(60) - into_minute
+}
+
+// offset of local time east of UTC under the current time zone
+pub fn utc_offset_minutes(secs: u64) -> Option<i32> {
+    let tm = local_tm(secs)?;
+    let local = timesource::utc_seconds(
+        tm.tm_year + 1900,
+        tm.tm_mon as u8 + 1,
+        tm.tm_mday as u8,
+        tm.tm_hour as u8,
+        tm.tm_min as u8,
+        tm.tm_sec as u8,
+    );
+    Some(((local - secs as i64) / 60) as i32)
+}
+
+// POSIX TZ for a fixed offset east of UTC, whose sign POSIX has the other way round:
+// 60 -> "<+01>-1", -210 -> "<-0330>3:30"
+pub fn fixed_offset_tz(offset_minutes: i32) -> String {
+    let sign = if offset_minutes < 0 { '-' } else { '+' };
+    let posix_sign = if offset_minutes < 0 { "" } else { "-" };
+    let (hours, minutes) = (offset_minutes.abs() / 60, offset_minutes.abs() % 60);
+    if minutes == 0 {
+        format!("<{sign}{hours:02}>{posix_sign}{hours}")
+    } else {
+        format!("<{sign}{hours:02}{minutes:02}>{posix_sign}{hours}:{minutes:02}")
+    }
+}
+
+// survives deep sleep like the system clock itself
+#[link_section = ".rtc.data.clock"]
+static mut LAST_SYNC: Option<Reading> = None;
+
+// Owner of the system clock and the time zone, shared by the tasks with a time source.
+pub struct TimeKeeper {
+    selector: SourceSelector,
+    nvs: Option<EspNvs<NvsDefault>>,
+    tz: String,
+}
+
+impl TimeKeeper {
+    pub fn new(partition: Option<EspDefaultNvsPartition>) -> Self {
+        let nvs = partition.and_then(|p| match EspNvs::new(p, NAMESPACE, true) {
+            Ok(nvs) => Some(nvs),
+            Err(e) => {
+                log::warn!("clock settings unavailable: {e:?}");
+                None
+            }
+        });
+        let mut buf = [0u8; MAX_TZ_LEN];
+        let tz = nvs
+            .as_ref()
+            .and_then(|nvs| nvs.get_str(TZ_KEY, &mut buf).ok().flatten())
+            .unwrap_or(DEFAULT_TZ)
+            .to_string();
+        apply_tz(&tz);
+        // only one keeper exists
+        let last = unsafe { *ptr::addr_of!(LAST_SYNC) };
+        Self {
+            selector: SourceSelector::new(last),
+            nvs,
+            tz,
+        }
+    }
+
+    // whether a reading from `source` would be used
+    pub fn wants(&self, source: TimeSource) -> bool {
+        match unix_time() {
+            Some(now) => self.selector.wants(source, now.as_secs()),
+            None => true,
+        }
+    }
+
+    // Sets the clock to `utc` if it is better than what the clock has.
+    pub fn offer(&mut self, source: TimeSource, utc: Duration) -> bool {
+        if !is_plausible(utc.as_secs()) || !self.wants(source) {
+            return false;
+        }
+        let tv = sys::timeval {
+            tv_sec: utc.as_secs() as _,
+            tv_usec: utc.subsec_micros() as _,
+        };
+        if unsafe { sys::settimeofday(&tv, ptr::null()) } != 0 {
+            log::warn!("setting the clock failed");
+            return false;
+        }
+        log::info!("clock set from {source:?}");
+        self.synced(source);
+        true
+    }
+
+    // the clock was set from `source` elsewhere (SNTP sets it itself)
+    pub fn synced(&mut self, source: TimeSource) {
+        let now = unix_time().map_or(0, |t| t.as_secs());
+        self.selector.synced(source, now);
+        unsafe { *ptr::addr_of_mut!(LAST_SYNC) = self.selector.last() };
+    }
+
+    // POSIX TZ, e.g. "GMT0BST,M3.5.0/1,M10.5.0"
+    pub fn set_timezone(&mut self, tz: &str) {
+        if tz == self.tz {
+            return;
+        }
+        apply_tz(tz);
+        self.tz = tz.to_string();
+        if let Some(nvs) = self.nvs.as_mut() {
+            if let Err(e) = nvs.set_str(TZ_KEY, tz) {
+                log::warn!("saving the time zone failed: {e:?}");
+            }
+        }
+    }
+
+    // Offset from UTC the network says applies here. A time zone that agrees is kept (it
+    // knows about daylight saving), one that doesn't is replaced, e.g. after travelling.
+    pub fn network_offset(&mut self, offset_minutes: i32) {
+        let Some(now) = unix_time() else {
+            return;
+        };
+        if utc_offset_minutes(now.as_secs()) == Some(offset_minutes) {
+            return;
+        }
+        let tz = fixed_offset_tz(offset_minutes);
+        log::info!("time zone {} from the network", tz);
+        self.set_timezone(&tz);
+    }
+}
+
+fn apply_tz(tz: &str) {
+    std::env::set_var("TZ", tz);
+    unsafe { sys::tzset() };
+}
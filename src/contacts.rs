@@ -0,0 +1,222 @@
+// Address book of the display task, kept on the SD card as a vCard file (contacts/contacts.vcf)
+// so it can be edited on a computer as well. Without a card the book lives in memory and is
+// written out once one is mounted. Other .vcf files copied to the contacts directory can be
+// imported, and the book exported as vCard 3.0 or 4.0 next to them.
+
+use crate::storage::{Area, Storage, StorageError};
+use crate::vcard::{self, digits_of, Contact, Version};
+
+const FILE: &str = "contacts.vcf";
+pub const EXPORT_FILE: &str = "export.vcf";
+// numbers are compared on this many trailing digits, so "07700 900123" and "+44 7700 900123"
+// are the same
+const MATCH_DIGITS: usize = 9;
+// shorter numbers (short codes, extensions) have to match in full
+const MIN_MATCH_DIGITS: usize = 7;
+
+// Whether two numbers reach the same phone, whatever the national or international format.
+pub fn numbers_match(a: &str, b: &str) -> bool {
+    let (a, b) = (digits_of(a), digits_of(b));
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    if a == b {
+        return true;
+    }
+    if a.len() < MIN_MATCH_DIGITS || b.len() < MIN_MATCH_DIGITS {
+        return false;
+    }
+    let n = MATCH_DIGITS.min(a.len()).min(b.len());
+    a[a.len() - n..] == b[b.len() - n..]
+}
+
+#[derive(Default)]
+pub struct Contacts {
+    // sorted by name
+    contacts: Vec<Contact>,
+    next_id: u32,
+    // changed since the file was last written, e.g. while there was no card
+    unsaved: bool,
+}
+
+impl Contacts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn all(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Contact> {
+        self.contacts.iter().find(|c| c.id == id)
+    }
+
+    // Contacts with a name word or a number starting with `query`, all of them when empty.
+    pub fn search(&self, query: &str) -> Vec<&Contact> {
+        self.contacts.iter().filter(|c| c.matches(query)).collect()
+    }
+
+    pub fn by_number(&self, number: &str) -> Option<&Contact> {
+        self.contacts
+            .iter()
+            .find(|c| c.numbers.iter().any(|n| numbers_match(n, number)))
+    }
+
+    pub fn by_node(&self, node: u32) -> Option<&Contact> {
+        self.contacts.iter().find(|c| c.nodes.contains(&node))
+    }
+
+    // how to show a caller or sender: "Alice Smith (+44 7700 900123)" or just the number
+    pub fn describe(&self, number: &str) -> String {
+        match self.by_number(number) {
+            Some(contact) => format!("{} ({number})", contact.name),
+            None => number.to_string(),
+        }
+    }
+
+    // the name of the contact with `number`, or the number itself
+    pub fn name_for(&self, number: &str) -> String {
+        self.by_number(number)
+            .map_or_else(|| number.to_string(), |c| c.name.clone())
+    }
+
+    // Adds a contact and returns its id.
+    pub fn add(&mut self, mut contact: Contact) -> u32 {
+        self.next_id += 1;
+        contact.id = self.next_id;
+        self.insert_sorted(contact);
+        self.unsaved = true;
+        self.next_id
+    }
+
+    // Replaces the contact with the same id, false if there is none.
+    pub fn update(&mut self, contact: Contact) -> bool {
+        let Some(i) = self.contacts.iter().position(|c| c.id == contact.id) else {
+            return false;
+        };
+        self.contacts.remove(i);
+        self.insert_sorted(contact);
+        self.unsaved = true;
+        true
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.contacts.len();
+        self.contacts.retain(|c| c.id != id);
+        self.unsaved |= self.contacts.len() != len;
+        self.contacts.len() != len
+    }
+
+    // Adds `contacts`, folding those with the name of an existing contact into it. Returns
+    // how many were new.
+    pub fn merge(&mut self, contacts: Vec<Contact>) -> usize {
+        let mut added = 0;
+        for contact in contacts {
+            let existing = self
+                .contacts
+                .iter_mut()
+                .find(|c| c.name.eq_ignore_ascii_case(&contact.name));
+            let Some(existing) = existing else {
+                // contacts from this book (not a file) keep their ids
+                if contact.id != 0 && self.get(contact.id).is_none() {
+                    self.insert_sorted(contact);
+                    self.unsaved = true;
+                } else {
+                    self.add(contact);
+                }
+                added += 1;
+                continue;
+            };
+            for number in contact.numbers {
+                if !existing.numbers.iter().any(|n| numbers_match(n, &number)) {
+                    existing.numbers.push(number);
+                    self.unsaved = true;
+                }
+            }
+            for node in contact.nodes {
+                if !existing.nodes.contains(&node) {
+                    existing.nodes.push(node);
+                    self.unsaved = true;
+                }
+            }
+            if !contact.note.is_empty() && !existing.note.contains(&contact.note) {
+                if !existing.note.is_empty() {
+                    existing.note.push('\n');
+                }
+                existing.note.push_str(&contact.note);
+                self.unsaved = true;
+            }
+        }
+        added
+    }
+
+    fn insert_sorted(&mut self, contact: Contact) {
+        let key = contact.name.to_lowercase();
+        let i = self
+            .contacts
+            .partition_point(|c| c.name.to_lowercase() <= key);
+        self.contacts.insert(i, contact);
+    }
+
+    // Reads the book from a newly mounted card. Contacts added or changed while there was no
+    // card are merged into it and written back (ones deleted meanwhile come back).
+    pub fn load(&mut self, storage: &Storage) -> Result<(), StorageError> {
+        let loaded = match storage.read(Area::Contacts, FILE) {
+            Ok(data) => vcard::parse(&String::from_utf8_lossy(&data)),
+            Err(StorageError::NotFound(_)) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let previous = std::mem::take(&mut self.contacts);
+        let unsaved = self.unsaved;
+        self.merge(loaded);
+        // contacts that were known keep their ids, for the pages showing them
+        for contact in self.contacts.iter_mut() {
+            let known = previous
+                .iter()
+                .find(|p| p.name.eq_ignore_ascii_case(&contact.name));
+            if let Some(known) = known {
+                contact.id = known.id;
+            }
+        }
+        self.unsaved = false;
+        log::info!("{} contacts", self.contacts.len());
+        if unsaved {
+            self.merge(previous);
+            self.save(storage)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self, storage: &Storage) -> Result<(), StorageError> {
+        let text = vcard::write(&self.contacts, Version::V3);
+        storage.write(Area::Contacts, FILE, text.as_bytes())?;
+        self.unsaved = false;
+        Ok(())
+    }
+
+    // Merges every other .vcf file in the contacts directory, returns how many contacts
+    // were new.
+    pub fn import(&mut self, storage: &Storage) -> Result<usize, StorageError> {
+        let mut added = 0;
+        for name in storage.list(Area::Contacts)? {
+            let is_vcard = name.to_lowercase().ends_with(".vcf");
+            if !is_vcard || name == FILE || name == EXPORT_FILE {
+                continue;
+            }
+            let data = storage.read(Area::Contacts, &name)?;
+            let contacts = vcard::parse(&String::from_utf8_lossy(&data));
+            log::info!("importing {} contacts from {name}", contacts.len());
+            added += self.merge(contacts);
+        }
+        if self.unsaved {
+            self.save(storage)?;
+        }
+        Ok(added)
+    }
+
+    pub fn export(&self, storage: &Storage, version: Version) -> Result<(), StorageError> {
+        let text = vcard::write(&self.contacts, version);
+        storage.write(Area::Contacts, EXPORT_FILE, text.as_bytes())
+    }
+}
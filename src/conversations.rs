@@ -0,0 +1,540 @@
+// Message history of the display task: every SMS and direct Meshtastic message sent or
+// received, kept on the SD card in the messages directory with one file per number or node
+// ("sms_+447700900123.txt", "mesh_a1b2c3d4.txt"), so it outlives the SIM and the radio's
+// short buffers. Each line of a file is "<unix seconds>\t<status>\t<text>", oldest first, with
+// tabs, line breaks and backslashes escaped.
+//
+// Threads are put together from the address book when shown: a contact's numbers and nodes
+// make one conversation, anything else is a conversation of its own. The channel's broadcast
+// messages stay in the Mesh app.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::contacts::Contacts;
+use crate::mesh::{Delivery, MeshMessage};
+use crate::meshtastic;
+use crate::sms::{Message, Outgoing, OutgoingStatus};
+use crate::storage::{Area, Storage, StorageError};
+use crate::timesource;
+use crate::vcard;
+
+// older messages are dropped from a file once it holds this many
+pub const MAX_PER_PEER: usize = 200;
+// characters of the last message shown in the thread list
+const PREVIEW_CHARS: usize = 40;
+
+// The other end of a message.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Peer {
+    Sms(String),
+    Mesh(u32),
+}
+
+impl Peer {
+    fn file_name(&self) -> String {
+        match self {
+            Peer::Sms(number) => {
+                let number: String = number
+                    .chars()
+                    .filter(|c| c.is_ascii_digit() || *c == '+')
+                    .collect();
+                format!("sms_{number}.txt")
+            }
+            Peer::Mesh(node) => format!("mesh_{node:08x}.txt"),
+        }
+    }
+
+    fn from_file_name(name: &str) -> Option<Peer> {
+        let stem = name.strip_suffix(".txt")?;
+        if let Some(number) = stem.strip_prefix("sms_") {
+            return (!number.is_empty()).then(|| Peer::Sms(number.to_string()));
+        }
+        stem.strip_prefix("mesh_")
+            .and_then(vcard::parse_node)
+            .map(Peer::Mesh)
+    }
+
+    // the number, or the node as "!a1b2c3d4"
+    pub fn address(&self) -> String {
+        match self {
+            Peer::Sms(number) => number.clone(),
+            Peer::Mesh(node) => meshtastic::node_id(*node),
+        }
+    }
+
+    // what the message went over, for the bubbles of a conversation that uses both
+    pub fn medium(&self) -> &'static str {
+        match self {
+            Peer::Sms(_) => "SMS",
+            Peer::Mesh(_) => "LoRa",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    // received
+    Unread,
+    Read,
+    // sent, as far as it got
+    Sending,
+    Sent,
+    Delivered,
+    Failed,
+}
+
+impl Status {
+    pub fn is_outgoing(self) -> bool {
+        !matches!(self, Status::Unread | Status::Read)
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Status::Unread => "unread",
+            Status::Read => "read",
+            Status::Sending => "sending",
+            Status::Sent => "sent",
+            Status::Delivered => "delivered",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Status> {
+        Some(match code {
+            "unread" => Status::Unread,
+            "read" => Status::Read,
+            // the outbox and the radio queue don't survive a restart
+            "sending" | "failed" => Status::Failed,
+            "sent" => Status::Sent,
+            "delivered" => Status::Delivered,
+            _ => return None,
+        })
+    }
+
+    fn from_outgoing(status: &OutgoingStatus) -> Status {
+        match status {
+            OutgoingStatus::Queued | OutgoingStatus::Sending => Status::Sending,
+            OutgoingStatus::Sent => Status::Sent,
+            OutgoingStatus::Delivered => Status::Delivered,
+            OutgoingStatus::Failed(_) => Status::Failed,
+        }
+    }
+
+    fn from_delivery(delivery: Delivery) -> Status {
+        match delivery {
+            Delivery::Received => Status::Unread,
+            Delivery::Queued => Status::Sending,
+            Delivery::Sent => Status::Sent,
+            Delivery::Failed => Status::Failed,
+        }
+    }
+}
+
+// Where a message of this session lives, for its status updates. Not stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Inbox(u32),
+    Outbox(u32),
+    Mesh(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    // seconds since the Unix epoch, 0 when the clock was not set
+    pub time: u64,
+    pub status: Status,
+    pub text: String,
+    pub source: Option<Source>,
+}
+
+impl Entry {
+    // the same message, e.g. an SMS filed again from the SIM after a restart
+    fn same_as(&self, other: &Entry) -> bool {
+        self.time == other.time
+            && self.text == other.text
+            && self.status.is_outgoing() == other.status.is_outgoing()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadKey {
+    Contact(u32),
+    // a number or node that is not in the address book
+    Peer(Peer),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thread {
+    pub key: ThreadKey,
+    // the contact's name, or the address of the peer
+    pub name: String,
+    // the numbers and nodes of the conversation, the one last used first
+    pub peers: Vec<Peer>,
+    // of the newest message, 0 without messages
+    pub time: u64,
+    pub preview: String,
+    pub unread: usize,
+}
+
+#[derive(Default)]
+pub struct Conversations {
+    // oldest first
+    entries: BTreeMap<Peer, Vec<Entry>>,
+    // files to write, e.g. messages that came while there was no card
+    unsaved: Vec<Peer>,
+}
+
+impl Conversations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, peer: Peer, entry: Entry) {
+        let entries = self.entries.entry(peer.clone()).or_default();
+        // keep the file in time order, for messages stamped by the service centre; without
+        // a time a message goes last
+        let i = match entry.time {
+            0 => entries.len(),
+            time => entries.partition_point(|e| e.time <= time),
+        };
+        entries.insert(i, entry);
+        if entries.len() > MAX_PER_PEER {
+            entries.remove(0);
+        }
+        self.changed(peer);
+    }
+
+    fn changed(&mut self, peer: Peer) {
+        if !self.unsaved.contains(&peer) {
+            self.unsaved.push(peer);
+        }
+    }
+
+    // whether the message is in the history
+    pub fn filed(&self, source: Source) -> bool {
+        self.entries
+            .values()
+            .flatten()
+            .any(|e| e.source == Some(source))
+    }
+
+    fn find_source(&mut self, source: Source) -> Option<(Peer, &mut Entry)> {
+        self.entries.iter_mut().find_map(|(peer, entries)| {
+            let entry = entries.iter_mut().find(|e| e.source == Some(source))?;
+            Some((peer.clone(), entry))
+        })
+    }
+
+    // Files an SMS from the mailbox. Messages still on the SIM are filed again after every
+    // restart, they are recognised by their time stamp. Returns whether it was new.
+    pub fn received_sms(&mut self, message: &Message) -> bool {
+        let t = message.timestamp;
+        let local =
+            timesource::utc_seconds(t.year as i32, t.month, t.day, t.hour, t.minute, t.second);
+        let entry = Entry {
+            time: u64::try_from(local - t.tz_quarters as i64 * 15 * 60).unwrap_or(0),
+            status: if message.read {
+                Status::Read
+            } else {
+                Status::Unread
+            },
+            text: message.text.clone(),
+            source: Some(Source::Inbox(message.id)),
+        };
+        let peer = Peer::Sms(message.from.clone());
+        let entries = self.entries.entry(peer.clone()).or_default();
+        if let Some(known) = entries.iter_mut().find(|e| e.same_as(&entry)) {
+            known.source = entry.source;
+            return false;
+        }
+        self.add(peer, entry);
+        true
+    }
+
+    // A message queued in the mailbox's outbox.
+    pub fn sent_sms(&mut self, outgoing: &Outgoing, now: u64) {
+        let entry = Entry {
+            time: now,
+            status: Status::from_outgoing(&outgoing.status),
+            text: outgoing.text.clone(),
+            source: Some(Source::Outbox(outgoing.id)),
+        };
+        self.add(Peer::Sms(outgoing.to.clone()), entry);
+    }
+
+    pub fn sms_status(&mut self, outgoing: &Outgoing) {
+        let status = Status::from_outgoing(&outgoing.status);
+        if let Some((peer, entry)) = self.find_source(Source::Outbox(outgoing.id)) {
+            if entry.status != status {
+                entry.status = status;
+                self.changed(peer);
+            }
+        }
+    }
+
+    // A direct message received or sent by this node (`own`), broadcasts are left out.
+    pub fn mesh_message(&mut self, message: &MeshMessage, own: u32, now: u64) {
+        if message.to == meshtastic::BROADCAST {
+            return;
+        }
+        let source = Source::Mesh(message.id);
+        let peer = if message.from == own {
+            Peer::Mesh(message.to)
+        } else {
+            Peer::Mesh(message.from)
+        };
+        if let Some(entries) = self.entries.get(&peer) {
+            if entries.iter().any(|e| e.source == Some(source)) {
+                return;
+            }
+        }
+        let entry = Entry {
+            time: now,
+            status: Status::from_delivery(message.delivery),
+            text: message.text.clone(),
+            source: Some(source),
+        };
+        self.add(peer, entry);
+    }
+
+    pub fn mesh_status(&mut self, message: &MeshMessage) {
+        let status = Status::from_delivery(message.delivery);
+        if let Some((peer, entry)) = self.find_source(Source::Mesh(message.id)) {
+            if entry.status.is_outgoing() && entry.status != status {
+                entry.status = status;
+                self.changed(peer);
+            }
+        }
+    }
+
+    // Marks the messages from `peers` read, returns where they came from so they can be
+    // marked read there too.
+    pub fn mark_read(&mut self, peers: &[Peer]) -> Vec<Source> {
+        let mut sources = Vec::new();
+        for peer in peers {
+            let Some(entries) = self.entries.get_mut(peer) else {
+                continue;
+            };
+            let mut changed = false;
+            for entry in entries.iter_mut().filter(|e| e.status == Status::Unread) {
+                entry.status = Status::Read;
+                changed = true;
+                sources.extend(entry.source);
+            }
+            if changed {
+                self.changed(peer.clone());
+            }
+        }
+        sources
+    }
+
+    pub fn unread(&self) -> usize {
+        self.entries
+            .values()
+            .flatten()
+            .filter(|e| e.status == Status::Unread)
+            .count()
+    }
+
+    // The conversation a message with `peer` belongs to.
+    pub fn key_for(&self, peer: &Peer, contacts: &Contacts) -> ThreadKey {
+        let contact = match peer {
+            Peer::Sms(number) => contacts.by_number(number),
+            Peer::Mesh(node) => contacts.by_node(*node),
+        };
+        match contact {
+            Some(contact) => ThreadKey::Contact(contact.id),
+            None => ThreadKey::Peer(peer.clone()),
+        }
+    }
+
+    // Every conversation with messages, the newest first.
+    pub fn threads(&self, contacts: &Contacts) -> Vec<Thread> {
+        let mut keys: Vec<ThreadKey> = Vec::new();
+        for peer in self.entries.keys() {
+            let key = self.key_for(peer, contacts);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        let mut threads: Vec<Thread> = keys
+            .into_iter()
+            .map(|key| self.thread(key, contacts))
+            .collect();
+        threads.sort_by_key(|t| Reverse(t.time));
+        threads
+    }
+
+    // The conversation with a contact or peer, which may have no messages yet.
+    pub fn thread(&self, key: ThreadKey, contacts: &Contacts) -> Thread {
+        let (name, mut peers) = match &key {
+            ThreadKey::Contact(id) => match contacts.get(*id) {
+                Some(contact) => {
+                    let numbers = contact.numbers.iter().map(|n| Peer::Sms(n.clone()));
+                    let nodes = contact.nodes.iter().map(|&n| Peer::Mesh(n));
+                    (contact.name.clone(), numbers.chain(nodes).collect())
+                }
+                None => (String::new(), Vec::new()),
+            },
+            ThreadKey::Peer(peer) => (peer.address(), vec![peer.clone()]),
+        };
+        // numbers in the history may be written differently from the address book's
+        for peer in self.entries.keys() {
+            if !peers.contains(peer) && self.key_for(peer, contacts) == key {
+                peers.push(peer.clone());
+            }
+        }
+        let history = self.history(&peers);
+        let last = history.last();
+        // replies go the way the conversation last went
+        if let Some((peer, _)) = last {
+            let peer = (*peer).clone();
+            peers.retain(|p| *p != peer);
+            peers.insert(0, peer);
+        }
+        Thread {
+            name,
+            time: last.map_or(0, |(_, e)| e.time),
+            preview: last.map_or(String::new(), |(_, e)| {
+                e.text
+                    .replace('\n', " ")
+                    .chars()
+                    .take(PREVIEW_CHARS)
+                    .collect()
+            }),
+            unread: history
+                .iter()
+                .filter(|(_, e)| e.status == Status::Unread)
+                .count(),
+            peers,
+            key,
+        }
+    }
+
+    // The messages with `peers`, oldest first.
+    pub fn history(&self, peers: &[Peer]) -> Vec<(&Peer, &Entry)> {
+        let mut history: Vec<(&Peer, &Entry)> = peers
+            .iter()
+            .filter_map(|peer| self.entries.get_key_value(peer))
+            .flat_map(|(peer, entries)| entries.iter().map(move |e| (peer, e)))
+            .collect();
+        history.sort_by_key(|(_, e)| e.time);
+        history
+    }
+
+    // Reads the history from a newly mounted card, adding the messages that came while
+    // there was none, and writes back what changed.
+    pub fn load(&mut self, storage: &Storage) -> Result<(), StorageError> {
+        let mut loaded = BTreeMap::new();
+        for name in storage.list(Area::Messages)? {
+            let Some(peer) = Peer::from_file_name(&name) else {
+                continue;
+            };
+            let data = storage.read(Area::Messages, &name)?;
+            loaded.insert(peer, parse(&String::from_utf8_lossy(&data)));
+        }
+        let previous = std::mem::replace(&mut self.entries, loaded);
+        for (peer, entries) in previous {
+            for entry in entries {
+                let loaded = self.entries.entry(peer.clone()).or_default();
+                let Some(known) = loaded.iter_mut().find(|e| e.same_as(&entry)) else {
+                    self.add(peer.clone(), entry);
+                    continue;
+                };
+                known.source = entry.source;
+                // this session has the current status of a message it sent, and a message
+                // read before the restart stays read
+                let status = match (known.status, entry.status) {
+                    (Status::Read, Status::Unread) => Status::Read,
+                    (_, status) => status,
+                };
+                if known.status != status {
+                    known.status = status;
+                    self.changed(peer.clone());
+                }
+            }
+        }
+        log::info!("{} conversations", self.entries.len());
+        self.save(storage)
+    }
+
+    // Writes the files that changed. Without a card they are kept for later.
+    pub fn save(&mut self, storage: &Storage) -> Result<(), StorageError> {
+        while let Some(peer) = self.unsaved.last() {
+            if let Some(entries) = self.entries.get(peer) {
+                storage.write(Area::Messages, &peer.file_name(), write(entries).as_bytes())?;
+            }
+            self.unsaved.pop();
+        }
+        Ok(())
+    }
+}
+
+fn parse(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(time), Some(status), Some(message)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let (Ok(time), Some(status)) = (time.parse(), Status::from_code(status)) else {
+            log::warn!("ignoring message line {line:?}");
+            continue;
+        };
+        entries.push(Entry {
+            time,
+            status,
+            text: unescape(message),
+            source: None,
+        });
+    }
+    entries
+}
+
+fn write(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            entry.time,
+            entry.status.code(),
+            escape(&entry.text)
+        ));
+    }
+    out
+}
+
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
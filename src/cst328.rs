@@ -0,0 +1,98 @@
+// Hynitron CST328 capacitive touch controller. Registers are 16 bit, sent big endian.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::I2c;
+
+pub const DEFAULT_ADDRESS: u8 = 0x1A;
+
+const REG_TOUCH_DATA: u16 = 0xD000;
+const REG_FINGER_COUNT: u16 = 0xD005;
+const REG_MODE_NORMAL: u16 = 0xD109;
+const REG_MODE_INFO: u16 = 0xD101;
+const REG_RESOLUTION: u16 = 0xD1F8;
+
+pub const MAX_POINTS: usize = 5;
+const POINT_STATUS_PRESSED: u8 = 0x06;
+// finger 1 sits at 0xD000, the count byte and a reserved byte follow, then fingers 2..5
+const DATA_LEN: usize = 27;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TouchPoint {
+    pub x: u16,
+    pub y: u16,
+}
+
+pub struct Cst328<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Cst328<I2C>
+where
+    I2C: I2c,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    pub fn reset<RST: OutputPin, DELAY: DelayNs>(
+        &mut self,
+        rst: &mut RST,
+        delay: &mut DELAY,
+    ) -> Result<(), I2C::Error> {
+        rst.set_low().ok();
+        delay.delay_ms(10);
+        rst.set_high().ok();
+        delay.delay_ms(60);
+        self.write_command(REG_MODE_NORMAL)
+    }
+
+    // (x, y) resolution the controller reports coordinates in
+    pub fn resolution(&mut self) -> Result<(u16, u16), I2C::Error> {
+        self.write_command(REG_MODE_INFO)?;
+        let mut buf = [0u8; 4];
+        self.read_register(REG_RESOLUTION, &mut buf)?;
+        self.write_command(REG_MODE_NORMAL)?;
+        Ok((
+            u16::from_le_bytes([buf[0], buf[1]]),
+            u16::from_le_bytes([buf[2], buf[3]]),
+        ))
+    }
+
+    pub fn read_points(&mut self, points: &mut Vec<TouchPoint>) -> Result<(), I2C::Error> {
+        points.clear();
+        let mut count = [0u8];
+        self.read_register(REG_FINGER_COUNT, &mut count)?;
+        let count = (count[0] & 0x0F) as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        let mut data = [0u8; DATA_LEN];
+        self.read_register(REG_TOUCH_DATA, &mut data)?;
+        // acknowledge, otherwise the controller keeps reporting the same frame
+        self.i2c.write(self.address, &[0xD0, 0x05, 0x00])?;
+
+        for i in 0..count.min(MAX_POINTS) {
+            let offset = if i == 0 { 0 } else { 5 * i + 2 };
+            let p = &data[offset..offset + 5];
+            if p[0] & 0x0F != POINT_STATUS_PRESSED {
+                continue;
+            }
+            points.push(TouchPoint {
+                x: ((p[1] as u16) << 4) | ((p[3] as u16) >> 4),
+                y: ((p[2] as u16) << 4) | ((p[3] as u16) & 0x0F),
+            });
+        }
+        Ok(())
+    }
+
+    fn write_command(&mut self, register: u16) -> Result<(), I2C::Error> {
+        self.i2c.write(self.address, &register.to_be_bytes())
+    }
+
+    fn read_register(&mut self, register: u16, buf: &mut [u8]) -> Result<(), I2C::Error> {
+        self.i2c
+            .write_read(self.address, &register.to_be_bytes(), buf)
+    }
+}
@@ -0,0 +1,280 @@
+use std::time::Instant;
+
+use crate::app::{App, Context, Transition};
+use crate::audio::{Route, Tone};
+use crate::call::{self, CallState, EndReason};
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::keyboard;
+use crate::ui::{Ui, WidgetId};
+use crate::widget::{Align, Button, Label, StatusBar};
+
+pub const NAME: &str = "Phone";
+const MAX_DIGITS: usize = 32;
+
+// Dialer and in-call screen. Digits are typed without Sym (the number printed on a key is
+// used), Enter dials or answers, Back hangs up. In a call Up/Down (or a swipe) change the
+// volume, M mutes the microphone and L switches to the loudspeaker (keys that carry no digit,
+// so all of them still send tones). Only the labels that change are redrawn, so the call
+// timer ticks with a partial refresh.
+pub struct Dialer {
+    ui: Ui,
+    number: WidgetId,
+    status: WidgetId,
+    waiting: WidgetId,
+    button: WidgetId,
+    tones: WidgetId,
+    audio: WidgetId,
+    typed: String,
+    sent_tones: String,
+    state: CallState,
+}
+
+impl Dialer {
+    pub fn launch(_ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        ui.add(StatusBar::new(NAME));
+        let number = ui.add(Label::new("").align(Align::Center));
+        let status = ui.add(Label::new("").align(Align::Center));
+        let waiting = ui.add(Label::new("").align(Align::Center));
+        let button = ui.add(Button::new("Call"));
+        let tones = ui.add(Label::new("").align(Align::Center));
+        let audio = ui.add(Label::new("").align(Align::Center));
+        Box::new(Self {
+            ui,
+            number,
+            status,
+            waiting,
+            button,
+            tones,
+            audio,
+            typed: String::new(),
+            sent_tones: String::new(),
+            state: CallState::Idle,
+        })
+    }
+
+    fn set_label(&mut self, id: WidgetId, text: &str) {
+        self.ui.get_mut::<Label>(id).unwrap().set_text(text);
+    }
+
+    // Pulls the call state from the shared call control into the widgets, callers in the
+    // address book by name.
+    fn refresh(&mut self, ctx: &mut Context) {
+        let contacts = &ctx.contacts;
+        let calls = ctx.calls.lock().unwrap();
+        let state = calls.state();
+        if state != self.state {
+            if self.state == CallState::Idle || state == CallState::Idle {
+                self.sent_tones.clear();
+            }
+            if state == CallState::Idle && calls.last_end() == Some(&EndReason::Busy) {
+                ctx.play(Tone::Busy);
+            }
+            // the audio task rings until the call is answered or gone
+            if state == CallState::Incoming {
+                ctx.play(Tone::Ring);
+            }
+            self.state = state;
+        }
+        let (number, status, button) = match calls.call() {
+            None => {
+                let status = match calls.last_end() {
+                    Some(EndReason::Busy) => "Busy".to_string(),
+                    Some(EndReason::NoAnswer) => "No answer".to_string(),
+                    Some(EndReason::Failed(_)) => "Call failed".to_string(),
+                    Some(_) => "Call ended".to_string(),
+                    None => String::new(),
+                };
+                let number = if self.typed.is_empty() {
+                    "Enter number".to_string()
+                } else {
+                    self.typed.clone()
+                };
+                (number, status, "Call")
+            }
+            Some(call) => {
+                let number = call
+                    .number
+                    .as_deref()
+                    .map_or_else(|| "Unknown".to_string(), |n| contacts.describe(n));
+                let status = match call.state {
+                    CallState::Dialing => "Calling...".to_string(),
+                    CallState::Alerting => "Ringing...".to_string(),
+                    CallState::Incoming => "Incoming call".to_string(),
+                    CallState::Held => "On hold".to_string(),
+                    _ => {
+                        let secs = call.duration(Instant::now()).as_secs();
+                        format!("{:02}:{:02}", secs / 60, secs % 60)
+                    }
+                };
+                let button = if call.state == CallState::Incoming {
+                    "Answer"
+                } else {
+                    "Hang up"
+                };
+                (number, status, button)
+            }
+        };
+        let waiting = match calls.waiting() {
+            Some(number) => format!(
+                "Waiting: {} (Enter)",
+                number
+                    .as_deref()
+                    .map_or_else(|| "Unknown".to_string(), |n| contacts.name_for(n))
+            ),
+            None => String::new(),
+        };
+        let audio = if calls.call().is_some() {
+            let speaker = if calls.route() == Route::Speaker {
+                "  speaker"
+            } else {
+                ""
+            };
+            let muted = if calls.muted() { "  muted" } else { "" };
+            format!("volume {}{speaker}{muted}", calls.volume().level())
+        } else {
+            String::new()
+        };
+        drop(calls);
+
+        self.set_label(self.number, &number);
+        self.set_label(self.status, &status);
+        self.set_label(self.waiting, &waiting);
+        let tones = if self.sent_tones.is_empty() {
+            String::new()
+        } else {
+            format!("Tones: {}", self.sent_tones)
+        };
+        self.set_label(self.tones, &tones);
+        self.set_label(self.audio, &audio);
+        let button_widget = self.ui.get_mut::<Button>(self.button).unwrap();
+        button_widget.set_text(button);
+        // stands out while ringing
+        button_widget.set_focused(self.state == CallState::Incoming);
+    }
+
+    // Enter also answers a waiting call, the button does what its label says
+    fn primary(&mut self, ctx: &mut Context, answer_waiting: bool) {
+        let mut calls = ctx.calls.lock().unwrap();
+        match calls.state() {
+            CallState::Idle if !self.typed.is_empty() => calls.dial(&self.typed),
+            CallState::Incoming => calls.answer(),
+            _ if answer_waiting && calls.waiting().is_some() => calls.answer_waiting(true),
+            CallState::Idle => {}
+            _ => calls.hang_up(),
+        }
+    }
+
+    // call audio settings only apply while a call is up
+    fn adjust_audio(&mut self, ctx: &mut Context, louder: Option<bool>, toggle: Option<char>) {
+        let mut calls = ctx.calls.lock().unwrap();
+        if calls.call().is_none() {
+            return;
+        }
+        let volume = calls.volume();
+        match louder {
+            Some(true) => calls.set_volume(volume.up()),
+            Some(false) => calls.set_volume(volume.down()),
+            None => {}
+        }
+        match toggle {
+            Some('m') => {
+                let muted = calls.muted();
+                calls.set_muted(!muted);
+            }
+            Some('l') => {
+                let route = match calls.route() {
+                    Route::Handset => Route::Speaker,
+                    Route::Speaker => Route::Handset,
+                };
+                calls.set_route(route);
+            }
+            _ => {}
+        }
+    }
+
+    fn digit(&mut self, ctx: &mut Context, c: char) {
+        // the dialer takes the Sym layer character of a key, so digits need no modifier
+        let c = match c {
+            '0'..='9' | '*' | '#' | '+' => c,
+            _ => match keyboard::symbol(c) {
+                Some(s @ ('0'..='9' | '*' | '#' | '+')) => s,
+                _ => return,
+            },
+        };
+        match self.state {
+            // '+' only makes sense as the international prefix
+            CallState::Idle
+                if self.typed.len() < MAX_DIGITS && (c != '+' || self.typed.is_empty()) =>
+            {
+                self.typed.push(c);
+                if call::is_dtmf(c) {
+                    ctx.play(Tone::Dtmf(c));
+                }
+            }
+            CallState::Active if call::is_dtmf(c) => {
+                ctx.calls.lock().unwrap().dtmf(c);
+                self.sent_tones.push(c);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl App for Dialer {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Key(Key::Char(c @ ('m' | 'l'))) if self.state != CallState::Idle => {
+                self.adjust_audio(ctx, None, Some(*c))
+            }
+            Event::Key(Key::Char(c)) => self.digit(ctx, *c),
+            Event::Key(Key::Up) => self.adjust_audio(ctx, Some(true), None),
+            Event::Key(Key::Down) => self.adjust_audio(ctx, Some(false), None),
+            Event::Key(Key::Backspace) if self.state == CallState::Idle => {
+                self.typed.pop();
+            }
+            Event::Key(Key::Enter) => self.primary(ctx, true),
+            Event::Key(Key::Back) => {
+                let mut calls = ctx.calls.lock().unwrap();
+                if calls.waiting().is_some() {
+                    calls.reject_waiting();
+                } else if calls.call().is_some() {
+                    calls.hang_up();
+                } else {
+                    return Transition::Pop;
+                }
+            }
+            Event::Touch(Gesture::Tap { x, y }) if self.ui.hit(*x, *y) == Some(self.button) => {
+                self.primary(ctx, false);
+            }
+            Event::Touch(Gesture::Swipe {
+                direction: Direction::Right,
+                ..
+            }) if self.state == CallState::Idle => return Transition::Pop,
+            Event::Touch(Gesture::Swipe {
+                direction: Direction::Up,
+                ..
+            }) => self.adjust_audio(ctx, Some(true), None),
+            Event::Touch(Gesture::Swipe {
+                direction: Direction::Down,
+                ..
+            }) => self.adjust_audio(ctx, Some(false), None),
+            _ => {}
+        }
+        // every event (the call state, the one second tick for the timer) may change labels
+        self.refresh(ctx);
+        Transition::None
+    }
+}
@@ -2,7 +2,9 @@ use embedded_hal::delay::DelayNs;
 use embedded_hal::digital::OutputPin;
 use embedded_hal::spi::{Operation, SpiDevice};
 
+use crate::canvas::{Canvas, Refresh};
 use crate::epdisplay::Colour;
+use crate::retained::EpdSnapshot;
 
 const WIDTH: u16 = 240;
 const HEIGHT: u16 = 320;
@@ -25,6 +27,10 @@ pub struct Epd310Gdeq031t10<SPI, DC, BUSY, DELAY> {
     initial_write: bool,
     using_partial_mode: bool,
     partial_dimensions: (i16, i16, i16, i16),
+    // partial refreshes since the last full one
+    partial_refreshes: u32,
+    // restored from a snapshot, nothing shown since
+    resumed: bool,
     pub buffer: [u8; BUFFER_SIZE],
 }
 
@@ -48,9 +54,46 @@ where
             initial_write: true,
             using_partial_mode: false,
             partial_dimensions: (0, 0, WIDTH as i16, HEIGHT as i16),
+            partial_refreshes: 0,
+            resumed: false,
             buffer: [0xFFu8; BUFFER_SIZE],
         }
     }
+
+    pub fn snapshot(&self) -> EpdSnapshot {
+        EpdSnapshot {
+            initial_refresh: self.initial_refresh,
+            initial_write: self.initial_write,
+            partial_refreshes: self.partial_refreshes,
+            buffer: self.buffer.to_vec(),
+        }
+    }
+
+    // Picks up from a snapshot taken before the MCU slept. The controller's previous image
+    // RAM is rewritten from it, so the next update only changes the pixels that differ.
+    pub fn restore(&mut self, snapshot: &EpdSnapshot) -> Result<(), SPI::Error> {
+        if snapshot.buffer.len() != BUFFER_SIZE {
+            return Ok(());
+        }
+        self.buffer.copy_from_slice(&snapshot.buffer);
+        self.initial_refresh = snapshot.initial_refresh;
+        self.initial_write = snapshot.initial_write;
+        self.partial_refreshes = snapshot.partial_refreshes;
+        if !self.initial_write {
+            self._write_buffer_part(0x10, 0, 0, WIDTH as i16, HEIGHT as i16)?;
+        }
+        self.resumed = !self.initial_refresh && !self.initial_write;
+        Ok(())
+    }
+
+    // whether the panel shows a restored image not yet updated, asked once
+    pub fn take_resumed(&mut self) -> bool {
+        std::mem::take(&mut self.resumed)
+    }
+
+    pub fn partial_refreshes(&self) -> u32 {
+        self.partial_refreshes
+    }
     pub fn init(&mut self) -> Result<(), SPI::Error> {
         // Panel Setting (soft reset)
         self.write_command(0x00)?;
@@ -117,9 +160,19 @@ where
         Ok(())
     }
 
+    // Lowest power state the panel can reach on this board. The deep sleep command (0x07)
+    // is only left with a pulse on RST, which is not wired up, so the panel is just powered
+    // off; the image stays either way.
+    pub fn hibernate(&mut self) -> Result<(), SPI::Error> {
+        self.power_off()?;
+        self.init_display_done = false;
+        Ok(())
+    }
+
     pub fn refresh_full(&mut self) -> Result<(), SPI::Error> {
         self.update_full()?;
         self.initial_refresh = false;
+        self.partial_refreshes = 0;
         Ok(())
     }
 
@@ -154,6 +207,7 @@ where
             self.write_command(0x91)?; // partial in
             self.set_partial_ram_area(x1 as u16, y1 as u16, w1 as u16, h1 as u16)?;
             self.update_part()?;
+            self.partial_refreshes += 1;
             self.write_command(0x92) // partial out
         }
     }
@@ -195,6 +249,7 @@ where
 
     pub fn write_screen_buffer(&mut self, value: u8) -> Result<(), SPI::Error> {
         if self.initial_write {
+            self.initial_write = false;
             self.clear_screen(value)
         } else {
             self._write_screen_buffer(0x13, value)
@@ -205,19 +260,6 @@ where
         self._write_screen_buffer(0x10, value)
     }
 
-    pub fn write_image(
-        &mut self,
-        bitmap: &[u8],
-        x: i16,
-        y: i16,
-        w: i16,
-        h: i16,
-        invert: bool,
-        mirror_y: bool,
-    ) -> Result<(), SPI::Error> {
-        self._write_image(0x13, bitmap, x, y, w, h, invert, mirror_y)
-    }
-
     pub fn write_image_again(
         &mut self,
         bitmap: &[u8],
@@ -317,6 +359,48 @@ where
         Ok(())
     }
 
+    // writes the window (x, w on byte boundaries) of the framebuffer to the controller RAM
+    fn _write_buffer_part(
+        &mut self,
+        command: u8,
+        x: i16,
+        y: i16,
+        w: i16,
+        h: i16,
+    ) -> Result<(), SPI::Error> {
+        if (w <= 0) || (h <= 0) {
+            return Ok(());
+        };
+        self.delay.delay_ms(1);
+        if !self.init_display_done {
+            self.init()?;
+        };
+        if self.initial_write {
+            self.write_screen_buffer(0xFF)?
+        };
+        self.write_command(0x91)?;
+        self.set_partial_ram_area(x as u16, y as u16, w as u16, h as u16)?;
+        self.write_command(command)?;
+
+        let bytes_per_row = (WIDTH / 8) as usize;
+        let (x_byte, w_bytes) = ((x / 8) as usize, (w / 8) as usize);
+        let mut out = Vec::with_capacity(w_bytes * h as usize);
+        for row in y as usize..(y + h) as usize {
+            let start = row * bytes_per_row + x_byte;
+            out.extend_from_slice(&self.buffer[start..start + w_bytes]);
+        }
+
+        const CHUNK: usize = 1024;
+        for chunk in out.chunks(CHUNK) {
+            self.transfer(chunk)?;
+            esp_idf_hal::delay::FreeRtos::delay_ms(1); // yield to feed watchdog
+        }
+
+        self.write_command(0x92)?;
+        self.delay.delay_ms(1);
+        Ok(())
+    }
+
     fn write_command(&mut self, command: u8) -> Result<(), SPI::Error> {
         self.dc.set_low().ok();
         self.spi.transaction(&mut [Operation::Write(&[command])])?;
@@ -356,9 +440,9 @@ where
         let (x, y, w, h) = self.partial_dimensions;
         if self.using_partial_mode {
             logger("using partial mode");
-            self.write_image(&self.buffer.clone(), x, y, w, h, false, false)?;
+            self._write_buffer_part(0x13, x, y, w, h)?;
             self.refresh_part(x, y, w, h)?;
-            self.write_image_again(&self.buffer.clone(), x, y, w, h, false, false)?;
+            self._write_buffer_part(0x10, x, y, w, h)?;
         } else {
             logger("not partial mode");
             self.write_image_for_full_refresh(
@@ -386,6 +470,38 @@ where
         self.partial_dimensions = (0, 0, WIDTH as i16, HEIGHT as i16);
     }
 
+    // window in logical (rotated) coordinates, stored in panel coordinates on byte boundaries
+    pub fn set_partial_window(&mut self, x: i16, y: i16, w: i16, h: i16) {
+        let x = x.clamp(0, self.width());
+        let y = y.clamp(0, self.height());
+        let w = w.min(self.width() - x);
+        let h = h.min(self.height() - y);
+        let (mut x, y, mut w, h) = match self.rotation {
+            1 => (WIDTH as i16 - y - h, x, h, w),
+            2 => (WIDTH as i16 - x - w, HEIGHT as i16 - y - h, w, h),
+            3 => (y, HEIGHT as i16 - x - w, h, w),
+            _ => (x, y, w, h),
+        };
+        // make x, w multiple of 8
+        w += x % 8;
+        if w % 8 > 0 {
+            w += 8 - w % 8
+        };
+        x -= x % 8;
+        self.using_partial_mode = true;
+        self.partial_dimensions = (x, y, w, h);
+    }
+
+    pub fn show(&mut self, refresh: Refresh, logger: fn(&str)) -> Result<(), SPI::Error> {
+        match refresh {
+            Refresh::None => return Ok(()),
+            Refresh::Partial(r) => self.set_partial_window(r.x, r.y, r.w, r.h),
+            Refresh::Full => self.set_full_window(),
+        }
+        self.next_page(logger)?;
+        Ok(())
+    }
+
     pub fn fill_screen(&mut self, val: u8) {
         self.buffer = [val; BUFFER_SIZE];
     }
@@ -393,4 +509,68 @@ where
     pub fn draw(&mut self) {
         self.buffer[50..4000].fill(Colour::BLACK as u8);
     }
+
+    pub fn draw_pixel(&mut self, mut x: i16, mut y: i16, colour: Colour) {
+        if x < 0 || x >= self.width() || y < 0 || y >= self.height() {
+            return;
+        }
+        // map logical (rotated) coordinates onto the panel's native orientation
+        match self.rotation {
+            1 => {
+                (x, y) = (y, x);
+                x = WIDTH as i16 - x - 1;
+            }
+            2 => {
+                x = WIDTH as i16 - x - 1;
+                y = HEIGHT as i16 - y - 1;
+            }
+            3 => {
+                (x, y) = (y, x);
+                y = HEIGHT as i16 - y - 1;
+            }
+            _ => {}
+        }
+        let i = x as usize / 8 + y as usize * (WIDTH as usize / 8);
+        let mask = 1 << (7 - x % 8);
+        match colour {
+            Colour::WHITE => self.buffer[i] |= mask,
+            Colour::BLACK => self.buffer[i] &= !mask,
+        }
+    }
+
+    pub fn width(&self) -> i16 {
+        if self.rotation % 2 == 1 {
+            HEIGHT as i16
+        } else {
+            WIDTH as i16
+        }
+    }
+
+    pub fn height(&self) -> i16 {
+        if self.rotation % 2 == 1 {
+            WIDTH as i16
+        } else {
+            HEIGHT as i16
+        }
+    }
+}
+
+impl<SPI, DC, BUSY, DELAY> Canvas for Epd310Gdeq031t10<SPI, DC, BUSY, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    BUSY: embedded_hal::digital::InputPin,
+    DELAY: DelayNs,
+{
+    fn width(&self) -> i16 {
+        Epd310Gdeq031t10::width(self)
+    }
+
+    fn height(&self) -> i16 {
+        Epd310Gdeq031t10::height(self)
+    }
+
+    fn draw_pixel(&mut self, x: i16, y: i16, colour: Colour) {
+        Epd310Gdeq031t10::draw_pixel(self, x, y, colour)
+    }
 }
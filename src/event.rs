@@ -0,0 +1,85 @@
+use crate::battery::BatteryStatus;
+use crate::call::CallState;
+use crate::gnss::GnssEvent;
+use crate::mesh::MeshEvent;
+use crate::modem::NetworkStatus;
+use crate::settings::Setting;
+use crate::sms::SmsEvent;
+use crate::storage::StorageStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Back,
+    Home,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+// Touch gestures in logical (rotated) framebuffer coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap {
+        x: i16,
+        y: i16,
+    },
+    LongPress {
+        x: i16,
+        y: i16,
+    },
+    // start position and the dominant direction of movement
+    Swipe {
+        x: i16,
+        y: i16,
+        direction: Direction,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorEvent {
+    // picked up and tilted towards the user
+    Raised,
+    // put down with the screen against something
+    FaceDown,
+    // something close to the screen, only reported during calls
+    Near(bool),
+    // too dark to read the screen without the backlight
+    Dark(bool),
+}
+
+// Everything the display task reacts to, delivered over a single channel.
+#[derive(Debug, Clone)]
+pub enum Event {
+    Key(Key),
+    Touch(Gesture),
+    // SIM, registration or signal changed
+    Network(NetworkStatus),
+    Sms(SmsEvent),
+    // the call state changed, details are in `Context::calls`
+    Call(CallState),
+    // messages and nodes are read from `Context::mesh`
+    Mesh(MeshEvent),
+    // position and satellites are read from `Context::gnss`
+    Gnss(GnssEvent),
+    // the battery level or charging state changed
+    Battery(BatteryStatus),
+    Sensor(SensorEvent),
+    // the SD card was mounted, removed or found unreadable
+    Storage(StorageStatus),
+    // a setting changed, its value is read from `Context::settings`
+    Settings(Setting),
+    // periodic wake-up of the display task when nothing else happened
+    Tick,
+}
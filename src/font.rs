@@ -0,0 +1,385 @@
+// Glyph lookup with fallback across several fonts. The built-in 6x10 Latin font lives in
+// flash as part of the binary, larger fonts (CJK, Cyrillic, emoji) are stored in a data
+// partition or on the SD card and their glyphs are read on demand and cached in RAM.
+//
+// Stored font file layout (all integers little endian):
+//   header (16 bytes): b"DFNT", version u8, line_height u8, ascent u8, reserved u8,
+//                      glyph_count u32, bitmap_offset u32
+//   index (16 bytes per glyph, sorted by codepoint): codepoint u32, bitmap u32 (relative to
+//                      bitmap_offset), width u8, height u8, x_offset i8, y_offset i8,
+//                      advance u8, 3 reserved bytes
+//   bitmaps: 1 bit per pixel, rows padded to whole bytes, MSB is the leftmost pixel
+//
+// tools/mkfont builds these files from BDF fonts, its README has the flashing steps.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use crate::canvas::Canvas;
+use crate::epdisplay::Colour;
+use crate::font_6x10;
+use crate::partition::Partition;
+
+const MAGIC: &[u8; 4] = b"DFNT";
+const VERSION: u8 = 1;
+const HEADER_SIZE: u32 = 16;
+const INDEX_ENTRY_SIZE: u32 = 16;
+const CACHE_GLYPHS: usize = 256;
+
+const REPLACEMENT_CHAR: char = '\u{FFFD}';
+const ZERO_WIDTH: [char; 4] = ['\u{200B}', '\u{200D}', '\u{FE0E}', '\u{FE0F}'];
+
+#[derive(Debug)]
+pub enum FontError {
+    NotFound(String),
+    InvalidFormat(String),
+    Storage(String),
+}
+impl FontError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        FontError::Storage(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Glyph {
+    pub width: u8,
+    pub height: u8,
+    // offset of the top left pixel from the pen position on the baseline
+    pub x_offset: i8,
+    pub y_offset: i8,
+    pub advance: u8,
+    pub bitmap: Vec<u8>,
+}
+
+impl Glyph {
+    fn pixel(&self, x: u8, y: u8) -> bool {
+        let row_bytes = (self.width as usize).div_ceil(8);
+        let byte = self.bitmap[y as usize * row_bytes + x as usize / 8];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+}
+
+pub trait GlyphSource {
+    fn line_height(&self) -> u8;
+    fn ascent(&self) -> u8;
+    fn glyph(&mut self, c: char) -> Result<Option<Glyph>, FontError>;
+}
+
+pub struct BuiltinFont;
+
+impl GlyphSource for BuiltinFont {
+    fn line_height(&self) -> u8 {
+        font_6x10::GLYPH_HEIGHT
+    }
+
+    fn ascent(&self) -> u8 {
+        font_6x10::ASCENT
+    }
+
+    fn glyph(&mut self, c: char) -> Result<Option<Glyph>, FontError> {
+        let code = c as u32;
+        if !(font_6x10::FIRST_CHAR..=font_6x10::LAST_CHAR).contains(&code) {
+            return Ok(None);
+        }
+        let rows = font_6x10::GLYPHS[(code - font_6x10::FIRST_CHAR) as usize];
+        Ok(Some(Glyph {
+            width: font_6x10::GLYPH_WIDTH,
+            height: font_6x10::GLYPH_HEIGHT,
+            x_offset: 0,
+            y_offset: -(font_6x10::ASCENT as i8),
+            advance: font_6x10::GLYPH_WIDTH,
+            bitmap: rows.to_vec(),
+        }))
+    }
+}
+
+// Random access to the bytes of a stored font.
+pub trait FontStorage {
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), FontError>;
+}
+
+// Font file on a mounted filesystem (SD card via the ESP-IDF VFS, or the host).
+pub struct FileStorage {
+    file: File,
+}
+
+impl FileStorage {
+    pub fn open(path: &str) -> Result<Self, FontError> {
+        let file = File::open(path).map_err(|_| FontError::NotFound(path.to_string()))?;
+        Ok(Self { file })
+    }
+}
+
+impl FontStorage for FileStorage {
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), FontError> {
+        self.file
+            .seek(SeekFrom::Start(offset as u64))
+            .map_err(FontError::from_debug)?;
+        self.file.read_exact(buf).map_err(FontError::from_debug)
+    }
+}
+
+// Font file written raw into the "fonts" data partition.
+impl FontStorage for Partition {
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), FontError> {
+        Partition::read_at(self, offset, buf).map_err(FontError::from_debug)
+    }
+}
+
+// A font in the DFNT format. Only the header is held in RAM, glyphs are located by a
+// binary search over the index on the storage itself.
+pub struct StoredFont<S> {
+    storage: S,
+    line_height: u8,
+    ascent: u8,
+    glyph_count: u32,
+    bitmap_offset: u32,
+}
+
+impl<S: FontStorage> StoredFont<S> {
+    pub fn new(mut storage: S) -> Result<Self, FontError> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        storage.read_at(0, &mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(FontError::InvalidFormat("bad magic".to_string()));
+        }
+        if header[4] != VERSION {
+            return Err(FontError::InvalidFormat(format!(
+                "unsupported version {}",
+                header[4]
+            )));
+        }
+        Ok(Self {
+            storage,
+            line_height: header[5],
+            ascent: header[6],
+            glyph_count: u32::from_le_bytes([header[8], header[9], header[10], header[11]]),
+            bitmap_offset: u32::from_le_bytes([header[12], header[13], header[14], header[15]]),
+        })
+    }
+
+    fn read_index(&mut self, i: u32) -> Result<[u8; INDEX_ENTRY_SIZE as usize], FontError> {
+        let mut entry = [0u8; INDEX_ENTRY_SIZE as usize];
+        self.storage
+            .read_at(HEADER_SIZE + i * INDEX_ENTRY_SIZE, &mut entry)?;
+        Ok(entry)
+    }
+}
+
+impl<S: FontStorage> GlyphSource for StoredFont<S> {
+    fn line_height(&self) -> u8 {
+        self.line_height
+    }
+
+    fn ascent(&self) -> u8 {
+        self.ascent
+    }
+
+    fn glyph(&mut self, c: char) -> Result<Option<Glyph>, FontError> {
+        let code = c as u32;
+        let (mut lo, mut hi) = (0, self.glyph_count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = self.read_index(mid)?;
+            let mid_code = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            if mid_code < code {
+                lo = mid + 1;
+            } else if mid_code > code {
+                hi = mid;
+            } else {
+                let offset = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+                let (width, height) = (entry[8], entry[9]);
+                let mut bitmap = vec![0u8; (width as usize).div_ceil(8) * height as usize];
+                self.storage
+                    .read_at(self.bitmap_offset + offset, &mut bitmap)?;
+                return Ok(Some(Glyph {
+                    width,
+                    height,
+                    x_offset: entry[10] as i8,
+                    y_offset: entry[11] as i8,
+                    advance: entry[12],
+                    bitmap,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Ordered list of fonts: the first one that has a glyph for a character wins. Lookups
+// (including misses) are cached so flash or SD is only touched once per character.
+pub struct FontStack {
+    fonts: Vec<Box<dyn GlyphSource + Send>>,
+    cache: HashMap<char, Option<Arc<Glyph>>>,
+    cache_order: VecDeque<char>,
+}
+
+impl Default for FontStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FontStack {
+    pub fn new() -> Self {
+        Self {
+            fonts: vec![Box::new(BuiltinFont)],
+            cache: HashMap::new(),
+            cache_order: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, font: Box<dyn GlyphSource + Send>) {
+        self.fonts.push(font);
+        self.cache.clear();
+        self.cache_order.clear();
+    }
+
+    pub fn line_height(&self) -> i16 {
+        self.ascent()
+            + self
+                .fonts
+                .iter()
+                .map(|f| f.line_height() as i16 - f.ascent() as i16)
+                .max()
+                .unwrap_or(0)
+    }
+
+    pub fn ascent(&self) -> i16 {
+        self.fonts
+            .iter()
+            .map(|f| f.ascent() as i16)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn glyph(&mut self, c: char) -> Option<Arc<Glyph>> {
+        if let Some(cached) = self.cache.get(&c) {
+            return cached.clone();
+        }
+        let mut found = None;
+        for font in self.fonts.iter_mut() {
+            match font.glyph(c) {
+                Ok(Some(glyph)) => {
+                    found = Some(Arc::new(glyph));
+                    break;
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("glyph lookup for {c:?} failed: {e:?}"),
+            }
+        }
+        if self.cache_order.len() >= CACHE_GLYPHS {
+            if let Some(old) = self.cache_order.pop_front() {
+                self.cache.remove(&old);
+            }
+        }
+        self.cache.insert(c, found.clone());
+        self.cache_order.push_back(c);
+        found
+    }
+
+    // Falls back to U+FFFD and then '?' for characters no font covers.
+    fn glyph_or_replacement(&mut self, c: char) -> Option<Arc<Glyph>> {
+        self.glyph(c)
+            .or_else(|| self.glyph(REPLACEMENT_CHAR))
+            .or_else(|| self.glyph('?'))
+    }
+
+    pub fn text_width(&mut self, text: &str) -> i16 {
+        text.chars()
+            .filter(|c| !ZERO_WIDTH.contains(c))
+            .filter_map(|c| self.glyph_or_replacement(c))
+            .map(|g| g.advance as i16)
+            .sum()
+    }
+
+    // Greedy word wrap into lines no wider than `width`. Words that don't fit on a line of
+    // their own (or scripts without spaces) are broken between characters.
+    pub fn wrap(&mut self, text: &str, width: i16) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            for word in paragraph.split(' ') {
+                let candidate = if line.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{line} {word}")
+                };
+                if self.text_width(&candidate) <= width {
+                    line = candidate;
+                    continue;
+                }
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                for c in word.chars() {
+                    line.push(c);
+                    if self.text_width(&line) > width && line.chars().count() > 1 {
+                        line.pop();
+                        lines.push(std::mem::take(&mut line));
+                        line.push(c);
+                    }
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    // Draws a single line of text with its top left corner at (x, y) and returns the x
+    // position following the last glyph.
+    pub fn draw_text(
+        &mut self,
+        canvas: &mut dyn Canvas,
+        x: i16,
+        y: i16,
+        text: &str,
+        colour: Colour,
+    ) -> i16 {
+        self.draw_text_scaled(canvas, x, y, text, 1, colour)
+    }
+
+    // As `draw_text`, with every pixel drawn as a `scale` x `scale` square (e.g. for a
+    // clock). Line height and widths scale the same way.
+    pub fn draw_text_scaled(
+        &mut self,
+        canvas: &mut dyn Canvas,
+        x: i16,
+        y: i16,
+        text: &str,
+        scale: i16,
+        colour: Colour,
+    ) -> i16 {
+        let baseline = y + self.ascent() * scale;
+        let mut pen = x;
+        for c in text.chars() {
+            if ZERO_WIDTH.contains(&c) {
+                continue;
+            }
+            let Some(glyph) = self.glyph_or_replacement(c) else {
+                continue;
+            };
+            let left = pen + glyph.x_offset as i16 * scale;
+            let top = baseline + glyph.y_offset as i16 * scale;
+            for row in 0..glyph.height {
+                for col in 0..glyph.width {
+                    if !glyph.pixel(col, row) {
+                        continue;
+                    }
+                    let (px, py) = (left + col as i16 * scale, top + row as i16 * scale);
+                    if scale == 1 {
+                        canvas.draw_pixel(px, py, colour);
+                    } else {
+                        canvas.fill_rect(px, py, scale, scale, colour);
+                    }
+                }
+            }
+            pen += glyph.advance as i16 * scale;
+        }
+        pen
+    }
+}
@@ -0,0 +1,493 @@
+// GNSS receiver (u-blox MIA-M10Q) state: the current fix, UTC time and the satellites in view,
+// updated from the NMEA sentences and UBX frames the receiver sends, and the update rate and
+// power mode to configure it with. The location task feeds it; apps read it from
+// `Context::gnss`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::nmea::{Date, Position, Sentence, System, Time};
+use crate::ubx::{self, Frame, NavPvt, PvtFix};
+
+// NMEA allows 82 characters, leave room for proprietary sentences
+const MAX_SENTENCE: usize = 120;
+const KNOTS_TO_MPS: f32 = 0.514_444;
+// track points are kept this far apart, the oldest are dropped past MAX_TRACK
+const TRACK_SPACING_M: f64 = 10.0;
+const MAX_TRACK: usize = 500;
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FixKind {
+    #[default]
+    None,
+    TwoD,
+    ThreeD,
+}
+
+// The latest navigation solution. Fields stay None until the receiver reports them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Fix {
+    pub kind: FixKind,
+    pub position: Option<Position>,
+    // above mean sea level
+    pub altitude_m: Option<f32>,
+    pub speed_mps: Option<f32>,
+    pub course_deg: Option<f32>,
+    // UTC of the epoch
+    pub date: Option<Date>,
+    pub time: Option<Time>,
+    pub satellites_used: u8,
+    pub hdop: Option<f32>,
+    pub pdop: Option<f32>,
+    // estimated horizontal accuracy, UBX only
+    pub accuracy_m: Option<f32>,
+    // when the epoch arrived
+    pub at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Satellite {
+    pub system: System,
+    pub prn: u16,
+    pub elevation_deg: Option<u8>,
+    pub azimuth_deg: Option<u16>,
+    pub snr: Option<u8>,
+    // part of the current solution
+    pub used: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerMode {
+    // continuous tracking
+    Full,
+    // cyclic tracking, keeps the update rate at lower current
+    Cyclic,
+    // the receiver sleeps between fixes this far apart
+    OnOff(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GnssConfig {
+    // time between navigation solutions
+    pub rate: Duration,
+    pub power: PowerMode,
+}
+
+impl Default for GnssConfig {
+    fn default() -> Self {
+        Self {
+            rate: Duration::from_secs(1),
+            power: PowerMode::Full,
+        }
+    }
+}
+
+impl GnssConfig {
+    // One CFG-VALSET for the RAM layer. It also switches the fix data over to NAV-PVT and
+    // keeps GSA and GSV for the satellite view; the receiver applies all of it or none, so a
+    // NAK leaves the NMEA defaults working.
+    pub fn frame(&self) -> Frame {
+        let rate_ms = self.rate.as_millis().clamp(25, 10_000) as u64;
+        let mut items = vec![
+            (ubx::CFG_RATE_MEAS, rate_ms),
+            (ubx::CFG_RATE_NAV, 1),
+            (ubx::CFG_MSGOUT_UBX_NAV_PVT_UART1, 1),
+            (ubx::CFG_MSGOUT_NMEA_GGA_UART1, 0),
+            (ubx::CFG_MSGOUT_NMEA_RMC_UART1, 0),
+            (ubx::CFG_MSGOUT_NMEA_GSA_UART1, 1),
+            (ubx::CFG_MSGOUT_NMEA_GSV_UART1, 1),
+        ];
+        match self.power {
+            PowerMode::Full => items.push((ubx::CFG_PM_OPERATEMODE, ubx::PM_FULL as u64)),
+            PowerMode::Cyclic => items.push((ubx::CFG_PM_OPERATEMODE, ubx::PM_PSMCT as u64)),
+            PowerMode::OnOff(period) => {
+                items.push((ubx::CFG_PM_OPERATEMODE, ubx::PM_PSMOO as u64));
+                // in seconds; an on-time of 0 lets the receiver sleep as soon as it has a fix
+                let period = period.as_secs().max(1);
+                items.push((ubx::CFG_PM_POSUPDATEPERIOD, period));
+                items.push((ubx::CFG_PM_ACQPERIOD, period));
+                items.push((ubx::CFG_PM_ONTIME, 0));
+            }
+        }
+        ubx::valset(ubx::LAYER_RAM, &items)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GnssEvent {
+    // the kind of fix changed
+    Fix(FixKind),
+    // a new position in the current fix
+    Position,
+}
+
+pub struct Gnss {
+    fix: Fix,
+    satellites: Vec<Satellite>,
+    // GSV sentences of the cycle being received
+    incoming: Vec<Satellite>,
+    // (system, PRN) of the satellites in the solution, from GSA
+    used: Vec<(System, u16)>,
+    // time of the last epoch reported with GnssEvent::Position
+    reported: Option<Time>,
+    // positions of the fixes so far, oldest first
+    track: VecDeque<Position>,
+    enabled: bool,
+    config: GnssConfig,
+    // settings the task has not applied yet
+    changed: bool,
+}
+
+impl Gnss {
+    pub fn new(config: GnssConfig) -> Self {
+        Self {
+            fix: Fix::default(),
+            satellites: Vec::new(),
+            incoming: Vec::new(),
+            used: Vec::new(),
+            reported: None,
+            track: VecDeque::new(),
+            enabled: true,
+            config,
+            changed: true,
+        }
+    }
+
+    pub fn fix(&self) -> &Fix {
+        &self.fix
+    }
+
+    // position of the current fix
+    pub fn position(&self) -> Option<Position> {
+        match self.fix.kind {
+            FixKind::None => None,
+            _ => self.fix.position,
+        }
+    }
+
+    // UTC date and time of the last epoch and when it arrived
+    pub fn utc(&self) -> Option<(Date, Time, Instant)> {
+        Some((self.fix.date?, self.fix.time?, self.fix.at?))
+    }
+
+    pub fn satellites(&self) -> &[Satellite] {
+        &self.satellites
+    }
+
+    pub fn track(&self) -> &VecDeque<Position> {
+        &self.track
+    }
+
+    pub fn clear_track(&mut self) {
+        self.track.clear();
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Powers the receiver up or down with GPS_EN. It loses its fix when off.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.changed = true;
+        }
+    }
+
+    pub fn config(&self) -> GnssConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: GnssConfig) {
+        if self.config != config {
+            self.config = config;
+            self.changed = true;
+        }
+    }
+
+    // the enable and configuration, if either changed since last asked
+    pub fn take_changes(&mut self) -> Option<(bool, GnssConfig)> {
+        let changed = std::mem::take(&mut self.changed);
+        changed.then_some((self.enabled, self.config))
+    }
+
+    pub fn update(&mut self, sentence: &Sentence, now: Instant) -> Option<GnssEvent> {
+        let before = self.fix.kind;
+        match sentence {
+            Sentence::Gga(gga) => {
+                self.fix.time = gga.time.or(self.fix.time);
+                self.fix.at = Some(now);
+                self.fix.satellites_used = gga.satellites;
+                self.fix.hdop = gga.hdop;
+                if gga.quality == 0 {
+                    self.fix.kind = FixKind::None;
+                } else {
+                    self.fix.position = gga.position;
+                    self.fix.altitude_m = gga.altitude_m;
+                    if self.fix.kind == FixKind::None {
+                        // GSA tells 2D from 3D
+                        self.fix.kind = FixKind::TwoD;
+                    }
+                }
+            }
+            Sentence::Rmc(rmc) => {
+                self.fix.time = rmc.time.or(self.fix.time);
+                self.fix.date = rmc.date.or(self.fix.date);
+                self.fix.at = Some(now);
+                if rmc.valid {
+                    self.fix.position = rmc.position;
+                    self.fix.speed_mps = rmc.speed_knots.map(|k| k * KNOTS_TO_MPS);
+                    self.fix.course_deg = rmc.course_deg;
+                    if self.fix.kind == FixKind::None {
+                        self.fix.kind = FixKind::TwoD;
+                    }
+                } else {
+                    self.fix.kind = FixKind::None;
+                }
+            }
+            Sentence::Gsa(gsa) => {
+                // with several systems there is one GSA each, so the solution's kind is
+                // the one of the sentences seen last
+                self.fix.kind = match gsa.fix_type {
+                    2 => FixKind::TwoD,
+                    3 => FixKind::ThreeD,
+                    _ => FixKind::None,
+                };
+                self.fix.pdop = gsa.pdop;
+                self.fix.hdop = gsa.hdop.or(self.fix.hdop);
+                self.used.retain(|(system, _)| *system != gsa.system);
+                self.used
+                    .extend(gsa.prns.iter().map(|&prn| (gsa.system, prn)));
+                self.mark_used();
+            }
+            Sentence::Gsv(gsv) => {
+                if gsv.number <= 1 {
+                    self.incoming.clear();
+                }
+                self.incoming
+                    .extend(gsv.satellites.iter().map(|s| Satellite {
+                        system: gsv.system,
+                        prn: s.prn,
+                        elevation_deg: s.elevation_deg,
+                        azimuth_deg: s.azimuth_deg,
+                        snr: s.snr,
+                        used: false,
+                    }));
+                if gsv.number >= gsv.total {
+                    self.satellites.retain(|s| s.system != gsv.system);
+                    self.satellites.append(&mut self.incoming);
+                    self.mark_used();
+                }
+            }
+            Sentence::Other(_) => {}
+        }
+        self.report(before)
+    }
+
+    pub fn update_pvt(&mut self, pvt: &NavPvt, now: Instant) -> Option<GnssEvent> {
+        let before = self.fix.kind;
+        self.fix.kind = match pvt.fix {
+            _ if !pvt.fix_ok => FixKind::None,
+            PvtFix::TwoD => FixKind::TwoD,
+            PvtFix::ThreeD | PvtFix::GnssDeadReckoning => FixKind::ThreeD,
+            PvtFix::None | PvtFix::DeadReckoning | PvtFix::TimeOnly => FixKind::None,
+        };
+        self.fix.date = pvt.date.or(self.fix.date);
+        self.fix.time = pvt.time.or(self.fix.time);
+        self.fix.at = Some(now);
+        self.fix.satellites_used = pvt.satellites;
+        self.fix.pdop = Some(pvt.pdop);
+        if self.fix.kind != FixKind::None {
+            self.fix.position = Some(pvt.position);
+            self.fix.altitude_m = Some(pvt.altitude_m);
+            self.fix.speed_mps = Some(pvt.speed_mps);
+            self.fix.course_deg = Some(pvt.heading_deg);
+            self.fix.accuracy_m = Some(pvt.horizontal_accuracy_m);
+        }
+        self.report(before)
+    }
+
+    // the receiver was switched off or stopped talking
+    pub fn lost(&mut self) -> Option<GnssEvent> {
+        let before = self.fix.kind;
+        self.fix.kind = FixKind::None;
+        self.satellites.clear();
+        self.used.clear();
+        self.report(before)
+    }
+
+    fn mark_used(&mut self) {
+        for s in self.satellites.iter_mut() {
+            s.used = self.used.contains(&(s.system, s.prn));
+        }
+    }
+
+    // one event per epoch at most: the fix kind changing, otherwise a new position
+    fn report(&mut self, before: FixKind) -> Option<GnssEvent> {
+        let event = if self.fix.kind != before {
+            GnssEvent::Fix(self.fix.kind)
+        } else if self.fix.kind == FixKind::None || self.fix.time == self.reported {
+            return None;
+        } else {
+            GnssEvent::Position
+        };
+        self.reported = self.fix.time;
+        if let Some(position) = self.position() {
+            self.record(position);
+        }
+        Some(event)
+    }
+
+    fn record(&mut self, position: Position) {
+        if let Some(last) = self.track.back() {
+            if distance_m(*last, position) < TRACK_SPACING_M {
+                return;
+            }
+        }
+        if self.track.len() == MAX_TRACK {
+            self.track.pop_front();
+        }
+        self.track.push_back(position);
+    }
+}
+
+// equirectangular approximation, good for the short distances between fixes
+pub fn distance_m(a: Position, b: Position) -> f64 {
+    let mean_lat = ((a.latitude + b.latitude) / 2.0).to_radians();
+    let x = (b.longitude - a.longitude).to_radians() * mean_lat.cos();
+    let y = (b.latitude - a.latitude).to_radians();
+    (x * x + y * y).sqrt() * EARTH_RADIUS_M
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Nmea(String),
+    Ubx(Frame),
+}
+
+// Splits the receiver's byte stream into NMEA sentences and UBX frames, skipping noise and
+// corrupt frames.
+#[derive(Default)]
+pub struct Splitter {
+    buf: Vec<u8>,
+}
+
+impl Splitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn next_packet(&mut self) -> Option<Packet> {
+        loop {
+            let Some(start) = self
+                .buf
+                .iter()
+                .position(|&b| b == b'$' || b == ubx::SYNC[0])
+            else {
+                self.buf.clear();
+                return None;
+            };
+            self.buf.drain(..start);
+            if self.buf[0] == b'$' {
+                let Some(end) = self.buf.iter().position(|&b| b == b'\n') else {
+                    if self.buf.len() > MAX_SENTENCE {
+                        self.buf.remove(0);
+                        continue;
+                    }
+                    return None;
+                };
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                if let Ok(line) = std::str::from_utf8(&line) {
+                    return Some(Packet::Nmea(line.trim_end().to_string()));
+                }
+                continue;
+            }
+            if self.buf.len() < 6 {
+                return None;
+            }
+            let len = u16::from_le_bytes([self.buf[4], self.buf[5]]) as usize;
+            if self.buf[1] != ubx::SYNC[1] || len > ubx::MAX_PAYLOAD {
+                self.buf.remove(0);
+                continue;
+            }
+            if self.buf.len() < ubx::OVERHEAD + len {
+                return None;
+            }
+            match Frame::decode(&self.buf[..ubx::OVERHEAD + len]) {
+                Ok(frame) => {
+                    self.buf.drain(..ubx::OVERHEAD + len);
+                    return Some(Packet::Ubx(frame));
+                }
+                Err(e) => {
+                    log::debug!("dropping UBX frame: {e:?}");
+                    self.buf.remove(0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GNGGA,083559.00,5228.54810,N,01324.11822,E,1,12,0.71,48.3,M,39.6,M,,*7F";
+
+    #[test]
+    fn mixed_stream() {
+        let ack = Frame::new(
+            ubx::CLASS_ACK,
+            ubx::ACK_ACK,
+            vec![ubx::CLASS_CFG, ubx::CFG_VALSET],
+        );
+        let mut stream = b"\x00\xff".to_vec();
+        stream.extend(format!("{GGA}\r\n").bytes());
+        stream.extend(ack.encode());
+        stream.extend(b"$GAGSV,1,1,00,7*73\r\n");
+        let mut splitter = Splitter::new();
+        // in small reads, as the UART hands them over
+        let mut packets = Vec::new();
+        for chunk in stream.chunks(7) {
+            splitter.push(chunk);
+            while let Some(packet) = splitter.next_packet() {
+                packets.push(packet);
+            }
+        }
+        assert_eq!(
+            packets,
+            [
+                Packet::Nmea(GGA.to_string()),
+                Packet::Ubx(ack),
+                Packet::Nmea("$GAGSV,1,1,00,7*73".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_frame_resyncs() {
+        let mut bad = Frame::new(ubx::CLASS_NAV, ubx::NAV_PVT, vec![1, 2, 3]).encode();
+        let last = bad.len() - 1;
+        bad[last] ^= 0xFF;
+        let mut splitter = Splitter::new();
+        splitter.push(&bad);
+        splitter.push(format!("{GGA}\r\n").as_bytes());
+        assert_eq!(splitter.next_packet(), Some(Packet::Nmea(GGA.to_string())));
+        assert_eq!(splitter.next_packet(), None);
+    }
+
+    #[test]
+    fn runaway_sentence() {
+        // a '$' without a line end is given up on once it is too long to be a sentence
+        let mut splitter = Splitter::new();
+        splitter.push(b"$GPTXT");
+        splitter.push(&[b'x'; MAX_SENTENCE]);
+        assert_eq!(splitter.next_packet(), None);
+        splitter.push(format!("{GGA}\r\n").as_bytes());
+        assert_eq!(splitter.next_packet(), Some(Packet::Nmea(GGA.to_string())));
+    }
+}
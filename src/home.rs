@@ -0,0 +1,136 @@
+use crate::app::{App, Context, Transition};
+use crate::clock;
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::launcher::Launcher;
+use crate::ui::{Ui, WidgetId};
+use crate::widget::{Align, Label, LargeLabel, StatusBar};
+
+pub const NAME: &str = "Home";
+// the 6x10 font at this scale is 60 pixels high
+const CLOCK_SCALE: i16 = 6;
+
+// Home and lock screen at the bottom of the app stack: the time, the date and what arrived
+// while the phone was put away. Only the clock changes each minute, so it is a small partial
+// refresh; the whole screen is redrawn on the hour to clear the ghosting those leave behind.
+pub struct Home {
+    ui: Ui,
+    clock: WidgetId,
+    date: WidgetId,
+    sms: WidgetId,
+    mesh: WidgetId,
+    // of the last full refresh
+    hour: Option<u8>,
+}
+
+impl Home {
+    pub fn new() -> Self {
+        let mut ui = Ui::new();
+        ui.add(StatusBar::new("dynatac"));
+        let clock = ui.add(LargeLabel::new("--:--", CLOCK_SCALE));
+        let date = ui.add(Label::new("").align(Align::Center));
+        let sms = ui.add(Label::new("").align(Align::Center));
+        let mesh = ui.add(Label::new("").align(Align::Center));
+        ui.add(Label::new("Enter for apps").align(Align::Center));
+        Self {
+            ui,
+            clock,
+            date,
+            sms,
+            mesh,
+            hour: None,
+        }
+    }
+
+    fn set_label(&mut self, id: WidgetId, text: &str) {
+        self.ui.get_mut::<Label>(id).unwrap().set_text(text);
+    }
+
+    fn update_clock(&mut self) {
+        let Some(now) = clock::now() else {
+            self.set_label(self.date, "time not set");
+            return;
+        };
+        self.ui
+            .get_mut::<LargeLabel>(self.clock)
+            .unwrap()
+            .set_text(&now.time_text());
+        self.set_label(self.date, &now.date_text());
+        if self.hour != Some(now.hour) {
+            if self.hour.is_some() {
+                self.ui.invalidate();
+            }
+            self.hour = Some(now.hour);
+        }
+    }
+
+    fn update_unread(&mut self, ctx: &Context) {
+        let mailbox = ctx.mailbox.lock().unwrap();
+        let sms = mailbox.unread();
+        let last_from = mailbox
+            .inbox()
+            .iter()
+            .rev()
+            .find(|m| !m.read)
+            .map(|m| ctx.contacts.name_for(&m.from));
+        drop(mailbox);
+        let mesh = ctx.mesh.lock().unwrap().unread();
+        let mut sms_text = unread_text(sms, "message");
+        match last_from {
+            Some(from) if sms == 1 => sms_text.push_str(&format!(" from {from}")),
+            Some(from) => sms_text.push_str(&format!(", last from {from}")),
+            None => {}
+        }
+        self.set_label(self.sms, &sms_text);
+        self.set_label(self.mesh, &unread_text(mesh, "mesh message"));
+    }
+}
+
+impl Default for Home {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// "No new messages", "1 new message", "3 new messages"
+fn unread_text(count: usize, what: &str) -> String {
+    match count {
+        0 => format!("No new {what}s"),
+        1 => format!("1 new {what}"),
+        n => format!("{n} new {what}s"),
+    }
+}
+
+impl App for Home {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        // coming back redraws the whole screen anyway
+        self.hour = None;
+        self.update_clock();
+        self.update_unread(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        // a steady stream of events can hold off the ticks, so any event moves the clock on
+        self.update_clock();
+        match event {
+            Event::Sms(_) | Event::Mesh(_) => self.update_unread(ctx),
+            Event::Key(Key::Enter) | Event::Key(Key::Up) => {
+                return Transition::Push(Box::new(Launcher::new()))
+            }
+            Event::Touch(Gesture::Tap { .. })
+            | Event::Touch(Gesture::Swipe {
+                direction: Direction::Up,
+                ..
+            }) => return Transition::Push(Box::new(Launcher::new())),
+            _ => {}
+        }
+        Transition::None
+    }
+}
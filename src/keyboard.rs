@@ -0,0 +1,214 @@
+use std::num::NonZeroU32;
+use std::sync::mpsc::Sender;
+
+use embedded_hal::i2c::I2c;
+use esp_idf_hal::gpio::{Input, InputPin, InterruptType, PinDriver};
+use esp_idf_hal::task::notification::Notification;
+
+use crate::event::{Event, Key};
+use crate::tca8418::{KeyEvent, Tca8418};
+
+pub const ROWS: u8 = 4;
+pub const COLS: u8 = 10;
+
+// poll the controller even without an interrupt, in case an edge was missed
+const POLL_TICKS: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyDef {
+    // base character and its symbol layer alternative
+    C(char, char),
+    Space,
+    Enter,
+    Backspace,
+    Shift,
+    Sym,
+    Alt,
+    Blank,
+}
+
+use KeyDef::*;
+
+const LAYOUT: [[KeyDef; COLS as usize]; ROWS as usize] = [
+    [
+        C('q', '#'),
+        C('w', '1'),
+        C('e', '2'),
+        C('r', '3'),
+        C('t', '('),
+        C('y', ')'),
+        C('u', '_'),
+        C('i', '-'),
+        C('o', '+'),
+        C('p', '@'),
+    ],
+    [
+        C('a', '*'),
+        C('s', '4'),
+        C('d', '5'),
+        C('f', '6'),
+        C('g', '/'),
+        C('h', ':'),
+        C('j', ';'),
+        C('k', '\''),
+        C('l', '"'),
+        Backspace,
+    ],
+    [
+        Alt,
+        C('z', '7'),
+        C('x', '8'),
+        C('c', '9'),
+        C('v', '?'),
+        C('b', '!'),
+        C('n', ','),
+        C('m', '.'),
+        C('$', '0'),
+        Enter,
+    ],
+    [
+        Blank, Shift, Blank, Blank, Space, Blank, Blank, Blank, Sym, Shift,
+    ],
+];
+
+// The Sym layer character printed on the key for `c`, e.g. 'w' -> '1'.
+pub fn symbol(c: char) -> Option<char> {
+    let c = c.to_ascii_lowercase();
+    LAYOUT.iter().flatten().find_map(|def| match *def {
+        C(base, sym) if base == c => Some(sym),
+        _ => None,
+    })
+}
+
+// A modifier applies while held, or to the next key after a tap on its own.
+#[derive(Debug, Default, Clone, Copy)]
+struct Modifier {
+    held: bool,
+    used: bool,
+    latched: bool,
+}
+
+impl Modifier {
+    fn press(&mut self) {
+        self.held = true;
+        self.used = false;
+    }
+
+    fn release(&mut self) {
+        self.held = false;
+        if !self.used {
+            self.latched = !self.latched;
+        }
+    }
+
+    // whether the modifier applies to the key being pressed, consuming a latch
+    fn take(&mut self) -> bool {
+        let active = self.held || self.latched;
+        self.used |= self.held;
+        self.latched = false;
+        active
+    }
+}
+
+#[derive(Default)]
+pub struct Keymap {
+    shift: Modifier,
+    sym: Modifier,
+    alt: Modifier,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn map(&mut self, event: KeyEvent) -> Option<Key> {
+        let def = *LAYOUT.get(event.row as usize)?.get(event.col as usize)?;
+        let modifier = match def {
+            Shift => Some(&mut self.shift),
+            Sym => Some(&mut self.sym),
+            Alt => Some(&mut self.alt),
+            _ => None,
+        };
+        if let Some(modifier) = modifier {
+            if event.pressed {
+                modifier.press();
+            } else {
+                modifier.release();
+            }
+            return None;
+        }
+        if !event.pressed {
+            return None;
+        }
+
+        let shift = self.shift.take();
+        let sym = self.sym.take();
+        // Alt turns the left hand keys into navigation
+        if self.alt.take() {
+            return match def {
+                C('w', _) => Some(Key::Up),
+                C('a', _) => Some(Key::Left),
+                C('s', _) => Some(Key::Down),
+                C('d', _) => Some(Key::Right),
+                C('q', _) | Backspace => Some(Key::Back),
+                C('h', _) => Some(Key::Home),
+                Enter => Some(Key::Enter),
+                _ => None,
+            };
+        }
+        match def {
+            C(_, alt) if sym => Some(Key::Char(alt)),
+            C(c, _) if shift => Some(Key::Char(c.to_ascii_uppercase())),
+            C(c, _) => Some(Key::Char(c)),
+            Space => Some(Key::Char(' ')),
+            Enter => Some(Key::Enter),
+            Backspace => Some(Key::Backspace),
+            _ => None,
+        }
+    }
+}
+
+// Keyboard task: waits for the TCA8418 interrupt, drains its FIFO and forwards the mapped
+// keys to the display task.
+pub fn run<I2C, INT>(
+    mut keypad: Tca8418<I2C>,
+    mut int_pin: PinDriver<'static, INT, Input>,
+    events: Sender<Event>,
+) -> anyhow::Result<()>
+where
+    I2C: I2c,
+    INT: InputPin,
+{
+    keypad
+        .configure(ROWS, COLS)
+        .map_err(|e| anyhow::anyhow!("keypad configuration failed: {e:?}"))?;
+
+    let notification = Notification::new();
+    let notifier = notification.notifier();
+    int_pin.set_interrupt_type(InterruptType::NegEdge)?;
+    unsafe {
+        int_pin.subscribe(move || {
+            notifier.notify_and_yield(NonZeroU32::new(1).unwrap());
+        })?;
+    }
+
+    let mut keymap = Keymap::new();
+    let mut key_events = Vec::new();
+    loop {
+        int_pin.enable_interrupt()?;
+        notification.wait(POLL_TICKS);
+
+        key_events.clear();
+        if let Err(e) = keypad.read_events(&mut key_events) {
+            log::warn!("keypad read error: {e:?}");
+            continue;
+        }
+        for key in key_events.iter().filter_map(|&e| keymap.map(e)) {
+            if events.send(Event::Key(key)).is_err() {
+                // display task is gone, nothing left to deliver keys to
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::app::{App, Context, Transition};
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::ui::{Ui, WidgetId};
+use crate::widget::{List, StatusBar};
+
+const VISIBLE_ROWS: usize = 16;
+
+// Lists the installed apps, opened from the home screen.
+pub struct Launcher {
+    ui: Ui,
+    list: WidgetId,
+}
+
+impl Launcher {
+    pub fn new() -> Self {
+        let mut ui = Ui::new();
+        ui.add(StatusBar::new("Apps"));
+        let list = ui.add(List::new(VISIBLE_ROWS));
+        Self { ui, list }
+    }
+
+    fn list(&mut self) -> &mut List {
+        self.ui.get_mut::<List>(self.list).unwrap()
+    }
+
+    // "Messages (2)" while notifications are waiting in the app
+    fn update_badges(&mut self, ctx: &Context) {
+        let names = ctx
+            .apps
+            .iter()
+            .map(|a| match ctx.notifications.badge(a.name) {
+                0 => a.name.to_string(),
+                n => format!("{} ({n})", a.name),
+            })
+            .collect();
+        self.list().set_items(names);
+    }
+
+    fn launch_selected(&mut self, ctx: &mut Context) -> Transition {
+        match self.list().selected() {
+            Some(i) => {
+                let launch = ctx.apps[i].launch;
+                Transition::Push(launch(ctx))
+            }
+            None => Transition::None,
+        }
+    }
+}
+
+impl Default for Launcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl App for Launcher {
+    fn name(&self) -> &str {
+        "Launcher"
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.update_badges(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Key(Key::Up) => self.list().select_previous(),
+            Event::Key(Key::Down) => self.list().select_next(),
+            Event::Key(Key::Enter) | Event::Key(Key::Right) => return self.launch_selected(ctx),
+            Event::Key(Key::Back) | Event::Key(Key::Left) => return Transition::Pop,
+            Event::Touch(Gesture::Tap { x, y }) => {
+                let list = self.list;
+                if self.ui.hit(*x, *y) == Some(list) {
+                    let top = self.ui.bounds(list).unwrap().y;
+                    if let Some(i) = self.list().index_at(*y - top) {
+                        self.list().select(i);
+                        return self.launch_selected(ctx);
+                    }
+                }
+            }
+            Event::Touch(Gesture::Swipe { direction, .. }) => match direction {
+                Direction::Up => self.list().select_next(),
+                Direction::Down => self.list().select_previous(),
+                Direction::Right => return Transition::Pop,
+                Direction::Left => {}
+            },
+            Event::Sms(_)
+            | Event::Mesh(_)
+            | Event::Call(_)
+            | Event::Storage(_)
+            | Event::Battery(_) => self.update_badges(ctx),
+            _ => {}
+        }
+        Transition::None
+    }
+}
@@ -0,0 +1,18 @@
+// The parts of the firmware that need no ESP-IDF: protocols, codecs and parsers, built for the
+// board and tested on the host. The tasks and drivers that use them are in the binary.
+
+pub mod aes;
+pub mod audio;
+pub mod call;
+pub mod gnss;
+pub mod meshtastic;
+pub mod modem;
+pub mod nmea;
+pub mod pdu;
+pub mod retained;
+pub mod settings;
+pub mod sms;
+pub mod sx1262;
+pub mod timesource;
+pub mod ubx;
+pub mod vcard;
@@ -0,0 +1,149 @@
+// Location task: powers the GNSS receiver on its own UART, configures it with UBX and feeds
+// the shared `Gnss` state everything it sends. Fix changes go out as `Event::Gnss`, the time of
+// each fix to the clock.
+
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::OutputPin;
+
+use crate::clock::TimeKeeper;
+use crate::event::Event;
+use crate::gnss::{Gnss, Packet, Splitter};
+use crate::modem::Transport;
+use crate::nmea;
+use crate::timesource::{self, TimeSource};
+use crate::ubx::{self, Ack, Frame, NavPvt};
+
+// the module needs a moment after GPS_EN before it accepts commands
+const BOOT_TIME: Duration = Duration::from_millis(1000);
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const CONFIG_ATTEMPTS: u8 = 3;
+
+// GNSS task: applies the settings from `gnss` whenever they change and feeds it everything
+// the receiver sends. The time of each fix goes to the clock.
+pub fn run<T, EN>(
+    mut uart: T,
+    mut enable: EN,
+    gnss: Arc<Mutex<Gnss>>,
+    clock: Arc<Mutex<TimeKeeper>>,
+    events: Sender<Event>,
+) -> anyhow::Result<()>
+where
+    T: Transport,
+    EN: OutputPin,
+{
+    let mut splitter = Splitter::new();
+    let mut buf = [0u8; 256];
+    let mut powered = false;
+    // the configuration sent last, until the receiver acknowledges it
+    let mut pending: Option<(Frame, Instant, u8)> = None;
+
+    loop {
+        let changes = gnss.lock().unwrap().take_changes();
+        if let Some((enabled, config)) = changes {
+            if enabled != powered {
+                enable
+                    .set_state(enabled.into())
+                    .map_err(|e| anyhow::anyhow!("GPS_EN: {e:?}"))?;
+                powered = enabled;
+                log::info!("GNSS {}", if enabled { "on" } else { "off" });
+                if enabled {
+                    thread::sleep(BOOT_TIME);
+                } else {
+                    pending = None;
+                    let event = gnss.lock().unwrap().lost();
+                    if let Some(event) = event {
+                        events.send(Event::Gnss(event)).ok();
+                    }
+                }
+            }
+            if powered {
+                // the RAM layer is lost with power, so this runs after every power-up too
+                pending = Some((config.frame(), Instant::now(), 0));
+            }
+        }
+
+        if let Some((frame, sent, attempts)) = pending.as_mut() {
+            if *attempts == 0 || sent.elapsed() >= ACK_TIMEOUT {
+                if *attempts == CONFIG_ATTEMPTS {
+                    log::warn!("GNSS receiver did not answer the configuration");
+                    pending = None;
+                } else {
+                    uart.write(&frame.encode())
+                        .map_err(|e| anyhow::anyhow!("GNSS write: {e:?}"))?;
+                    *sent = Instant::now();
+                    *attempts += 1;
+                }
+            }
+        }
+
+        let n = uart
+            .read(&mut buf, READ_TIMEOUT)
+            .map_err(|e| anyhow::anyhow!("GNSS read: {e:?}"))?;
+        splitter.push(&buf[..n]);
+        while let Some(packet) = splitter.next_packet() {
+            let now = Instant::now();
+            let event = match packet {
+                Packet::Nmea(line) => match nmea::parse(&line) {
+                    Ok(sentence) => gnss.lock().unwrap().update(&sentence, now),
+                    Err(e) => {
+                        log::debug!("bad NMEA sentence {line:?}: {e:?}");
+                        None
+                    }
+                },
+                Packet::Ubx(frame) => {
+                    if let Some(ack) = Ack::decode(&frame) {
+                        if (ack.class, ack.id) == (ubx::CLASS_CFG, ubx::CFG_VALSET) {
+                            if ack.accepted {
+                                log::info!("GNSS configured");
+                            } else {
+                                log::warn!("GNSS configuration rejected, using NMEA defaults");
+                            }
+                            pending = None;
+                        }
+                        None
+                    } else if let Some(pvt) = NavPvt::decode(&frame) {
+                        gnss.lock().unwrap().update_pvt(&pvt, now)
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some(event) = event {
+                if gnss.lock().unwrap().position().is_some() {
+                    set_clock(&gnss, &clock);
+                }
+                events.send(Event::Gnss(event)).ok();
+            }
+        }
+    }
+}
+
+// Receivers report a time before they have a fix, possibly off by the leap seconds they have
+// yet to learn, so only the time of a fix is used.
+fn set_clock(gnss: &Mutex<Gnss>, clock: &Mutex<TimeKeeper>) {
+    let Some((date, time, at)) = gnss.lock().unwrap().utc() else {
+        return;
+    };
+    let mut clock = clock.lock().unwrap();
+    if !clock.wants(TimeSource::Gnss) {
+        return;
+    }
+    let secs = timesource::utc_seconds(
+        date.year as i32,
+        date.month,
+        date.day,
+        time.hour,
+        time.minute,
+        time.second,
+    );
+    let Ok(secs) = u64::try_from(secs) else {
+        return;
+    };
+    let utc = Duration::from_secs(secs) + Duration::from_millis(time.millis as u64) + at.elapsed();
+    clock.offer(TimeSource::Gnss, utc);
+}
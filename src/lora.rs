@@ -0,0 +1,212 @@
+// LoRa radio task. The receiver is on whenever the radio is not sending; every packet goes to
+// the shared Meshtastic node, and the frames the node queues (messages from the apps,
+// rebroadcasts) are sent with listen-before-talk (CAD). Changes go out as `Event::Mesh`.
+
+use std::num::NonZeroU32;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital;
+use embedded_hal::spi::SpiDevice;
+use esp_idf_hal::gpio::{Input, InputPin, InterruptType, PinDriver};
+use esp_idf_hal::task::notification::Notification;
+
+use crate::event::Event;
+use crate::mesh::Mesh;
+use crate::sx1262::{
+    LoraConfig, RadioError, Sx1262, IRQ_ALL, IRQ_CAD_DETECTED, IRQ_CAD_DONE, IRQ_CRC_ERROR,
+    IRQ_HEADER_ERROR, IRQ_RX_DONE, IRQ_TIMEOUT, IRQ_TX_DONE,
+};
+
+const LBT_ATTEMPTS: u32 = 5;
+const CAD_TIMEOUT: Duration = Duration::from_millis(500);
+// check for due frames at least this often while receiving
+const POLL_TICKS: u32 = 100;
+const IRQ_POLL_TICKS: u32 = 10;
+
+pub fn run<SPI, BUSY, RST, D, INT>(
+    mut radio: Sx1262<SPI, BUSY, RST, D>,
+    mut dio1: PinDriver<'static, INT, Input>,
+    config: LoraConfig,
+    mesh: Arc<Mutex<Mesh>>,
+    events: Sender<Event>,
+) -> anyhow::Result<()>
+where
+    SPI: SpiDevice,
+    BUSY: digital::InputPin,
+    RST: digital::OutputPin,
+    D: DelayNs,
+    INT: InputPin,
+{
+    radio
+        .init(&config)
+        .map_err(|e| anyhow::anyhow!("radio init failed: {e:?}"))?;
+    log::info!(
+        "LoRa up at {} Hz, SF{}",
+        config.frequency_hz,
+        config.spreading_factor
+    );
+    log_device_errors(&mut radio);
+
+    let notification = Notification::new();
+    let notifier = notification.notifier();
+    dio1.set_interrupt_type(InterruptType::PosEdge)?;
+    unsafe {
+        dio1.subscribe(move || {
+            notifier.notify_and_yield(NonZeroU32::new(1).unwrap());
+        })?;
+    }
+
+    radio.start_receive().map_err(radio_error)?;
+    let mut buf = [0u8; 255];
+    loop {
+        dio1.enable_interrupt()?;
+        notification.wait(POLL_TICKS);
+
+        let irq = radio.irq_status().map_err(radio_error)?;
+        if irq != 0 {
+            radio.clear_irq(irq).map_err(radio_error)?;
+        }
+        if irq & IRQ_RX_DONE != 0 {
+            if irq & (IRQ_CRC_ERROR | IRQ_HEADER_ERROR) != 0 {
+                log::warn!("LoRa packet dropped, irq {irq:#06x}");
+            } else {
+                let status = radio.read_packet(&mut buf).map_err(radio_error)?;
+                log::debug!("LoRa packet, {} bytes, {} dBm", status.len, status.rssi_dbm);
+                let event = mesh.lock().unwrap().receive(
+                    &buf[..status.len],
+                    status.snr_db(),
+                    Instant::now(),
+                );
+                if let Some(event) = event {
+                    events.send(Event::Mesh(event)).ok();
+                }
+            }
+        }
+
+        mesh.lock().unwrap().tick(Instant::now());
+        // the lock is not held while the radio is transmitting
+        let mut sent_any = false;
+        loop {
+            let Some(frame) = mesh.lock().unwrap().next_frame(Instant::now()) else {
+                break;
+            };
+            let ok = match transmit(&mut radio, &mut dio1, &notification, &frame.data) {
+                Ok(ok) => ok,
+                Err(e) => {
+                    log::warn!("LoRa transmit error: {e:?}");
+                    log_device_errors(&mut radio);
+                    false
+                }
+            };
+            if let Some(id) = frame.message {
+                if let Some(event) = mesh.lock().unwrap().sent(id, ok) {
+                    events.send(Event::Mesh(event)).ok();
+                }
+            }
+            sent_any = true;
+        }
+        if sent_any {
+            radio.start_receive().map_err(radio_error)?;
+        }
+    }
+}
+
+// Calibration, oscillator and PLL failures the chip has latched, e.g. a TCXO that didn't
+// start. They are not cleared, so a later report includes the earlier ones.
+fn log_device_errors<SPI, BUSY, RST, D>(radio: &mut Sx1262<SPI, BUSY, RST, D>)
+where
+    SPI: SpiDevice,
+    BUSY: digital::InputPin,
+    RST: digital::OutputPin,
+    D: DelayNs,
+{
+    match radio.device_errors() {
+        Ok(0) => {}
+        Ok(errors) => log::warn!("LoRa radio device errors {errors:#06x}"),
+        Err(e) => log::warn!("reading LoRa radio device errors failed: {e:?}"),
+    }
+}
+
+// Sends once the channel is clear, returns false if it stayed busy or the send timed out.
+fn transmit<SPI, BUSY, RST, D, INT>(
+    radio: &mut Sx1262<SPI, BUSY, RST, D>,
+    dio1: &mut PinDriver<'static, INT, Input>,
+    notification: &Notification,
+    payload: &[u8],
+) -> Result<bool, RadioError>
+where
+    SPI: SpiDevice,
+    BUSY: digital::InputPin,
+    RST: digital::OutputPin,
+    D: DelayNs,
+    INT: InputPin,
+{
+    for attempt in 0..LBT_ATTEMPTS {
+        radio.start_cad()?;
+        let irq = wait_irq(radio, dio1, notification, IRQ_CAD_DONE, CAD_TIMEOUT)?;
+        if irq & IRQ_CAD_DETECTED == 0 {
+            let air_time = radio.config().time_on_air_ms(payload.len());
+            radio.start_transmit(payload)?;
+            let timeout = Duration::from_millis(2 * air_time as u64 + 200);
+            let irq = wait_irq(
+                radio,
+                dio1,
+                notification,
+                IRQ_TX_DONE | IRQ_TIMEOUT,
+                timeout,
+            )?;
+            return Ok(irq & IRQ_TX_DONE != 0);
+        }
+        thread::sleep(backoff(attempt));
+    }
+    Ok(false)
+}
+
+// Waits until one of `mask` is raised, returns the IRQ status (0 on timeout) and clears it.
+fn wait_irq<SPI, BUSY, RST, D, INT>(
+    radio: &mut Sx1262<SPI, BUSY, RST, D>,
+    dio1: &mut PinDriver<'static, INT, Input>,
+    notification: &Notification,
+    mask: u16,
+    timeout: Duration,
+) -> Result<u16, RadioError>
+where
+    SPI: SpiDevice,
+    BUSY: digital::InputPin,
+    RST: digital::OutputPin,
+    D: DelayNs,
+    INT: InputPin,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        let irq = radio.irq_status()?;
+        if irq & mask != 0 {
+            radio.clear_irq(IRQ_ALL)?;
+            return Ok(irq);
+        }
+        if Instant::now() >= deadline {
+            radio.standby()?;
+            radio.clear_irq(IRQ_ALL)?;
+            return Ok(0);
+        }
+        dio1.enable_interrupt()
+            .map_err(|e| RadioError::Pin(format!("{e:?}")))?;
+        notification.wait(IRQ_POLL_TICKS);
+    }
+}
+
+// random back-off so two nodes that heard the same packet do not collide again
+fn backoff(attempt: u32) -> Duration {
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_micros() % 100);
+    Duration::from_millis(50 * (attempt as u64 + 1) + jitter as u64)
+}
+
+fn radio_error(e: RadioError) -> anyhow::Error {
+    anyhow::anyhow!("radio error: {e:?}")
+}
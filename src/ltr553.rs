@@ -0,0 +1,118 @@
+// LTR-553ALS ambient light and proximity sensor datasheet:
+// https://optoelectronics.liteon.com/upload/download/DS86-2013-0003/LTR-553ALS-01_DS_V1.pdf
+
+use embedded_hal::i2c::I2c;
+
+pub const DEFAULT_ADDRESS: u8 = 0x23;
+
+const REG_ALS_CONTR: u8 = 0x80;
+const REG_PS_CONTR: u8 = 0x81;
+const REG_PS_MEAS_RATE: u8 = 0x84;
+const REG_ALS_MEAS_RATE: u8 = 0x85;
+const REG_PART_ID: u8 = 0x86;
+// CH1 low, CH1 high, CH0 low, CH0 high; CH1 has to be read first
+const REG_ALS_DATA: u8 = 0x88;
+const REG_PS_DATA: u8 = 0x8D;
+
+const PART_NUMBER: u8 = 0x09;
+const ALS_ACTIVE: u8 = 0x01;
+// gain 1x, 1 to 64k lux
+const ALS_GAIN_1X: u8 = 0x00;
+// 100 ms integration, repeated every 500 ms
+const ALS_MEAS_RATE_100MS_500MS: u8 = 0x03;
+const PS_ACTIVE: u8 = 0x03;
+// 50 ms between measurements
+const PS_MEAS_RATE_50MS: u8 = 0x00;
+const PS_SATURATED: u16 = 0x8000;
+const PS_DATA_MASK: u16 = 0x07FF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightError<E> {
+    I2c(E),
+    UnknownPart(u8),
+}
+
+pub struct Ltr553<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Ltr553<I2C>
+where
+    I2C: I2c,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    // Checks the part and starts the light sensor; proximity stays off until enabled.
+    pub fn init(&mut self) -> Result<(), LightError<I2C::Error>> {
+        let part = self.read_register(REG_PART_ID).map_err(LightError::I2c)?;
+        if part >> 4 != PART_NUMBER {
+            return Err(LightError::UnknownPart(part));
+        }
+        self.write_register(REG_ALS_MEAS_RATE, ALS_MEAS_RATE_100MS_500MS)
+            .and_then(|_| self.write_register(REG_ALS_CONTR, ALS_ACTIVE | ALS_GAIN_1X))
+            .and_then(|_| self.write_register(REG_PS_MEAS_RATE, PS_MEAS_RATE_50MS))
+            .map_err(LightError::I2c)
+    }
+
+    // the IR LED draws tens of mA while pulsing, so only run it when needed
+    pub fn set_proximity_enabled(&mut self, enabled: bool) -> Result<(), I2C::Error> {
+        self.write_register(REG_PS_CONTR, if enabled { PS_ACTIVE } else { 0 })
+    }
+
+    // raw channel counts (visible + IR, IR)
+    pub fn als_channels(&mut self) -> Result<(u16, u16), I2C::Error> {
+        let mut buf = [0u8; 4];
+        self.i2c
+            .write_read(self.address, &[REG_ALS_DATA], &mut buf)?;
+        let ch1 = u16::from_le_bytes([buf[0], buf[1]]);
+        let ch0 = u16::from_le_bytes([buf[2], buf[3]]);
+        Ok((ch0, ch1))
+    }
+
+    pub fn lux(&mut self) -> Result<u32, I2C::Error> {
+        let (ch0, ch1) = self.als_channels()?;
+        Ok(lux(ch0, ch1))
+    }
+
+    // 0 (far) to 2047 (near); None while saturated by ambient light
+    pub fn proximity(&mut self) -> Result<Option<u16>, I2C::Error> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(self.address, &[REG_PS_DATA], &mut buf)?;
+        let raw = u16::from_le_bytes(buf);
+        Ok((raw & PS_SATURATED == 0).then_some(raw & PS_DATA_MASK))
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u8, I2C::Error> {
+        let mut buf = [0u8];
+        self.i2c.write_read(self.address, &[reg], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(self.address, &[reg, value])
+    }
+}
+
+// The datasheet's lux formula for 1x gain and 100 ms integration, chosen by the share of
+// IR in the reading.
+pub fn lux(ch0: u16, ch1: u16) -> u32 {
+    let (ch0, ch1) = (ch0 as f32, ch1 as f32);
+    if ch0 + ch1 == 0.0 {
+        return 0;
+    }
+    let ratio = ch1 / (ch0 + ch1);
+    let lux = if ratio < 0.45 {
+        1.7743 * ch0 + 1.1059 * ch1
+    } else if ratio < 0.64 {
+        4.2785 * ch0 - 1.9548 * ch1
+    } else if ratio < 0.85 {
+        0.5926 * ch0 + 0.1185 * ch1
+    } else {
+        0.0
+    };
+    lux.max(0.0) as u32
+}
@@ -1,62 +1,393 @@
+mod about;
+mod app;
+mod battery;
+mod bhi260;
+mod bq25896;
+mod bq27220;
+mod canvas;
+mod cellular;
+mod chat;
+mod clock;
+mod contacts;
+mod conversations;
+mod cst328;
+mod dialer;
 mod epd;
 mod epdisplay;
+mod event;
+mod font;
+mod font_6x10;
+mod home;
+mod keyboard;
+mod launcher;
+mod location;
+mod lora;
+mod ltr553;
+mod map;
+mod mesh;
+mod messages;
+mod notifications;
+mod partition;
+mod phonebook;
+mod power;
+mod preferences;
+mod sensors;
+mod sntp;
+mod storage;
+mod tca8418;
+mod tiles;
+mod touch;
+mod ui;
+mod widget;
 
+use canvas::{Rect, Refresh};
+use dynatac::{
+    audio, call, gnss, meshtastic, modem, nmea, pdu, retained, settings, sms, sx1262, timesource,
+    ubx, vcard,
+};
+use embedded_hal_bus::i2c::MutexDevice;
 use epdisplay::{Colour, DisplayError};
-use esp_idf_hal::delay::Ets;
-use esp_idf_hal::gpio::AnyInputPin;
-use esp_idf_hal::gpio::PinDriver;
+use esp_idf_hal::delay::{Ets, FreeRtos};
+use esp_idf_hal::gpio::AnyIOPin;
+use esp_idf_hal::gpio::{PinDriver, Pull};
+use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
+use esp_idf_hal::i2s::config::{
+    Config, DataBitWidth, SlotMode, StdClkConfig, StdConfig, StdGpioConfig, StdSlotConfig,
+};
+use esp_idf_hal::i2s::I2sDriver;
 use esp_idf_hal::prelude::*;
-use esp_idf_hal::spi::{SpiConfig, SpiDeviceDriver, SpiDriver, SpiDriverConfig};
+use esp_idf_hal::spi::{Dma, SpiConfig, SpiDeviceDriver, SpiDriver, SpiDriverConfig};
+use esp_idf_hal::uart::{self, UartDriver};
+use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::log::EspLogger;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_svc::sys::link_patches;
 
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 
 const WIDTH: usize = 240;
 const HEIGHT: usize = 320;
 const BUFFER_SIZE: usize = WIDTH * HEIGHT / 8;
+// clock of the LoRa radio on the shared SPI bus, the panel's is a setting
+const LORA_SPI_HZ: u32 = 8_000_000;
+const SPI_DMA_SIZE: usize = 4096;
+const MESH_REGION: meshtastic::Region = meshtastic::Region::Eu868;
+const GNSS_BAUD: u32 = 38_400;
 
 fn main() -> anyhow::Result<()> {
     // initialize runtime + logging
     link_patches();
     EspLogger::initialize_default();
-    log::info!("start");
+    let wake = power::wake_cause();
+    log::info!("start, wake: {wake:?}");
 
-    // Take peripherals once and leak (so we can move references into threads)
-    let peripherals = Box::leak(Box::new(
-        esp_idf_hal::peripherals::Peripherals::take().unwrap(),
-    ));
+    // Take peripherals once; each task's 'move' closure captures only the fields it uses
+    let mut peripherals = esp_idf_hal::peripherals::Peripherals::take().unwrap();
 
-    // Spawn a thread that owns all SPI + display work.
-    // The 'move' closure captures the leaked 'peripherals' reference (which is 'static).
-    let builder = thread::Builder::new().stack_size(32 * 1024);
-    let handle = builder.spawn(move || {
-        // build SPI + pins inside task context
-        let spi = &mut peripherals.spi2;
-        let sclk = &mut peripherals.pins.gpio36; // SCK (board wiring)
-        let sdo = &mut peripherals.pins.gpio33; // MOSI
-        let sdi: Option<AnyInputPin> = None; // MISO if needed
-        let cs = &mut peripherals.pins.gpio34; // CS
+    let sysloop = EspSystemEventLoop::take()?;
+    let nvs = match EspDefaultNvsPartition::take() {
+        Ok(nvs) => Some(nvs),
+        Err(e) => {
+            log::warn!("no NVS: {e:?}");
+            None
+        }
+    };
 
-        let driver_config = SpiDriverConfig::new();
-        let spi_driver = SpiDriver::new(spi, sclk, sdo, sdi, &driver_config).unwrap();
+    // Input and system events for the app framework on the display task.
+    let (events_tx, events_rx) = mpsc::channel::<event::Event>();
 
-        let spi_device_config = SpiConfig::new().baudrate(115200.Hz());
-        let spi_device_driver =
-            SpiDeviceDriver::new(spi_driver, Some(cs), &spi_device_config).unwrap();
+    // User settings, changes are applied by the display task
+    let settings = Arc::new(Mutex::new(settings::Settings::open(nvs.clone())));
+    let settings_events = events_tx.clone();
+    settings.lock().unwrap().subscribe(move |setting| {
+        settings_events
+            .send(event::Event::Settings(setting))
+            .is_ok()
+    });
+
+    // System clock and time zone, set by the cellular, GNSS and SNTP tasks
+    let clock = Arc::new(Mutex::new(clock::TimeKeeper::new(nvs.clone())));
 
-        log::info!("SPI initialized successfully in display task");
+    // I2C bus shared by the keyboard, touch, power and sensor drivers. Leaked so every task
+    // can hold its own device on it.
+    let i2c_config = I2cConfig::new().baudrate(400.kHz().into());
+    let i2c = I2cDriver::new(
+        peripherals.i2c0,
+        peripherals.pins.gpio13, // SDA
+        peripherals.pins.gpio14, // SCL
+        &i2c_config,
+    )?;
+    let i2c_bus: &'static Mutex<I2cDriver<'static>> = Box::leak(Box::new(Mutex::new(i2c)));
 
+    // Keyboard task: TCA8418 on the shared bus, INT on gpio15
+    let keypad = tca8418::Tca8418::new(MutexDevice::new(i2c_bus), tca8418::DEFAULT_ADDRESS);
+    let keypad_int = PinDriver::input(peripherals.pins.gpio15)?;
+    let key_events = events_tx.clone();
+    thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+        if let Err(e) = keyboard::run(keypad, keypad_int, key_events) {
+            log::error!("keyboard task error: {e:?}");
+        }
+    })?;
+
+    // Battery task: BQ27220 fuel gauge and BQ25896 charger on the shared bus
+    let gauge = bq27220::Bq27220::new(MutexDevice::new(i2c_bus), bq27220::DEFAULT_ADDRESS);
+    let charger = bq25896::Bq25896::new(MutexDevice::new(i2c_bus), bq25896::DEFAULT_ADDRESS);
+    let battery_events = events_tx.clone();
+    thread::Builder::new().stack_size(6 * 1024).spawn(move || {
+        if let Err(e) = battery::run(gauge, charger, battery_events) {
+            log::error!("battery task error: {e:?}");
+        }
+    })?;
+
+    // Cellular task: A7682E on UART1, PWRKEY on gpio40, supply enable on gpio41
+    let uart_config = uart::config::Config::new().baudrate(115_200.Hz());
+    let modem_uart = UartDriver::new(
+        peripherals.uart1,
+        peripherals.pins.gpio11, // TX, to the modem's RXD
+        peripherals.pins.gpio10, // RX, from the modem's TXD
+        Option::<AnyIOPin>::None,
+        Option::<AnyIOPin>::None,
+        &uart_config,
+    )?;
+    // the modem stays powered through deep sleep, keep it that way when the pins are released
+    let mut modem_en = PinDriver::output(peripherals.pins.gpio41)?;
+    modem_en.set_high()?;
+    let modem_pwrkey = PinDriver::output(peripherals.pins.gpio40)?;
+    // RI only wakes the chip, the URCs themselves come over the UART
+    let mut modem_ri = PinDriver::input(peripherals.pins.gpio7)?;
+    modem_ri.set_pull(Pull::Up)?;
+    let modem_lock =
+        power::WakeLock::new(c"modem").map_err(|e| anyhow::anyhow!("modem wake lock: {e:?}"))?;
+    // SMS and call state, serviced by the cellular task and read by the apps
+    let mailbox = Arc::new(Mutex::new(sms::Mailbox::new()));
+    let calls = Arc::new(Mutex::new(call::Calls::new()));
+    let modem_mailbox = mailbox.clone();
+    let modem_calls = calls.clone();
+    let modem_clock = clock.clone();
+    let modem_events = events_tx.clone();
+    thread::Builder::new()
+        .stack_size(12 * 1024)
+        .spawn(move || {
+            // keep the modem powered for as long as the task runs
+            let _modem_en = modem_en;
+            let modem = modem::Modem::new(power::AwakeTransport::new(modem_uart, modem_lock));
+            if let Err(e) = cellular::run(
+                modem,
+                modem_pwrkey,
+                FreeRtos,
+                modem_mailbox,
+                modem_calls,
+                modem_clock,
+                modem_events,
+            ) {
+                log::error!("cellular task error: {e:?}");
+            }
+        })?;
+
+    // Alert task: beeps through the modem and buzzes the vibration motor on gpio2 for
+    // notifications
+    let (alerts_tx, alerts_rx) = mpsc::channel::<audio::Alert>();
+    let motor = PinDriver::output(peripherals.pins.gpio2)?;
+    let alert_calls = calls.clone();
+    thread::Builder::new().stack_size(4 * 1024).spawn(move || {
+        if let Err(e) = audio::run_alerts(motor, FreeRtos, alerts_rx, alert_calls) {
+            log::error!("alert task error: {e:?}");
+        }
+    })?;
+
+    // Audio task: dialer key tones, the ringtone and the busy signal, mixed into the I2S DAC
+    // (BCLK gpio16, LRCK gpio9, DIN gpio8)
+    let (tones_tx, tones_rx) = mpsc::channel::<audio::Tone>();
+    let i2s_config = StdConfig::new(
+        Config::default(),
+        StdClkConfig::from_sample_rate_hz(audio::SAMPLE_RATE),
+        StdSlotConfig::philips_slot_default(DataBitWidth::Bits16, SlotMode::Mono),
+        StdGpioConfig::default(),
+    );
+    let i2s = I2sDriver::new_std_tx(
+        peripherals.i2s0,
+        &i2s_config,
+        peripherals.pins.gpio16,
+        peripherals.pins.gpio8,
+        Option::<AnyIOPin>::None,
+        peripherals.pins.gpio9,
+    )?;
+    let speaker = audio::I2sSink::new(i2s).map_err(|e| anyhow::anyhow!("I2S: {e:?}"))?;
+    let audio_calls = calls.clone();
+    thread::Builder::new().stack_size(4 * 1024).spawn(move || {
+        if let Err(e) = audio::run(speaker, tones_rx, audio_calls) {
+            log::error!("audio task error: {e:?}");
+        }
+    })?;
+
+    // Sensor task: BHI260AP IMU (INT on gpio21) and LTR-553ALS light and proximity sensor on
+    // the shared bus
+    let imu = bhi260::Bhi260::new(MutexDevice::new(i2c_bus), bhi260::DEFAULT_ADDRESS);
+    let imu_int = PinDriver::input(peripherals.pins.gpio21)?;
+    let light = ltr553::Ltr553::new(MutexDevice::new(i2c_bus), ltr553::DEFAULT_ADDRESS);
+    let imu_firmware = match partition::Partition::open("imu") {
+        Ok(partition) => Some(partition),
+        Err(e) => {
+            log::warn!("no IMU firmware: {e:?}");
+            None
+        }
+    };
+    let sensor_calls = calls.clone();
+    let sensor_events = events_tx.clone();
+    thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+        if let Err(e) = sensors::run(
+            imu,
+            imu_int,
+            light,
+            imu_firmware,
+            sensor_calls,
+            sensor_events,
+        ) {
+            log::error!("sensor task error: {e:?}");
+        }
+    })?;
+
+    // SPI bus shared by the e-paper panel, the LoRa radio and the SD card. Each device has its
+    // own CS and clock; ESP-IDF locks the bus for the length of a transaction, so the tasks
+    // using it need no further arbitration. DMA (needed by the SD card) moves the panel's
+    // frame in 4 KiB chunks instead of 64 byte ones.
+    let spi_bus = Arc::new(SpiDriver::new(
+        peripherals.spi2,
+        peripherals.pins.gpio36,       // SCK
+        peripherals.pins.gpio33,       // MOSI
+        Some(peripherals.pins.gpio47), // MISO
+        &SpiDriverConfig::new().dma(Dma::Auto(SPI_DMA_SIZE)),
+    )?);
+    let epd_spi = SpiDeviceDriver::new(
+        spi_bus.clone(),
+        Some(peripherals.pins.gpio34), // CS
+        &SpiConfig::new().baudrate(settings.lock().unwrap().epd_spi_hz().Hz()),
+    )?;
+
+    // storage task: the SD card on the shared bus, CS gpio48, mounted at /sd
+    let storage = Arc::new(Mutex::new(storage::Storage::new(storage::MOUNT_POINT)));
+    let sd_spi = spi_bus.clone();
+    let sd_storage = storage.clone();
+    let sd_events = events_tx.clone();
+    thread::Builder::new().stack_size(6 * 1024).spawn(move || {
+        if let Err(e) = storage::run(sd_spi, peripherals.pins.gpio48, sd_storage, sd_events) {
+            log::error!("storage task error: {e:?}");
+        }
+    })?;
+
+    // LoRa task: SX1262 on the shared bus, CS gpio3, RST gpio4, DIO1 gpio5, BUSY gpio6,
+    // supply enable on gpio46
+    let mut lora_en = PinDriver::output(peripherals.pins.gpio46)?;
+    lora_en.set_high()?;
+    let lora_spi = SpiDeviceDriver::new(
+        spi_bus.clone(),
+        Some(peripherals.pins.gpio3),
+        &SpiConfig::new().baudrate(LORA_SPI_HZ.Hz()),
+    )?;
+    let radio = sx1262::Sx1262::new(
+        lora_spi,
+        PinDriver::input(peripherals.pins.gpio6)?,
+        PinDriver::output(peripherals.pins.gpio4)?,
+        Ets,
+    );
+    let lora_dio1 = PinDriver::input(peripherals.pins.gpio5)?;
+    // Meshtastic node on the default channel; the node number is the end of the MAC address,
+    // as on other Meshtastic devices
+    let channel = meshtastic::Channel::default_channel();
+    let lora_config = sx1262::LoraConfig {
+        // the module's TCXO is powered from DIO3
+        tcxo: Some(sx1262::TcxoVoltage::V1_8),
+        ..meshtastic::lora_config(MESH_REGION, &channel)
+    };
+    let node = node_number();
+    let short_name = format!("{:04x}", node & 0xFFFF);
+    let mesh = Arc::new(Mutex::new(mesh::Mesh::new(
+        node,
+        &format!("dynatac {short_name}"),
+        &short_name,
+        channel,
+    )));
+    let radio_mesh = mesh.clone();
+    let radio_events = events_tx.clone();
+    thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+        // keep the radio powered for as long as the task runs
+        let _lora_en = lora_en;
+        if let Err(e) = lora::run(radio, lora_dio1, lora_config, radio_mesh, radio_events) {
+            log::error!("LoRa task error: {e:?}");
+        }
+    })?;
+
+    // GNSS task: MIA-M10Q on UART0 (the console is on USB), supply enable on gpio39
+    let gnss_uart = UartDriver::new(
+        peripherals.uart0,
+        peripherals.pins.gpio43, // TX, to the receiver's RXD
+        peripherals.pins.gpio44, // RX, from the receiver's TXD
+        Option::<AnyIOPin>::None,
+        Option::<AnyIOPin>::None,
+        &uart::config::Config::new().baudrate(GNSS_BAUD.Hz()),
+    )?;
+    let gnss_en = PinDriver::output(peripherals.pins.gpio39)?;
+    let gnss = Arc::new(Mutex::new(gnss::Gnss::new(gnss::GnssConfig::default())));
+    let receiver_gnss = gnss.clone();
+    let gnss_clock = clock.clone();
+    let gnss_events = events_tx.clone();
+    thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+        if let Err(e) = location::run(gnss_uart, gnss_en, receiver_gnss, gnss_clock, gnss_events) {
+            log::error!("GNSS task error: {e:?}");
+        }
+    })?;
+
+    // SNTP task: brings Wi-Fi up when the clock needs a sync
+    if let Some(nvs) = nvs.clone() {
+        let wifi_modem = peripherals.modem;
+        let sntp_clock = clock.clone();
+        thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+            if let Err(e) = sntp::run(wifi_modem, sysloop, nvs, sntp_clock) {
+                log::error!("SNTP task error: {e:?}");
+            }
+        })?;
+    }
+
+    // Display rotation, shared with the touch task so touch points follow the framebuffer
+    let rotation = Arc::new(AtomicU8::new(settings.lock().unwrap().rotation()));
+
+    // Touch task: CST328 on the shared bus, INT on gpio12, reset on gpio45
+    let mut touch = cst328::Cst328::new(MutexDevice::new(i2c_bus), cst328::DEFAULT_ADDRESS);
+    let touch_int = PinDriver::input(peripherals.pins.gpio12)?;
+    let mut touch_rst = PinDriver::output(peripherals.pins.gpio45)?;
+    let touch_rotation = rotation.clone();
+    let touch_events = events_tx.clone();
+    thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+        if let Err(e) = touch.reset(&mut touch_rst, &mut FreeRtos) {
+            log::error!("touch reset error: {e:?}");
+            return;
+        }
+        if let Err(e) = touch::run(touch, touch_int, touch_rotation, touch_events) {
+            log::error!("touch task error: {e:?}");
+        }
+    })?;
+
+    // Spawn a thread that owns the panel and runs the apps.
+    let builder = thread::Builder::new().stack_size(32 * 1024);
+    let handle = builder.spawn(move || {
+        // keep RI an input with its pull-up while the device runs
+        let _modem_ri = modem_ri;
         // control pins (on your board)
         let busy = PinDriver::input(&mut peripherals.pins.gpio37).unwrap(); // BUSY
         let dc = PinDriver::output(&mut peripherals.pins.gpio35).unwrap(); // DC
-        let mut led_en = PinDriver::output(&mut peripherals.pins.gpio42).unwrap();
-        led_en.set_high().ok();
+        let led_en = PinDriver::output(&mut peripherals.pins.gpio42).unwrap();
+        // every pin held through deep sleep has its driver now
+        power::release_holds();
+        if let Err(e) = power::enable_light_sleep() {
+            log::warn!("light sleep unavailable: {e:?}");
+        }
 
         let delay = Ets;
 
         // Create the display instance (owned by this task)
-        let mut display = epd::Epd310Gdeq031t10::new(spi_device_driver, dc, busy, delay);
+        let mut display = epd::Epd310Gdeq031t10::new(epd_spi, dc, busy, delay);
 
         // small logger adapter
         fn logger(s: &str) {
@@ -70,31 +401,117 @@ fn main() -> anyhow::Result<()> {
         }
         log::info!("done init display");
 
-        display.set_rotation(1);
-        display.set_full_window();
-        display.first_page();
-        display.fill_screen(0x00);
-        // display.draw();
+        // built-in Latin font first, larger fonts from the data partition as fallback
+        let mut fonts = font::FontStack::new();
+        let stored = partition::Partition::open("fonts")
+            .map_err(font::FontError::from_debug)
+            .and_then(font::StoredFont::new);
+        match stored {
+            Ok(stored) => fonts.push(Box::new(stored)),
+            Err(e) => log::warn!("no fallback fonts: {e:?}"),
+        }
 
-        match display.next_page(logger) {
-            Ok(_) => log::info!("display sequence finished"),
-            Err(e) => log::error!("display sequence error: {e:?}"),
+        display.set_rotation(rotation.load(Ordering::Relaxed));
+        display.first_page();
+        // after deep sleep the panel still shows the last screen, carry on from it
+        if let Some(snapshot) = retained::take() {
+            if wake == power::WakeCause::PowerOn {
+                log::info!("ignoring display snapshot from before a reset");
+            } else if let Err(e) = display.restore(&snapshot) {
+                log::error!("display restore error: {e:?}");
+            }
         }
 
-        // keep the task alive so we can inspect logs / avoid dropping peripherals immediately
+        let mut ctx = app::Context::new(events_tx, mailbox, calls, mesh, gnss, storage, settings);
+        ctx.notifications.set_alerts(alerts_tx);
+        ctx.tones = Some(tones_tx);
+        ctx.install(dialer::NAME, dialer::Dialer::launch);
+        ctx.install(messages::NAME, messages::Messages::launch);
+        ctx.install(phonebook::NAME, phonebook::Phonebook::launch);
+        ctx.install(chat::NAME, chat::Chat::launch);
+        ctx.install(map::NAME, map::Map::launch);
+        ctx.install(preferences::NAME, preferences::Preferences::launch);
+        ctx.install("About", about::About::launch);
+        let mut nav = app::Navigator::new(Box::new(home::Home::new()), &mut ctx);
+        let power_config = ctx.settings.lock().unwrap().power_config();
+        let mut power = match power::PowerManager::new(power_config, wake, led_en, ctx.gnss.clone())
+        {
+            Ok(power) => power,
+            Err(e) => {
+                log::error!("power manager error: {e:?}");
+                return;
+            }
+        };
+        // render whatever changed, then block until the next event (or tick)
         loop {
-            // sleep inside task - this doesn't block other tasks
-            esp_idf_hal::delay::FreeRtos::delay_ms(10_000);
+            let mut refresh = nav.render(&mut display, &mut fonts);
+            // after deep sleep the panel still shows the restored screen, so the full redraw
+            // an app starts with only has to change what differs
+            if display.take_resumed() && refresh == Refresh::Full {
+                refresh = Refresh::Partial(Rect::new(0, 0, display.width(), display.height()));
+            }
+            // the framebuffer always holds the whole screen, so any update can be made full
+            // to clear the ghosting partial ones leave behind. The panel counts the partial
+            // ones since the last full one, across deep sleep.
+            if let Refresh::Partial(_) = refresh {
+                let every = ctx.settings.lock().unwrap().full_refresh_every();
+                if every.is_some_and(|every| display.partial_refreshes() + 1 >= every) {
+                    refresh = Refresh::Full;
+                }
+            }
+            if let Err(e) = display.show(refresh, logger) {
+                log::error!("display refresh error: {e:?}");
+            }
+            if power.state() == power::PowerState::Idle {
+                if let Err(e) = display.hibernate() {
+                    log::error!("display power off error: {e:?}");
+                }
+            }
+            let event = match events_rx.recv_timeout(power.tick_period()) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => event::Event::Tick,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            // held to the ear, the cheek would press buttons
+            if !(power.screen_locked() && matches!(event, event::Event::Touch(_))) {
+                nav.handle_event(&mut ctx, &event);
+            }
+            if let event::Event::Settings(_) = event {
+                let settings = ctx.settings.lock().unwrap();
+                let rotation_now = settings.rotation();
+                if rotation_now != rotation.load(Ordering::Relaxed) {
+                    rotation.store(rotation_now, Ordering::Relaxed);
+                    display.set_rotation(rotation_now);
+                    nav.invalidate();
+                }
+                power.set_config(settings.power_config());
+            }
+            let in_call = ctx.calls.lock().unwrap().state() != call::CallState::Idle;
+            if power.handle_event(&event, in_call) {
+                if let Err(e) = display.hibernate() {
+                    log::error!("display power off error: {e:?}");
+                }
+                if !retained::save(&display.snapshot()) {
+                    log::info!("screen too busy to keep, full refresh on wake");
+                }
+                power.deep_sleep();
+            }
         }
-    });
-
-    // Option A: join the thread (blocks here) - optional
-    // handle.join().expect("display thread panicked");
+        log::error!("event channel closed, display task exiting");
+    })?;
 
-    // Option B: let main continue (we'll block here indefinitely)
-    loop {
-        esp_idf_hal::delay::FreeRtos::delay_ms(60_000);
+    // the display task runs for as long as the device is awake
+    if handle.join().is_err() {
+        log::error!("display task panicked");
     }
+    Ok(())
+}
 
-    // Ok(())  -- unreachable due to loop above
+// last four bytes of the factory MAC address
+fn node_number() -> u32 {
+    let mut mac = [0u8; 6];
+    unsafe {
+        esp_idf_svc::sys::esp_efuse_mac_get_default(mac.as_mut_ptr());
+    }
+    u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]])
 }
@@ -0,0 +1,437 @@
+use std::cell::{Cell, RefCell};
+
+use crate::app::{App, Context, Transition};
+use crate::canvas::{Canvas, Rect};
+use crate::epdisplay::Colour;
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::font::FontStack;
+use crate::gnss::FixKind;
+use crate::nmea::Position;
+use crate::storage::Area;
+use crate::tiles::{self, TileKey, TileStore, MAX_ZOOM, TILE_SIZE};
+use crate::ui::{Ui, WidgetId};
+use crate::widget::{Label, StatusBar, Widget};
+
+pub const NAME: &str = "Map";
+const FIX_ZOOM: u8 = 15;
+// the whole world until there is a fix
+const WORLD_ZOOM: u8 = 1;
+// panning is a partial refresh; a full one now and then clears the ghosting
+const FULL_REFRESH_EVERY: u32 = 10;
+// following recentres once the position is this far out of the middle (fraction of the view)
+const FOLLOW_MARGIN: f64 = 0.25;
+const MARKER_RADIUS: i16 = 4;
+// missing tiles show a grid of dots this far apart
+const DOT_SPACING: i64 = 16;
+// track segments longer than this (off screen) are skipped instead of rasterised
+const MAX_SEGMENT: i64 = 4096;
+
+// Tiles around a centre point with the position and track drawn over them. Tiles are read
+// while drawing, so the widget owns the store.
+pub struct MapView {
+    store: RefCell<TileStore>,
+    zoom: u8,
+    // view centre in world pixels at `zoom`
+    centre: (f64, f64),
+    position: Option<Position>,
+    track: Vec<Position>,
+    // size of the last draw, for panning by a part of the view
+    size: Cell<(i16, i16)>,
+    dirty: bool,
+}
+
+impl MapView {
+    pub fn new(store: TileStore) -> Self {
+        Self {
+            store: RefCell::new(store),
+            zoom: WORLD_ZOOM,
+            centre: tiles::world_pixel(Position::default(), WORLD_ZOOM),
+            position: None,
+            track: Vec::new(),
+            size: Cell::new((0, 0)),
+            dirty: true,
+        }
+    }
+
+    pub fn zoom(&self) -> u8 {
+        self.zoom
+    }
+
+    pub fn centre(&self) -> Position {
+        tiles::position_at(self.centre.0, self.centre.1, self.zoom)
+    }
+
+    pub fn size(&self) -> (i16, i16) {
+        self.size.get()
+    }
+
+    pub fn set_centre(&mut self, centre: Position) {
+        self.centre = tiles::world_pixel(centre, self.zoom);
+        self.dirty = true;
+    }
+
+    // keeps the centre where it is
+    pub fn set_zoom(&mut self, zoom: u8) {
+        let zoom = zoom.min(MAX_ZOOM);
+        if zoom != self.zoom {
+            let centre = self.centre();
+            self.zoom = zoom;
+            self.set_centre(centre);
+        }
+    }
+
+    // moves the view by (dx, dy) pixels, wrapping around east-west
+    pub fn pan(&mut self, dx: i16, dy: i16) {
+        let world = (TILE_SIZE << self.zoom) as f64;
+        self.centre.0 = (self.centre.0 + dx as f64).rem_euclid(world);
+        self.centre.1 = (self.centre.1 + dy as f64).clamp(0.0, world);
+        self.dirty = true;
+    }
+
+    pub fn set_position(&mut self, position: Option<Position>) {
+        let pixel = |p: Option<Position>| {
+            p.map(|p| {
+                let (x, y) = tiles::world_pixel(p, self.zoom);
+                (x as i64, y as i64)
+            })
+        };
+        if pixel(position) != pixel(self.position) {
+            self.dirty = true;
+        }
+        self.position = position;
+    }
+
+    pub fn set_track(&mut self, track: Vec<Position>) {
+        if track != self.track {
+            self.track = track;
+            self.dirty = true;
+        }
+    }
+
+    // offset of `position` from the centre as a fraction of the view, (0, 0) in the middle
+    pub fn offset_of(&self, position: Position) -> (f64, f64) {
+        let (x, y) = tiles::world_pixel(position, self.zoom);
+        let (w, h) = self.size.get();
+        (
+            (x - self.centre.0) / w.max(1) as f64,
+            (y - self.centre.1) / h.max(1) as f64,
+        )
+    }
+
+    // forgets cached tiles, so tiles copied to a card since show up
+    pub fn reload(&mut self) {
+        self.store.borrow_mut().clear();
+        self.dirty = true;
+    }
+
+    fn draw_tiles(&self, canvas: &mut dyn Canvas, bounds: Rect, left: i64, top: i64) {
+        let tiles = 1i64 << self.zoom;
+        let mut store = self.store.borrow_mut();
+        let right = left + bounds.w as i64;
+        let bottom = top + bounds.h as i64;
+        for ty in top.div_euclid(TILE_SIZE)..=(bottom - 1).div_euclid(TILE_SIZE) {
+            for tx in left.div_euclid(TILE_SIZE)..=(right - 1).div_euclid(TILE_SIZE) {
+                let tile = if (0..tiles).contains(&ty) {
+                    store.get(TileKey {
+                        zoom: self.zoom,
+                        x: tx.rem_euclid(tiles) as u32,
+                        y: ty as u32,
+                    })
+                } else {
+                    None
+                };
+                // the part of this tile in view, in world pixels
+                let (x0, x1) = (
+                    (tx * TILE_SIZE).max(left),
+                    ((tx + 1) * TILE_SIZE).min(right),
+                );
+                let (y0, y1) = (
+                    (ty * TILE_SIZE).max(top),
+                    ((ty + 1) * TILE_SIZE).min(bottom),
+                );
+                for wy in y0..y1 {
+                    for wx in x0..x1 {
+                        let black = match tile {
+                            Some(tile) => tile.is_black(wx - tx * TILE_SIZE, wy - ty * TILE_SIZE),
+                            None => wx % DOT_SPACING == 0 && wy % DOT_SPACING == 0,
+                        };
+                        let colour = if black { Colour::BLACK } else { Colour::WHITE };
+                        let x = bounds.x + (wx - left) as i16;
+                        let y = bounds.y + (wy - top) as i16;
+                        canvas.draw_pixel(x, y, colour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Widget for MapView {
+    fn height(&self, _fonts: &FontStack) -> i16 {
+        TILE_SIZE as i16 / 2
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, _fonts: &mut FontStack, bounds: Rect) {
+        self.size.set((bounds.w, bounds.h));
+        let left = self.centre.0 as i64 - bounds.w as i64 / 2;
+        let top = self.centre.1 as i64 - bounds.h as i64 / 2;
+        self.draw_tiles(canvas, bounds, left, top);
+
+        let screen = |p: Position| {
+            let (x, y) = tiles::world_pixel(p, self.zoom);
+            (
+                bounds.x as i64 + x as i64 - left,
+                bounds.y as i64 + y as i64 - top,
+            )
+        };
+        let points: Vec<(i64, i64)> = self.track.iter().map(|&p| screen(p)).collect();
+        for segment in points.windows(2) {
+            draw_line(canvas, bounds, segment[0], segment[1]);
+        }
+        if let Some(position) = self.position {
+            let (x, y) = screen(position);
+            let inside = (bounds.x as i64..bounds.right() as i64).contains(&x)
+                && (bounds.y as i64..bounds.bottom() as i64).contains(&y);
+            if inside {
+                // black dot on a white halo, visible on any background
+                fill_circle(canvas, x as i16, y as i16, MARKER_RADIUS + 2, Colour::WHITE);
+                fill_circle(canvas, x as i16, y as i16, MARKER_RADIUS, Colour::BLACK);
+            }
+        }
+    }
+
+    fn full_bleed(&self) -> bool {
+        true
+    }
+
+    fn expands(&self) -> bool {
+        true
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = dirty;
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
+
+// two pixels wide so the track stands out from roads, clipped to `clip`
+fn draw_line(canvas: &mut dyn Canvas, clip: Rect, from: (i64, i64), to: (i64, i64)) {
+    let (mut x, mut y) = from;
+    let (dx, dy) = ((to.0 - x).abs(), -(to.1 - y).abs());
+    let outside = from.0.max(to.0) < clip.x as i64
+        || from.0.min(to.0) >= clip.right() as i64
+        || from.1.max(to.1) < clip.y as i64
+        || from.1.min(to.1) >= clip.bottom() as i64;
+    if outside || dx > MAX_SEGMENT || -dy > MAX_SEGMENT {
+        return;
+    }
+    let (sx, sy) = ((to.0 - x).signum(), (to.1 - y).signum());
+    let mut err = dx + dy;
+    loop {
+        for (px, py) in [(x, y), (x + 1, y), (x, y + 1)] {
+            if clip.contains(px as i16, py as i16) {
+                canvas.draw_pixel(px as i16, py as i16, Colour::BLACK);
+            }
+        }
+        if (x, y) == to {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+fn fill_circle(canvas: &mut dyn Canvas, cx: i16, cy: i16, r: i16, colour: Colour) {
+    for dy in -r..=r {
+        for dx in -r..=r {
+            if dx * dx + dy * dy <= r * r {
+                canvas.draw_pixel(cx + dx, cy + dy, colour);
+            }
+        }
+    }
+}
+
+// Offline map around the GNSS position. Arrow keys and swipes pan, I/O (or +/-) zoom,
+// C centres on the position and keeps following it, X clears the track.
+pub struct Map {
+    ui: Ui,
+    status: WidgetId,
+    info: WidgetId,
+    view: WidgetId,
+    follow: bool,
+    // the view has not been on a fix yet
+    waiting_for_fix: bool,
+    partial_refreshes: u32,
+}
+
+impl Map {
+    pub fn launch(ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        let status = ui.add(StatusBar::new(NAME));
+        let info = ui.add(Label::new(""));
+        // tiles on the SD card, see tiles.rs for the layout
+        let tiles = ctx.storage.lock().unwrap().dir(Area::Maps);
+        let view = ui.add(MapView::new(TileStore::new(tiles)));
+        Box::new(Self {
+            ui,
+            status,
+            info,
+            view,
+            follow: true,
+            waiting_for_fix: true,
+            partial_refreshes: 0,
+        })
+    }
+
+    fn view(&mut self) -> &mut MapView {
+        self.ui.get_mut::<MapView>(self.view).unwrap()
+    }
+
+    // pans by half the view in `direction`
+    fn pan(&mut self, direction: Direction) {
+        self.follow = false;
+        let view = self.view();
+        let (w, h) = view.size();
+        match direction {
+            Direction::Up => view.pan(0, -h / 2),
+            Direction::Down => view.pan(0, h / 2),
+            Direction::Left => view.pan(-w / 2, 0),
+            Direction::Right => view.pan(w / 2, 0),
+        }
+    }
+
+    fn zoom_by(&mut self, steps: i8) {
+        let view = self.view();
+        let zoom = view.zoom().saturating_add_signed(steps);
+        view.set_zoom(zoom);
+    }
+
+    // Takes the position and track from the GNSS state, recentres when following.
+    fn refresh(&mut self, ctx: &mut Context) {
+        let gnss = ctx.gnss.lock().unwrap();
+        let position = gnss.position();
+        let track: Vec<Position> = gnss.track().iter().copied().collect();
+        let fix = *gnss.fix();
+        let indicators = if !gnss.enabled() {
+            "GPS off".to_string()
+        } else {
+            match fix.kind {
+                FixKind::None => format!("searching, {} sats", gnss.satellites().len()),
+                FixKind::TwoD => format!("2D, {} sats", fix.satellites_used),
+                FixKind::ThreeD => format!("3D, {} sats", fix.satellites_used),
+            }
+        };
+        drop(gnss);
+
+        let follow = self.follow;
+        let first_fix = self.waiting_for_fix && position.is_some();
+        let view = self.view();
+        view.set_position(position);
+        view.set_track(track);
+        if let Some(p) = position {
+            if first_fix {
+                view.set_zoom(FIX_ZOOM);
+                view.set_centre(p);
+            } else if follow {
+                let (dx, dy) = view.offset_of(p);
+                if dx.abs() > FOLLOW_MARGIN || dy.abs() > FOLLOW_MARGIN {
+                    view.set_centre(p);
+                }
+            }
+        }
+        if first_fix {
+            self.waiting_for_fix = false;
+        }
+
+        let view = self.view();
+        let centre = view.centre();
+        let mut info = format!(
+            "z{} {:.5} {:.5}",
+            view.zoom(),
+            centre.latitude,
+            centre.longitude
+        );
+        let scale = tiles::meters_per_pixel(centre.latitude, view.zoom());
+        info.push_str(&format!("  {:.0} m/px", scale.max(1.0)));
+        if self.follow && position.is_some() {
+            info.push_str("  follow");
+        }
+        self.ui.get_mut::<Label>(self.info).unwrap().set_text(&info);
+        self.ui
+            .get_mut::<StatusBar>(self.status)
+            .unwrap()
+            .set_indicators(&indicators);
+    }
+}
+
+impl App for Map {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.view().reload();
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Key(Key::Up) => self.pan(Direction::Up),
+            Event::Key(Key::Down) => self.pan(Direction::Down),
+            Event::Key(Key::Left) => self.pan(Direction::Left),
+            Event::Key(Key::Right) => self.pan(Direction::Right),
+            // the map follows the finger, so a swipe moves the view the other way
+            Event::Touch(Gesture::Swipe { direction, .. }) => self.pan(match direction {
+                Direction::Up => Direction::Down,
+                Direction::Down => Direction::Up,
+                Direction::Left => Direction::Right,
+                Direction::Right => Direction::Left,
+            }),
+            Event::Key(Key::Char('i' | 'I' | '+' | '=')) => self.zoom_by(1),
+            Event::Key(Key::Char('o' | 'O' | '-')) => self.zoom_by(-1),
+            Event::Key(Key::Char('c' | 'C')) => {
+                self.follow = true;
+                let position = ctx.gnss.lock().unwrap().position();
+                if let Some(p) = position {
+                    self.view().set_centre(p);
+                }
+            }
+            Event::Key(Key::Char('x' | 'X')) => ctx.gnss.lock().unwrap().clear_track(),
+            // tiles that were missing may be on the card just put in
+            Event::Storage(_) => self.view().reload(),
+            Event::Key(Key::Back) => return Transition::Pop,
+            _ => {}
+        }
+        self.refresh(ctx);
+        if self.view().is_dirty() {
+            self.partial_refreshes += 1;
+            if self.partial_refreshes >= FULL_REFRESH_EVERY {
+                self.partial_refreshes = 0;
+                self.ui.invalidate();
+            }
+        }
+        Transition::None
+    }
+}
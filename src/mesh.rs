@@ -0,0 +1,327 @@
+// Meshtastic node state: the channel's text messages, the nodes heard and flood routing.
+// Shared between the radio task, which feeds it received frames and transmits the frames it
+// queues, and the apps on the display task, like the SMS mailbox.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::meshtastic::{
+    self, Channel, Data, Header, MeshError, User, BROADCAST, DEFAULT_HOP_LIMIT, MAX_HOP_LIMIT,
+    PORT_NODE_INFO, PORT_TEXT,
+};
+
+pub const MAX_MESSAGES: usize = 100;
+const MAX_NODES: usize = 64;
+// (sender, packet id) of recent packets, to drop the copies other nodes rebroadcast
+const MAX_SEEN: usize = 64;
+const NODE_INFO_INTERVAL: Duration = Duration::from_secs(3 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Received,
+    Queued,
+    Sent,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshMessage {
+    // packet id, unique per sender
+    pub id: u32,
+    pub from: u32,
+    pub to: u32,
+    pub text: String,
+    pub at: Instant,
+    pub delivery: Delivery,
+    // signal of received messages
+    pub snr_db: Option<f32>,
+    pub hops: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub num: u32,
+    pub long_name: String,
+    pub short_name: String,
+    pub last_heard: Instant,
+    pub snr_db: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshEvent {
+    // a text message arrived, by packet id
+    Message(u32),
+    NodeSeen(u32),
+    // delivery of one of our messages changed
+    Delivery(u32),
+}
+
+// A frame waiting for the radio. Rebroadcasts wait so nodes further away go first.
+pub struct Frame {
+    pub data: Vec<u8>,
+    not_before: Instant,
+    key: (u32, u32),
+    // our own text message, for the delivery status
+    pub message: Option<u32>,
+}
+
+pub struct Mesh {
+    node: u32,
+    user: User,
+    channel: Channel,
+    messages: VecDeque<MeshMessage>,
+    nodes: Vec<Node>,
+    seen: VecDeque<(u32, u32)>,
+    outgoing: VecDeque<Frame>,
+    next_id: u32,
+    last_node_info: Option<Instant>,
+    unread: usize,
+}
+
+impl Mesh {
+    pub fn new(node: u32, long_name: &str, short_name: &str, channel: Channel) -> Self {
+        // packet ids only need to differ from the ones used before a restart
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        Self {
+            node,
+            user: User::new(node, long_name, short_name),
+            channel,
+            messages: VecDeque::new(),
+            nodes: Vec::new(),
+            seen: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            next_id: seed ^ node.rotate_left(16),
+            last_node_info: None,
+            unread: 0,
+        }
+    }
+
+    pub fn node(&self) -> u32 {
+        self.node
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &MeshMessage> {
+        self.messages.iter()
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    pub fn mark_read(&mut self) {
+        self.unread = 0;
+    }
+
+    // short name if the node has sent its info, otherwise its id
+    pub fn name_of(&self, node: u32) -> String {
+        if node == self.node {
+            return self.user.short_name.clone();
+        }
+        match self.nodes.iter().find(|n| n.num == node) {
+            Some(n) if !n.short_name.is_empty() => n.short_name.clone(),
+            _ => meshtastic::node_id(node),
+        }
+    }
+
+    // Queues a text message to `to` (BROADCAST for the whole channel), returns its id.
+    pub fn send_text(&mut self, to: u32, text: &str, now: Instant) -> Result<u32, MeshError> {
+        let id = self.queue(to, Data::text(text), now, true)?;
+        self.push_message(MeshMessage {
+            id,
+            from: self.node,
+            to,
+            text: text.to_string(),
+            at: now,
+            delivery: Delivery::Queued,
+            snr_db: None,
+            hops: None,
+        });
+        Ok(id)
+    }
+
+    // Announces this node now and then so other nodes can show its name.
+    pub fn tick(&mut self, now: Instant) {
+        let due = match self.last_node_info {
+            Some(t) => now.duration_since(t) >= NODE_INFO_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.last_node_info = Some(now);
+            let data = Data {
+                portnum: PORT_NODE_INFO,
+                payload: self.user.encode(),
+                ..Default::default()
+            };
+            if let Err(e) = self.queue(BROADCAST, data, now, false) {
+                log::warn!("node info not sent: {e:?}");
+            }
+        }
+    }
+
+    // The next frame that is due for transmission.
+    pub fn next_frame(&mut self, now: Instant) -> Option<Frame> {
+        let index = self.outgoing.iter().position(|f| f.not_before <= now)?;
+        self.outgoing.remove(index)
+    }
+
+    pub fn sent(&mut self, message: u32, ok: bool) -> Option<MeshEvent> {
+        let m = self
+            .messages
+            .iter_mut()
+            .find(|m| m.id == message && m.from == self.node)?;
+        m.delivery = if ok { Delivery::Sent } else { Delivery::Failed };
+        Some(MeshEvent::Delivery(message))
+    }
+
+    // Handles a frame from the radio: drops duplicates, files what is for us and queues a
+    // rebroadcast while hops remain.
+    pub fn receive(&mut self, frame: &[u8], snr_db: f32, now: Instant) -> Option<MeshEvent> {
+        let (header, payload) = match Header::parse(frame) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                log::warn!("bad mesh frame: {e:?}");
+                return None;
+            }
+        };
+        if header.sender == self.node {
+            return None;
+        }
+        let key = (header.sender, header.id);
+        if self.seen.contains(&key) {
+            // someone else relayed it first, ours is not needed any more
+            self.outgoing
+                .retain(|f| f.key != key || f.message.is_some());
+            return None;
+        }
+        if self.seen.len() == MAX_SEEN {
+            self.seen.pop_front();
+        }
+        self.seen.push_back(key);
+
+        // Relayed before decoding on purpose: like Meshtastic firmware, frames on channels
+        // this node has no key for (which then fail to decode) are passed on all the same.
+        if header.hop_limit > 0 && header.dest != self.node {
+            self.rebroadcast(&header, payload, snr_db, now);
+        }
+
+        let data = meshtastic::decode_payload(&header, &self.channel, payload).ok()?;
+        self.heard(header.sender, snr_db, now);
+        let hops = header.hop_start.checked_sub(header.hop_limit);
+        match data.portnum {
+            PORT_TEXT if header.dest == BROADCAST || header.dest == self.node => {
+                self.push_message(MeshMessage {
+                    id: header.id,
+                    from: header.sender,
+                    to: header.dest,
+                    text: String::from_utf8_lossy(&data.payload).into_owned(),
+                    at: now,
+                    delivery: Delivery::Received,
+                    snr_db: Some(snr_db),
+                    hops,
+                });
+                self.unread += 1;
+                Some(MeshEvent::Message(header.id))
+            }
+            PORT_NODE_INFO => {
+                let user = User::decode(&data.payload).ok()?;
+                let node = self.nodes.iter_mut().find(|n| n.num == header.sender)?;
+                node.long_name = user.long_name;
+                node.short_name = user.short_name;
+                Some(MeshEvent::NodeSeen(header.sender))
+            }
+            _ => None,
+        }
+    }
+
+    // `track` asks for a delivery update once the radio has sent it
+    fn queue(&mut self, to: u32, data: Data, now: Instant, track: bool) -> Result<u32, MeshError> {
+        self.next_id = self.next_id.wrapping_add(1);
+        let id = self.next_id;
+        let header = Header {
+            dest: to,
+            sender: self.node,
+            id,
+            hop_limit: DEFAULT_HOP_LIMIT,
+            hop_start: DEFAULT_HOP_LIMIT,
+            relay_node: self.node as u8,
+            ..Default::default()
+        };
+        let data = meshtastic::encode_packet(&header, &self.channel, &data)?;
+        self.outgoing.push_back(Frame {
+            data,
+            not_before: now,
+            key: (self.node, id),
+            message: track.then_some(id),
+        });
+        Ok(id)
+    }
+
+    fn rebroadcast(&mut self, header: &Header, payload: &[u8], snr_db: f32, now: Instant) {
+        let header = Header {
+            hop_limit: (header.hop_limit - 1).min(MAX_HOP_LIMIT),
+            relay_node: self.node as u8,
+            ..*header
+        };
+        let mut data = Vec::with_capacity(meshtastic::HEADER_LEN + payload.len());
+        header.encode(&mut data);
+        data.extend(payload);
+        // a weak signal means the sender is far away, so this node relays sooner
+        let weight = (snr_db.clamp(-20.0, 10.0) + 20.0) as u64;
+        let jitter = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_micros() % 100) as u64;
+        self.outgoing.push_back(Frame {
+            data,
+            not_before: now + Duration::from_millis(100 + 20 * weight + jitter),
+            key: (header.sender, header.id),
+            message: None,
+        });
+    }
+
+    fn heard(&mut self, num: u32, snr_db: f32, now: Instant) {
+        match self.nodes.iter_mut().find(|n| n.num == num) {
+            Some(node) => {
+                node.last_heard = now;
+                node.snr_db = snr_db;
+            }
+            None => {
+                if self.nodes.len() == MAX_NODES {
+                    // forget the node heard least recently
+                    if let Some(oldest) = self
+                        .nodes
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, n)| n.last_heard)
+                        .map(|(i, _)| i)
+                    {
+                        self.nodes.swap_remove(oldest);
+                    }
+                }
+                self.nodes.push(Node {
+                    num,
+                    long_name: String::new(),
+                    short_name: String::new(),
+                    last_heard: now,
+                    snr_db,
+                });
+            }
+        }
+    }
+
+    fn push_message(&mut self, message: MeshMessage) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+}
@@ -0,0 +1,636 @@
+// Meshtastic over-the-air packet format. A radio packet is a 16 byte little endian header
+// followed by a `Data` protobuf encrypted with AES-CTR under the channel key. Only the few
+// protobuf fields needed for text messages and node info are handled.
+
+use std::fmt::Debug;
+
+use crate::aes::{Aes, BLOCK_LEN};
+use crate::sx1262::{Bandwidth, CodingRate, LoraConfig};
+
+pub const BROADCAST: u32 = 0xFFFF_FFFF;
+pub const HEADER_LEN: usize = 16;
+pub const MAX_PAYLOAD: usize = 255 - HEADER_LEN;
+pub const SYNC_WORD: u8 = 0x2B;
+pub const DEFAULT_HOP_LIMIT: u8 = 3;
+pub const MAX_HOP_LIMIT: u8 = 7;
+
+// key behind the one byte PSK 1 ("AQ==") of the default channel
+pub const DEFAULT_KEY: [u8; 16] = [
+    0xd4, 0xf1, 0xbb, 0x3a, 0x20, 0x29, 0x07, 0x59, 0xf0, 0xbc, 0xff, 0xab, 0xcf, 0x4e, 0x69, 0x01,
+];
+pub const DEFAULT_CHANNEL: &str = "LongFast";
+
+// PortNum values
+pub const PORT_TEXT: u32 = 1;
+pub const PORT_NODE_INFO: u32 = 4;
+
+// header flag bits
+const FLAG_HOP_LIMIT: u8 = 0x07;
+const FLAG_WANT_ACK: u8 = 0x08;
+const FLAG_VIA_MQTT: u8 = 0x10;
+const FLAG_HOP_START_SHIFT: u8 = 5;
+
+// protobuf wire types
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshError {
+    Truncated,
+    Invalid(String),
+}
+impl MeshError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        MeshError::Invalid(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Header {
+    pub dest: u32,
+    pub sender: u32,
+    pub id: u32,
+    pub hop_limit: u8,
+    pub want_ack: bool,
+    pub via_mqtt: bool,
+    // hop limit the packet was first sent with, 0 from old firmware
+    pub hop_start: u8,
+    pub channel: u8,
+    // last byte of the node ids of the intended next hop and the node that relayed it
+    pub next_hop: u8,
+    pub relay_node: u8,
+}
+
+impl Header {
+    pub fn parse(frame: &[u8]) -> Result<(Header, &[u8]), MeshError> {
+        if frame.len() < HEADER_LEN {
+            return Err(MeshError::Truncated);
+        }
+        let word =
+            |i: usize| u32::from_le_bytes([frame[i], frame[i + 1], frame[i + 2], frame[i + 3]]);
+        let flags = frame[12];
+        let header = Header {
+            dest: word(0),
+            sender: word(4),
+            id: word(8),
+            hop_limit: flags & FLAG_HOP_LIMIT,
+            want_ack: flags & FLAG_WANT_ACK != 0,
+            via_mqtt: flags & FLAG_VIA_MQTT != 0,
+            hop_start: flags >> FLAG_HOP_START_SHIFT,
+            channel: frame[13],
+            next_hop: frame[14],
+            relay_node: frame[15],
+        };
+        Ok((header, &frame[HEADER_LEN..]))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.dest.to_le_bytes());
+        out.extend(self.sender.to_le_bytes());
+        out.extend(self.id.to_le_bytes());
+        let mut flags =
+            (self.hop_limit & FLAG_HOP_LIMIT) | (self.hop_start << FLAG_HOP_START_SHIFT);
+        if self.want_ack {
+            flags |= FLAG_WANT_ACK;
+        }
+        if self.via_mqtt {
+            flags |= FLAG_VIA_MQTT;
+        }
+        out.push(flags);
+        out.push(self.channel);
+        out.push(self.next_hop);
+        out.push(self.relay_node);
+    }
+}
+
+// A channel is identified on air only by its one byte hash, the name and key never leave
+// the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channel {
+    pub name: String,
+    // empty for an unencrypted channel
+    key: Vec<u8>,
+}
+
+impl Channel {
+    // `psk` as configured in the apps: empty (no encryption), one byte (an index derived
+    // from the default key) or a full 16 or 32 byte key.
+    pub fn new(name: &str, psk: &[u8]) -> Result<Self, MeshError> {
+        let key = match psk.len() {
+            0 => Vec::new(),
+            1 if psk[0] == 0 => Vec::new(),
+            1 => {
+                let mut key = DEFAULT_KEY.to_vec();
+                key[15] = key[15].wrapping_add(psk[0] - 1);
+                key
+            }
+            16 | 32 => psk.to_vec(),
+            n => return Err(MeshError::Invalid(format!("{n} byte channel key"))),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            key,
+        })
+    }
+
+    pub fn default_channel() -> Self {
+        Self {
+            name: DEFAULT_CHANNEL.to_string(),
+            key: DEFAULT_KEY.to_vec(),
+        }
+    }
+
+    pub fn hash(&self) -> u8 {
+        let xor = |bytes: &[u8]| bytes.iter().fold(0u8, |acc, b| acc ^ b);
+        xor(self.name.as_bytes()) ^ xor(&self.key)
+    }
+
+    // AES-CTR with a nonce of the packet id (64 bit) and the sender, in place. Encryption
+    // and decryption are the same operation.
+    pub fn crypt(&self, sender: u32, id: u32, data: &mut [u8]) {
+        let Some(aes) = Aes::new(&self.key) else {
+            return;
+        };
+        let mut nonce = [0u8; BLOCK_LEN];
+        nonce[..8].copy_from_slice(&(id as u64).to_le_bytes());
+        nonce[8..12].copy_from_slice(&sender.to_le_bytes());
+        aes.ctr(&nonce, data);
+    }
+}
+
+// The `Data` message carried in every packet.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Data {
+    pub portnum: u32,
+    pub payload: Vec<u8>,
+    pub want_response: bool,
+    pub dest: u32,
+    pub source: u32,
+    pub request_id: u32,
+    pub reply_id: u32,
+    pub emoji: u32,
+}
+
+impl Data {
+    pub fn text(text: &str) -> Self {
+        Self {
+            portnum: PORT_TEXT,
+            payload: text.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_varint_field(&mut out, 1, self.portnum as u64);
+        put_bytes_field(&mut out, 2, &self.payload);
+        if self.want_response {
+            put_varint_field(&mut out, 3, 1);
+        }
+        put_fixed32_field(&mut out, 4, self.dest);
+        put_fixed32_field(&mut out, 5, self.source);
+        put_fixed32_field(&mut out, 6, self.request_id);
+        put_fixed32_field(&mut out, 7, self.reply_id);
+        put_fixed32_field(&mut out, 8, self.emoji);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, MeshError> {
+        let mut data = Data::default();
+        for field in Fields::new(bytes) {
+            match field? {
+                (1, Value::Varint(v)) => data.portnum = v as u32,
+                (2, Value::Bytes(b)) => data.payload = b.to_vec(),
+                (3, Value::Varint(v)) => data.want_response = v != 0,
+                (4, Value::Fixed32(v)) => data.dest = v,
+                (5, Value::Fixed32(v)) => data.source = v,
+                (6, Value::Fixed32(v)) => data.request_id = v,
+                (7, Value::Fixed32(v)) => data.reply_id = v,
+                (8, Value::Fixed32(v)) => data.emoji = v,
+                _ => {}
+            }
+        }
+        Ok(data)
+    }
+}
+
+// The `User` message of NODEINFO_APP packets.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct User {
+    // "!" followed by the node number in hex
+    pub id: String,
+    pub long_name: String,
+    pub short_name: String,
+    pub hw_model: u32,
+}
+
+impl User {
+    pub fn new(node: u32, long_name: &str, short_name: &str) -> Self {
+        Self {
+            id: node_id(node),
+            long_name: long_name.to_string(),
+            short_name: short_name.to_string(),
+            // PRIVATE_HW, there is no model number for this firmware
+            hw_model: 255,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_bytes_field(&mut out, 1, self.id.as_bytes());
+        put_bytes_field(&mut out, 2, self.long_name.as_bytes());
+        put_bytes_field(&mut out, 3, self.short_name.as_bytes());
+        put_varint_field(&mut out, 5, self.hw_model as u64);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, MeshError> {
+        let mut user = User::default();
+        let string = |b: &[u8]| String::from_utf8_lossy(b).into_owned();
+        for field in Fields::new(bytes) {
+            match field? {
+                (1, Value::Bytes(b)) => user.id = string(b),
+                (2, Value::Bytes(b)) => user.long_name = string(b),
+                (3, Value::Bytes(b)) => user.short_name = string(b),
+                (5, Value::Varint(v)) => user.hw_model = v as u32,
+                _ => {}
+            }
+        }
+        Ok(user)
+    }
+}
+
+pub fn node_id(node: u32) -> String {
+    format!("!{node:08x}")
+}
+
+// Builds an encrypted radio packet.
+pub fn encode_packet(
+    header: &Header,
+    channel: &Channel,
+    data: &Data,
+) -> Result<Vec<u8>, MeshError> {
+    let mut payload = data.encode();
+    if payload.len() > MAX_PAYLOAD {
+        return Err(MeshError::Invalid(format!(
+            "{} byte payload",
+            payload.len()
+        )));
+    }
+    channel.crypt(header.sender, header.id, &mut payload);
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    let header = Header {
+        channel: channel.hash(),
+        ..*header
+    };
+    header.encode(&mut frame);
+    frame.extend(payload);
+    Ok(frame)
+}
+
+// Decrypts and decodes the payload of a packet on `channel`. A packet on another channel,
+// or one whose key differs, usually fails to decode.
+pub fn decode_payload(
+    header: &Header,
+    channel: &Channel,
+    payload: &[u8],
+) -> Result<Data, MeshError> {
+    if header.channel != channel.hash() {
+        return Err(MeshError::Invalid("other channel".to_string()));
+    }
+    let mut plain = payload.to_vec();
+    channel.crypt(header.sender, header.id, &mut plain);
+    Data::decode(&plain)
+}
+
+// Frequency plan of a region, in Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Us,
+    Eu433,
+    Eu868,
+    Anz,
+}
+
+impl Region {
+    fn band(self) -> (u32, u32) {
+        match self {
+            Region::Us => (902_000_000, 928_000_000),
+            Region::Eu433 => (433_000_000, 434_000_000),
+            Region::Eu868 => (869_400_000, 869_650_000),
+            Region::Anz => (915_000_000, 928_000_000),
+        }
+    }
+
+    // legal limit, the radio tops out at 22 dBm anyway
+    fn max_power_dbm(self) -> i8 {
+        match self {
+            Region::Us | Region::Anz => 30,
+            Region::Eu868 => 27,
+            Region::Eu433 => 12,
+        }
+    }
+}
+
+// The LongFast modem preset on the channel's frequency slot, which is picked by hashing the
+// channel name so nodes agree on it without configuration.
+pub fn lora_config(region: Region, channel: &Channel) -> LoraConfig {
+    let bandwidth = Bandwidth::Khz250;
+    let (start, end) = region.band();
+    let slots = ((end - start) / bandwidth.hz()).max(1);
+    let slot = djb2(&channel.name) % slots;
+    LoraConfig {
+        frequency_hz: start + bandwidth.hz() / 2 + slot * bandwidth.hz(),
+        spreading_factor: 11,
+        bandwidth,
+        coding_rate: CodingRate::Cr4_5,
+        preamble_len: 16,
+        sync_word: SYNC_WORD,
+        tx_power_dbm: region.max_power_dbm().min(22),
+        crc: true,
+        ..LoraConfig::default()
+    }
+}
+
+fn djb2(s: &str) -> u32 {
+    s.bytes().fold(5381u32, |hash, b| {
+        hash.wrapping_mul(33).wrapping_add(b as u32)
+    })
+}
+
+// protobuf encoding, proto3: fields with default values are left out
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_key(out: &mut Vec<u8>, field: u32, wire: u8) {
+    put_varint(out, ((field as u64) << 3) | wire as u64);
+}
+
+fn put_varint_field(out: &mut Vec<u8>, field: u32, value: u64) {
+    if value != 0 {
+        put_key(out, field, WIRE_VARINT);
+        put_varint(out, value);
+    }
+}
+
+fn put_fixed32_field(out: &mut Vec<u8>, field: u32, value: u32) {
+    if value != 0 {
+        put_key(out, field, WIRE_FIXED32);
+        out.extend(value.to_le_bytes());
+    }
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        put_key(out, field, WIRE_LEN);
+        put_varint(out, bytes.len() as u64);
+        out.extend(bytes);
+    }
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    Fixed64,
+    Bytes(&'a [u8]),
+}
+
+// Iterates over the (field number, value) pairs of a message.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64, MeshError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self.bytes.get(self.pos).ok_or(MeshError::Truncated)?;
+            self.pos += 1;
+            value |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(MeshError::Invalid("varint too long".to_string()))
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], MeshError> {
+        let end = self.pos.checked_add(n).ok_or(MeshError::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(MeshError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn field(&mut self) -> Result<(u32, Value<'a>), MeshError> {
+        let key = self.varint()?;
+        let field = (key >> 3) as u32;
+        let value = match (key & 0x07) as u8 {
+            WIRE_VARINT => Value::Varint(self.varint()?),
+            WIRE_FIXED64 => {
+                self.take(8)?;
+                Value::Fixed64
+            }
+            WIRE_LEN => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            WIRE_FIXED32 => {
+                let b = self.take(4)?;
+                Value::Fixed32(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            }
+            wire => return Err(MeshError::Invalid(format!("wire type {wire}"))),
+        };
+        Ok((field, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Value<'a>), MeshError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // stop after the first error
+            self.pos = self.bytes.len();
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header {
+            dest: BROADCAST,
+            sender: 0xdeadbeef,
+            id: 0x1234_5678,
+            hop_limit: DEFAULT_HOP_LIMIT,
+            hop_start: DEFAULT_HOP_LIMIT,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn psk_index() {
+        // "AQ==" is the default key, 2 to 255 are the same key with the last byte bumped
+        assert_eq!(
+            Channel::new(DEFAULT_CHANNEL, &[1]).unwrap(),
+            Channel::default_channel()
+        );
+        let channel = Channel::new("Test", &[10]).unwrap();
+        assert_eq!(channel.key[..15], DEFAULT_KEY[..15]);
+        assert_eq!(channel.key[15], DEFAULT_KEY[15] + 9);
+        // index 0 and an empty PSK are both unencrypted
+        assert!(Channel::new("Open", &[0]).unwrap().key.is_empty());
+        assert!(Channel::new("Open", &[]).unwrap().key.is_empty());
+        assert_eq!(Channel::new("Full", &[3; 32]).unwrap().key, vec![3; 32]);
+        assert!(Channel::new("Bad", &[3; 24]).is_err());
+    }
+
+    #[test]
+    fn channel_hash() {
+        // the well known hash of the default LongFast channel
+        assert_eq!(Channel::default_channel().hash(), 8);
+        // without a key only the name counts
+        let open = Channel::new("AB", &[]).unwrap();
+        assert_eq!(open.hash(), b'A' ^ b'B');
+    }
+
+    #[test]
+    fn nonce_layout() {
+        let channel = Channel::default_channel();
+        let mut keystream = [0u8; 20];
+        channel.crypt(0xdeadbeef, 0x1234_5678, &mut keystream);
+        // packet id as a 64 bit little endian number, sender little endian, block counter
+        let nonce = [
+            0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde, 0, 0, 0, 0,
+        ];
+        let aes = Aes::new(&DEFAULT_KEY).unwrap();
+        let mut first = nonce;
+        aes.encrypt_block(&mut first);
+        assert_eq!(keystream[..BLOCK_LEN], first);
+        let mut second = nonce;
+        second[15] = 1;
+        aes.encrypt_block(&mut second);
+        assert_eq!(keystream[BLOCK_LEN..], second[..4]);
+
+        // no key, no encryption
+        let mut data = *b"plain";
+        Channel::new("Open", &[]).unwrap().crypt(1, 2, &mut data);
+        assert_eq!(&data, b"plain");
+    }
+
+    #[test]
+    fn data_encoding() {
+        assert_eq!(
+            Data::text("hi").encode(),
+            [0x08, 0x01, 0x12, 0x02, b'h', b'i']
+        );
+        let data = Data {
+            portnum: PORT_NODE_INFO,
+            payload: User::new(0xdeadbeef, "Dyna TAC", "DT").encode(),
+            want_response: true,
+            request_id: 300,
+            ..Default::default()
+        };
+        let decoded = Data::decode(&data.encode()).unwrap();
+        assert_eq!(decoded, data);
+        let user = User::decode(&decoded.payload).unwrap();
+        assert_eq!(user.id, "!deadbeef");
+        assert_eq!(user.long_name, "Dyna TAC");
+        assert_eq!(user.short_name, "DT");
+        assert_eq!(user.hw_model, 255);
+
+        // unknown fields are skipped, broken ones are an error
+        let mut bytes = Data::text("hi").encode();
+        bytes.extend([0x49, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(Data::decode(&bytes).unwrap(), Data::text("hi"));
+        assert_eq!(Data::decode(&[0x12, 0x05, b'h']), Err(MeshError::Truncated));
+        assert!(Data::decode(&[0x0b]).is_err());
+    }
+
+    #[test]
+    fn header_layout() {
+        let header = Header {
+            want_ack: true,
+            via_mqtt: true,
+            channel: 8,
+            next_hop: 0x11,
+            relay_node: 0x22,
+            ..header()
+        };
+        let mut bytes = Vec::new();
+        header.encode(&mut bytes);
+        assert_eq!(
+            bytes,
+            [
+                0xff, 0xff, 0xff, 0xff, 0xef, 0xbe, 0xad, 0xde, 0x78, 0x56, 0x34, 0x12, 0x7b, 0x08,
+                0x11, 0x22
+            ]
+        );
+        assert_eq!(Header::parse(&bytes).unwrap(), (header, &[][..]));
+        assert_eq!(Header::parse(&bytes[..15]), Err(MeshError::Truncated));
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let channel = Channel::default_channel();
+        let data = Data::text("Hello mesh");
+        let frame = encode_packet(&header(), &channel, &data).unwrap();
+        let (received, payload) = Header::parse(&frame).unwrap();
+        assert_eq!(received.channel, channel.hash());
+        assert_eq!(received.sender, 0xdeadbeef);
+        // encrypted on air
+        assert_ne!(payload, data.encode());
+        assert_eq!(payload.len(), data.encode().len());
+        assert_eq!(decode_payload(&received, &channel, payload).unwrap(), data);
+
+        // another channel is told apart by its hash
+        let other = Channel::new("MediumSlow", &[1]).unwrap();
+        assert!(decode_payload(&received, &other, payload).is_err());
+        // same hash, different key: the plaintext doesn't come out
+        let mut key = DEFAULT_KEY;
+        key[0] ^= 0x55;
+        key[1] ^= 0x55;
+        let imposter = Channel::new(DEFAULT_CHANNEL, &key).unwrap();
+        assert_eq!(imposter.hash(), channel.hash());
+        assert_ne!(decode_payload(&received, &imposter, payload), Ok(data));
+
+        let long = Data::text(&"x".repeat(MAX_PAYLOAD));
+        assert!(encode_packet(&header(), &channel, &long).is_err());
+    }
+
+    #[test]
+    fn frequency_slot() {
+        let channel = Channel::default_channel();
+        // LongFast's usual frequencies: slot 20 of 104 in the US, the only one in EU868
+        let us = lora_config(Region::Us, &channel);
+        assert_eq!(us.frequency_hz, 906_875_000);
+        assert_eq!(us.bandwidth, Bandwidth::Khz250);
+        assert_eq!(us.spreading_factor, 11);
+        assert_eq!(us.tx_power_dbm, 22);
+        let eu = lora_config(Region::Eu868, &channel);
+        assert_eq!(eu.frequency_hz, 869_525_000);
+        assert_eq!(lora_config(Region::Eu433, &channel).tx_power_dbm, 12);
+        assert_eq!(djb2(""), 5381);
+        assert_eq!(djb2(DEFAULT_CHANNEL) % 104, 19);
+    }
+}
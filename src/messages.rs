@@ -0,0 +1,540 @@
+use std::time::Instant;
+
+use crate::app::{App, Context, Transition};
+use crate::chat;
+use crate::clock::{self, MONTHS};
+use crate::conversations::{Entry, Peer, Source, Status, ThreadKey};
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::mesh::MeshEvent;
+use crate::sms::SmsEvent;
+use crate::storage::StorageError;
+use crate::ui::{Ui, WidgetId};
+use crate::vcard;
+use crate::widget::{Bubble, Conversation, Label, List, StatusBar, TextInput};
+
+pub const NAME: &str = "Messages";
+const VISIBLE_ROWS: usize = 12;
+const CONTACT_ROWS: usize = 10;
+// a long SMS goes out in up to four parts
+const MAX_SMS_TEXT: usize = 4 * 153;
+const MAX_ADDRESS: usize = 40;
+
+// Files messages into the history as the cellular and radio tasks report them, whatever app
+// is open.
+pub fn record(ctx: &mut Context, event: &Event) {
+    let now = clock::unix_now().unwrap_or(0);
+    match event {
+        Event::Sms(SmsEvent::Received(id)) => {
+            let mailbox = ctx.mailbox.lock().unwrap();
+            if let Some(message) = mailbox.message(*id) {
+                ctx.conversations.received_sms(message);
+            }
+        }
+        Event::Sms(SmsEvent::StatusChanged(id)) => {
+            let mailbox = ctx.mailbox.lock().unwrap();
+            if let Some(outgoing) = mailbox.outgoing(*id) {
+                ctx.conversations.sms_status(outgoing);
+            }
+        }
+        Event::Mesh(MeshEvent::Message(id)) => {
+            let mesh = ctx.mesh.lock().unwrap();
+            let own = mesh.node();
+            let message = mesh.messages().find(|m| m.id == *id && m.from != own);
+            if let Some(m) = message {
+                ctx.conversations.mesh_message(m, own, now);
+            }
+        }
+        Event::Mesh(MeshEvent::Delivery(id)) => {
+            let mesh = ctx.mesh.lock().unwrap();
+            let own = mesh.node();
+            let message = mesh.messages().find(|m| m.id == *id && m.from == own);
+            if let Some(m) = message {
+                ctx.conversations.mesh_status(m);
+            }
+        }
+        _ => return,
+    }
+    save(ctx);
+}
+
+// Writes what changed to the card; without one it is kept until a card is mounted.
+fn save(ctx: &mut Context) {
+    let storage = ctx.storage.lock().unwrap();
+    match ctx.conversations.save(&storage) {
+        Ok(()) => release_sms(ctx),
+        Err(StorageError::NotMounted) => {}
+        Err(e) => log::warn!("saving messages failed: {e:?}"),
+    }
+}
+
+// Once on the card, received SMS no longer need their copies on the SIM.
+pub fn release_sms(ctx: &Context) {
+    let mut mailbox = ctx.mailbox.lock().unwrap();
+    let saved: Vec<u32> = mailbox
+        .inbox()
+        .iter()
+        .map(|m| m.id)
+        .filter(|id| ctx.conversations.filed(Source::Inbox(*id)))
+        .collect();
+    for id in saved {
+        mailbox.saved(id);
+    }
+}
+
+// the contact's name, the node's short name or the number
+fn name_of(ctx: &Context, key: &ThreadKey, name: &str) -> String {
+    match key {
+        ThreadKey::Peer(Peer::Mesh(node)) => ctx.mesh.lock().unwrap().name_of(*node),
+        _ => name.to_string(),
+    }
+}
+
+fn is_back(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Key(Key::Back)
+            | Event::Touch(Gesture::Swipe {
+                direction: Direction::Right,
+                ..
+            })
+    )
+}
+
+// index of the list row under a tap
+fn tapped_row(ui: &Ui, list: WidgetId, x: i16, y: i16) -> Option<usize> {
+    if ui.hit(x, y) != Some(list) {
+        return None;
+    }
+    let top = ui.bounds(list)?.y;
+    ui.get::<List>(list)?.index_at(y - top)
+}
+
+// SMS and direct mesh messages by contact, the most recent conversation first. Enter opens
+// the selected one, or starts a new one from the first row.
+pub struct Messages {
+    ui: Ui,
+    status: WidgetId,
+    list: WidgetId,
+    // of the rows after "New message"
+    threads: Vec<ThreadKey>,
+}
+
+impl Messages {
+    pub fn launch(_ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        let status = ui.add(StatusBar::new(NAME));
+        let list = ui.add(List::new(VISIBLE_ROWS));
+        Box::new(Self {
+            ui,
+            status,
+            list,
+            threads: Vec::new(),
+        })
+    }
+
+    fn list(&mut self) -> &mut List {
+        self.ui.get_mut::<List>(self.list).unwrap()
+    }
+
+    fn refresh(&mut self, ctx: &Context) {
+        let threads = ctx.conversations.threads(&ctx.contacts);
+        let mut items = vec!["+ New message".to_string()];
+        for thread in threads.iter() {
+            let mark = if thread.unread > 0 { "* " } else { "" };
+            let name = name_of(ctx, &thread.key, &thread.name);
+            items.push(format!("{mark}{name}: {}", thread.preview));
+        }
+        self.threads = threads.into_iter().map(|t| t.key).collect();
+        self.list().set_items(items);
+        let unread = match ctx.conversations.unread() {
+            0 => String::new(),
+            n => format!("{n} new"),
+        };
+        self.ui
+            .get_mut::<StatusBar>(self.status)
+            .unwrap()
+            .set_indicators(&unread);
+    }
+
+    fn open(&mut self, ctx: &mut Context) -> Transition {
+        match self.list().selected() {
+            Some(0) => Transition::Push(NewMessage::launch(ctx)),
+            Some(i) => match self.threads.get(i - 1) {
+                Some(key) => Transition::Push(ThreadView::launch(key.clone())),
+                None => Transition::None,
+            },
+            None => Transition::None,
+        }
+    }
+}
+
+impl App for Messages {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Key(Key::Up) => self.list().select_previous(),
+            Event::Key(Key::Down) => self.list().select_next(),
+            Event::Key(Key::Enter) | Event::Key(Key::Right) => return self.open(ctx),
+            event if is_back(event) => return Transition::Pop,
+            Event::Touch(Gesture::Tap { x, y }) => {
+                if let Some(i) = tapped_row(&self.ui, self.list, *x, *y) {
+                    self.list().select(i);
+                    return self.open(ctx);
+                }
+            }
+            Event::Touch(Gesture::Swipe { direction, .. }) => match direction {
+                Direction::Up => self.list().select_next(),
+                Direction::Down => self.list().select_previous(),
+                _ => {}
+            },
+            Event::Sms(_) | Event::Mesh(_) | Event::Storage(_) => self.refresh(ctx),
+            _ => {}
+        }
+        Transition::None
+    }
+}
+
+// Picks who a new conversation is with: typing searches the address book, Enter opens the
+// selected contact, or the typed number or mesh node ("!a1b2c3d4") when nothing matches.
+pub struct NewMessage {
+    ui: Ui,
+    input: WidgetId,
+    list: WidgetId,
+    hint: WidgetId,
+    contacts: Vec<u32>,
+}
+
+impl NewMessage {
+    pub fn launch(_ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        ui.add(StatusBar::new("New message"));
+        let mut input = TextInput::new("Name, number or !node");
+        input.set_focused(true);
+        let input = ui.add(input);
+        let list = ui.add(List::new(CONTACT_ROWS));
+        let hint = ui.add(Label::new("Name, number or mesh node"));
+        Box::new(Self {
+            ui,
+            input,
+            list,
+            hint,
+            contacts: Vec::new(),
+        })
+    }
+
+    fn input(&mut self) -> &mut TextInput {
+        self.ui.get_mut::<TextInput>(self.input).unwrap()
+    }
+
+    fn list(&mut self) -> &mut List {
+        self.ui.get_mut::<List>(self.list).unwrap()
+    }
+
+    fn refresh(&mut self, ctx: &Context) {
+        let query = self.input().text().to_string();
+        // contacts without a number or node can't be messaged
+        let contacts: Vec<_> = ctx
+            .contacts
+            .search(&query)
+            .into_iter()
+            .filter(|c| !c.numbers.is_empty() || !c.nodes.is_empty())
+            .collect();
+        let items = contacts.iter().map(|c| c.name.clone()).collect();
+        self.contacts = contacts.iter().map(|c| c.id).collect();
+        self.list().set_items(items);
+    }
+
+    fn open(&mut self, ctx: &mut Context) -> Transition {
+        if let Some(&id) = self.list().selected().and_then(|i| self.contacts.get(i)) {
+            return Transition::Replace(ThreadView::launch(ThreadKey::Contact(id)));
+        }
+        let text = self.input().text().trim().to_string();
+        let peer = if text.starts_with('!') {
+            vcard::parse_node(&text).map(Peer::Mesh)
+        } else {
+            let is_number = text.chars().any(|c| c.is_ascii_digit())
+                && text
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || matches!(c, '+' | ' ' | '-'));
+            is_number.then(|| Peer::Sms(text.clone()))
+        };
+        match peer {
+            Some(peer) => {
+                let key = ctx.conversations.key_for(&peer, &ctx.contacts);
+                Transition::Replace(ThreadView::launch(key))
+            }
+            None => {
+                let hint = format!("{text} is not a number or mesh node");
+                self.ui.get_mut::<Label>(self.hint).unwrap().set_text(&hint);
+                Transition::None
+            }
+        }
+    }
+}
+
+impl App for NewMessage {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Key(Key::Char(c)) if self.input().text().len() < MAX_ADDRESS => {
+                self.input().insert(*c);
+                self.list().select(0);
+            }
+            Event::Key(Key::Backspace) => self.input().backspace(),
+            Event::Key(Key::Up) => self.list().select_previous(),
+            Event::Key(Key::Down) => self.list().select_next(),
+            Event::Key(Key::Enter) => return self.open(ctx),
+            event if is_back(event) => return Transition::Pop,
+            Event::Touch(Gesture::Tap { x, y }) => {
+                if let Some(i) = tapped_row(&self.ui, self.list, *x, *y) {
+                    self.list().select(i);
+                    return self.open(ctx);
+                }
+            }
+            _ => return Transition::None,
+        }
+        self.refresh(ctx);
+        Transition::None
+    }
+}
+
+// "14:05" today, "3 Oct 14:05" before
+fn time_text(time: u64, today: Option<(i32, u8, u8)>) -> String {
+    let Some(t) = Some(time).filter(|&t| t > 0).and_then(clock::local_time) else {
+        return String::new();
+    };
+    if today == Some((t.year, t.month, t.day)) {
+        t.time_text()
+    } else {
+        let month = &MONTHS[(t.month as usize + 11) % 12][..3];
+        format!("{} {month} {}", t.day, t.time_text())
+    }
+}
+
+fn caption(peer: &Peer, entry: &Entry, mixed: bool, today: Option<(i32, u8, u8)>) -> String {
+    let mut parts = vec![time_text(entry.time, today)];
+    // only worth saying when the conversation goes over both
+    if mixed {
+        parts.push(peer.medium().to_string());
+    }
+    parts.push(
+        match entry.status {
+            Status::Unread | Status::Read => "",
+            Status::Sending => "sending",
+            Status::Sent => "sent",
+            Status::Delivered => "delivered",
+            Status::Failed => "not sent",
+        }
+        .to_string(),
+    );
+    parts.retain(|p| !p.is_empty());
+    parts.join(", ")
+}
+
+// One conversation as bubbles above the input line. Typing goes straight into the input, so
+// each key only refreshes that line; Enter sends, Up/Down scroll and Left/Right switch
+// between the contact's numbers and mesh nodes.
+pub struct ThreadView {
+    ui: Ui,
+    status: WidgetId,
+    conversation: WidgetId,
+    input: WidgetId,
+    key: ThreadKey,
+    peers: Vec<Peer>,
+    // where the next message goes
+    via: Option<Peer>,
+}
+
+impl ThreadView {
+    pub fn launch(key: ThreadKey) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        let status = ui.add(StatusBar::new(NAME));
+        let conversation = ui.add(Conversation::new());
+        let mut input = TextInput::new("Message");
+        input.set_focused(true);
+        let input = ui.add(input);
+        Box::new(Self {
+            ui,
+            status,
+            conversation,
+            input,
+            key,
+            peers: Vec::new(),
+            via: None,
+        })
+    }
+
+    fn input(&mut self) -> &mut TextInput {
+        self.ui.get_mut::<TextInput>(self.input).unwrap()
+    }
+
+    fn conversation(&mut self) -> &mut Conversation {
+        self.ui.get_mut::<Conversation>(self.conversation).unwrap()
+    }
+
+    fn refresh(&mut self, ctx: &mut Context) {
+        let thread = ctx.conversations.thread(self.key.clone(), &ctx.contacts);
+        // reading the conversation reads its messages in the mailbox and the mesh too
+        let sources = ctx.conversations.mark_read(&thread.peers);
+        for source in sources.iter() {
+            match source {
+                Source::Inbox(id) => ctx.mailbox.lock().unwrap().mark_read(*id),
+                Source::Mesh(_) => ctx.mesh.lock().unwrap().mark_read(),
+                Source::Outbox(_) => {}
+            }
+        }
+        if !sources.is_empty() {
+            save(ctx);
+        }
+
+        let today = clock::now().map(|t| (t.year, t.month, t.day));
+        let mixed = thread.peers.iter().any(|p| matches!(p, Peer::Sms(_)))
+            && thread.peers.iter().any(|p| matches!(p, Peer::Mesh(_)));
+        let bubbles = ctx
+            .conversations
+            .history(&thread.peers)
+            .into_iter()
+            .map(|(peer, entry)| Bubble {
+                text: entry.text.clone(),
+                caption: caption(peer, entry, mixed, today),
+                outgoing: entry.status.is_outgoing(),
+            })
+            .collect();
+        self.conversation().set_bubbles(bubbles);
+
+        if !self.via.as_ref().is_some_and(|p| thread.peers.contains(p)) {
+            self.via = thread.peers.first().cloned();
+        }
+        self.peers = thread.peers;
+        let name = name_of(ctx, &self.key, &thread.name);
+        self.update_status(&name);
+    }
+
+    fn update_status(&mut self, name: &str) {
+        let via = match &self.via {
+            Some(peer) if self.peers.len() > 1 => format!("{} {}", peer.medium(), peer.address()),
+            Some(peer) => peer.medium().to_string(),
+            None => "no number".to_string(),
+        };
+        let bar = self.ui.get_mut::<StatusBar>(self.status).unwrap();
+        bar.set_title(name);
+        bar.set_indicators(&via);
+    }
+
+    // Moves the next message to another of the contact's numbers and nodes.
+    fn switch(&mut self, ctx: &mut Context, steps: i32) {
+        let Some(current) = self.peers.iter().position(|p| Some(p) == self.via.as_ref()) else {
+            return;
+        };
+        let next = (current as i32 + steps).rem_euclid(self.peers.len() as i32) as usize;
+        self.via = Some(self.peers[next].clone());
+        self.refresh(ctx);
+    }
+
+    fn max_text(&self) -> usize {
+        match self.via {
+            Some(Peer::Mesh(_)) => chat::MAX_TEXT,
+            _ => MAX_SMS_TEXT,
+        }
+    }
+
+    fn send(&mut self, ctx: &mut Context) {
+        let Some(via) = self.via.clone() else {
+            return;
+        };
+        let text = self.input().take();
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let now = clock::unix_now().unwrap_or(0);
+        match via {
+            Peer::Sms(number) => {
+                let mut mailbox = ctx.mailbox.lock().unwrap();
+                let id = mailbox.send(&number, text);
+                if let Some(outgoing) = mailbox.outgoing(id) {
+                    ctx.conversations.sent_sms(outgoing, now);
+                }
+            }
+            Peer::Mesh(node) => {
+                let mut mesh = ctx.mesh.lock().unwrap();
+                match mesh.send_text(node, text, Instant::now()) {
+                    Ok(id) => {
+                        let own = mesh.node();
+                        let message = mesh.messages().find(|m| m.id == id && m.from == own);
+                        if let Some(m) = message {
+                            ctx.conversations.mesh_message(m, own, now);
+                        }
+                    }
+                    Err(e) => log::warn!("mesh message not queued: {e:?}"),
+                }
+            }
+        }
+        save(ctx);
+    }
+}
+
+impl App for ThreadView {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            // only the input line changes, and only its area is refreshed
+            Event::Key(Key::Char(c))
+                if self.input().text().len() + c.len_utf8() <= self.max_text() =>
+            {
+                self.input().insert(*c);
+            }
+            Event::Key(Key::Backspace) => self.input().backspace(),
+            Event::Key(Key::Enter) => {
+                self.send(ctx);
+                self.refresh(ctx);
+            }
+            Event::Key(Key::Up) => self.conversation().scroll_older(),
+            Event::Key(Key::Down) => self.conversation().scroll_newer(),
+            Event::Key(Key::Left) => self.switch(ctx, -1),
+            Event::Key(Key::Right) => self.switch(ctx, 1),
+            event if is_back(event) => return Transition::Pop,
+            Event::Touch(Gesture::Swipe { direction, .. }) => match direction {
+                Direction::Down => self.conversation().scroll_older(),
+                Direction::Up => self.conversation().scroll_newer(),
+                _ => {}
+            },
+            Event::Sms(_) | Event::Mesh(_) | Event::Storage(_) => self.refresh(ctx),
+            _ => {}
+        }
+        Transition::None
+    }
+}
@@ -0,0 +1,682 @@
+// AT command engine for the SIMCom A7682E LTE modem.
+// AT command manual: https://www.simcom.com/product/A7682E.html (A76XX Series AT Command Manual)
+
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::delay::TickType;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::uart::UartDriver;
+
+use crate::timesource;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+const READY_TIMEOUT: Duration = Duration::from_secs(20);
+const READY_PROBE_INTERVAL: Duration = Duration::from_millis(500);
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(5);
+const PWRKEY_PULSE_MS: u32 = 100;
+const CTRL_Z: u8 = 0x1A;
+
+// Lines the modem may send at any time, outside of a command's response.
+const URC_PREFIXES: &[&str] = &[
+    "RING",
+    "+CLIP:",
+    "+CRING:",
+    "+CCWA:",
+    "NO CARRIER",
+    "BUSY",
+    "NO ANSWER",
+    "VOICE CALL:",
+    "+CMTI:",
+    "+CDSI:",
+    "+CREG:",
+    "+CEREG:",
+    "+CGREG:",
+    "+CPIN:",
+    "+CTZV:",
+    "*ATREADY",
+    "SMS DONE",
+    "PB DONE",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModemError {
+    Timeout,
+    // ERROR, +CME ERROR or +CMS ERROR final result
+    Command(String),
+    Transport(String),
+    Unexpected(String),
+}
+impl ModemError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        ModemError::Transport(format!("{:?}", e))
+    }
+}
+
+// Byte stream to the modem, the UART on the device or a scripted fake on the host.
+pub trait Transport {
+    // reads whatever is available, waiting up to `timeout`; 0 means nothing arrived
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, ModemError>;
+    fn write(&mut self, data: &[u8]) -> Result<(), ModemError>;
+    // brackets each command exchange, e.g. to keep the chip awake until the response is in
+    fn set_busy(&mut self, _busy: bool) {}
+}
+
+#[cfg(target_os = "espidf")]
+impl Transport for UartDriver<'_> {
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, ModemError> {
+        let ticks = TickType::new_millis(timeout.as_millis() as u64).ticks();
+        UartDriver::read(self, buf, ticks).map_err(ModemError::from_debug)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ModemError> {
+        let mut written = 0;
+        while written < data.len() {
+            written += UartDriver::write(self, &data[written..]).map_err(ModemError::from_debug)?;
+        }
+        Ok(())
+    }
+}
+
+// Fake modem for host testing: every command written must match the next expected
+// command of the script, which then replies with the scripted text.
+#[cfg(test)]
+pub struct ScriptedTransport {
+    script: VecDeque<(String, String)>,
+    rx: VecDeque<u8>,
+}
+
+#[cfg(test)]
+impl ScriptedTransport {
+    pub fn new() -> Self {
+        Self {
+            script: VecDeque::new(),
+            rx: VecDeque::new(),
+        }
+    }
+
+    pub fn expect(mut self, command: &str, reply: &str) -> Self {
+        self.script
+            .push_back((command.to_string(), reply.to_string()));
+        self
+    }
+
+    // data the modem sends without being asked (URCs)
+    pub fn unsolicited(mut self, data: &str) -> Self {
+        self.rx.extend(data.as_bytes());
+        self
+    }
+
+    pub fn finished(&self) -> bool {
+        self.script.is_empty()
+    }
+}
+
+#[cfg(test)]
+impl Default for ScriptedTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl Transport for ScriptedTransport {
+    fn read(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize, ModemError> {
+        let n = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ModemError> {
+        let written = String::from_utf8_lossy(data);
+        let (expected, reply) = self
+            .script
+            .pop_front()
+            .ok_or_else(|| ModemError::Unexpected(format!("unscripted write {written:?}")))?;
+        if written != expected {
+            return Err(ModemError::Unexpected(format!(
+                "expected {expected:?}, got {written:?}"
+            )));
+        }
+        self.rx.extend(reply.as_bytes());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimStatus {
+    Ready,
+    PinRequired,
+    PukRequired,
+    NotInserted,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Registration {
+    NotRegistered,
+    Home,
+    Searching,
+    Denied,
+    Roaming,
+    Unknown,
+}
+
+impl Registration {
+    fn from_stat(stat: u8) -> Self {
+        match stat {
+            0 => Registration::NotRegistered,
+            1 => Registration::Home,
+            2 => Registration::Searching,
+            3 => Registration::Denied,
+            5 => Registration::Roaming,
+            _ => Registration::Unknown,
+        }
+    }
+
+    pub fn is_registered(&self) -> bool {
+        matches!(self, Registration::Home | Registration::Roaming)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkStatus {
+    pub sim: SimStatus,
+    pub registration: Registration,
+    pub signal_dbm: Option<i16>,
+}
+
+impl NetworkStatus {
+    // 0 to 4 bars for the status bar, None without a network
+    pub fn signal_bars(&self) -> Option<u8> {
+        if !self.registration.is_registered() {
+            return None;
+        }
+        let bars = match self.signal_dbm? {
+            dbm if dbm >= -75 => 4,
+            dbm if dbm >= -85 => 3,
+            dbm if dbm >= -95 => 2,
+            dbm if dbm >= -105 => 1,
+            _ => 0,
+        };
+        Some(bars)
+    }
+}
+
+// the modem's clock as set from NITZ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkTime {
+    // seconds since the Unix epoch
+    pub utc: i64,
+    // of local time, east of UTC
+    pub offset_minutes: i32,
+}
+
+type UrcHandler = Box<dyn FnMut(&str) + Send>;
+
+pub struct Modem<T> {
+    transport: T,
+    rx: Vec<u8>,
+    handlers: Vec<(&'static str, UrcHandler)>,
+}
+
+impl<T> Modem<T>
+where
+    T: Transport,
+{
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            rx: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+
+    // Pulses PWRKEY and waits until the modem answers AT.
+    pub fn power_on<P: OutputPin, D: DelayNs>(
+        &mut self,
+        pwrkey: &mut P,
+        delay: &mut D,
+    ) -> Result<(), ModemError> {
+        pwrkey.set_high().map_err(ModemError::from_debug)?;
+        delay.delay_ms(PWRKEY_PULSE_MS);
+        pwrkey.set_low().map_err(ModemError::from_debug)?;
+        self.wait_ready(READY_TIMEOUT)
+    }
+
+    pub fn power_off(&mut self) -> Result<(), ModemError> {
+        self.command("AT+CPOF", DEFAULT_TIMEOUT).map(|_| ())
+    }
+
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), ModemError> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self.command("AT", READY_PROBE_INTERVAL).is_ok() {
+                return Ok(());
+            }
+        }
+        Err(ModemError::Timeout)
+    }
+
+    // echo off, verbose error codes
+    pub fn init(&mut self) -> Result<(), ModemError> {
+        self.command("ATE0", DEFAULT_TIMEOUT)?;
+        self.command("AT+CMEE=2", DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+
+    // Registers a handler for unsolicited lines starting with `prefix`. Handlers run on
+    // the thread driving the modem, from `command` or `poll`.
+    pub fn subscribe(&mut self, prefix: &'static str, handler: UrcHandler) {
+        self.handlers.push((prefix, handler));
+    }
+
+    // Sends a command and returns its information lines once the final OK arrives.
+    pub fn command(&mut self, command: &str, timeout: Duration) -> Result<Vec<String>, ModemError> {
+        self.transport.set_busy(true);
+        let result = self
+            .transport
+            .write(format!("{command}\r").as_bytes())
+            .and_then(|_| self.read_response(command, Instant::now() + timeout));
+        self.transport.set_busy(false);
+        result
+    }
+
+    // For commands that take a payload after a "> " prompt (e.g. AT+CMGS), the payload is
+    // terminated with Ctrl-Z.
+    pub fn command_with_payload(
+        &mut self,
+        command: &str,
+        payload: &str,
+        timeout: Duration,
+    ) -> Result<Vec<String>, ModemError> {
+        self.transport.set_busy(true);
+        let result = self.exchange_with_payload(command, payload, timeout);
+        self.transport.set_busy(false);
+        result
+    }
+
+    fn exchange_with_payload(
+        &mut self,
+        command: &str,
+        payload: &str,
+        timeout: Duration,
+    ) -> Result<Vec<String>, ModemError> {
+        self.transport.write(format!("{command}\r").as_bytes())?;
+        self.wait_prompt(Instant::now() + PROMPT_TIMEOUT)?;
+        let mut data = payload.as_bytes().to_vec();
+        data.push(CTRL_Z);
+        self.transport.write(&data)?;
+        self.read_response(command, Instant::now() + timeout)
+    }
+
+    // Reads and dispatches unsolicited lines for up to `timeout`.
+    pub fn poll(&mut self, timeout: Duration) -> Result<(), ModemError> {
+        let deadline = Instant::now() + timeout;
+        while let Some(line) = self.read_line(deadline)? {
+            if !self.dispatch(&line) {
+                log::debug!("modem: ignored {line:?}");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sim_status(&mut self) -> Result<SimStatus, ModemError> {
+        match self.command("AT+CPIN?", DEFAULT_TIMEOUT) {
+            Ok(lines) => {
+                let status = find_value(&lines, "+CPIN:").unwrap_or_default();
+                Ok(match status {
+                    "READY" => SimStatus::Ready,
+                    "SIM PIN" => SimStatus::PinRequired,
+                    "SIM PUK" => SimStatus::PukRequired,
+                    _ => SimStatus::Unknown,
+                })
+            }
+            Err(ModemError::Command(e)) if e.contains("not inserted") || e.ends_with(" 10") => {
+                Ok(SimStatus::NotInserted)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // LTE (EPS) registration, falling back to circuit switched registration
+    pub fn registration(&mut self) -> Result<Registration, ModemError> {
+        for (command, prefix) in [("AT+CEREG?", "+CEREG:"), ("AT+CREG?", "+CREG:")] {
+            let lines = self.command(command, DEFAULT_TIMEOUT)?;
+            let stat = find_value(&lines, prefix)
+                .and_then(|v| v.split(',').nth(1))
+                .and_then(|s| s.trim().parse::<u8>().ok())
+                .map(Registration::from_stat)
+                .unwrap_or(Registration::Unknown);
+            if stat.is_registered() {
+                return Ok(stat);
+            }
+            if command == "AT+CREG?" {
+                return Ok(stat);
+            }
+        }
+        Ok(Registration::Unknown)
+    }
+
+    pub fn signal_dbm(&mut self) -> Result<Option<i16>, ModemError> {
+        let lines = self.command("AT+CSQ", DEFAULT_TIMEOUT)?;
+        let rssi = find_value(&lines, "+CSQ:")
+            .and_then(|v| v.split(',').next())
+            .and_then(|s| s.trim().parse::<i16>().ok());
+        // 0..=31 maps to -113..=-51 dBm, 99 is unknown
+        Ok(rssi.filter(|&r| r <= 31).map(|r| -113 + 2 * r))
+    }
+
+    pub fn network_status(&mut self) -> Result<NetworkStatus, ModemError> {
+        let sim = self.sim_status()?;
+        let (registration, signal_dbm) = if sim == SimStatus::Ready {
+            (self.registration()?, self.signal_dbm()?)
+        } else {
+            (Registration::NotRegistered, None)
+        };
+        Ok(NetworkStatus {
+            sim,
+            registration,
+            signal_dbm,
+        })
+    }
+
+    pub fn network_time(&mut self) -> Result<Option<NetworkTime>, ModemError> {
+        let lines = self.command("AT+CCLK?", DEFAULT_TIMEOUT)?;
+        Ok(find_value(&lines, "+CCLK:").and_then(parse_cclk))
+    }
+
+    fn read_response(
+        &mut self,
+        command: &str,
+        deadline: Instant,
+    ) -> Result<Vec<String>, ModemError> {
+        let prefix = response_prefix(command);
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line(deadline)?.ok_or(ModemError::Timeout)?;
+            if line == command {
+                continue; // echo
+            }
+            if line == "OK" {
+                return Ok(lines);
+            }
+            if line == "ERROR" || line.starts_with("+CME ERROR:") || line.starts_with("+CMS ERROR:")
+            {
+                return Err(ModemError::Command(line));
+            }
+            let is_response = prefix.as_deref().is_some_and(|p| line.starts_with(p));
+            if is_response || !self.dispatch(&line) {
+                lines.push(line);
+            }
+        }
+    }
+
+    // Hands a URC to its handler, returns false if the line is not a URC.
+    fn dispatch(&mut self, line: &str) -> bool {
+        if let Some((_, handler)) = self.handlers.iter_mut().find(|(p, _)| line.starts_with(p)) {
+            handler(line);
+            return true;
+        }
+        if URC_PREFIXES.iter().any(|p| line.starts_with(p)) {
+            log::info!("modem: unhandled URC {line:?}");
+            return true;
+        }
+        false
+    }
+
+    fn wait_prompt(&mut self, deadline: Instant) -> Result<(), ModemError> {
+        loop {
+            if let Some(i) = self.rx.windows(2).position(|w| w == b"> ") {
+                // anything before the prompt is unsolicited
+                let before = String::from_utf8_lossy(&self.rx[..i]).to_string();
+                self.rx.drain(..i + 2);
+                for line in before.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                    self.dispatch(line);
+                }
+                return Ok(());
+            }
+            if let Some(line) = self.take_line() {
+                if line.starts_with("+CMS ERROR:")
+                    || line.starts_with("+CME ERROR:")
+                    || line == "ERROR"
+                {
+                    return Err(ModemError::Command(line));
+                }
+                self.dispatch(&line);
+                continue;
+            }
+            if !self.fill(deadline)? {
+                return Err(ModemError::Timeout);
+            }
+        }
+    }
+
+    // Next non-empty line, or None once the deadline passes.
+    fn read_line(&mut self, deadline: Instant) -> Result<Option<String>, ModemError> {
+        loop {
+            if let Some(line) = self.take_line() {
+                return Ok(Some(line));
+            }
+            if !self.fill(deadline)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> Option<String> {
+        while let Some(end) = self.rx.iter().position(|&b| b == b'\n') {
+            let raw: Vec<u8> = self.rx.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&raw).trim().to_string();
+            if !line.is_empty() {
+                return Some(line);
+            }
+        }
+        None
+    }
+
+    // returns false if nothing arrived before the deadline
+    fn fill(&mut self, deadline: Instant) -> Result<bool, ModemError> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        let mut buf = [0u8; 256];
+        let n = self.transport.read(&mut buf, deadline - now)?;
+        self.rx.extend_from_slice(&buf[..n]);
+        Ok(n > 0 || Instant::now() < deadline)
+    }
+}
+
+// "AT+CSQ" -> "+CSQ:", "AT+CPIN?" -> "+CPIN:", "AT+CMGS=12" -> "+CMGS:"
+fn response_prefix(command: &str) -> Option<String> {
+    let name = command.strip_prefix("AT")?;
+    if !name.starts_with('+') && !name.starts_with('*') {
+        return None;
+    }
+    let end = name.find(['=', '?']).unwrap_or(name.len());
+    Some(format!("{}:", &name[..end]))
+}
+
+// "\"24/10/19,14:05:09+04\"": local time and its offset in quarter hours. Two digit years
+// from 70 are 19xx as with POSIX %y, which is where the modem's clock starts before NITZ.
+pub fn parse_cclk(value: &str) -> Option<NetworkTime> {
+    let (date, time) = value.trim().trim_matches('"').split_once(',')?;
+    let mut date = date.split('/').map(|s| s.parse::<u8>().ok());
+    let (yy, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, zone) = time.split_at(time.find(['+', '-'])?);
+    let mut time = time.split(':').map(|s| s.parse::<u8>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    let quarters: i32 = zone.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let year = if yy >= 70 { 1900 } else { 2000 } + yy as i32;
+    let local = timesource::utc_seconds(year, month, day, hour, minute, second);
+    let offset_minutes = quarters * 15;
+    Some(NetworkTime {
+        utc: local - offset_minutes as i64 * 60,
+        offset_minutes,
+    })
+}
+
+// value after "<prefix> " in the first line starting with prefix
+pub fn find_value<'a>(lines: &'a [String], prefix: &str) -> Option<&'a str> {
+    lines
+        .iter()
+        .find_map(|l| l.strip_prefix(prefix))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const SHORT: Duration = Duration::from_millis(50);
+
+    fn modem(script: ScriptedTransport) -> Modem<ScriptedTransport> {
+        Modem::new(script)
+    }
+
+    #[test]
+    fn information_lines() {
+        let mut modem = modem(
+            ScriptedTransport::new()
+                .expect("AT+CSQ\r", "\r\n+CSQ: 20,99\r\n\r\nOK\r\n")
+                .expect("AT+CSQ\r", "\r\n+CSQ: 99,99\r\n\r\nOK\r\n"),
+        );
+        assert_eq!(modem.signal_dbm(), Ok(Some(-73)));
+        assert_eq!(modem.signal_dbm(), Ok(None));
+        assert!(modem.transport.finished());
+    }
+
+    #[test]
+    fn echo_skipped() {
+        let mut modem = modem(
+            ScriptedTransport::new()
+                .expect("ATE0\r", "ATE0\r\r\nOK\r\n")
+                .expect("AT+CMEE=2\r", "\r\nOK\r\n"),
+        );
+        assert_eq!(modem.init(), Ok(()));
+        assert!(modem.transport.finished());
+    }
+
+    #[test]
+    fn urc_dispatch() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handler_seen = seen.clone();
+        let mut modem = modem(
+            ScriptedTransport::new()
+                .unsolicited("\r\n+CMTI: \"SM\",3\r\n")
+                .expect(
+                    "AT+CPIN?\r",
+                    "\r\n+CMTI: \"SM\",4\r\n\r\nRING\r\n\r\n+CPIN: READY\r\n\r\nOK\r\n",
+                ),
+        );
+        modem.subscribe(
+            "+CMTI:",
+            Box::new(move |line| handler_seen.lock().unwrap().push(line.to_string())),
+        );
+        // between commands
+        modem.poll(SHORT).unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["+CMTI: \"SM\",3"]);
+        // in the middle of a response, URCs without a handler are dropped too
+        assert_eq!(
+            modem.command("AT+CPIN?", DEFAULT_TIMEOUT),
+            Ok(vec!["+CPIN: READY".to_string()])
+        );
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn response_prefix_wins_over_urc() {
+        // +CREG: is also a URC, but here it is the answer
+        let mut modem = modem(
+            ScriptedTransport::new()
+                .expect("AT+CEREG?\r", "\r\n+CEREG: 0,4\r\n\r\nOK\r\n")
+                .expect("AT+CREG?\r", "\r\n+CREG: 0,5\r\n\r\nOK\r\n"),
+        );
+        assert_eq!(modem.registration(), Ok(Registration::Roaming));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut modem = modem(ScriptedTransport::new().expect("AT+CCLK?\r", "\r\n+CCLK: "));
+        let start = Instant::now();
+        assert_eq!(modem.command("AT+CCLK?", SHORT), Err(ModemError::Timeout));
+        assert!(start.elapsed() >= SHORT);
+    }
+
+    #[test]
+    fn error_results() {
+        let mut modem = modem(
+            ScriptedTransport::new()
+                .expect("AT+CPIN?\r", "\r\n+CME ERROR: SIM not inserted\r\n")
+                .expect("AT+CPIN?\r", "\r\n+CME ERROR: 10\r\n")
+                .expect("AT+CPOF\r", "\r\n+CME ERROR: operation not allowed\r\n")
+                .expect("AT+XYZ\r", "\r\nERROR\r\n"),
+        );
+        assert_eq!(modem.sim_status(), Ok(SimStatus::NotInserted));
+        assert_eq!(modem.sim_status(), Ok(SimStatus::NotInserted));
+        assert_eq!(
+            modem.power_off(),
+            Err(ModemError::Command(
+                "+CME ERROR: operation not allowed".to_string()
+            ))
+        );
+        assert_eq!(
+            modem.command("AT+XYZ", DEFAULT_TIMEOUT),
+            Err(ModemError::Command("ERROR".to_string()))
+        );
+    }
+
+    #[test]
+    fn payload_after_prompt() {
+        let mut modem = modem(
+            ScriptedTransport::new()
+                .expect("AT+CMGS=12\r", "\r\n> ")
+                .expect("0011000B91\x1a", "\r\n+CMGS: 7\r\n\r\nOK\r\n")
+                .expect("AT+CMGS=12\r", "\r\n+CMS ERROR: 304\r\n"),
+        );
+        assert_eq!(
+            modem.command_with_payload("AT+CMGS=12", "0011000B91", DEFAULT_TIMEOUT),
+            Ok(vec!["+CMGS: 7".to_string()])
+        );
+        assert_eq!(
+            modem.command_with_payload("AT+CMGS=12", "0011000B91", DEFAULT_TIMEOUT),
+            Err(ModemError::Command("+CMS ERROR: 304".to_string()))
+        );
+    }
+
+    #[test]
+    fn unscripted_command() {
+        let mut modem = modem(ScriptedTransport::new().expect("AT\r", "\r\nOK\r\n"));
+        assert!(matches!(
+            modem.command("AT+CSQ", SHORT),
+            Err(ModemError::Unexpected(_))
+        ));
+    }
+
+    #[test]
+    fn network_time() {
+        // 14:05:09 at UTC+1
+        assert_eq!(
+            parse_cclk("\"24/10/19,14:05:09+04\""),
+            Some(NetworkTime {
+                utc: 1_729_343_109,
+                offset_minutes: 60
+            })
+        );
+        assert_eq!(parse_cclk("\"70/01/01,00:00:00-00\"").unwrap().utc, 0);
+        assert_eq!(parse_cclk("\"24/13/19,14:05:09+04\""), None);
+        assert_eq!(parse_cclk("\"24/10/19\""), None);
+    }
+}
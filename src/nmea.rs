@@ -0,0 +1,516 @@
+// NMEA 0183 sentences from the GNSS receiver: GGA, RMC, GSV and GSA, from any talker
+// (GP, GL, GA, GB, GN...). Empty fields parse as None.
+
+use std::fmt::Debug;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NmeaError {
+    Checksum,
+    Invalid(String),
+}
+impl NmeaError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        NmeaError::Invalid(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millis: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+// degrees, north and east positive
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum System {
+    Gps,
+    Glonass,
+    Galileo,
+    Beidou,
+    Qzss,
+    // GN, the combined solution
+    Multiple,
+    Other,
+}
+
+impl System {
+    fn from_talker(talker: &str) -> Self {
+        match talker {
+            "GP" => System::Gps,
+            "GL" => System::Glonass,
+            "GA" => System::Galileo,
+            "GB" | "BD" => System::Beidou,
+            "GQ" => System::Qzss,
+            "GN" => System::Multiple,
+            _ => System::Other,
+        }
+    }
+
+    // NMEA 4.10 system id of GSA / GSV
+    fn from_id(id: u8) -> Self {
+        match id {
+            1 => System::Gps,
+            2 => System::Glonass,
+            3 => System::Galileo,
+            4 => System::Beidou,
+            5 => System::Qzss,
+            _ => System::Other,
+        }
+    }
+}
+
+// fix data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gga {
+    pub time: Option<Time>,
+    pub position: Option<Position>,
+    // 0 no fix, 1 GPS, 2 differential, 6 dead reckoning...
+    pub quality: u8,
+    pub satellites: u8,
+    pub hdop: Option<f32>,
+    // above mean sea level
+    pub altitude_m: Option<f32>,
+}
+
+// recommended minimum data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rmc {
+    pub time: Option<Time>,
+    pub valid: bool,
+    pub position: Option<Position>,
+    pub speed_knots: Option<f32>,
+    pub course_deg: Option<f32>,
+    pub date: Option<Date>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SatelliteInView {
+    pub prn: u16,
+    pub elevation_deg: Option<u8>,
+    pub azimuth_deg: Option<u16>,
+    // C/N0 in dB-Hz, None while not tracked
+    pub snr: Option<u8>,
+}
+
+// satellites in view, one of `total` sentences
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gsv {
+    pub system: System,
+    pub total: u8,
+    pub number: u8,
+    pub in_view: u8,
+    pub satellites: Vec<SatelliteInView>,
+}
+
+// DOP and the satellites used in the solution
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gsa {
+    pub system: System,
+    // 1 no fix, 2 2D, 3 3D
+    pub fix_type: u8,
+    pub prns: Vec<u16>,
+    pub pdop: Option<f32>,
+    pub hdop: Option<f32>,
+    pub vdop: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsv(Gsv),
+    Gsa(Gsa),
+    // any other sentence, by its formatter ("VTG", "TXT"...)
+    Other(String),
+}
+
+// Parses one sentence, "$GPGGA,...*hh" with or without the line ending.
+pub fn parse(line: &str) -> Result<Sentence, NmeaError> {
+    let body = verify(line.trim_end())?;
+    let mut fields = body.split(',');
+    let address = fields.next().unwrap_or_default();
+    if address.len() != 5 || !address.is_ascii() {
+        return Err(NmeaError::Invalid(format!("address {address}")));
+    }
+    let (talker, formatter) = address.split_at(2);
+    let fields: Vec<&str> = fields.collect();
+    let field = |i: usize| fields.get(i).copied().unwrap_or("");
+    let sentence = match formatter {
+        "GGA" => Sentence::Gga(Gga {
+            time: time(field(0))?,
+            position: position(field(1), field(2), field(3), field(4))?,
+            quality: number(field(5))?.unwrap_or(0),
+            satellites: number(field(6))?.unwrap_or(0),
+            hdop: number(field(7))?,
+            altitude_m: number(field(8))?,
+        }),
+        "RMC" => Sentence::Rmc(Rmc {
+            time: time(field(0))?,
+            valid: field(1) == "A",
+            position: position(field(2), field(3), field(4), field(5))?,
+            speed_knots: number(field(6))?,
+            course_deg: number(field(7))?,
+            date: date(field(8))?,
+        }),
+        "GSV" => {
+            let mut satellites = Vec::new();
+            let mut i = 3;
+            // blocks of four fields, an optional signal id may follow the last one
+            while i + 3 < fields.len() || (i + 3 == fields.len() && !field(i).is_empty()) {
+                if let Some(prn) = number(field(i))? {
+                    satellites.push(SatelliteInView {
+                        prn,
+                        elevation_deg: number(field(i + 1))?,
+                        azimuth_deg: number(field(i + 2))?,
+                        snr: number(field(i + 3))?,
+                    });
+                }
+                i += 4;
+            }
+            Sentence::Gsv(Gsv {
+                system: System::from_talker(talker),
+                total: number(field(0))?.unwrap_or(0),
+                number: number(field(1))?.unwrap_or(0),
+                in_view: number(field(2))?.unwrap_or(0),
+                satellites,
+            })
+        }
+        "GSA" => {
+            let mut prns = Vec::new();
+            for i in 2..14 {
+                if let Some(prn) = number(field(i))? {
+                    prns.push(prn);
+                }
+            }
+            // the system id field only exists from NMEA 4.10 on
+            let system = match number::<u8>(field(17))? {
+                Some(id) => System::from_id(id),
+                None => System::from_talker(talker),
+            };
+            Sentence::Gsa(Gsa {
+                system,
+                fix_type: number(field(1))?.unwrap_or(1),
+                prns,
+                pdop: number(field(14))?,
+                hdop: number(field(15))?,
+                vdop: number(field(16))?,
+            })
+        }
+        other => Sentence::Other(other.to_string()),
+    };
+    Ok(sentence)
+}
+
+// Checks the framing and checksum, returns what is between '$' and '*'.
+fn verify(line: &str) -> Result<&str, NmeaError> {
+    let body = line
+        .strip_prefix('$')
+        .ok_or_else(|| NmeaError::Invalid("no '$'".to_string()))?;
+    let (body, checksum) = body
+        .rsplit_once('*')
+        .ok_or_else(|| NmeaError::Invalid("no checksum".to_string()))?;
+    let expected = u8::from_str_radix(checksum, 16).map_err(NmeaError::from_debug)?;
+    if body.bytes().fold(0u8, |acc, b| acc ^ b) != expected {
+        return Err(NmeaError::Checksum);
+    }
+    Ok(body)
+}
+
+// Builds a sentence with its checksum, e.g. for proprietary commands.
+pub fn sentence(body: &str) -> String {
+    let checksum = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    format!("${body}*{checksum:02X}\r\n")
+}
+
+fn number<T: std::str::FromStr>(field: &str) -> Result<Option<T>, NmeaError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    field
+        .parse()
+        .map(Some)
+        .map_err(|_| NmeaError::Invalid(format!("number {field}")))
+}
+
+// hhmmss.sss
+fn time(field: &str) -> Result<Option<Time>, NmeaError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || NmeaError::Invalid(format!("time {field}"));
+    if field.len() < 6 || !field.is_ascii() {
+        return Err(invalid());
+    }
+    let part = |range: std::ops::Range<usize>| field[range].parse::<u8>().map_err(|_| invalid());
+    let millis = match field.get(6..) {
+        Some(fraction) if fraction.len() > 1 => {
+            let digits = &fraction[1..fraction.len().min(4)];
+            let value: u16 = digits.parse().map_err(|_| invalid())?;
+            value * 10u16.pow(3 - digits.len() as u32)
+        }
+        _ => 0,
+    };
+    Ok(Some(Time {
+        hour: part(0..2)?,
+        minute: part(2..4)?,
+        second: part(4..6)?,
+        millis,
+    }))
+}
+
+// ddmmyy
+fn date(field: &str) -> Result<Option<Date>, NmeaError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || NmeaError::Invalid(format!("date {field}"));
+    if field.len() != 6 || !field.is_ascii() {
+        return Err(invalid());
+    }
+    let part = |range: std::ops::Range<usize>| field[range].parse::<u8>().map_err(|_| invalid());
+    Ok(Some(Date {
+        day: part(0..2)?,
+        month: part(2..4)?,
+        year: 2000 + part(4..6)? as u16,
+    }))
+}
+
+// ddmm.mmmm,N,dddmm.mmmm,E
+fn position(lat: &str, ns: &str, lon: &str, ew: &str) -> Result<Option<Position>, NmeaError> {
+    if lat.is_empty() || lon.is_empty() {
+        return Ok(None);
+    }
+    let mut latitude = degrees(lat, 2)?;
+    let mut longitude = degrees(lon, 3)?;
+    if ns == "S" {
+        latitude = -latitude;
+    }
+    if ew == "W" {
+        longitude = -longitude;
+    }
+    Ok(Some(Position {
+        latitude,
+        longitude,
+    }))
+}
+
+fn degrees(field: &str, degree_digits: usize) -> Result<f64, NmeaError> {
+    let invalid = || NmeaError::Invalid(format!("coordinate {field}"));
+    if field.len() < degree_digits || !field.is_ascii() {
+        return Err(invalid());
+    }
+    let (deg, min) = field.split_at(degree_digits);
+    let deg: f64 = deg.parse().map_err(|_| invalid())?;
+    let min: f64 = min.parse().map_err(|_| invalid())?;
+    Ok(deg + min / 60.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one epoch recorded from the MIA-M10Q, NMEA 4.11
+    const LOG: &str = "\
+$GNRMC,083559.00,A,5228.54810,N,01324.11822,E,0.012,,191026,,,A,V*11\r
+$GNGGA,083559.00,5228.54810,N,01324.11822,E,1,12,0.71,48.3,M,39.6,M,,*7F\r
+$GNGSA,A,3,02,12,25,29,32,,,,,,,,1.32,0.71,1.11,1*0A\r
+$GPGSV,3,1,11,02,35,296,42,05,12,041,,11,08,325,31,12,64,089,47,1*67\r
+$GPGSV,3,3,11,29,19,146,38,31,05,203,,32,42,249,44,1*51\r
+$GAGSV,1,1,00,7*73\r
+";
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    fn recorded() -> Vec<Sentence> {
+        LOG.lines().map(|line| parse(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn recorded_rmc() {
+        let Sentence::Rmc(rmc) = &recorded()[0] else {
+            panic!("not RMC");
+        };
+        assert!(rmc.valid);
+        assert_eq!(
+            rmc.time,
+            Some(Time {
+                hour: 8,
+                minute: 35,
+                second: 59,
+                millis: 0
+            })
+        );
+        assert_eq!(
+            rmc.date,
+            Some(Date {
+                year: 2026,
+                month: 10,
+                day: 19
+            })
+        );
+        let position = rmc.position.unwrap();
+        assert!(close(position.latitude, 52.0 + 28.5481 / 60.0));
+        assert!(close(position.longitude, 13.0 + 24.11822 / 60.0));
+        assert_eq!(rmc.speed_knots, Some(0.012));
+        assert_eq!(rmc.course_deg, None);
+    }
+
+    #[test]
+    fn recorded_gga() {
+        let Sentence::Gga(gga) = &recorded()[1] else {
+            panic!("not GGA");
+        };
+        assert_eq!(gga.quality, 1);
+        assert_eq!(gga.satellites, 12);
+        assert_eq!(gga.hdop, Some(0.71));
+        assert_eq!(gga.altitude_m, Some(48.3));
+        assert!(gga.position.is_some());
+    }
+
+    #[test]
+    fn recorded_gsa() {
+        let Sentence::Gsa(gsa) = &recorded()[2] else {
+            panic!("not GSA");
+        };
+        // GN talker, the system id says which constellation
+        assert_eq!(gsa.system, System::Gps);
+        assert_eq!(gsa.fix_type, 3);
+        assert_eq!(gsa.prns, [2, 12, 25, 29, 32]);
+        assert_eq!(gsa.vdop, Some(1.11));
+    }
+
+    #[test]
+    fn recorded_gsv() {
+        let sentences = recorded();
+        let Sentence::Gsv(first) = &sentences[3] else {
+            panic!("not GSV");
+        };
+        assert_eq!(first.system, System::Gps);
+        assert_eq!((first.total, first.number, first.in_view), (3, 1, 11));
+        assert_eq!(first.satellites.len(), 4);
+        assert_eq!(
+            first.satellites[1],
+            SatelliteInView {
+                prn: 5,
+                elevation_deg: Some(12),
+                azimuth_deg: Some(41),
+                snr: None
+            }
+        );
+        // the last sentence has fewer blocks, the signal id is not a satellite
+        let Sentence::Gsv(last) = &sentences[4] else {
+            panic!("not GSV");
+        };
+        assert_eq!(last.satellites.len(), 3);
+        assert_eq!(last.satellites[2].prn, 32);
+        assert_eq!(last.satellites[2].snr, Some(44));
+        let Sentence::Gsv(galileo) = &sentences[5] else {
+            panic!("not GSV");
+        };
+        assert_eq!(galileo.system, System::Galileo);
+        assert!(galileo.satellites.is_empty());
+    }
+
+    #[test]
+    fn nmea_3_sentences() {
+        // no signal id and no mode field
+        let gsv = "$GPGSV,2,1,08,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45*75";
+        let Sentence::Gsv(gsv) = parse(gsv).unwrap() else {
+            panic!("not GSV");
+        };
+        assert_eq!(gsv.satellites.len(), 4);
+        assert_eq!(gsv.satellites[3].snr, Some(45));
+        let rmc = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+        let Sentence::Rmc(rmc) = parse(rmc).unwrap() else {
+            panic!("not RMC");
+        };
+        assert_eq!(rmc.date.map(|date| (date.day, date.month)), Some((23, 3)));
+        assert_eq!(rmc.course_deg, Some(84.4));
+    }
+
+    #[test]
+    fn empty_fields() {
+        // no fix yet
+        let Sentence::Gga(gga) = parse("$GPGGA,,,,,,0,00,99.99,,,,,,*48").unwrap() else {
+            panic!("not GGA");
+        };
+        assert_eq!(gga.time, None);
+        assert_eq!(gga.position, None);
+        assert_eq!(gga.quality, 0);
+        assert_eq!(gga.hdop, Some(99.99));
+        assert_eq!(gga.altitude_m, None);
+        let Sentence::Rmc(rmc) = parse("$GPRMC,,V,,,,,,,,,,N*53").unwrap() else {
+            panic!("not RMC");
+        };
+        assert!(!rmc.valid);
+        assert_eq!(rmc.time, None);
+        assert_eq!(rmc.date, None);
+    }
+
+    #[test]
+    fn missing_fields() {
+        // a short sentence with a good checksum: the fields it lacks are empty
+        let Sentence::Rmc(rmc) = parse("$GPRMC,235959.5,V*07").unwrap() else {
+            panic!("not RMC");
+        };
+        assert_eq!(rmc.time.unwrap().millis, 500);
+        assert_eq!(rmc.position, None);
+        assert_eq!(rmc.speed_knots, None);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let line = "$GNGGA,083559.00,5228.54810,N,01324.11822,E,1,12,0.71,48.3,M,39.6,M,,*7E";
+        assert_eq!(parse(line), Err(NmeaError::Checksum));
+        // one changed digit
+        let line = "$GNGGA,083559.00,5228.54810,N,01324.11822,E,1,13,0.71,48.3,M,39.6,M,,*7F";
+        assert_eq!(parse(line), Err(NmeaError::Checksum));
+    }
+
+    #[test]
+    fn truncated() {
+        let line = LOG.lines().nth(1).unwrap();
+        for len in [0, 1, 6, 30, line.len() - 4] {
+            assert!(parse(&line[..len]).is_err(), "{}", &line[..len]);
+        }
+        // cut inside the checksum
+        assert!(parse(&line[..line.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let line = sentence("GPGGA,12a519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,");
+        assert!(matches!(parse(&line), Err(NmeaError::Invalid(_))));
+        let line = sentence("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,2303,003.1,W");
+        assert!(matches!(parse(&line), Err(NmeaError::Invalid(_))));
+        // address too short
+        assert!(matches!(
+            parse(&sentence("GPGG,1")),
+            Err(NmeaError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn built_sentence() {
+        let line = sentence("GPTXT,01,01,02,ANTSTATUS=OK");
+        assert_eq!(line, "$GPTXT,01,01,02,ANTSTATUS=OK*3B\r\n");
+        assert_eq!(parse(&line), Ok(Sentence::Other("TXT".to_string())));
+    }
+}
@@ -0,0 +1,555 @@
+// Notifications: one queue for everything that wants the user's attention (SMS, calls, LoRa
+// messages, the battery), fed by the display task from the other tasks' events. A priority
+// decides how each is announced: low ones only count towards the launcher's badges, the rest
+// are shown as a toast over the open app and beep and buzz, more insistently the higher they
+// are. Repeats of the same thing (messages from one sender, the battery running lower) update
+// the notification that is already pending instead of adding another.
+//
+// Pending notifications are dismissed when their app is opened. They are kept on the SD card
+// in system/notifications.txt, one per line:
+// "<id>\t<kind>\t<priority>\t<count>\t<unix seconds>\t<key>\t<title>\t<text>", escaped like
+// the message history.
+
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::app::{Context, Transition};
+use crate::audio::{Alert, Tone};
+use crate::battery::BatteryStatus;
+use crate::call::CallState;
+use crate::chat;
+use crate::clock;
+use crate::conversations::{self, Peer};
+use crate::dialer;
+use crate::event::Event;
+use crate::mesh::MeshEvent;
+use crate::meshtastic::{self, BROADCAST};
+use crate::messages;
+use crate::sms::SmsEvent;
+use crate::storage::{Area, Storage, StorageError};
+use crate::vcard;
+
+const FILE: &str = "notifications.txt";
+const MAX_PENDING: usize = 50;
+// repeats within this long of the last announcement are not announced again, e.g. the parts
+// of a long message or a busy channel
+const QUIET_PERIOD: Duration = Duration::from_secs(30);
+const CRITICAL_PERCENT: u8 = 5;
+
+// vibration patterns, (on, off) milliseconds
+const SHORT_BUZZ: [(u32, u32); 1] = [(150, 0)];
+const DOUBLE_BUZZ: [(u32, u32); 2] = [(200, 150), (200, 0)];
+const LONG_BUZZ: [(u32, u32); 3] = [(600, 300), (600, 300), (600, 0)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    // badge only
+    Low,
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    fn code(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Priority> {
+        Some(match code {
+            "low" => Priority::Low,
+            "normal" => Priority::Normal,
+            "high" => Priority::High,
+            "urgent" => Priority::Urgent,
+            _ => return None,
+        })
+    }
+
+    fn buzz(self) -> &'static [(u32, u32)] {
+        match self {
+            Priority::Low => &[],
+            Priority::Normal => &SHORT_BUZZ,
+            Priority::High => &DOUBLE_BUZZ,
+            Priority::Urgent => &LONG_BUZZ,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Sms,
+    // direct Meshtastic message
+    Mesh,
+    // broadcast on the Meshtastic channel
+    Channel,
+    // ringing now
+    Call,
+    MissedCall,
+    Battery,
+}
+
+impl Kind {
+    fn code(self) -> &'static str {
+        match self {
+            Kind::Sms => "sms",
+            Kind::Mesh => "mesh",
+            Kind::Channel => "channel",
+            Kind::Call => "call",
+            Kind::MissedCall => "missed",
+            Kind::Battery => "battery",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Kind> {
+        Some(match code {
+            "sms" => Kind::Sms,
+            "mesh" => Kind::Mesh,
+            "channel" => Kind::Channel,
+            "call" => Kind::Call,
+            "missed" => Kind::MissedCall,
+            "battery" => Kind::Battery,
+            _ => return None,
+        })
+    }
+
+    // the app that shows what it is about, and dismisses it when opened
+    pub fn app(self) -> Option<&'static str> {
+        match self {
+            Kind::Sms | Kind::Mesh => Some(messages::NAME),
+            Kind::Channel => Some(chat::NAME),
+            Kind::Call | Kind::MissedCall => Some(dialer::NAME),
+            Kind::Battery => None,
+        }
+    }
+
+    // repeats add up ("3 messages") rather than replace each other
+    fn counts(self) -> bool {
+        matches!(
+            self,
+            Kind::Sms | Kind::Mesh | Kind::Channel | Kind::MissedCall
+        )
+    }
+
+    // messages show up in their app as they come, nothing to announce while it is open
+    fn quiet_in_app(self) -> bool {
+        matches!(self, Kind::Sms | Kind::Mesh | Kind::Channel)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    // assigned by `Notifications`
+    pub id: u32,
+    pub kind: Kind,
+    pub priority: Priority,
+    // what repeats share, e.g. the sender's number
+    pub key: String,
+    pub title: String,
+    pub text: String,
+    // times posted since it was last dismissed
+    pub count: u32,
+    // unix seconds of the latest, 0 without the time
+    pub time: u64,
+    // when it last beeped or buzzed, not kept across restarts
+    alerted: Option<Instant>,
+}
+
+impl Notification {
+    pub fn new(kind: Kind, priority: Priority, key: &str, title: &str, text: &str) -> Self {
+        Self {
+            id: 0,
+            kind,
+            priority,
+            key: key.to_string(),
+            title: title.to_string(),
+            text: text.to_string(),
+            count: 1,
+            time: 0,
+            alerted: None,
+        }
+    }
+
+    // "Alice (3)"
+    pub fn heading(&self) -> String {
+        if self.count > 1 {
+            format!("{} ({})", self.title, self.count)
+        } else {
+            self.title.clone()
+        }
+    }
+
+    fn is_same(&self, other: &Notification) -> bool {
+        self.kind == other.kind && self.key == other.key
+    }
+}
+
+#[derive(Default)]
+pub struct Notifications {
+    // newest first
+    pending: Vec<Notification>,
+    next_id: u32,
+    // changed since the file was last written
+    unsaved: bool,
+    // to the alert task
+    alerts: Option<Sender<Alert>>,
+    // number of the call ringing now ("" when withheld), for a missed call
+    ringing: Option<String>,
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Alerts are played by the task at the other end; without one notifications are silent.
+    pub fn set_alerts(&mut self, alerts: Sender<Alert>) {
+        self.alerts = Some(alerts);
+    }
+
+    // Adds a notification or updates the pending one with the same kind and key. Returns it
+    // when it should be announced: it is new or says something new, isn't low priority and
+    // wasn't announced moments ago.
+    pub fn post(
+        &mut self,
+        mut notification: Notification,
+        time: u64,
+        now: Instant,
+    ) -> Option<&Notification> {
+        notification.time = time;
+        match self.pending.iter().position(|n| n.is_same(&notification)) {
+            Some(i) => {
+                let previous = self.pending.remove(i);
+                let unchanged = previous.title == notification.title
+                    && previous.text == notification.text
+                    && previous.priority == notification.priority;
+                if unchanged && !notification.kind.counts() {
+                    self.pending.insert(i, previous);
+                    return None;
+                }
+                notification.id = previous.id;
+                notification.alerted = previous.alerted;
+                if notification.kind.counts() {
+                    notification.count = previous.count + 1;
+                }
+            }
+            None => {
+                self.next_id += 1;
+                notification.id = self.next_id;
+            }
+        }
+        let quiet = notification
+            .alerted
+            .is_some_and(|t| now.saturating_duration_since(t) < QUIET_PERIOD);
+        let announce = !quiet && notification.priority > Priority::Low;
+        if announce {
+            notification.alerted = Some(now);
+        }
+        self.pending.insert(0, notification);
+        self.trim();
+        self.unsaved = true;
+        if announce {
+            self.pending.first()
+        } else {
+            None
+        }
+    }
+
+    // drops the least important, oldest first, beyond MAX_PENDING
+    fn trim(&mut self) {
+        while self.pending.len() > MAX_PENDING {
+            let lowest = self.pending.iter().map(|n| n.priority).min();
+            let Some(i) = self
+                .pending
+                .iter()
+                .rposition(|n| Some(n.priority) == lowest)
+            else {
+                break;
+            };
+            self.pending.remove(i);
+        }
+    }
+
+    pub fn dismiss(&mut self, kind: Kind, key: &str) {
+        let len = self.pending.len();
+        self.pending.retain(|n| n.kind != kind || n.key != key);
+        self.unsaved |= self.pending.len() != len;
+    }
+
+    // everything the app shows, e.g. when it is opened
+    pub fn dismiss_app(&mut self, app: &str) {
+        let len = self.pending.len();
+        self.pending.retain(|n| n.kind.app() != Some(app));
+        self.unsaved |= self.pending.len() != len;
+    }
+
+    // how many things are waiting in the app, for its badge
+    pub fn badge(&self, app: &str) -> u32 {
+        self.pending
+            .iter()
+            .filter(|n| n.kind.app() == Some(app))
+            .map(|n| n.count)
+            .sum()
+    }
+
+    fn alert(&self, priority: Priority, sound: bool, vibration: bool) {
+        let Some(alerts) = self.alerts.as_ref() else {
+            return;
+        };
+        let alert = Alert {
+            tone: sound.then_some(Tone::Beep),
+            buzz: if vibration { priority.buzz() } else { &[] },
+        };
+        if alert.tone.is_none() && alert.buzz.is_empty() {
+            return;
+        }
+        if alerts.send(alert).is_err() {
+            log::warn!("alert task has gone");
+        }
+    }
+
+    // Reads the notifications pending before a restart from a newly mounted card; those
+    // posted since are kept over them.
+    pub fn load(&mut self, storage: &Storage) -> Result<(), StorageError> {
+        let mut loaded = match storage.read(Area::System, FILE) {
+            Ok(data) => parse(&String::from_utf8_lossy(&data)),
+            Err(StorageError::NotFound(_)) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        loaded.retain(|l| !self.pending.iter().any(|n| n.is_same(l)));
+        self.pending.append(&mut loaded);
+        self.pending.sort_by_key(|n| std::cmp::Reverse(n.time));
+        self.trim();
+        // ids from the file may clash with those given out since
+        for (i, notification) in self.pending.iter_mut().enumerate() {
+            notification.id = i as u32 + 1;
+        }
+        self.next_id = self.pending.len() as u32;
+        log::info!("{} notifications pending", self.pending.len());
+        self.unsaved = true;
+        self.save(storage)
+    }
+
+    pub fn save(&mut self, storage: &Storage) -> Result<(), StorageError> {
+        if !self.unsaved {
+            return Ok(());
+        }
+        storage.write(Area::System, FILE, write(&self.pending).as_bytes())?;
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+fn parse(text: &str) -> Vec<Notification> {
+    let mut notifications = Vec::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.splitn(8, '\t').collect();
+        let [id, kind, priority, count, time, key, title, text] = fields[..] else {
+            continue;
+        };
+        let parsed = (
+            id.parse(),
+            Kind::from_code(kind),
+            Priority::from_code(priority),
+            count.parse(),
+            time.parse(),
+        );
+        let (Ok(id), Some(kind), Some(priority), Ok(count), Ok(time)) = parsed else {
+            log::warn!("ignoring notification line {line:?}");
+            continue;
+        };
+        notifications.push(Notification {
+            id,
+            kind,
+            priority,
+            key: conversations::unescape(key),
+            title: conversations::unescape(title),
+            text: conversations::unescape(text),
+            count,
+            time,
+            alerted: None,
+        });
+    }
+    notifications
+}
+
+fn write(notifications: &[Notification]) -> String {
+    let mut out = String::new();
+    // a call that was ringing is over by the time this is read
+    for n in notifications.iter().filter(|n| n.kind != Kind::Call) {
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            n.id,
+            n.kind.code(),
+            n.priority.code(),
+            n.count,
+            n.time,
+            conversations::escape(&n.key),
+            conversations::escape(&n.title),
+            conversations::escape(&n.text)
+        ));
+    }
+    out
+}
+
+// Turns the other tasks' events into notifications, announcing them with the alert the
+// settings allow. Returns the notification to show as a toast, if any.
+pub fn record(ctx: &mut Context, event: &Event, current: &str) -> Option<Notification> {
+    let notification = match event {
+        Event::Sms(SmsEvent::Received(id)) => {
+            let mailbox = ctx.mailbox.lock().unwrap();
+            // ones read before, listed again from the SIM after a restart
+            let message = mailbox.message(*id).filter(|m| !m.read)?;
+            Notification::new(
+                Kind::Sms,
+                Priority::High,
+                &message.from,
+                &ctx.contacts.name_for(&message.from),
+                &message.text,
+            )
+        }
+        Event::Mesh(MeshEvent::Message(id)) => {
+            let mesh = ctx.mesh.lock().unwrap();
+            let own = mesh.node();
+            let message = mesh.messages().find(|m| m.id == *id && m.from != own)?;
+            let name = ctx
+                .contacts
+                .by_node(message.from)
+                .map_or_else(|| mesh.name_of(message.from), |c| c.name.clone());
+            if message.to == BROADCAST {
+                let text = format!("{name}: {}", message.text);
+                Notification::new(Kind::Channel, Priority::Low, "", chat::NAME, &text)
+            } else if message.to == own {
+                let key = meshtastic::node_id(message.from);
+                Notification::new(Kind::Mesh, Priority::Normal, &key, &name, &message.text)
+            } else {
+                return None;
+            }
+        }
+        Event::Call(state) => call_notification(ctx, *state)?,
+        Event::Battery(status) => battery_notification(ctx, status)?,
+        _ => return None,
+    };
+    if notification.kind.quiet_in_app() && notification.kind.app() == Some(current) {
+        return None;
+    }
+    let (sound, vibration) = {
+        let settings = ctx.settings.lock().unwrap();
+        (settings.alert_sound(), settings.alert_vibration())
+    };
+    let time = clock::unix_now().unwrap_or(0);
+    let posted = ctx
+        .notifications
+        .post(notification, time, Instant::now())
+        .cloned();
+    if let Some(posted) = posted.as_ref() {
+        ctx.notifications.alert(posted.priority, sound, vibration);
+    }
+    save(ctx);
+    posted
+}
+
+// Ringing is urgent, and becomes a missed call if it stops without being answered.
+fn call_notification(ctx: &mut Context, state: CallState) -> Option<Notification> {
+    let number = ctx
+        .calls
+        .lock()
+        .unwrap()
+        .call()
+        .and_then(|c| c.number.clone())
+        .unwrap_or_default();
+    let caller = |number: &str| {
+        if number.is_empty() {
+            "Unknown number".to_string()
+        } else {
+            ctx.contacts.name_for(number)
+        }
+    };
+    match state {
+        CallState::Incoming => {
+            let text = caller(&number);
+            ctx.notifications.ringing = Some(number);
+            Some(Notification::new(
+                Kind::Call,
+                Priority::Urgent,
+                "",
+                "Incoming call",
+                &text,
+            ))
+        }
+        CallState::Idle => {
+            let missed = ctx.notifications.ringing.take();
+            ctx.notifications.dismiss(Kind::Call, "");
+            let missed = missed?;
+            let text = caller(&missed);
+            Some(Notification::new(
+                Kind::MissedCall,
+                Priority::High,
+                &missed,
+                "Missed call",
+                &text,
+            ))
+        }
+        _ => {
+            ctx.notifications.ringing = None;
+            ctx.notifications.dismiss(Kind::Call, "");
+            None
+        }
+    }
+}
+
+// Once when the battery gets low and again when it is nearly empty, gone on the charger.
+fn battery_notification(ctx: &mut Context, status: &BatteryStatus) -> Option<Notification> {
+    if !status.is_low() {
+        ctx.notifications.dismiss(Kind::Battery, "");
+        save(ctx);
+        return None;
+    }
+    let (priority, title) = if status.percent <= CRITICAL_PERCENT || status.empty {
+        (Priority::Urgent, "Battery nearly empty")
+    } else {
+        (Priority::High, "Battery low")
+    };
+    Some(Notification::new(
+        Kind::Battery,
+        priority,
+        "",
+        title,
+        "Connect the charger",
+    ))
+}
+
+// Opens what a notification is about, e.g. when its toast is tapped.
+pub fn open(ctx: &mut Context, notification: &Notification) -> Transition {
+    let peer = match notification.kind {
+        Kind::Sms => Peer::Sms(notification.key.clone()),
+        Kind::Mesh => match vcard::parse_node(&notification.key) {
+            Some(node) => Peer::Mesh(node),
+            None => return Transition::None,
+        },
+        Kind::Channel => return Transition::Push(chat::Chat::launch(ctx)),
+        Kind::Call | Kind::MissedCall => return Transition::Push(dialer::Dialer::launch(ctx)),
+        Kind::Battery => return Transition::None,
+    };
+    let key = ctx.conversations.key_for(&peer, &ctx.contacts);
+    Transition::Push(messages::ThreadView::launch(key))
+}
+
+// Dismisses what the app that just opened shows.
+pub fn seen(ctx: &mut Context, app: &str) {
+    ctx.notifications.dismiss_app(app);
+    save(ctx);
+}
+
+// Without a card notifications are kept in memory until one is mounted.
+fn save(ctx: &mut Context) {
+    let storage = ctx.storage.lock().unwrap();
+    match ctx.notifications.save(&storage) {
+        Ok(()) | Err(StorageError::NotMounted) => {}
+        Err(e) => log::warn!("saving notifications failed: {e:?}"),
+    }
+}
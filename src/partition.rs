@@ -0,0 +1,56 @@
+// Raw data partitions (see partitions.csv). The fallback fonts and the IMU firmware are
+// written into them when flashing and read in place.
+
+use std::ffi::CString;
+use std::fmt::Debug;
+
+use esp_idf_svc::sys;
+
+#[derive(Debug)]
+pub enum PartitionError {
+    NotFound(String),
+    Flash(String),
+}
+impl PartitionError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        PartitionError::Flash(format!("{:?}", e))
+    }
+}
+
+pub struct Partition {
+    partition: *const sys::esp_partition_t,
+}
+
+// the partition table entry is static and reads go through the thread safe flash driver
+unsafe impl Send for Partition {}
+
+impl Partition {
+    // the data partition labelled `label`
+    pub fn open(label: &str) -> Result<Self, PartitionError> {
+        let c_label =
+            CString::new(label).map_err(|_| PartitionError::NotFound(label.to_string()))?;
+        let partition = unsafe {
+            sys::esp_partition_find_first(
+                sys::esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+                sys::esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
+                c_label.as_ptr(),
+            )
+        };
+        if partition.is_null() {
+            return Err(PartitionError::NotFound(label.to_string()));
+        }
+        Ok(Self { partition })
+    }
+
+    pub fn read_at(&self, offset: u32, buf: &mut [u8]) -> Result<(), PartitionError> {
+        sys::esp!(unsafe {
+            sys::esp_partition_read(
+                self.partition,
+                offset as usize,
+                buf.as_mut_ptr() as *mut core::ffi::c_void,
+                buf.len(),
+            )
+        })
+        .map_err(PartitionError::from_debug)
+    }
+}
@@ -0,0 +1,703 @@
+// SMS PDU encoding and decoding (3GPP TS 23.040 / 23.038). Outgoing messages are
+// SMS-SUBMIT, incoming ones SMS-DELIVER or SMS-STATUS-REPORT. PDUs read with AT+CMGR and
+// AT+CMGL start with the SMSC address, PDUs sent with AT+CMGS use the default SMSC.
+
+use std::fmt::Debug;
+
+// GSM 7-bit default alphabet, indexed by septet. 0x1B is the escape to the extension table.
+const GSM7_BASIC: &str = "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞ\u{1B}ÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà";
+const GSM7_ESCAPE: u8 = 0x1B;
+const GSM7_EXTENSION: [(u8, char); 10] = [
+    (0x0A, '\u{0C}'),
+    (0x14, '^'),
+    (0x28, '{'),
+    (0x29, '}'),
+    (0x2F, '\\'),
+    (0x3C, '['),
+    (0x3D, '~'),
+    (0x3E, ']'),
+    (0x40, '|'),
+    (0x65, '€'),
+];
+
+// user data limits, in septets for GSM 7-bit and in bytes for UCS-2
+const GSM7_SINGLE: usize = 160;
+const GSM7_PART: usize = 153;
+const UCS2_SINGLE: usize = 140;
+const UCS2_PART: usize = 134;
+pub const MAX_PARTS: usize = 255;
+
+// first octet bits
+const MTI_MASK: u8 = 0x03;
+const MTI_DELIVER: u8 = 0x00;
+const MTI_SUBMIT: u8 = 0x01;
+const MTI_STATUS_REPORT: u8 = 0x02;
+const VPF_RELATIVE: u8 = 0x10;
+const SRR: u8 = 0x20;
+const UDHI: u8 = 0x40;
+
+const DCS_GSM7: u8 = 0x00;
+const DCS_UCS2: u8 = 0x08;
+// relative validity period of 4 days
+const VALIDITY_4_DAYS: u8 = 0xAA;
+
+const TYPE_INTERNATIONAL: u8 = 0x91;
+const TYPE_UNKNOWN: u8 = 0x81;
+const TON_MASK: u8 = 0x70;
+const TON_ALPHANUMERIC: u8 = 0x50;
+
+// concatenation information elements, 8 and 16 bit reference
+const IE_CONCAT_8: u8 = 0x00;
+const IE_CONCAT_16: u8 = 0x08;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PduError {
+    Truncated,
+    Invalid(String),
+}
+impl PduError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        PduError::Invalid(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    Gsm7,
+    Ucs2,
+    Data,
+}
+
+// Service centre time stamp, local time of the SMSC with its offset from UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    // offset from UTC in quarters of an hour
+    pub tz_quarters: i8,
+}
+
+// part `seq` (1 based) of `total` for the message identified by `reference`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Concat {
+    pub reference: u16,
+    pub total: u8,
+    pub seq: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deliver {
+    pub sender: String,
+    pub timestamp: Timestamp,
+    pub text: String,
+    pub concat: Option<Concat>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusReport {
+    pub reference: u8,
+    pub recipient: String,
+    pub delivered_at: Timestamp,
+    // TP-Status: 0x00..0x1F completed, 0x20..0x3F still trying, 0x40.. failed
+    pub status: u8,
+}
+
+impl StatusReport {
+    pub fn delivered(&self) -> bool {
+        self.status < 0x20
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status >= 0x40
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pdu {
+    Deliver(Deliver),
+    StatusReport(StatusReport),
+    // a sent or unsent message stored on the SIM, the recipient only
+    Submit { recipient: String },
+}
+
+// Encoded SMS-SUBMIT: the hex string for AT+CMGS and the TPDU length it expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmitPdu {
+    pub hex: String,
+    pub length: usize,
+}
+
+pub fn gsm7_char(septet: u8) -> Option<char> {
+    GSM7_BASIC.chars().nth(septet as usize)
+}
+
+// Septets for `c`, two for extension table characters, None if it is not in the alphabet.
+fn gsm7_septets(c: char) -> Option<Vec<u8>> {
+    if c != '\u{1B}' {
+        if let Some(i) = GSM7_BASIC.chars().position(|b| b == c) {
+            return Some(vec![i as u8]);
+        }
+    }
+    GSM7_EXTENSION
+        .iter()
+        .find(|(_, e)| *e == c)
+        .map(|(s, _)| vec![GSM7_ESCAPE, *s])
+}
+
+pub fn is_gsm7(text: &str) -> bool {
+    text.chars().all(|c| gsm7_septets(c).is_some())
+}
+
+pub fn gsm7_decode(septets: &[u8]) -> String {
+    let mut text = String::new();
+    let mut iter = septets.iter();
+    while let Some(&s) = iter.next() {
+        if s == GSM7_ESCAPE {
+            if let Some(&e) = iter.next() {
+                match GSM7_EXTENSION.iter().find(|(x, _)| *x == e) {
+                    Some((_, c)) => text.push(*c),
+                    // unknown extension, shown as its basic table character
+                    None => text.extend(gsm7_char(e)),
+                }
+            }
+        } else {
+            text.extend(gsm7_char(s));
+        }
+    }
+    text
+}
+
+// Packs septets LSB first, starting after `fill_bits` padding bits.
+pub fn pack_septets(septets: &[u8], fill_bits: usize) -> Vec<u8> {
+    let mut out = vec![0u8; (fill_bits + septets.len() * 7).div_ceil(8)];
+    for (i, &s) in septets.iter().enumerate() {
+        let bit = fill_bits + i * 7;
+        let value = ((s & 0x7F) as u16) << (bit % 8);
+        out[bit / 8] |= value as u8;
+        if value > 0xFF {
+            out[bit / 8 + 1] |= (value >> 8) as u8;
+        }
+    }
+    out
+}
+
+pub fn unpack_septets(data: &[u8], fill_bits: usize, count: usize) -> Vec<u8> {
+    (0..count)
+        .map_while(|i| {
+            let bit = fill_bits + i * 7;
+            let low = *data.get(bit / 8)? as u16;
+            let high = data.get(bit / 8 + 1).copied().unwrap_or(0) as u16;
+            Some((((high << 8) | low) >> (bit % 8)) as u8 & 0x7F)
+        })
+        .collect()
+}
+
+// Splits text into SMS-SUBMIT PDUs, concatenated with an 8 bit reference when it does not
+// fit in one message. GSM 7-bit is used when every character is in the alphabet, UCS-2
+// otherwise.
+pub fn encode_submit(
+    recipient: &str,
+    text: &str,
+    reference: u8,
+    request_report: bool,
+) -> Result<Vec<SubmitPdu>, PduError> {
+    let (alphabet, parts) = split(text);
+    if parts.len() > MAX_PARTS {
+        return Err(PduError::Invalid("message too long".to_string()));
+    }
+    let address = encode_address(recipient)?;
+    let total = parts.len();
+    Ok(parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let udh =
+                (total > 1).then(|| vec![5, IE_CONCAT_8, 3, reference, total as u8, (i + 1) as u8]);
+            let mut fo = MTI_SUBMIT | VPF_RELATIVE;
+            if request_report {
+                fo |= SRR;
+            }
+            if udh.is_some() {
+                fo |= UDHI;
+            }
+            let udh = udh.unwrap_or_default();
+            let (dcs, udl, ud) = match alphabet {
+                Alphabet::Gsm7 => {
+                    let fill = (7 - (udh.len() * 8) % 7) % 7;
+                    let udl = (udh.len() * 8 + fill) / 7 + part.len();
+                    let mut ud = udh.clone();
+                    ud.extend(pack_septets(part, fill));
+                    (DCS_GSM7, udl, ud)
+                }
+                _ => {
+                    let mut ud = udh.clone();
+                    ud.extend(part);
+                    (DCS_UCS2, ud.len(), ud)
+                }
+            };
+            // TP-MR 0, the modem assigns the reference and returns it in +CMGS
+            let mut tpdu = vec![fo, 0];
+            tpdu.extend(&address);
+            tpdu.extend([0, dcs, VALIDITY_4_DAYS, udl as u8]);
+            tpdu.extend(ud);
+            SubmitPdu {
+                // "00": no SMSC address, use the one configured on the SIM
+                hex: format!("00{}", to_hex(&tpdu)),
+                length: tpdu.len(),
+            }
+        })
+        .collect())
+}
+
+// Splits text into per-part user data: septets for GSM 7-bit, UTF-16BE bytes for UCS-2.
+// Escape sequences and surrogate pairs are never split across parts.
+fn split(text: &str) -> (Alphabet, Vec<Vec<u8>>) {
+    let (alphabet, units, single, part): (_, Vec<Vec<u8>>, _, _) = if is_gsm7(text) {
+        let units = text.chars().filter_map(gsm7_septets).collect();
+        (Alphabet::Gsm7, units, GSM7_SINGLE, GSM7_PART)
+    } else {
+        let units = text
+            .chars()
+            .map(|c| {
+                let mut buf = [0u16; 2];
+                c.encode_utf16(&mut buf)
+                    .iter()
+                    .flat_map(|u| u.to_be_bytes())
+                    .collect()
+            })
+            .collect();
+        (Alphabet::Ucs2, units, UCS2_SINGLE, UCS2_PART)
+    };
+    let len: usize = units.iter().map(Vec::len).sum();
+    let limit = if len <= single { single } else { part };
+    let mut parts = vec![Vec::new()];
+    for unit in units {
+        if parts.last().unwrap().len() + unit.len() > limit {
+            parts.push(Vec::new());
+        }
+        parts.last_mut().unwrap().extend(unit);
+    }
+    (alphabet, parts)
+}
+
+// Decodes a PDU as read with AT+CMGR or AT+CMGL (SMSC address first).
+pub fn decode(hex: &str) -> Result<Pdu, PduError> {
+    let data = from_hex(hex)?;
+    let mut r = Reader::new(&data);
+    let smsc_len = r.byte()? as usize;
+    r.skip(smsc_len)?;
+    let fo = r.byte()?;
+    match fo & MTI_MASK {
+        MTI_DELIVER => decode_deliver(fo, &mut r).map(Pdu::Deliver),
+        MTI_STATUS_REPORT => {
+            let reference = r.byte()?;
+            let recipient = r.address()?;
+            r.timestamp()?; // service centre time stamp
+            let delivered_at = r.timestamp()?;
+            let status = r.byte()?;
+            Ok(Pdu::StatusReport(StatusReport {
+                reference,
+                recipient,
+                delivered_at,
+                status,
+            }))
+        }
+        MTI_SUBMIT => {
+            r.byte()?; // message reference
+            Ok(Pdu::Submit {
+                recipient: r.address()?,
+            })
+        }
+        mti => Err(PduError::Invalid(format!("message type {mti}"))),
+    }
+}
+
+fn decode_deliver(fo: u8, r: &mut Reader) -> Result<Deliver, PduError> {
+    let sender = r.address()?;
+    r.byte()?; // protocol identifier
+    let alphabet = alphabet(r.byte()?);
+    let timestamp = r.timestamp()?;
+    let udl = r.byte()? as usize;
+    let ud = r.rest();
+
+    let mut concat = None;
+    let mut header_len = 0;
+    if fo & UDHI != 0 {
+        let udhl = *ud.first().ok_or(PduError::Truncated)? as usize;
+        let udh = ud.get(1..1 + udhl).ok_or(PduError::Truncated)?;
+        concat = parse_concat(udh);
+        header_len = 1 + udhl;
+    }
+
+    let text = match alphabet {
+        Alphabet::Gsm7 => {
+            let fill = (7 - (header_len * 8) % 7) % 7;
+            let skip = (header_len * 8 + fill) / 7;
+            let body = ud.get(header_len..).unwrap_or_default();
+            gsm7_decode(&unpack_septets(body, fill, udl.saturating_sub(skip)))
+        }
+        Alphabet::Ucs2 => {
+            let body = ud.get(header_len..udl.min(ud.len())).unwrap_or_default();
+            let units: Vec<u16> = body
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        Alphabet::Data => {
+            let body = ud.get(header_len..udl.min(ud.len())).unwrap_or_default();
+            String::from_utf8_lossy(body).to_string()
+        }
+    };
+    Ok(Deliver {
+        sender,
+        timestamp,
+        text,
+        concat,
+    })
+}
+
+fn alphabet(dcs: u8) -> Alphabet {
+    match dcs >> 4 {
+        // general data coding, possibly compressed or with a message class
+        0x0..=0x3 => match (dcs >> 2) & 0x03 {
+            0 => Alphabet::Gsm7,
+            2 => Alphabet::Ucs2,
+            _ => Alphabet::Data,
+        },
+        // message waiting indication groups
+        0xC | 0xD => Alphabet::Gsm7,
+        0xE => Alphabet::Ucs2,
+        0xF if dcs & 0x04 == 0 => Alphabet::Gsm7,
+        _ => Alphabet::Data,
+    }
+}
+
+fn parse_concat(mut udh: &[u8]) -> Option<Concat> {
+    while udh.len() >= 2 {
+        let (id, len) = (udh[0], udh[1] as usize);
+        let data = udh.get(2..2 + len)?;
+        match (id, data) {
+            (IE_CONCAT_8, &[reference, total, seq]) => {
+                return Some(Concat {
+                    reference: reference as u16,
+                    total,
+                    seq,
+                })
+            }
+            (IE_CONCAT_16, &[hi, lo, total, seq]) => {
+                return Some(Concat {
+                    reference: u16::from_be_bytes([hi, lo]),
+                    total,
+                    seq,
+                })
+            }
+            _ => {}
+        }
+        udh = &udh[2 + len..];
+    }
+    None
+}
+
+fn encode_address(number: &str) -> Result<Vec<u8>, PduError> {
+    let (kind, digits) = match number.strip_prefix('+') {
+        Some(rest) => (TYPE_INTERNATIONAL, rest),
+        None => (TYPE_UNKNOWN, number),
+    };
+    let digits: Vec<u8> = digits
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '(' | ')'))
+        .map(|c| match c {
+            '0'..='9' => Ok(c as u8 - b'0'),
+            '*' => Ok(0xA),
+            '#' => Ok(0xB),
+            _ => Err(PduError::Invalid(format!("bad digit {c:?} in {number:?}"))),
+        })
+        .collect::<Result<_, _>>()?;
+    if digits.is_empty() {
+        return Err(PduError::Invalid("empty number".to_string()));
+    }
+    let mut out = vec![digits.len() as u8, kind];
+    out.extend(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] | (pair.get(1).copied().unwrap_or(0xF) << 4)),
+    );
+    Ok(out)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn byte(&mut self) -> Result<u8, PduError> {
+        let b = *self.data.get(self.pos).ok_or(PduError::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], PduError> {
+        let slice = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(PduError::Truncated)?;
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), PduError> {
+        self.take(n).map(|_| ())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        self.pos = self.data.len();
+        rest
+    }
+
+    // length in semi-octets (digits), type of address, then the digits
+    fn address(&mut self) -> Result<String, PduError> {
+        let digits = self.byte()? as usize;
+        let kind = self.byte()?;
+        let data = self.take(digits.div_ceil(2))?;
+        if kind & TON_MASK == TON_ALPHANUMERIC {
+            return Ok(gsm7_decode(&unpack_septets(data, 0, digits * 4 / 7)));
+        }
+        let mut number = String::new();
+        if kind == TYPE_INTERNATIONAL {
+            number.push('+');
+        }
+        for nibble in data.iter().flat_map(|b| [b & 0x0F, b >> 4]).take(digits) {
+            number.push(match nibble {
+                0..=9 => (b'0' + nibble) as char,
+                0xA => '*',
+                0xB => '#',
+                0xC => 'a',
+                0xD => 'b',
+                0xE => 'c',
+                _ => continue,
+            });
+        }
+        Ok(number)
+    }
+
+    // seven swapped BCD semi-octet pairs: year, month, day, hour, minute, second, zone
+    fn timestamp(&mut self) -> Result<Timestamp, PduError> {
+        let data = self.take(7)?;
+        let bcd = |b: u8| (b & 0x0F) * 10 + ((b >> 4) & 0x0F);
+        let zone = bcd(data[6] & 0xF7) as i8;
+        Ok(Timestamp {
+            year: 2000 + bcd(data[0]) as u16,
+            month: bcd(data[1]),
+            day: bcd(data[2]),
+            hour: bcd(data[3]),
+            minute: bcd(data[4]),
+            second: bcd(data[5]),
+            tz_quarters: if data[6] & 0x08 != 0 { -zone } else { zone },
+        })
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, PduError> {
+    let hex = hex.trim();
+    if hex.len() & 1 != 0 {
+        return Err(PduError::Invalid("odd hex length".to_string()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| PduError::Invalid(format!("bad hex at {i}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gsm7_packing() {
+        let septets: Vec<u8> = "hellohello"
+            .chars()
+            .filter_map(gsm7_septets)
+            .flatten()
+            .collect();
+        let packed = pack_septets(&septets, 0);
+        assert_eq!(to_hex(&packed), "E8329BFD4697D9EC37");
+        assert_eq!(unpack_septets(&packed, 0, septets.len()), septets);
+        // after a 6 byte header the text starts on a septet boundary
+        assert_eq!(unpack_septets(&pack_septets(&septets, 1), 1, 10), septets);
+
+        let septets: Vec<u8> = "€[1]".chars().filter_map(gsm7_septets).flatten().collect();
+        assert_eq!(septets, [0x1B, 0x65, 0x1B, 0x3C, 0x31, 0x1B, 0x3E]);
+        assert_eq!(gsm7_decode(&septets), "€[1]");
+        assert!(is_gsm7("Grüße {Ωmega}"));
+        assert!(!is_gsm7("Привет"));
+    }
+
+    #[test]
+    fn submit() {
+        let pdus = encode_submit("+46708251358", "hellohello", 0, false).unwrap();
+        assert_eq!(
+            pdus,
+            [SubmitPdu {
+                hex: "0011000B916407281553F80000AA0AE8329BFD4697D9EC37".to_string(),
+                length: 23,
+            }]
+        );
+        // a delivery report requested, a national number
+        let pdus = encode_submit("0708251358", "hellohello", 0, true).unwrap();
+        assert_eq!(
+            pdus[0].hex,
+            "0031000A8170805231850000AA0AE8329BFD4697D9EC37"
+        );
+        assert_eq!(
+            decode(&pdus[0].hex).unwrap(),
+            Pdu::Submit {
+                recipient: "0708251358".to_string()
+            }
+        );
+        // separators as typed are dropped
+        let pdus = encode_submit("+46 (70) 825-13 58", "hellohello", 0, false).unwrap();
+        assert_eq!(&pdus[0].hex[6..20], "0B916407281553");
+        assert!(encode_submit("+46 70 CALL ME", "hi", 0, false).is_err());
+    }
+
+    #[test]
+    fn submit_ucs2() {
+        let pdus = encode_submit("+46708251358", "Привет", 0, false).unwrap();
+        assert_eq!(
+            pdus[0].hex,
+            "0011000B916407281553F80008AA0C041F04400438043204350442"
+        );
+        assert_eq!(pdus[0].length, 26);
+    }
+
+    #[test]
+    fn submit_concatenated() {
+        let text = "a".repeat(200);
+        let pdus = encode_submit("+46708251358", &text, 7, false).unwrap();
+        assert_eq!(pdus.len(), 2);
+        // UDHI, 160 septets: the 6 byte header, a fill bit and 153 characters
+        assert!(pdus[0]
+            .hex
+            .starts_with("0051000B916407281553F80000AAA0050003070201"));
+        assert!(pdus[1]
+            .hex
+            .starts_with("0051000B916407281553F80000AA36050003070202"));
+        for pdu in &pdus {
+            assert_eq!(pdu.hex.len(), 2 + pdu.length * 2);
+        }
+
+        // UCS-2 parts keep surrogate pairs together: 33 emoji in 132 of the 134 bytes
+        let text = "😀".repeat(40);
+        let pdus = encode_submit("+46708251358", &text, 8, false).unwrap();
+        assert_eq!(pdus.len(), 2);
+        assert!(pdus[0].hex.contains("AA8A050003080201"));
+        assert!(pdus[1].hex.contains("AA22050003080202"));
+    }
+
+    #[test]
+    fn deliver() {
+        let pdu = "07917283010010F5040BC87238880900F10000993092516195800AE8329BFD4697D9EC37";
+        assert_eq!(
+            decode(pdu).unwrap(),
+            Pdu::Deliver(Deliver {
+                sender: "27838890001".to_string(),
+                // two digit years are taken as this century
+                timestamp: Timestamp {
+                    year: 2099,
+                    month: 3,
+                    day: 29,
+                    hour: 15,
+                    minute: 16,
+                    second: 59,
+                    tz_quarters: 8,
+                },
+                text: "hellohello".to_string(),
+                concat: None,
+            })
+        );
+    }
+
+    #[test]
+    fn deliver_concatenated_ucs2() {
+        let parts = [
+            "0791448720003023440C91447700091032000862019141035080160500032A0201041F04400438043204350442002C0020",
+            "0791448720003023440C914477000910320008620191410350800E0500032A0202043C043804400021",
+        ];
+        let parts: Vec<Deliver> = parts
+            .iter()
+            .map(|hex| match decode(hex).unwrap() {
+                Pdu::Deliver(deliver) => deliver,
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(parts[0].sender, "+447700900123");
+        assert_eq!(
+            parts[0].timestamp,
+            Timestamp {
+                year: 2026,
+                month: 10,
+                day: 19,
+                hour: 14,
+                minute: 30,
+                second: 5,
+                tz_quarters: 8,
+            }
+        );
+        assert_eq!(
+            parts[0].concat,
+            Some(Concat {
+                reference: 0x2A,
+                total: 2,
+                seq: 1
+            })
+        );
+        assert_eq!(parts[1].concat.map(|c| c.seq), Some(2));
+        assert_eq!(
+            format!("{}{}", parts[0].text, parts[1].text),
+            "Привет, мир!"
+        );
+    }
+
+    #[test]
+    fn status_report() {
+        let pdu = "0791448720003023060C0B916407281553F8620191410350806201914104518000";
+        let Pdu::StatusReport(report) = decode(pdu).unwrap() else {
+            panic!("not a status report");
+        };
+        assert_eq!(report.reference, 0x0C);
+        assert_eq!(report.recipient, "+46708251358");
+        assert_eq!(
+            (report.delivered_at.minute, report.delivered_at.second),
+            (40, 15)
+        );
+        assert!(report.delivered() && !report.failed());
+
+        // 0x46: validity period expired
+        let pdu = "0791448720003023060D0B916407281553F8620191410350806201914104518046";
+        let Pdu::StatusReport(report) = decode(pdu).unwrap() else {
+            panic!("not a status report");
+        };
+        assert!(report.failed());
+        assert!(decode(&pdu[..40]).is_err());
+    }
+}
@@ -0,0 +1,561 @@
+use crate::app::{App, Context, Transition};
+use crate::contacts::EXPORT_FILE;
+use crate::conversations::ThreadKey;
+use crate::dialer::Dialer;
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::meshtastic;
+use crate::messages::ThreadView;
+use crate::storage::StorageError;
+use crate::ui::{Ui, WidgetId};
+use crate::vcard::{self, Contact, Version};
+use crate::widget::{Label, List, Modal, StatusBar, TextInput};
+
+pub const NAME: &str = "Contacts";
+const VISIBLE_ROWS: usize = 12;
+const DETAIL_ROWS: usize = 14;
+const MAX_FIELD: usize = 120;
+
+// Writes the book to the card. Without one the change stays in memory until a card is
+// mounted, which is not worth bothering the user with.
+fn save(ctx: &mut Context) {
+    let storage = ctx.storage.lock().unwrap();
+    match ctx.contacts.save(&storage) {
+        Ok(()) | Err(StorageError::NotMounted) => {}
+        Err(e) => log::warn!("saving contacts failed: {e:?}"),
+    }
+}
+
+fn is_back(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Key(Key::Back)
+            | Event::Touch(Gesture::Swipe {
+                direction: Direction::Right,
+                ..
+            })
+    )
+}
+
+// index of the list row under a tap
+fn tapped_row(ui: &Ui, list: WidgetId, x: i16, y: i16) -> Option<usize> {
+    if ui.hit(x, y) != Some(list) {
+        return None;
+    }
+    let top = ui.bounds(list)?.y;
+    ui.get::<List>(list)?.index_at(y - top)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Row {
+    New,
+    Contact(u32),
+    Import,
+    Export(Version),
+}
+
+// The address book: typing searches names and numbers by prefix, Enter opens the selected
+// contact. With an empty search the list also offers a new contact and vCard import/export
+// (see contacts.rs for the files).
+pub struct Phonebook {
+    ui: Ui,
+    status: WidgetId,
+    search: WidgetId,
+    list: WidgetId,
+    rows: Vec<Row>,
+}
+
+impl Phonebook {
+    pub fn launch(_ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        let status = ui.add(StatusBar::new(NAME));
+        let mut search = TextInput::new("Search");
+        search.set_focused(true);
+        let search = ui.add(search);
+        let list = ui.add(List::new(VISIBLE_ROWS));
+        Box::new(Self {
+            ui,
+            status,
+            search,
+            list,
+            rows: Vec::new(),
+        })
+    }
+
+    fn list(&mut self) -> &mut List {
+        self.ui.get_mut::<List>(self.list).unwrap()
+    }
+
+    fn search(&mut self) -> &mut TextInput {
+        self.ui.get_mut::<TextInput>(self.search).unwrap()
+    }
+
+    fn refresh(&mut self, ctx: &Context) {
+        let query = self.search().text().to_string();
+        let mut rows = Vec::new();
+        let mut items = Vec::new();
+        if query.is_empty() {
+            rows.push(Row::New);
+            items.push("+ New contact".to_string());
+        }
+        for contact in ctx.contacts.search(&query) {
+            rows.push(Row::Contact(contact.id));
+            items.push(match contact.numbers.first() {
+                Some(number) => format!("{}  {number}", contact.name),
+                None => contact.name.clone(),
+            });
+        }
+        if query.is_empty() {
+            rows.extend([
+                Row::Import,
+                Row::Export(Version::V3),
+                Row::Export(Version::V4),
+            ]);
+            items.extend([
+                "Import .vcf files from SD".to_string(),
+                "Export as vCard 3.0".to_string(),
+                "Export as vCard 4.0".to_string(),
+            ]);
+        }
+        self.rows = rows;
+        self.list().set_items(items);
+        let count = format!("{}", ctx.contacts.all().len());
+        self.ui
+            .get_mut::<StatusBar>(self.status)
+            .unwrap()
+            .set_indicators(&count);
+    }
+
+    fn open(&mut self, ctx: &mut Context) -> Transition {
+        let Some(row) = self
+            .list()
+            .selected()
+            .and_then(|i| self.rows.get(i).copied())
+        else {
+            return Transition::None;
+        };
+        match row {
+            Row::New => return Transition::Push(ContactEditor::launch(ctx, None)),
+            Row::Contact(id) => return Transition::Push(ContactView::launch(id)),
+            Row::Import => {
+                let storage = ctx.storage.lock().unwrap();
+                let message = match ctx.contacts.import(&storage) {
+                    Ok(0) => "No new contacts found".to_string(),
+                    Ok(1) => "Imported 1 contact".to_string(),
+                    Ok(n) => format!("Imported {n} contacts"),
+                    Err(e) => error_text(&e),
+                };
+                drop(storage);
+                self.ui.show_modal(Modal::new("Import", &message, &["OK"]));
+            }
+            Row::Export(version) => {
+                let storage = ctx.storage.lock().unwrap();
+                let message = match ctx.contacts.export(&storage, version) {
+                    Ok(()) => format!("Saved as contacts/{EXPORT_FILE}"),
+                    Err(e) => error_text(&e),
+                };
+                drop(storage);
+                self.ui.show_modal(Modal::new("Export", &message, &["OK"]));
+            }
+        }
+        Transition::None
+    }
+}
+
+fn error_text(e: &StorageError) -> String {
+    match e {
+        StorageError::NotMounted => "No SD card".to_string(),
+        StorageError::NotFound(name) => format!("{name} not found"),
+        StorageError::Io(_) => "SD card error".to_string(),
+    }
+}
+
+impl App for Phonebook {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        if self.ui.has_modal() {
+            if matches!(
+                event,
+                Event::Key(Key::Enter | Key::Back) | Event::Touch(Gesture::Tap { .. })
+            ) {
+                self.ui.close_modal();
+                // after an import
+                self.refresh(ctx);
+            }
+            return Transition::None;
+        }
+        match event {
+            Event::Key(Key::Char(c)) if self.search().text().len() < MAX_FIELD => {
+                self.search().insert(*c);
+                self.list().select(0);
+            }
+            Event::Key(Key::Backspace) => self.search().backspace(),
+            Event::Key(Key::Up) => self.list().select_previous(),
+            Event::Key(Key::Down) => self.list().select_next(),
+            Event::Key(Key::Enter) | Event::Key(Key::Right) => return self.open(ctx),
+            // Back clears the search first
+            Event::Key(Key::Back) if !self.search().text().is_empty() => {
+                self.search().take();
+            }
+            event if is_back(event) => return Transition::Pop,
+            Event::Touch(Gesture::Tap { x, y }) => {
+                if let Some(i) = tapped_row(&self.ui, self.list, *x, *y) {
+                    self.list().select(i);
+                    return self.open(ctx);
+                }
+            }
+            Event::Touch(Gesture::Swipe { direction, .. }) => match direction {
+                Direction::Up => self.list().select_next(),
+                Direction::Down => self.list().select_previous(),
+                _ => {}
+            },
+            _ => {}
+        }
+        self.refresh(ctx);
+        Transition::None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Call(String),
+    Message,
+    Edit,
+    Delete,
+    None,
+}
+
+// One contact: Enter on a number calls it, M opens the conversation with the contact, E edits
+// and D deletes it.
+pub struct ContactView {
+    ui: Ui,
+    status: WidgetId,
+    list: WidgetId,
+    id: u32,
+    actions: Vec<Action>,
+}
+
+impl ContactView {
+    pub fn launch(id: u32) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        let status = ui.add(StatusBar::new(NAME));
+        let list = ui.add(List::new(DETAIL_ROWS));
+        Box::new(Self {
+            ui,
+            status,
+            list,
+            id,
+            actions: Vec::new(),
+        })
+    }
+
+    fn list(&mut self) -> &mut List {
+        self.ui.get_mut::<List>(self.list).unwrap()
+    }
+
+    // false once the contact is gone
+    fn refresh(&mut self, ctx: &Context) -> bool {
+        let Some(contact) = ctx.contacts.get(self.id) else {
+            return false;
+        };
+        let mut items = Vec::new();
+        let mut actions = Vec::new();
+        for number in contact.numbers.iter() {
+            items.push(format!("Call {number}"));
+            actions.push(Action::Call(number.clone()));
+        }
+        for &node in contact.nodes.iter() {
+            let name = ctx.mesh.lock().unwrap().name_of(node);
+            let id = meshtastic::node_id(node);
+            items.push(if name == id {
+                format!("Mesh node {id}")
+            } else {
+                format!("Mesh node {id} ({name})")
+            });
+            actions.push(Action::None);
+        }
+        for line in contact.note.lines() {
+            items.push(line.to_string());
+            actions.push(Action::None);
+        }
+        if !contact.numbers.is_empty() || !contact.nodes.is_empty() {
+            items.push("Message (M)".to_string());
+            actions.push(Action::Message);
+        }
+        items.push("Edit (E)".to_string());
+        actions.push(Action::Edit);
+        items.push("Delete (D)".to_string());
+        actions.push(Action::Delete);
+        let name = contact.name.clone();
+
+        self.actions = actions;
+        self.list().set_items(items);
+        self.ui
+            .get_mut::<StatusBar>(self.status)
+            .unwrap()
+            .set_title(&name);
+        true
+    }
+
+    fn run(&mut self, ctx: &mut Context, action: Action) -> Transition {
+        match action {
+            Action::Call(number) => {
+                ctx.calls.lock().unwrap().dial(&number);
+                Transition::Push(Dialer::launch(ctx))
+            }
+            Action::Message => Transition::Push(ThreadView::launch(ThreadKey::Contact(self.id))),
+            Action::Edit => Transition::Push(ContactEditor::launch(ctx, Some(self.id))),
+            Action::Delete => {
+                self.ui.show_modal(Modal::new(
+                    "Delete",
+                    "Delete this contact?",
+                    &["Cancel", "Delete"],
+                ));
+                Transition::None
+            }
+            Action::None => Transition::None,
+        }
+    }
+
+    fn selected_action(&mut self) -> Action {
+        let selected = self.list().selected();
+        selected
+            .and_then(|i| self.actions.get(i).cloned())
+            .unwrap_or(Action::None)
+    }
+}
+
+impl App for ContactView {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.refresh(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        if let Some(modal) = self.ui.modal_mut() {
+            match event {
+                Event::Key(Key::Left) => modal.select_previous(),
+                Event::Key(Key::Right) => modal.select_next(),
+                Event::Key(Key::Enter) => {
+                    let delete = modal.selected() == 1;
+                    self.ui.close_modal();
+                    if delete {
+                        ctx.contacts.remove(self.id);
+                        save(ctx);
+                        return Transition::Pop;
+                    }
+                }
+                Event::Key(Key::Back) => {
+                    self.ui.close_modal();
+                }
+                _ => {}
+            }
+            return Transition::None;
+        }
+        let transition = match event {
+            Event::Key(Key::Up) => {
+                self.list().select_previous();
+                Transition::None
+            }
+            Event::Key(Key::Down) => {
+                self.list().select_next();
+                Transition::None
+            }
+            Event::Key(Key::Enter) | Event::Key(Key::Right) => {
+                let action = self.selected_action();
+                self.run(ctx, action)
+            }
+            Event::Key(Key::Char('m' | 'M')) => self.run(ctx, Action::Message),
+            Event::Key(Key::Char('e' | 'E')) => self.run(ctx, Action::Edit),
+            Event::Key(Key::Char('d' | 'D')) => self.run(ctx, Action::Delete),
+            event if is_back(event) => return Transition::Pop,
+            Event::Touch(Gesture::Tap { x, y }) => match tapped_row(&self.ui, self.list, *x, *y) {
+                Some(i) => {
+                    self.list().select(i);
+                    let action = self.selected_action();
+                    self.run(ctx, action)
+                }
+                None => Transition::None,
+            },
+            _ => Transition::None,
+        };
+        if !self.refresh(ctx) {
+            return Transition::Pop;
+        }
+        transition
+    }
+}
+
+// Fields of the editor, in order on the page.
+const FIELDS: [&str; 4] = [
+    "Name",
+    "Numbers, separated by commas",
+    "Mesh nodes, e.g. !a1b2c3d4",
+    "Note",
+];
+
+// New or existing contact: Up/Down move between the fields, Enter saves, Back leaves without
+// saving.
+pub struct ContactEditor {
+    ui: Ui,
+    inputs: [WidgetId; 4],
+    hint: WidgetId,
+    focus: usize,
+    // None for a new contact
+    id: Option<u32>,
+}
+
+impl ContactEditor {
+    pub fn launch(ctx: &mut Context, id: Option<u32>) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        let title = if id.is_some() {
+            "Edit contact"
+        } else {
+            "New contact"
+        };
+        ui.add(StatusBar::new(title));
+        let contact = id.and_then(|id| ctx.contacts.get(id)).cloned();
+        let contact = contact.unwrap_or_default();
+        let nodes: Vec<String> = contact
+            .nodes
+            .iter()
+            .map(|&n| meshtastic::node_id(n))
+            .collect();
+        let values = [
+            contact.name,
+            contact.numbers.join(", "),
+            nodes.join(", "),
+            contact.note.replace('\n', " "),
+        ];
+        let inputs = [0, 1, 2, 3].map(|i| {
+            ui.add(Label::new(FIELDS[i]));
+            let mut input = TextInput::new("");
+            input.set_text(&values[i]);
+            input.set_focused(i == 0);
+            ui.add(input)
+        });
+        let hint = ui.add(Label::new("Enter: save, Back: cancel"));
+        Box::new(Self {
+            ui,
+            inputs,
+            hint,
+            focus: 0,
+            id,
+        })
+    }
+
+    fn input(&mut self, i: usize) -> &mut TextInput {
+        self.ui.get_mut::<TextInput>(self.inputs[i]).unwrap()
+    }
+
+    fn focus(&mut self, i: usize) {
+        self.input(self.focus).set_focused(false);
+        self.focus = i;
+        self.input(i).set_focused(true);
+    }
+
+    fn set_hint(&mut self, text: &str) {
+        self.ui.get_mut::<Label>(self.hint).unwrap().set_text(text);
+    }
+
+    // The contact from the fields, or what is wrong with them.
+    fn contact(&mut self) -> Result<Contact, String> {
+        let name = self.input(0).text().trim().to_string();
+        let numbers: Vec<String> = split_list(self.input(1).text());
+        if name.is_empty() && numbers.is_empty() {
+            return Err("A contact needs a name or a number".to_string());
+        }
+        let mut nodes = Vec::new();
+        for node in split_list(self.input(2).text()) {
+            match vcard::parse_node(&node) {
+                Some(n) => nodes.push(n),
+                None => return Err(format!("{node} is not a mesh node id")),
+            }
+        }
+        Ok(Contact {
+            id: self.id.unwrap_or(0),
+            name: if name.is_empty() {
+                numbers[0].clone()
+            } else {
+                name
+            },
+            numbers,
+            nodes,
+            note: self.input(3).text().trim().to_string(),
+        })
+    }
+
+    fn save(&mut self, ctx: &mut Context) -> Transition {
+        let contact = match self.contact() {
+            Ok(contact) => contact,
+            Err(problem) => {
+                self.set_hint(&problem);
+                return Transition::None;
+            }
+        };
+        if self.id.is_some() {
+            ctx.contacts.update(contact);
+        } else {
+            ctx.contacts.add(contact);
+        }
+        save(ctx);
+        Transition::Pop
+    }
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl App for ContactEditor {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        let focus = self.focus;
+        match event {
+            Event::Key(Key::Char(c)) if self.input(focus).text().len() < MAX_FIELD => {
+                self.input(focus).insert(*c)
+            }
+            Event::Key(Key::Backspace) => self.input(focus).backspace(),
+            Event::Key(Key::Up) => self.focus(focus.saturating_sub(1)),
+            Event::Key(Key::Down) => self.focus((focus + 1).min(FIELDS.len() - 1)),
+            Event::Key(Key::Enter) => return self.save(ctx),
+            event if is_back(event) => return Transition::Pop,
+            Event::Touch(Gesture::Tap { x, y }) => {
+                let hit = self.ui.hit(*x, *y);
+                if let Some(i) = self.inputs.iter().position(|&id| Some(id) == hit) {
+                    self.focus(i);
+                }
+            }
+            _ => {}
+        }
+        Transition::None
+    }
+}
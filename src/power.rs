@@ -0,0 +1,412 @@
+// Power management. With power management enabled in ESP-IDF the chip drops into light sleep
+// whenever every task is blocked, unless a `WakeLock` is held; the keyboard, touch panel,
+// IMU, LoRa radio and the modem's RI line wake it again. The display task runs a
+// `PowerManager` that switches the backlight, panel and GNSS receiver off once the user
+// stops interacting (or when the phone is put face down) and puts the chip into deep sleep
+// after a longer while, to be woken by a key, the modem, motion or the RTC timer. The e-paper keeps
+// showing the last screen throughout.
+
+use std::ffi::CStr;
+use std::fmt::Debug;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::OutputPin;
+use esp_idf_svc::sys;
+
+use crate::clock;
+use crate::event::{Event, SensorEvent};
+use crate::gnss::Gnss;
+use crate::modem::{ModemError, Transport};
+use crate::settings::PowerConfig;
+
+// wake sources
+const KEYBOARD_INT_GPIO: i32 = 15;
+const TOUCH_INT_GPIO: i32 = 12;
+const IMU_INT_GPIO: i32 = 21;
+const LORA_DIO1_GPIO: i32 = 5;
+// the modem's RI output (GPIO7 in LilyGo's T-Deck Pro pin map), low while ringing and
+// pulsed low for other URCs
+const MODEM_RI_GPIO: i32 = 7;
+// Outputs held through deep sleep. The modem stays powered so a call or message can wake
+// the chip; GNSS, LoRa and the backlight are switched off.
+const MODEM_GPIOS: [i32; 2] = [41, 40]; // EN, PWRKEY
+const GATED_GPIOS: [i32; 3] = [39, 46, 42]; // GPS_EN, LoRa EN, LED_EN
+
+const MAX_CPU_MHZ: i32 = 240;
+const MIN_CPU_MHZ: i32 = 40;
+// how long a URC keeps the chip awake for the rest of it to arrive
+const URC_QUIET: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PowerError {
+    Esp(String),
+}
+impl PowerError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        PowerError::Esp(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeCause {
+    // reset or first power up
+    PowerOn,
+    Keyboard,
+    Modem,
+    // the IMU's any-motion detector
+    Motion,
+    Timer,
+}
+
+pub fn wake_cause() -> WakeCause {
+    let cause = unsafe { sys::esp_sleep_get_wakeup_cause() };
+    match cause {
+        sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => WakeCause::Timer,
+        sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1 => {
+            let pins = unsafe { sys::esp_sleep_get_ext1_wakeup_status() };
+            if pins & (1 << MODEM_RI_GPIO) != 0 {
+                WakeCause::Modem
+            } else if pins & (1 << IMU_INT_GPIO) != 0 {
+                WakeCause::Motion
+            } else {
+                WakeCause::Keyboard
+            }
+        }
+        _ => WakeCause::PowerOn,
+    }
+}
+
+// Lets go of the outputs held through deep sleep. Call once the pin drivers are set up
+// (with the modem enable already high), so nothing glitches on release.
+pub fn release_holds() {
+    unsafe {
+        sys::gpio_deep_sleep_hold_dis();
+        for gpio in MODEM_GPIOS.into_iter().chain(GATED_GPIOS) {
+            sys::gpio_hold_dis(gpio);
+        }
+    }
+}
+
+// Turns on automatic light sleep, with the CPU clock scaled down while it is busy.
+pub fn enable_light_sleep() -> Result<(), PowerError> {
+    let config = sys::esp_pm_config_t {
+        max_freq_mhz: MAX_CPU_MHZ,
+        min_freq_mhz: MIN_CPU_MHZ,
+        light_sleep_enable: true,
+    };
+    sys::esp!(unsafe { sys::esp_pm_configure(ptr::addr_of!(config).cast()) })
+        .map_err(PowerError::from_debug)?;
+    arm_light_sleep_wake()
+}
+
+// Level wake-ups on the interrupt lines. This changes the pins' interrupt type to the same
+// level; the tasks re-enable their interrupt only after clearing the source, so each event
+// is still seen once.
+fn arm_light_sleep_wake() -> Result<(), PowerError> {
+    let pins = [
+        (KEYBOARD_INT_GPIO, sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL),
+        (TOUCH_INT_GPIO, sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL),
+        (IMU_INT_GPIO, sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL),
+        (MODEM_RI_GPIO, sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL),
+        (LORA_DIO1_GPIO, sys::gpio_int_type_t_GPIO_INTR_HIGH_LEVEL),
+    ];
+    for (gpio, level) in pins {
+        sys::esp!(unsafe { sys::gpio_wakeup_enable(gpio, level) })
+            .map_err(PowerError::from_debug)?;
+    }
+    sys::esp!(unsafe { sys::esp_sleep_enable_gpio_wakeup() }).map_err(PowerError::from_debug)
+}
+
+// Keeps the chip out of light sleep while held.
+pub struct WakeLock {
+    handle: sys::esp_pm_lock_handle_t,
+    held: bool,
+}
+
+// the handle is only used through the thread safe esp_pm_lock_* calls
+unsafe impl Send for WakeLock {}
+
+impl WakeLock {
+    pub fn new(name: &'static CStr) -> Result<Self, PowerError> {
+        let mut handle = ptr::null_mut();
+        sys::esp!(unsafe {
+            sys::esp_pm_lock_create(
+                sys::esp_pm_lock_type_t_ESP_PM_NO_LIGHT_SLEEP,
+                0,
+                name.as_ptr(),
+                &mut handle,
+            )
+        })
+        .map_err(PowerError::from_debug)?;
+        Ok(Self {
+            handle,
+            held: false,
+        })
+    }
+
+    pub fn set(&mut self, held: bool) {
+        if held != self.held {
+            unsafe {
+                if held {
+                    sys::esp_pm_lock_acquire(self.handle);
+                } else {
+                    sys::esp_pm_lock_release(self.handle);
+                }
+            }
+            self.held = held;
+        }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+}
+
+impl Drop for WakeLock {
+    fn drop(&mut self) {
+        self.set(false);
+        unsafe {
+            sys::esp_pm_lock_delete(self.handle);
+        }
+    }
+}
+
+// A UART stops receiving in light sleep, so this keeps the chip awake for the whole of each
+// command exchange and briefly after unsolicited data. A URC that wakes the chip (through
+// RI) can still lose its first bytes; RING repeats and the status poll catches up.
+pub struct AwakeTransport<T> {
+    inner: T,
+    lock: WakeLock,
+    busy: bool,
+}
+
+impl<T: Transport> AwakeTransport<T> {
+    pub fn new(inner: T, lock: WakeLock) -> Self {
+        Self {
+            inner,
+            lock,
+            busy: false,
+        }
+    }
+}
+
+impl<T: Transport> Transport for AwakeTransport<T> {
+    fn read(&mut self, buf: &mut [u8], mut timeout: Duration) -> Result<usize, ModemError> {
+        if self.lock.is_held() && !self.busy {
+            let window = timeout.min(URC_QUIET);
+            let n = self.inner.read(buf, window)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            self.lock.set(false);
+            if window == timeout {
+                return Ok(0);
+            }
+            timeout -= window;
+        }
+        let n = self.inner.read(buf, timeout)?;
+        if n > 0 {
+            self.lock.set(true);
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ModemError> {
+        self.inner.write(data)
+    }
+
+    fn set_busy(&mut self, busy: bool) {
+        self.busy = busy;
+        self.lock.set(busy);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Active,
+    Idle,
+}
+
+const ACTIVE_TICK_PERIOD: Duration = Duration::from_secs(1);
+// after the minute rolls over, so the clock on screen is never behind
+const IDLE_TICK_MARGIN: Duration = Duration::from_millis(200);
+
+pub struct PowerManager<LED> {
+    config: PowerConfig,
+    state: PowerState,
+    last_input: Instant,
+    sleep_at: Instant,
+    lock: WakeLock,
+    backlight: LED,
+    gnss: Arc<Mutex<Gnss>>,
+    // GNSS was on when the device went idle, so it comes back with the user
+    gnss_resume: bool,
+    // from the light sensor; without one the backlight is always used
+    dark: bool,
+    // something against the screen during a call
+    near: bool,
+}
+
+impl<LED: OutputPin> PowerManager<LED> {
+    pub fn new(
+        config: PowerConfig,
+        cause: WakeCause,
+        backlight: LED,
+        gnss: Arc<Mutex<Gnss>>,
+    ) -> Result<Self, PowerError> {
+        let now = Instant::now();
+        let mut manager = Self {
+            config,
+            state: PowerState::Idle,
+            last_input: now,
+            sleep_at: now + config.sleep_after,
+            lock: WakeLock::new(c"display")?,
+            backlight,
+            gnss,
+            gnss_resume: false,
+            dark: true,
+            near: false,
+        };
+        if matches!(cause, WakeCause::Timer | WakeCause::Motion) {
+            // a quick look around, then back to sleep; a raise seen meanwhile wakes it up
+            manager.sleep_at = now + config.timer_wake_time;
+            manager.gnss_resume = manager.set_gnss(false);
+        } else {
+            manager.wake(now);
+        }
+        Ok(manager)
+    }
+
+    // new timeouts, counted from now
+    pub fn set_config(&mut self, config: PowerConfig) {
+        self.config = config;
+        let now = Instant::now();
+        self.last_input = now;
+        self.sleep_at = now + config.sleep_after;
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    // touches are ignored while the phone is held to the ear
+    pub fn screen_locked(&self) -> bool {
+        self.near
+    }
+
+    // how long the display task may wait for an event before a Tick
+    pub fn tick_period(&self) -> Duration {
+        match self.state {
+            PowerState::Active => ACTIVE_TICK_PERIOD,
+            PowerState::Idle => clock::until_next_minute() + IDLE_TICK_MARGIN,
+        }
+    }
+
+    // Called after every event; true once it is time for deep sleep. `in_call`: a call is
+    // ringing or in progress, which keeps the device awake.
+    pub fn handle_event(&mut self, event: &Event, in_call: bool) -> bool {
+        let now = Instant::now();
+        match event {
+            Event::Sensor(SensorEvent::Near(near)) => {
+                self.near = *near;
+                self.update_backlight();
+            }
+            Event::Sensor(SensorEvent::Dark(dark)) => {
+                self.dark = *dark;
+                self.update_backlight();
+            }
+            _ => {}
+        }
+        let input = match event {
+            Event::Key(_) | Event::Sensor(SensorEvent::Raised) => true,
+            Event::Touch(_) => !self.near,
+            _ => false,
+        };
+        if in_call || input {
+            self.wake(now);
+            return false;
+        }
+        if matches!(event, Event::Sensor(SensorEvent::FaceDown)) {
+            log::info!("face down");
+            return true;
+        }
+        if self.state == PowerState::Active
+            && now.duration_since(self.last_input) >= self.config.idle_after
+        {
+            self.state = PowerState::Idle;
+            self.update_backlight();
+            self.gnss_resume = self.set_gnss(false);
+            self.lock.set(false);
+            if let Err(e) = arm_light_sleep_wake() {
+                log::warn!("light sleep wake-up: {e:?}");
+            }
+        }
+        now >= self.sleep_at
+    }
+
+    fn wake(&mut self, now: Instant) {
+        self.last_input = now;
+        self.sleep_at = now + self.config.sleep_after;
+        if self.state == PowerState::Idle {
+            self.state = PowerState::Active;
+            self.update_backlight();
+            if self.gnss_resume {
+                self.set_gnss(true);
+            }
+            self.lock.set(true);
+        }
+    }
+
+    fn update_backlight(&mut self) {
+        let on = self.state == PowerState::Active && self.dark && !self.near;
+        self.backlight.set_state(on.into()).ok();
+    }
+
+    // returns whether the receiver was on
+    fn set_gnss(&mut self, enabled: bool) -> bool {
+        let mut gnss = self.gnss.lock().unwrap();
+        let was = gnss.enabled();
+        gnss.set_enabled(enabled);
+        was
+    }
+
+    // Switches the peripherals off and deep sleeps; the chip resets on wake. The panel must
+    // already be hibernating.
+    pub fn deep_sleep(mut self) -> ! {
+        log::info!("deep sleep");
+        self.backlight.set_low().ok();
+        unsafe {
+            for gpio in GATED_GPIOS {
+                sys::gpio_set_level(gpio, 0);
+            }
+            // the modem pins keep whatever level they have
+            for gpio in GATED_GPIOS.into_iter().chain(MODEM_GPIOS) {
+                sys::gpio_hold_en(gpio);
+            }
+            sys::gpio_deep_sleep_hold_en();
+
+            // all three lines idle high; the pull-ups need the RTC peripherals powered. The IMU
+            // only pulls its line low for motion, the accelerometer is off by then.
+            let mask =
+                (1u64 << KEYBOARD_INT_GPIO) | (1u64 << MODEM_RI_GPIO) | (1u64 << IMU_INT_GPIO);
+            sys::esp_sleep_pd_config(
+                sys::esp_sleep_pd_domain_t_ESP_PD_DOMAIN_RTC_PERIPH,
+                sys::esp_sleep_pd_option_t_ESP_PD_OPTION_ON,
+            );
+            for gpio in [KEYBOARD_INT_GPIO, MODEM_RI_GPIO, IMU_INT_GPIO] {
+                sys::rtc_gpio_pullup_en(gpio);
+                sys::rtc_gpio_pulldown_dis(gpio);
+            }
+            sys::esp_sleep_enable_ext1_wakeup(
+                mask,
+                sys::esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ANY_LOW,
+            );
+            if let Some(period) = self.config.wake_period {
+                sys::esp_sleep_enable_timer_wakeup(period.as_micros() as u64);
+            }
+            sys::esp_deep_sleep_start()
+        }
+    }
+}
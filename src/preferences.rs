@@ -0,0 +1,117 @@
+use crate::app::{App, Context, Transition};
+use crate::event::{Direction, Event, Gesture, Key};
+use crate::settings::Setting;
+use crate::ui::{Ui, WidgetId};
+use crate::widget::{Label, List, StatusBar};
+
+pub const NAME: &str = "Settings";
+const VISIBLE_ROWS: usize = 8;
+
+// Edits the user settings: Up/Down pick one, Enter/Right and Left step through its choices,
+// Backspace puts it back to the default. Changes are saved straight away.
+pub struct Preferences {
+    ui: Ui,
+    list: WidgetId,
+    hint: WidgetId,
+}
+
+impl Preferences {
+    pub fn launch(_ctx: &mut Context) -> Box<dyn App> {
+        let mut ui = Ui::new();
+        ui.add(StatusBar::new(NAME));
+        let list = ui.add(List::new(VISIBLE_ROWS));
+        let hint = ui.add(Label::new(""));
+        Box::new(Self { ui, list, hint })
+    }
+
+    fn list(&mut self) -> &mut List {
+        self.ui.get_mut::<List>(self.list).unwrap()
+    }
+
+    fn selected(&mut self) -> Option<Setting> {
+        self.list().selected().map(|i| Setting::ALL[i])
+    }
+
+    fn update(&mut self, ctx: &Context) {
+        let settings = ctx.settings.lock().unwrap();
+        let items = Setting::ALL
+            .iter()
+            .map(|&s| format!("{}: {}", s.label(), s.value_label(settings.get(s))))
+            .collect();
+        drop(settings);
+        self.list().set_items(items);
+        let hint = match self.selected() {
+            Some(s) if s.needs_restart() => "Takes effect after a restart",
+            Some(_) => "Enter/Right: next, Left: previous",
+            None => "",
+        };
+        self.ui.get_mut::<Label>(self.hint).unwrap().set_text(hint);
+    }
+
+    fn step(&mut self, ctx: &Context, steps: i32) {
+        let Some(setting) = self.selected() else {
+            return;
+        };
+        if let Err(e) = ctx.settings.lock().unwrap().step(setting, steps) {
+            log::warn!("changing {setting:?} failed: {e:?}");
+        }
+    }
+
+    fn reset(&mut self, ctx: &Context) {
+        let Some(setting) = self.selected() else {
+            return;
+        };
+        if let Err(e) = ctx.settings.lock().unwrap().reset(setting) {
+            log::warn!("resetting {setting:?} failed: {e:?}");
+        }
+    }
+}
+
+impl App for Preferences {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn ui(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    fn enter(&mut self, ctx: &mut Context) {
+        self.update(ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &Event) -> Transition {
+        match event {
+            Event::Key(Key::Up) => self.list().select_previous(),
+            Event::Key(Key::Down) => self.list().select_next(),
+            Event::Key(Key::Enter) | Event::Key(Key::Right) => self.step(ctx, 1),
+            Event::Key(Key::Left) => self.step(ctx, -1),
+            Event::Key(Key::Backspace) => self.reset(ctx),
+            Event::Key(Key::Back) => return Transition::Pop,
+            Event::Touch(Gesture::Tap { x, y }) => {
+                let list = self.list;
+                if self.ui.hit(*x, *y) == Some(list) {
+                    let top = self.ui.bounds(list).unwrap().y;
+                    if let Some(i) = self.list().index_at(*y - top) {
+                        // tapping the selected row changes it, another row selects it
+                        if self.list().selected() == Some(i) {
+                            self.step(ctx, 1);
+                        } else {
+                            self.list().select(i);
+                        }
+                    }
+                }
+            }
+            Event::Touch(Gesture::Swipe { direction, .. }) => match direction {
+                Direction::Up => self.list().select_next(),
+                Direction::Down => self.list().select_previous(),
+                Direction::Right => return Transition::Pop,
+                Direction::Left => {}
+            },
+            _ => {}
+        }
+        // values may have changed here or elsewhere (Event::Settings)
+        self.update(ctx);
+        Transition::None
+    }
+}
@@ -0,0 +1,217 @@
+// Display state kept in RTC slow memory, which survives deep sleep. The 9600 byte framebuffer
+// is more than the 6 KiB set aside for it here, so it is PackBits compressed; the mostly
+// white screens fit easily, a busy one (e.g. a dithered map) may not and is then redrawn
+// with a full refresh on wake.
+
+use std::ptr;
+
+const CAPACITY: usize = 6 * 1024;
+const MAGIC: u32 = 0x4550_4431; // "EPD1"
+
+// Driver state worth keeping while the MCU deep sleeps: the panel holds its image (and its
+// RAM) without power, so with this the next update need not start from a blank screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpdSnapshot {
+    pub initial_refresh: bool,
+    pub initial_write: bool,
+    pub partial_refreshes: u32,
+    pub buffer: Vec<u8>,
+}
+
+#[repr(C)]
+struct Retained {
+    // MAGIC while `data` holds a snapshot
+    magic: u32,
+    len: u16,
+    initial_refresh: bool,
+    initial_write: bool,
+    partial_refreshes: u32,
+    // of the unpacked buffer
+    checksum: u32,
+    data: [u8; CAPACITY],
+}
+
+// loaded with the image at power up, left alone when waking from deep sleep
+#[cfg_attr(target_os = "espidf", link_section = ".rtc.data.display")]
+static mut RETAINED: Retained = Retained {
+    magic: 0,
+    len: 0,
+    initial_refresh: true,
+    initial_write: true,
+    partial_refreshes: 0,
+    checksum: 0,
+    data: [0; CAPACITY],
+};
+
+// Keeps the snapshot for the next boot; false if it is too big.
+pub fn save(snapshot: &EpdSnapshot) -> bool {
+    let packed = pack(&snapshot.buffer);
+    // only the display task calls save and take
+    let retained = unsafe { &mut *ptr::addr_of_mut!(RETAINED) };
+    retained.magic = 0;
+    if packed.len() > CAPACITY {
+        return false;
+    }
+    retained.data[..packed.len()].copy_from_slice(&packed);
+    retained.len = packed.len() as u16;
+    retained.initial_refresh = snapshot.initial_refresh;
+    retained.initial_write = snapshot.initial_write;
+    retained.partial_refreshes = snapshot.partial_refreshes;
+    retained.checksum = checksum(&snapshot.buffer);
+    retained.magic = MAGIC;
+    true
+}
+
+// The saved snapshot, if any; it is only handed out once.
+pub fn take() -> Option<EpdSnapshot> {
+    let retained = unsafe { &mut *ptr::addr_of_mut!(RETAINED) };
+    if retained.magic != MAGIC {
+        return None;
+    }
+    retained.magic = 0;
+    let packed = retained.data.get(..retained.len as usize)?;
+    let buffer = unpack(packed)?;
+    if checksum(&buffer) != retained.checksum {
+        return None;
+    }
+    Some(EpdSnapshot {
+        initial_refresh: retained.initial_refresh,
+        initial_write: retained.initial_write,
+        partial_refreshes: retained.partial_refreshes,
+        buffer,
+    })
+}
+
+// PackBits: a header n of 0..=127 is followed by n + 1 literal bytes, -127..=-1 by one byte
+// repeated 1 - n times.
+pub fn pack(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && run < 128 && data[i + run] == data[i] {
+            run += 1;
+        }
+        if run > 1 {
+            out.push((1 - run as i16) as u8);
+            out.push(data[i]);
+            i += run;
+        } else {
+            let start = i;
+            while i < data.len()
+                && i - start < 128
+                && (i + 1 == data.len() || data[i] != data[i + 1])
+            {
+                i += 1;
+            }
+            out.push((i - start - 1) as u8);
+            out.extend_from_slice(&data[start..i]);
+        }
+    }
+    out
+}
+
+// None if `packed` is malformed
+pub fn unpack(packed: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < packed.len() {
+        let header = packed[i] as i8;
+        i += 1;
+        match header {
+            0..=127 => {
+                let n = header as usize + 1;
+                out.extend_from_slice(packed.get(i..i + n)?);
+                i += n;
+            }
+            -127..=-1 => {
+                let byte = *packed.get(i)?;
+                out.resize(out.len() + (1 - header as isize) as usize, byte);
+                i += 1;
+            }
+            // no-op in PackBits
+            -128 => {}
+        }
+    }
+    Some(out)
+}
+
+// FNV-1a
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUFFER_SIZE: usize = 9600;
+
+    fn snapshot(buffer: Vec<u8>) -> EpdSnapshot {
+        EpdSnapshot {
+            initial_refresh: false,
+            initial_write: false,
+            partial_refreshes: 3,
+            buffer,
+        }
+    }
+
+    #[test]
+    fn packbits() {
+        // the example from Apple's technical note TN1023
+        let data = [
+            0xAA, 0xAA, 0xAA, 0x80, 0x00, 0x2A, 0xAA, 0xAA, 0xAA, 0xAA, 0x80, 0x00, 0x2A, 0x22,
+            0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA,
+        ];
+        let packed = pack(&data);
+        assert_eq!(
+            packed,
+            [
+                0xFE, 0xAA, 0x02, 0x80, 0x00, 0x2A, 0xFD, 0xAA, 0x03, 0x80, 0x00, 0x2A, 0x22, 0xF7,
+                0xAA
+            ]
+        );
+        assert_eq!(unpack(&packed).unwrap(), data);
+
+        // runs and literals longer than one header holds
+        let mut data = vec![0xFF; 300];
+        data.extend((0..=255).chain(0..=44));
+        data.push(7);
+        let packed = pack(&data);
+        assert_eq!(unpack(&packed).unwrap(), data);
+        assert_eq!(pack(&[]), []);
+        // -128 is skipped
+        assert_eq!(unpack(&[0x80, 0x00, 0x41]).unwrap(), [0x41]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(unpack(&[0x02, 0x41, 0x42]), None);
+        assert_eq!(unpack(&[0xFE]), None);
+    }
+
+    #[test]
+    fn retained() {
+        // a white screen with a few lines of text
+        let mut buffer = vec![0xFF; BUFFER_SIZE];
+        for (i, byte) in buffer.iter_mut().enumerate().skip(800).take(1200) {
+            *byte = (i * 37 % 251) as u8;
+        }
+        assert!(save(&snapshot(buffer.clone())));
+        assert_eq!(take(), Some(snapshot(buffer)));
+        assert_eq!(take(), None);
+
+        // noise, like a dithered map, does not fit
+        let mut seed = 1u32;
+        let noise = (0..BUFFER_SIZE)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        assert!(!save(&snapshot(noise)));
+        assert_eq!(take(), None);
+    }
+}
@@ -0,0 +1,318 @@
+// Sensor task: the BHI260AP's accelerometer for raise to wake and face-down to sleep, the
+// LTR-553ALS for the backlight (ambient light) and for locking the screen against the ear
+// during calls (proximity). Findings go to the display task as `Event::Sensor`.
+//
+// The hub's any-motion detector is always on and raises the interrupt line (also a deep
+// sleep wake source), the accelerometer only runs for a few seconds after it fires.
+//
+// The IMU firmware lives in the "imu" data partition as a u32 little endian length followed
+// by the image, e.g. Bosch's BHI260AP.fw.
+
+use std::num::NonZeroU32;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use embedded_hal::i2c::I2c;
+use esp_idf_hal::delay::{FreeRtos, TickType};
+use esp_idf_hal::gpio::{Input, InputPin, InterruptType, PinDriver};
+use esp_idf_hal::task::notification::Notification;
+
+use crate::bhi260::{
+    Bhi260, FifoEvent, ACCEL_LSB_PER_G, SENSOR_ACCELEROMETER, SENSOR_ANY_MOTION_WAKE,
+};
+use crate::call::{CallState, Calls};
+use crate::event::{Event, SensorEvent};
+use crate::ltr553::Ltr553;
+use crate::partition::Partition;
+
+// enough to follow the phone being picked up; samples are batched to limit wake-ups
+const ACCEL_RATE_HZ: f32 = 12.5;
+const ACCEL_LATENCY: Duration = Duration::from_millis(200);
+// long enough to see the phone come to rest face down
+const ACCEL_AFTER_MOTION: Duration = Duration::from_secs(4);
+const LIGHT_PERIOD: Duration = Duration::from_secs(2);
+const PROXIMITY_PERIOD: Duration = Duration::from_millis(100);
+// lux, with hysteresis
+const DARK_LUX: u32 = 10;
+const BRIGHT_LUX: u32 = 30;
+// proximity counts, with hysteresis
+const NEAR_COUNTS: u16 = 400;
+const FAR_COUNTS: u16 = 250;
+
+// a pose has to be held this long to count
+const RAISE_HOLD: Duration = Duration::from_millis(300);
+const FACE_DOWN_HOLD: Duration = Duration::from_secs(2);
+
+// Sensor axes to device axes: x to the right of the screen, y towards its top edge, z out
+// of the screen. This takes the hub to sit on the top of the main board with its axes along
+// the board's; the first sample after each motion is logged at debug level to check it (flat
+// on a table z reads +1 g, standing on the bottom edge y reads +1 g).
+const AXES: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pose {
+    FlatUp,
+    FaceDown,
+    // held up in front of the user: top edge up, screen tilted towards them
+    Raised,
+    // anything else, including while moving
+    Other,
+}
+
+// `g` is the acceleration in g in device axes, gravity points away from the floor
+pub fn pose(g: [f32; 3]) -> Pose {
+    let magnitude = (g[0] * g[0] + g[1] * g[1] + g[2] * g[2]).sqrt();
+    if !(0.7..=1.3).contains(&magnitude) {
+        return Pose::Other;
+    }
+    let (y, z) = (g[1] / magnitude, g[2] / magnitude);
+    if z > 0.9 {
+        Pose::FlatUp
+    } else if z < -0.9 {
+        Pose::FaceDown
+    } else if y > 0.4 && z > 0.2 {
+        Pose::Raised
+    } else {
+        Pose::Other
+    }
+}
+
+// Turns a stream of accelerometer samples into gestures, each reported once per pose.
+pub struct MotionDetector {
+    pose: Pose,
+    since: Instant,
+    reported: bool,
+}
+
+impl MotionDetector {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            pose: Pose::Other,
+            since: now,
+            reported: false,
+        }
+    }
+
+    pub fn update(&mut self, g: [f32; 3], now: Instant) -> Option<SensorEvent> {
+        let pose = pose(g);
+        if pose != self.pose {
+            self.pose = pose;
+            self.since = now;
+            self.reported = false;
+        }
+        let held = now.duration_since(self.since);
+        let event = match pose {
+            Pose::Raised if held >= RAISE_HOLD => SensorEvent::Raised,
+            Pose::FaceDown if held >= FACE_DOWN_HOLD => SensorEvent::FaceDown,
+            _ => return None,
+        };
+        if self.reported {
+            return None;
+        }
+        self.reported = true;
+        Some(event)
+    }
+}
+
+fn device_axes(x: i16, y: i16, z: i16) -> [f32; 3] {
+    let raw = [x, y, z].map(|v| v as f32 / ACCEL_LSB_PER_G);
+    AXES.map(|row| row[0] * raw[0] + row[1] * raw[1] + row[2] * raw[2])
+}
+
+fn ticks(duration: Duration) -> u32 {
+    TickType::new_millis(duration.as_millis() as u64).ticks()
+}
+
+pub fn run<I, L, INT>(
+    mut imu: Bhi260<I>,
+    mut imu_int: PinDriver<'static, INT, Input>,
+    mut light: Ltr553<L>,
+    firmware: Option<Partition>,
+    calls: Arc<Mutex<Calls>>,
+    events: Sender<Event>,
+) -> anyhow::Result<()>
+where
+    I: I2c,
+    L: I2c,
+    INT: InputPin,
+{
+    let has_light = match light.init() {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("light sensor init failed: {e:?}");
+            false
+        }
+    };
+    let mut has_imu = start_imu(&mut imu, firmware.as_ref());
+    // started with the accelerometer on, for a raise that woke the chip from deep sleep
+    let mut accel_on = has_imu;
+    let mut accel_until = Instant::now() + ACCEL_AFTER_MOTION;
+    let mut log_sample = true;
+
+    let notification = Notification::new();
+    let notifier = notification.notifier();
+    imu_int.set_interrupt_type(InterruptType::NegEdge)?;
+    unsafe {
+        imu_int.subscribe(move || {
+            notifier.notify_and_yield(NonZeroU32::new(1).unwrap());
+        })?;
+    }
+
+    let mut detector = MotionDetector::new(Instant::now());
+    let mut fifo = Vec::new();
+    let mut proximity = false;
+    let mut near = false;
+    let mut dark: Option<bool> = None;
+    let mut next_light = Instant::now();
+    loop {
+        let in_call = calls.lock().unwrap().state() != CallState::Idle;
+        if has_light && in_call != proximity {
+            if let Err(e) = light.set_proximity_enabled(in_call) {
+                log::warn!("proximity sensor error: {e:?}");
+            }
+            proximity = in_call;
+            if !proximity && near {
+                near = false;
+                send(&events, SensorEvent::Near(false))?;
+            }
+        }
+
+        imu_int.enable_interrupt()?;
+        let wait = if proximity {
+            PROXIMITY_PERIOD
+        } else {
+            LIGHT_PERIOD
+        };
+        notification.wait(ticks(wait));
+
+        if has_imu {
+            fifo.clear();
+            match imu.read_events(&mut fifo) {
+                Ok(true) => {}
+                Ok(false) => {
+                    log::warn!("IMU reset or fault, restarting it");
+                    has_imu = start_imu(&mut imu, firmware.as_ref());
+                    accel_on = has_imu;
+                    accel_until = Instant::now() + ACCEL_AFTER_MOTION;
+                }
+                Err(e) => log::warn!("IMU read failed: {e:?}"),
+            }
+            let now = Instant::now();
+            for event in fifo.iter() {
+                match *event {
+                    FifoEvent::Motion => {
+                        accel_until = now + ACCEL_AFTER_MOTION;
+                        if !accel_on {
+                            accel_on = set_accelerometer(&mut imu, true);
+                            log_sample = true;
+                            // a fresh start, so holding the last pose again is reported
+                            detector = MotionDetector::new(now);
+                        }
+                    }
+                    FifoEvent::Accelerometer { x, y, z } => {
+                        let g = device_axes(x, y, z);
+                        if log_sample {
+                            log::debug!("acceleration {g:.2?} g");
+                            log_sample = false;
+                        }
+                        if let Some(gesture) = detector.update(g, now) {
+                            send(&events, gesture)?;
+                        }
+                    }
+                    FifoEvent::Meta { .. } => {}
+                }
+            }
+            if accel_on && now >= accel_until {
+                accel_on = !set_accelerometer(&mut imu, false);
+            }
+        }
+
+        if proximity {
+            match light.proximity() {
+                Ok(Some(counts)) => {
+                    let now_near = if near {
+                        counts > FAR_COUNTS
+                    } else {
+                        counts >= NEAR_COUNTS
+                    };
+                    if now_near != near {
+                        near = now_near;
+                        send(&events, SensorEvent::Near(near))?;
+                    }
+                }
+                // saturated by sunlight, keep the last state
+                Ok(None) => {}
+                Err(e) => log::warn!("proximity read failed: {e:?}"),
+            }
+        }
+
+        if has_light && Instant::now() >= next_light {
+            next_light = Instant::now() + LIGHT_PERIOD;
+            match light.lux() {
+                Ok(lux) => {
+                    let now_dark = match dark {
+                        Some(true) => lux < BRIGHT_LUX,
+                        _ => lux < DARK_LUX,
+                    };
+                    if dark != Some(now_dark) {
+                        dark = Some(now_dark);
+                        send(&events, SensorEvent::Dark(now_dark))?;
+                    }
+                }
+                Err(e) => log::warn!("light sensor read failed: {e:?}"),
+            }
+        }
+    }
+}
+
+fn send(events: &Sender<Event>, event: SensorEvent) -> anyhow::Result<()> {
+    events
+        .send(Event::Sensor(event))
+        .map_err(|_| anyhow::anyhow!("display task is gone"))
+}
+
+// Boots the IMU unless its firmware survived an MCU reset, then starts the any-motion
+// detector and the accelerometer.
+fn start_imu<I: I2c>(imu: &mut Bhi260<I>, firmware: Option<&Partition>) -> bool {
+    let running = matches!(imu.kernel_version(), Ok(version) if version != 0);
+    if !running {
+        let Some(firmware) = firmware else {
+            log::warn!("no IMU firmware partition");
+            return false;
+        };
+        let mut len = [0u8; 4];
+        if let Err(e) = firmware.read_at(0, &mut len) {
+            log::warn!("IMU firmware read failed: {e:?}");
+            return false;
+        }
+        let result = imu.boot(&mut FreeRtos, u32::from_le_bytes(len), |offset, buf| {
+            firmware.read_at(4 + offset, buf)
+        });
+        if let Err(e) = result {
+            log::warn!("IMU boot failed: {e:?}");
+            return false;
+        }
+        log::info!("IMU firmware booted");
+    }
+    let configured = imu
+        .configure_interrupt()
+        .and_then(|_| imu.configure_sensor(SENSOR_ANY_MOTION_WAKE, 1.0, Duration::ZERO));
+    if let Err(e) = configured {
+        log::warn!("IMU configuration failed: {e:?}");
+        return false;
+    }
+    set_accelerometer(imu, true)
+}
+
+// Returns whether the accelerometer was switched.
+fn set_accelerometer<I: I2c>(imu: &mut Bhi260<I>, on: bool) -> bool {
+    let rate = if on { ACCEL_RATE_HZ } else { 0.0 };
+    match imu.configure_sensor(SENSOR_ACCELEROMETER, rate, ACCEL_LATENCY) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("IMU accelerometer switch failed: {e:?}");
+            false
+        }
+    }
+}
@@ -0,0 +1,521 @@
+// User settings: a fixed schema of typed values, stored in NVS namespace "settings" (or in
+// memory on the host and without NVS). Every setting is one of a short list of choices so the
+// settings app can step through them with the keyboard; each is stored as a u32 under its own
+// key and read back through typed accessors. A value missing from the store, or one this
+// firmware doesn't know, reads as the default.
+//
+// Every change is announced to the subscribed listeners.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::time::Duration;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+#[cfg(target_os = "espidf")]
+const NAMESPACE: &str = "settings";
+const VERSION_KEY: &str = "version";
+// Bumped whenever a stored value changes meaning, with a migration from the previous version.
+pub const VERSION: u32 = 1;
+type Migration = fn(&mut dyn SettingsBackend) -> Result<(), SettingsError>;
+// MIGRATIONS[n] takes a store from version n to n + 1
+const MIGRATIONS: [Migration; VERSION as usize] = [migrate_unversioned];
+// told of each changed setting, and dropped once it returns false
+type Listener = Box<dyn FnMut(Setting) -> bool + Send>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsError {
+    Storage(String),
+    // not one of the setting's choices
+    Invalid(Setting, u32),
+}
+
+impl SettingsError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        SettingsError::Storage(format!("{:?}", e))
+    }
+}
+
+// Where the values live. NVS keys are at most 15 characters.
+pub trait SettingsBackend: Send {
+    fn get(&self, key: &str) -> Result<Option<u32>, SettingsError>;
+    fn set(&mut self, key: &str, value: u32) -> Result<(), SettingsError>;
+    fn remove(&mut self, key: &str) -> Result<(), SettingsError>;
+}
+
+#[cfg(target_os = "espidf")]
+pub struct NvsBackend {
+    nvs: EspNvs<NvsDefault>,
+}
+
+#[cfg(target_os = "espidf")]
+impl NvsBackend {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self, SettingsError> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true).map_err(SettingsError::from_debug)?;
+        Ok(Self { nvs })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl SettingsBackend for NvsBackend {
+    fn get(&self, key: &str) -> Result<Option<u32>, SettingsError> {
+        self.nvs.get_u32(key).map_err(SettingsError::from_debug)
+    }
+
+    fn set(&mut self, key: &str, value: u32) -> Result<(), SettingsError> {
+        self.nvs
+            .set_u32(key, value)
+            .map_err(SettingsError::from_debug)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), SettingsError> {
+        self.nvs
+            .remove(key)
+            .map(|_| ())
+            .map_err(SettingsError::from_debug)
+    }
+}
+
+// Lost on reset; for host tests and devices without NVS.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    values: HashMap<String, u32>,
+}
+
+impl SettingsBackend for MemoryBackend {
+    fn get(&self, key: &str) -> Result<Option<u32>, SettingsError> {
+        Ok(self.values.get(key).copied())
+    }
+
+    fn set(&mut self, key: &str, value: u32) -> Result<(), SettingsError> {
+        self.values.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), SettingsError> {
+        self.values.remove(key);
+        Ok(())
+    }
+}
+
+// Stores written before the settings had a version (none were, the store starts empty).
+fn migrate_unversioned(_backend: &mut dyn SettingsBackend) -> Result<(), SettingsError> {
+    Ok(())
+}
+
+// Brings a store up to VERSION one step at a time, recording each step so an interrupted
+// migration carries on where it stopped. A store from newer firmware is left alone.
+pub fn migrate(backend: &mut dyn SettingsBackend) -> Result<(), SettingsError> {
+    let stored = backend.get(VERSION_KEY)?.unwrap_or(0);
+    if stored > VERSION {
+        log::warn!("settings are from newer firmware (version {stored}), reading what is known");
+        return Ok(());
+    }
+    for version in stored..VERSION {
+        MIGRATIONS[version as usize](backend)?;
+        backend.set(VERSION_KEY, version + 1)?;
+        log::info!("settings migrated to version {}", version + 1);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Rotation,
+    EpdSpiClock,
+    FullRefresh,
+    ScreenOff,
+    DeepSleep,
+    Alerts,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 6] = [
+        Setting::Rotation,
+        Setting::EpdSpiClock,
+        Setting::FullRefresh,
+        Setting::ScreenOff,
+        Setting::DeepSleep,
+        Setting::Alerts,
+    ];
+
+    fn key(self) -> &'static str {
+        match self {
+            Setting::Rotation => "rotation",
+            Setting::EpdSpiClock => "epd_spi_hz",
+            Setting::FullRefresh => "full_refresh",
+            Setting::ScreenOff => "idle_after_s",
+            Setting::DeepSleep => "sleep_after_s",
+            Setting::Alerts => "alerts",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Setting::Rotation => "Rotation",
+            Setting::EpdSpiClock => "Display SPI",
+            Setting::FullRefresh => "Full refresh",
+            Setting::ScreenOff => "Screen off",
+            Setting::DeepSleep => "Deep sleep",
+            Setting::Alerts => "Alerts",
+        }
+    }
+
+    pub fn default(self) -> u32 {
+        match self {
+            Setting::Rotation => 1,
+            Setting::EpdSpiClock => 10_000_000,
+            Setting::FullRefresh => 0,
+            Setting::ScreenOff => 30,
+            Setting::DeepSleep => 5 * 60,
+            Setting::Alerts => 0,
+        }
+    }
+
+    // values with their labels, in the order the settings app steps through them
+    pub fn choices(self) -> &'static [(u32, &'static str)] {
+        match self {
+            // quarter turns of the panel, which is portrait
+            Setting::Rotation => &[
+                (0, "Portrait"),
+                (1, "Landscape"),
+                (2, "Portrait, flipped"),
+                (3, "Landscape, flipped"),
+            ],
+            // Hz
+            Setting::EpdSpiClock => &[
+                (4_000_000, "4 MHz"),
+                (10_000_000, "10 MHz"),
+                (20_000_000, "20 MHz"),
+            ],
+            // partial refreshes between full ones, 0 leaves it to the apps
+            Setting::FullRefresh => &[
+                (0, "When apps ask"),
+                (5, "Every 5 updates"),
+                (10, "Every 10 updates"),
+                (20, "Every 20 updates"),
+                (50, "Every 50 updates"),
+            ],
+            // seconds
+            Setting::ScreenOff => &[
+                (15, "15 s"),
+                (30, "30 s"),
+                (60, "1 min"),
+                (2 * 60, "2 min"),
+                (5 * 60, "5 min"),
+            ],
+            Setting::DeepSleep => &[
+                (60, "1 min"),
+                (5 * 60, "5 min"),
+                (15 * 60, "15 min"),
+                (30 * 60, "30 min"),
+                (60 * 60, "1 hour"),
+            ],
+            // how notifications are announced
+            Setting::Alerts => &[
+                (0, "Sound and vibration"),
+                (1, "Vibration only"),
+                (2, "Silent"),
+            ],
+        }
+    }
+
+    // taken at start-up only
+    pub fn needs_restart(self) -> bool {
+        self == Setting::EpdSpiClock
+    }
+
+    pub fn is_valid(self, value: u32) -> bool {
+        self.choices().iter().any(|&(v, _)| v == value)
+    }
+
+    pub fn value_label(self, value: u32) -> &'static str {
+        self.choices()
+            .iter()
+            .find(|&&(v, _)| v == value)
+            .map_or("?", |&(_, label)| label)
+    }
+}
+
+// Timeouts of the power manager, two of them from the settings (`Settings::power_config`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerConfig {
+    // without input for this long the backlight, panel and GNSS go off and the chip may
+    // light sleep
+    pub idle_after: Duration,
+    // and after this long, deep sleep
+    pub sleep_after: Duration,
+    // RTC timer wake from deep sleep, to refresh the screen and battery level; None sleeps
+    // until a key or the modem
+    pub wake_period: Option<Duration>,
+    // how long a timer wake stays up without input
+    pub timer_wake_time: Duration,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            idle_after: Duration::from_secs(30),
+            sleep_after: Duration::from_secs(5 * 60),
+            wake_period: Some(Duration::from_secs(30 * 60)),
+            timer_wake_time: Duration::from_secs(20),
+        }
+    }
+}
+
+pub struct Settings {
+    backend: Box<dyn SettingsBackend>,
+    // by `Setting as usize`
+    values: [u32; Setting::ALL.len()],
+    listeners: Vec<Listener>,
+}
+
+impl Settings {
+    // Settings from NVS, or defaults kept in memory without it.
+    #[cfg(target_os = "espidf")]
+    pub fn open(partition: Option<EspDefaultNvsPartition>) -> Self {
+        let backend: Box<dyn SettingsBackend> = match partition.map(NvsBackend::new) {
+            Some(Ok(nvs)) => Box::new(nvs),
+            Some(Err(e)) => {
+                log::warn!("settings unavailable, using defaults: {e:?}");
+                Box::<MemoryBackend>::default()
+            }
+            None => Box::<MemoryBackend>::default(),
+        };
+        Self::load(backend)
+    }
+
+    pub fn load(mut backend: Box<dyn SettingsBackend>) -> Self {
+        if let Err(e) = migrate(backend.as_mut()) {
+            log::warn!("settings migration failed: {e:?}");
+        }
+        let values = Setting::ALL.map(|setting| match backend.get(setting.key()) {
+            Ok(Some(value)) if setting.is_valid(value) => value,
+            Ok(Some(value)) => {
+                log::warn!("ignoring {setting:?} = {value}");
+                setting.default()
+            }
+            Ok(None) => setting.default(),
+            Err(e) => {
+                log::warn!("reading {setting:?} failed: {e:?}");
+                setting.default()
+            }
+        });
+        Self {
+            backend,
+            values,
+            listeners: Vec::new(),
+        }
+    }
+
+    // `listener` is called with each setting whose value changes
+    pub fn subscribe(&mut self, listener: impl FnMut(Setting) -> bool + Send + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub fn get(&self, setting: Setting) -> u32 {
+        self.values[setting as usize]
+    }
+
+    pub fn set(&mut self, setting: Setting, value: u32) -> Result<(), SettingsError> {
+        if !setting.is_valid(value) {
+            return Err(SettingsError::Invalid(setting, value));
+        }
+        if self.get(setting) == value {
+            return Ok(());
+        }
+        // the default is not stored, so a later firmware with a better default applies it
+        if value == setting.default() {
+            self.backend.remove(setting.key())?;
+        } else {
+            self.backend.set(setting.key(), value)?;
+        }
+        self.values[setting as usize] = value;
+        log::info!("{setting:?} set to {value}");
+        // drop listeners whose task has gone
+        self.listeners.retain_mut(|listener| listener(setting));
+        Ok(())
+    }
+
+    pub fn reset(&mut self, setting: Setting) -> Result<(), SettingsError> {
+        self.set(setting, setting.default())
+    }
+
+    // Moves `steps` choices on (back when negative), wrapping around.
+    pub fn step(&mut self, setting: Setting, steps: i32) -> Result<(), SettingsError> {
+        let choices = setting.choices();
+        let current = choices
+            .iter()
+            .position(|&(v, _)| v == self.get(setting))
+            .unwrap_or(0);
+        let next = (current as i32 + steps).rem_euclid(choices.len() as i32) as usize;
+        self.set(setting, choices[next].0)
+    }
+
+    // 0 to 3 quarter turns, as taken by the panel driver and touch.rs
+    pub fn rotation(&self) -> u8 {
+        self.get(Setting::Rotation) as u8
+    }
+
+    pub fn epd_spi_hz(&self) -> u32 {
+        self.get(Setting::EpdSpiClock)
+    }
+
+    // partial refreshes before a full one, None when only apps ask for them
+    pub fn full_refresh_every(&self) -> Option<u32> {
+        Some(self.get(Setting::FullRefresh)).filter(|&n| n > 0)
+    }
+
+    // whether notifications beep and buzz
+    pub fn alert_sound(&self) -> bool {
+        self.get(Setting::Alerts) == 0
+    }
+
+    pub fn alert_vibration(&self) -> bool {
+        self.get(Setting::Alerts) < 2
+    }
+
+    pub fn power_config(&self) -> PowerConfig {
+        PowerConfig {
+            idle_after: Duration::from_secs(self.get(Setting::ScreenOff) as u64),
+            sleep_after: Duration::from_secs(self.get(Setting::DeepSleep) as u64),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn stored(settings: &Settings, setting: Setting) -> Option<u32> {
+        settings.backend.get(setting.key()).unwrap()
+    }
+
+    fn backend(values: &[(&str, u32)]) -> Box<MemoryBackend> {
+        let mut backend = MemoryBackend::default();
+        for &(key, value) in values {
+            backend.set(key, value).unwrap();
+        }
+        Box::new(backend)
+    }
+
+    #[test]
+    fn migrate_empty_store() {
+        let mut backend = MemoryBackend::default();
+        migrate(&mut backend).unwrap();
+        assert_eq!(backend.get(VERSION_KEY).unwrap(), Some(VERSION));
+        // nothing but the version is written
+        assert_eq!(backend.values.len(), 1);
+        // and a second run has nothing to do
+        migrate(&mut backend).unwrap();
+        assert_eq!(backend.get(VERSION_KEY).unwrap(), Some(VERSION));
+    }
+
+    #[test]
+    fn migrate_newer_store() {
+        let newer = VERSION + 1;
+        let mut backend = backend(&[
+            (VERSION_KEY, newer),
+            ("rotation", 2),
+            ("alerts", 7),
+            ("new_setting", 1),
+        ]);
+        migrate(backend.as_mut()).unwrap();
+        assert_eq!(backend.get(VERSION_KEY).unwrap(), Some(newer));
+        assert_eq!(backend.get("new_setting").unwrap(), Some(1));
+
+        // what this firmware knows is read, a value it doesn't is the default
+        let settings = Settings::load(backend);
+        assert_eq!(settings.rotation(), 2);
+        assert_eq!(settings.get(Setting::Alerts), Setting::Alerts.default());
+        assert_eq!(stored(&settings, Setting::Alerts), Some(7));
+    }
+
+    #[test]
+    fn defaults() {
+        let settings = Settings::load(Box::<MemoryBackend>::default());
+        for setting in Setting::ALL {
+            assert_eq!(settings.get(setting), setting.default());
+            assert!(setting.is_valid(setting.default()), "{setting:?}");
+        }
+        assert_eq!(settings.full_refresh_every(), None);
+        assert!(settings.alert_sound() && settings.alert_vibration());
+    }
+
+    #[test]
+    fn invalid_value_rejected() {
+        let mut settings = Settings::load(Box::<MemoryBackend>::default());
+        assert_eq!(
+            settings.set(Setting::Rotation, 4),
+            Err(SettingsError::Invalid(Setting::Rotation, 4))
+        );
+        assert_eq!(
+            settings.set(Setting::ScreenOff, 45),
+            Err(SettingsError::Invalid(Setting::ScreenOff, 45))
+        );
+        assert_eq!(settings.get(Setting::Rotation), Setting::Rotation.default());
+        assert_eq!(stored(&settings, Setting::Rotation), None);
+        assert_eq!(stored(&settings, Setting::ScreenOff), None);
+    }
+
+    #[test]
+    fn default_removed_not_stored() {
+        let mut settings = Settings::load(Box::<MemoryBackend>::default());
+        settings.set(Setting::FullRefresh, 10).unwrap();
+        assert_eq!(settings.full_refresh_every(), Some(10));
+        assert_eq!(stored(&settings, Setting::FullRefresh), Some(10));
+
+        settings.set(Setting::FullRefresh, 0).unwrap();
+        assert_eq!(settings.full_refresh_every(), None);
+        assert_eq!(stored(&settings, Setting::FullRefresh), None);
+
+        settings.set(Setting::Alerts, 2).unwrap();
+        settings.reset(Setting::Alerts).unwrap();
+        assert_eq!(stored(&settings, Setting::Alerts), None);
+        // setting the default on an empty store writes nothing either
+        settings.set(Setting::Rotation, 1).unwrap();
+        assert_eq!(stored(&settings, Setting::Rotation), None);
+    }
+
+    #[test]
+    fn step_wraps() {
+        let mut settings = Settings::load(Box::<MemoryBackend>::default());
+        // "Sound and vibration", "Vibration only", "Silent"
+        settings.step(Setting::Alerts, 1).unwrap();
+        assert!(!settings.alert_sound() && settings.alert_vibration());
+        settings.step(Setting::Alerts, 1).unwrap();
+        assert!(!settings.alert_vibration());
+        settings.step(Setting::Alerts, 1).unwrap();
+        assert_eq!(settings.get(Setting::Alerts), 0);
+        settings.step(Setting::Alerts, -1).unwrap();
+        assert_eq!(settings.get(Setting::Alerts), 2);
+        settings.step(Setting::Alerts, -5).unwrap();
+        assert_eq!(settings.get(Setting::Alerts), 0);
+        settings.step(Setting::Rotation, 3).unwrap();
+        assert_eq!(settings.rotation(), 0);
+    }
+
+    #[test]
+    fn changes_announced() {
+        let mut settings = Settings::load(Box::<MemoryBackend>::default());
+        let (changes, listener) = mpsc::channel();
+        settings.subscribe(move |setting| changes.send(setting).is_ok());
+        settings.set(Setting::DeepSleep, 15 * 60).unwrap();
+        assert_eq!(listener.try_recv(), Ok(Setting::DeepSleep));
+        // only changes are
+        settings.set(Setting::DeepSleep, 15 * 60).unwrap();
+        assert!(listener.try_recv().is_err());
+        assert_eq!(
+            settings.power_config().sleep_after,
+            Duration::from_secs(15 * 60)
+        );
+
+        // a listener that has gone is dropped
+        drop(listener);
+        settings.set(Setting::DeepSleep, 60).unwrap();
+        assert!(settings.listeners.is_empty());
+    }
+}
@@ -0,0 +1,467 @@
+// SMS over the modem in PDU mode, and the mailbox shared between the cellular task (which
+// talks to the modem) and the apps on the display task. Apps queue outgoing messages in the
+// mailbox and tell it which received ones are saved elsewhere, the cellular task sends them,
+// frees the SIM slots of the saved ones and files incoming messages and delivery reports.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::modem::{find_value, Modem, ModemError, Transport, DEFAULT_TIMEOUT};
+use crate::pdu::{self, Concat, Deliver, Pdu, StatusReport, Timestamp};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+const LIST_TIMEOUT: Duration = Duration::from_secs(20);
+// incomplete multipart messages are dropped once this many others are waiting
+const MAX_PARTIAL: usize = 16;
+// read messages saved elsewhere are dropped from memory past this many
+const MAX_INBOX: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: u32,
+    pub from: String,
+    pub timestamp: Timestamp,
+    pub text: String,
+    pub read: bool,
+    // SIM storage indexes of the parts, until the message is saved elsewhere
+    slots: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutgoingStatus {
+    Queued,
+    Sending,
+    // waiting for delivery reports of the remaining parts
+    Sent,
+    Delivered,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outgoing {
+    pub id: u32,
+    pub to: String,
+    pub text: String,
+    pub status: OutgoingStatus,
+    // TP-MR of every sent part, removed as their reports arrive
+    references: Vec<u8>,
+}
+
+// Parts of a concatenated message received so far.
+struct Partial {
+    from: String,
+    concat: Concat,
+    timestamp: Timestamp,
+    parts: Vec<Option<String>>,
+    slots: Vec<u16>,
+}
+
+#[derive(Default)]
+pub struct Mailbox {
+    inbox: Vec<Message>,
+    outbox: Vec<Outgoing>,
+    partial: Vec<Partial>,
+    deleted_slots: Vec<u16>,
+    next_id: u32,
+    // reference for the concatenation header of the next multipart message
+    next_concat: u8,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inbox(&self) -> &[Message] {
+        &self.inbox
+    }
+
+    pub fn message(&self, id: u32) -> Option<&Message> {
+        self.inbox.iter().find(|m| m.id == id)
+    }
+
+    pub fn outgoing(&self, id: u32) -> Option<&Outgoing> {
+        self.outbox.iter().find(|m| m.id == id)
+    }
+
+    pub fn unread(&self) -> usize {
+        self.inbox.iter().filter(|m| !m.read).count()
+    }
+
+    pub fn mark_read(&mut self, id: u32) {
+        if let Some(m) = self.inbox.iter_mut().find(|m| m.id == id) {
+            m.read = true;
+        }
+        self.trim();
+    }
+
+    // Queues a message for the cellular task, returns its outbox id.
+    pub fn send(&mut self, to: &str, text: &str) -> u32 {
+        let id = self.allocate_id();
+        self.outbox.push(Outgoing {
+            id,
+            to: to.to_string(),
+            text: text.to_string(),
+            status: OutgoingStatus::Queued,
+            references: Vec::new(),
+        });
+        id
+    }
+
+    // The message is saved elsewhere (the history on the SD card): its SIM slots are freed
+    // by the cellular task, so the SIM never fills up, and once read it may be dropped here.
+    pub fn saved(&mut self, id: u32) {
+        if let Some(m) = self.inbox.iter_mut().find(|m| m.id == id) {
+            self.deleted_slots.append(&mut m.slots);
+        }
+        self.trim();
+    }
+
+    // Drops the oldest read messages saved elsewhere past MAX_INBOX.
+    fn trim(&mut self) {
+        let mut excess = self.inbox.len().saturating_sub(MAX_INBOX);
+        self.inbox.retain(|m| {
+            let drop = excess > 0 && m.read && m.slots.is_empty();
+            if drop {
+                excess -= 1;
+            }
+            !drop
+        });
+    }
+
+    // whether a message or part in memory is stored at SIM `slot`
+    fn holds(&self, slot: u16) -> bool {
+        self.inbox.iter().any(|m| m.slots.contains(&slot))
+            || self.partial.iter().any(|p| p.slots.contains(&slot))
+    }
+
+    fn allocate_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    // Files a received part stored at SIM `slot`, returns the inbox id once the message
+    // is complete.
+    pub fn receive(&mut self, slot: u16, deliver: Deliver, read: bool) -> Option<u32> {
+        // a part announced with +CMTI while the SIM was being listed at start up
+        if self.holds(slot) {
+            return None;
+        }
+        let Some(concat) = deliver.concat.filter(|c| c.total > 1) else {
+            return Some(self.file(
+                deliver.sender,
+                deliver.timestamp,
+                deliver.text,
+                vec![slot],
+                read,
+            ));
+        };
+        let i = match self.partial.iter().position(|p| {
+            p.from == deliver.sender
+                && p.concat.reference == concat.reference
+                && p.concat.total == concat.total
+        }) {
+            Some(i) => i,
+            None => {
+                if self.partial.len() >= MAX_PARTIAL {
+                    let dropped = self.partial.remove(0);
+                    self.deleted_slots.extend(dropped.slots);
+                }
+                self.partial.push(Partial {
+                    from: deliver.sender.clone(),
+                    concat,
+                    timestamp: deliver.timestamp,
+                    parts: vec![None; concat.total as usize],
+                    slots: Vec::new(),
+                });
+                self.partial.len() - 1
+            }
+        };
+        let partial = &mut self.partial[i];
+        partial.slots.push(slot);
+        if let Some(part) = partial.parts.get_mut(concat.seq.wrapping_sub(1) as usize) {
+            *part = Some(deliver.text);
+        }
+        if concat.seq == 1 {
+            partial.timestamp = deliver.timestamp;
+        }
+        if partial.parts.iter().any(Option::is_none) {
+            return None;
+        }
+        let partial = self.partial.remove(i);
+        let text = partial.parts.into_iter().flatten().collect();
+        Some(self.file(partial.from, partial.timestamp, text, partial.slots, read))
+    }
+
+    fn file(
+        &mut self,
+        from: String,
+        timestamp: Timestamp,
+        text: String,
+        slots: Vec<u16>,
+        read: bool,
+    ) -> u32 {
+        let id = self.allocate_id();
+        self.inbox.push(Message {
+            id,
+            from,
+            timestamp,
+            text,
+            read,
+            slots,
+        });
+        id
+    }
+
+    // Applies a delivery report, returns the outbox id whose status changed.
+    pub fn status_report(&mut self, report: &StatusReport) -> Option<u32> {
+        let message = self.outbox.iter_mut().find(|m| {
+            m.status == OutgoingStatus::Sent && m.references.contains(&report.reference)
+        })?;
+        if report.failed() {
+            message.status = OutgoingStatus::Failed(format!("status {:#04x}", report.status));
+        } else if report.delivered() {
+            message.references.retain(|&r| r != report.reference);
+            if message.references.is_empty() {
+                message.status = OutgoingStatus::Delivered;
+            }
+        } else {
+            // the SMSC is still trying, a final report follows
+            return None;
+        }
+        Some(message.id)
+    }
+
+    fn next_queued(&mut self) -> Option<(u32, String, String, u8)> {
+        let message = self
+            .outbox
+            .iter_mut()
+            .find(|m| m.status == OutgoingStatus::Queued)?;
+        message.status = OutgoingStatus::Sending;
+        let job = (
+            message.id,
+            message.to.clone(),
+            message.text.clone(),
+            self.next_concat,
+        );
+        self.next_concat = self.next_concat.wrapping_add(1);
+        Some(job)
+    }
+
+    fn sent(&mut self, id: u32, result: Result<Vec<u8>, String>) {
+        if let Some(m) = self.outbox.iter_mut().find(|m| m.id == id) {
+            match result {
+                Ok(references) => {
+                    m.references = references;
+                    m.status = OutgoingStatus::Sent;
+                }
+                Err(e) => m.status = OutgoingStatus::Failed(e),
+            }
+        }
+    }
+}
+
+// What the cellular task reports to the display task about the mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmsEvent {
+    Received(u32),
+    // an outbox message was sent, delivered or failed
+    StatusChanged(u32),
+}
+
+// PDU mode, new messages announced with +CMTI and delivery reports stored and announced
+// with +CDSI.
+pub fn init<T: Transport>(modem: &mut Modem<T>) -> Result<(), ModemError> {
+    modem.command("AT+CMGF=0", DEFAULT_TIMEOUT)?;
+    modem.command("AT+CPMS=\"SM\",\"SM\",\"SM\"", DEFAULT_TIMEOUT)?;
+    modem.command("AT+CNMI=2,1,0,2,0", DEFAULT_TIMEOUT)?;
+    Ok(())
+}
+
+// Storage and index from a "+CMTI: "SM",3" or "+CDSI: "SR",1" URC.
+pub fn parse_index_urc(line: &str) -> Option<(String, u16)> {
+    let (_, args) = line.split_once(':')?;
+    let (mem, index) = args.split_once(',')?;
+    Some((
+        mem.trim().trim_matches('"').to_string(),
+        index.trim().parse().ok()?,
+    ))
+}
+
+// Reads every message stored on the SIM as (index, message, read), for `Mailbox::receive`.
+pub fn load<T: Transport>(modem: &mut Modem<T>) -> Result<Vec<(u16, Deliver, bool)>, ModemError> {
+    // 4: all messages; each +CMGL: <index>,<stat>,,<length> line is followed by the PDU
+    let lines = modem.command("AT+CMGL=4", LIST_TIMEOUT)?;
+    let mut stored = Vec::new();
+    let mut iter = lines.iter();
+    while let Some(line) = iter.next() {
+        let Some(header) = line.strip_prefix("+CMGL:") else {
+            continue;
+        };
+        let Some(pdu) = iter.next() else {
+            break;
+        };
+        let mut fields = header.split(',').map(str::trim);
+        let index = fields.next().and_then(|s| s.parse::<u16>().ok());
+        // stat 0 is received unread
+        let read = fields.next() != Some("0");
+        match (index, pdu::decode(pdu)) {
+            (Some(index), Ok(Pdu::Deliver(deliver))) => stored.push((index, deliver, read)),
+            (Some(_), Ok(_)) => {}
+            (_, Err(e)) => log::warn!("undecodable stored SMS {header}: {e:?}"),
+            (None, _) => log::warn!("bad +CMGL line {line:?}"),
+        }
+    }
+    Ok(stored)
+}
+
+// Reads the PDU at `index` of storage `mem`, the current read storage is restored after.
+pub fn read<T: Transport>(modem: &mut Modem<T>, mem: &str, index: u16) -> Result<Pdu, ModemError> {
+    let switch = mem != "SM";
+    if switch {
+        modem.command(&format!("AT+CPMS=\"{mem}\""), DEFAULT_TIMEOUT)?;
+    }
+    let result = modem.command(&format!("AT+CMGR={index}"), DEFAULT_TIMEOUT);
+    if switch {
+        modem.command("AT+CPMS=\"SM\"", DEFAULT_TIMEOUT)?;
+    }
+    let lines = result?;
+    let pdu = lines
+        .iter()
+        .skip_while(|l| !l.starts_with("+CMGR:"))
+        .nth(1)
+        .ok_or_else(|| ModemError::Unexpected(format!("no PDU in {lines:?}")))?;
+    pdu::decode(pdu).map_err(|e| ModemError::Unexpected(format!("{e:?}")))
+}
+
+pub fn delete<T: Transport>(modem: &mut Modem<T>, mem: &str, index: u16) -> Result<(), ModemError> {
+    if mem != "SM" {
+        modem.command(&format!("AT+CPMS=\"{mem}\""), DEFAULT_TIMEOUT)?;
+    }
+    let result = modem.command(&format!("AT+CMGD={index}"), DEFAULT_TIMEOUT);
+    if mem != "SM" {
+        modem.command("AT+CPMS=\"SM\"", DEFAULT_TIMEOUT)?;
+    }
+    result.map(|_| ())
+}
+
+// Sends every part of a message, returns the TP-MR the network assigned to each part.
+pub fn send<T: Transport>(
+    modem: &mut Modem<T>,
+    to: &str,
+    text: &str,
+    concat_reference: u8,
+) -> Result<Vec<u8>, ModemError> {
+    let pdus = pdu::encode_submit(to, text, concat_reference, true)
+        .map_err(|e| ModemError::Unexpected(format!("{e:?}")))?;
+    let mut references = Vec::new();
+    for pdu in pdus {
+        let lines = modem.command_with_payload(
+            &format!("AT+CMGS={}", pdu.length),
+            &pdu.hex,
+            SEND_TIMEOUT,
+        )?;
+        let reference = find_value(&lines, "+CMGS:")
+            .and_then(|v| v.parse::<u8>().ok())
+            .ok_or_else(|| ModemError::Unexpected(format!("no reference in {lines:?}")))?;
+        references.push(reference);
+    }
+    Ok(references)
+}
+
+// Sends the next queued outbox message, returns its id. The mailbox is only locked around
+// the bookkeeping, not while the modem is busy.
+pub fn send_queued<T: Transport>(modem: &mut Modem<T>, mailbox: &Mutex<Mailbox>) -> Option<u32> {
+    let (id, to, text, concat) = mailbox.lock().unwrap().next_queued()?;
+    let result = send(modem, &to, &text, concat).map_err(|e| match e {
+        ModemError::Command(e) => e,
+        e => format!("{e:?}"),
+    });
+    if let Err(e) = &result {
+        log::warn!("SMS to {to} failed: {e}");
+    }
+    mailbox.lock().unwrap().sent(id, result);
+    Some(id)
+}
+
+// Frees the SIM slots of messages saved elsewhere and of dropped parts.
+pub fn purge<T: Transport>(modem: &mut Modem<T>, mailbox: &Mutex<Mailbox>) {
+    let slots = std::mem::take(&mut mailbox.lock().unwrap().deleted_slots);
+    for slot in slots {
+        if let Err(e) = delete(modem, "SM", slot) {
+            log::warn!("deleting SMS {slot} failed: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(text: &str, concat: Option<Concat>) -> Deliver {
+        Deliver {
+            sender: "+46708251358".to_string(),
+            timestamp: Timestamp::default(),
+            text: text.to_string(),
+            concat,
+        }
+    }
+
+    fn part(seq: u8) -> Option<Concat> {
+        Some(Concat {
+            reference: 7,
+            total: 2,
+            seq,
+        })
+    }
+
+    #[test]
+    fn receive_once() {
+        let mut mailbox = Mailbox::new();
+        assert!(mailbox.receive(1, deliver("Hello", None), false).is_some());
+        // the +CMTI for a message listed at start up
+        assert_eq!(mailbox.receive(1, deliver("Hello", None), false), None);
+        assert_eq!(mailbox.unread(), 1);
+
+        assert_eq!(mailbox.receive(3, deliver("world", part(2)), false), None);
+        assert_eq!(mailbox.receive(3, deliver("world", part(2)), false), None);
+        let id = mailbox
+            .receive(2, deliver("Hello ", part(1)), false)
+            .unwrap();
+        assert_eq!(mailbox.message(id).unwrap().text, "Hello world");
+        assert_eq!(mailbox.inbox().len(), 2);
+    }
+
+    #[test]
+    fn saved_frees_slots() {
+        let mut mailbox = Mailbox::new();
+        mailbox.receive(5, deliver("Hello ", part(1)), false);
+        let id = mailbox
+            .receive(6, deliver("world", part(2)), false)
+            .unwrap();
+        mailbox.saved(id);
+        assert_eq!(mailbox.deleted_slots, [5, 6]);
+        // unread, it stays
+        assert!(mailbox.message(id).is_some());
+        mailbox.saved(id);
+        assert_eq!(mailbox.deleted_slots, [5, 6]);
+
+        // the oldest read ones past the limit are dropped once saved
+        let ids: Vec<u32> = (0..MAX_INBOX as u16)
+            .map(|slot| {
+                mailbox
+                    .receive(10 + slot, deliver("Hi", None), true)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(mailbox.inbox().len(), MAX_INBOX + 1);
+        mailbox.mark_read(id);
+        assert!(mailbox.message(id).is_none());
+        mailbox.saved(ids[1]);
+        assert_eq!(mailbox.inbox().len(), MAX_INBOX);
+        mailbox.receive(100, deliver("Hi", None), true);
+        mailbox.saved(ids[0]);
+        assert!(mailbox.message(ids[0]).is_none());
+        assert!(mailbox.message(ids[1]).is_some());
+    }
+}
@@ -0,0 +1,108 @@
+// SNTP over Wi-Fi, the most accurate time source. Wi-Fi is only brought up while the clock
+// wants a sync, then turned off again. The network is read from NVS namespace "wifi", keys
+// "ssid" and "password" (empty or missing for an open network).
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::modem::Modem;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use esp_idf_svc::sntp::{EspSntp, SyncStatus};
+use esp_idf_svc::wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi};
+
+use crate::clock::TimeKeeper;
+use crate::timesource::TimeSource;
+
+const NAMESPACE: &str = "wifi";
+// how often to ask the clock whether it wants a sync
+const CHECK_PERIOD: Duration = Duration::from_secs(10 * 60);
+// after a failed attempt, e.g. out of range of the network
+const RETRY_PERIOD: Duration = Duration::from_secs(60 * 60);
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+const SYNC_POLL: Duration = Duration::from_millis(500);
+
+fn credentials(nvs: EspDefaultNvsPartition) -> anyhow::Result<Option<(String, String)>> {
+    // the namespace only exists once something has been stored in it
+    let Ok(nvs) = EspNvs::new(nvs, NAMESPACE, false) else {
+        return Ok(None);
+    };
+    let mut ssid = [0u8; 33];
+    let mut password = [0u8; 65];
+    let Some(ssid) = nvs.get_str("ssid", &mut ssid)? else {
+        return Ok(None);
+    };
+    let password = nvs.get_str("password", &mut password)?.unwrap_or("");
+    Ok(Some((ssid.to_string(), password.to_string())))
+}
+
+pub fn run(
+    modem: Modem,
+    sysloop: EspSystemEventLoop,
+    nvs: EspDefaultNvsPartition,
+    clock: Arc<Mutex<TimeKeeper>>,
+) -> anyhow::Result<()> {
+    let Some((ssid, password)) = credentials(nvs.clone()).unwrap_or_else(|e| {
+        log::warn!("reading Wi-Fi settings failed: {e:?}");
+        None
+    }) else {
+        log::info!("no Wi-Fi network set up, SNTP disabled");
+        return Ok(());
+    };
+    let mut wifi = BlockingWifi::wrap(EspWifi::new(modem, sysloop.clone(), Some(nvs))?, sysloop)?;
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        ssid: ssid
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("SSID too long"))?,
+        password: password
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Wi-Fi password too long"))?,
+        auth_method: if password.is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::WPA2Personal
+        },
+        ..Default::default()
+    }))?;
+
+    loop {
+        if !clock.lock().unwrap().wants(TimeSource::Sntp) {
+            thread::sleep(CHECK_PERIOD);
+            continue;
+        }
+        let result = sync(&mut wifi);
+        if let Err(e) = wifi.stop() {
+            log::warn!("stopping Wi-Fi failed: {e:?}");
+        }
+        match result {
+            Ok(()) => {
+                clock.lock().unwrap().synced(TimeSource::Sntp);
+                log::info!("clock set from SNTP");
+                thread::sleep(CHECK_PERIOD);
+            }
+            Err(e) => {
+                log::warn!("SNTP sync failed: {e:?}");
+                thread::sleep(RETRY_PERIOD);
+            }
+        }
+    }
+}
+
+// Connects and waits for SNTP to set the system clock.
+fn sync(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<()> {
+    wifi.start()?;
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+    let sntp = EspSntp::new_default()?;
+    let deadline = Instant::now() + SYNC_TIMEOUT;
+    while sntp.get_sync_status() != SyncStatus::Completed {
+        if Instant::now() >= deadline {
+            anyhow::bail!("no answer from the time server");
+        }
+        thread::sleep(SYNC_POLL);
+    }
+    Ok(())
+}
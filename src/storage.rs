@@ -0,0 +1,270 @@
+// microSD card on the shared SPI bus, mounted as FAT through the ESP-IDF VFS so everything
+// else uses std::fs. Each kind of data has a directory of its own under MOUNT_POINT. There
+// is no card detect pin: the storage task checks the card now and then and remounts it
+// after it was taken out and put back. Apps go through `Storage`, which refuses access while
+// no card is mounted.
+
+use std::fmt::Debug;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_hal::gpio::{AnyIOPin, OutputPin};
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_hal::spi::SpiDriver;
+use esp_idf_svc::fs::fatfs::Fatfs;
+use esp_idf_svc::io::vfs::MountedFatfs;
+use esp_idf_svc::sd::spi::SdSpiHostDriver;
+use esp_idf_svc::sd::{SdCardConfiguration, SdCardDriver};
+use esp_idf_svc::sys;
+
+use crate::event::Event;
+
+pub const MOUNT_POINT: &str = "/sd";
+// FAT logical drive of the card
+const DRIVE: u8 = 0;
+const MAX_OPEN_FILES: usize = 4;
+// between checks that a mounted card is still there
+const CHECK_PERIOD: Duration = Duration::from_secs(5);
+// between attempts to mount when there is no (usable) card
+const RETRY_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Area {
+    Messages,
+    // vCard files, see contacts.rs
+    Contacts,
+    // map tiles, see tiles.rs
+    Maps,
+    Fonts,
+    Logs,
+    // device state kept across restarts, e.g. pending notifications
+    System,
+}
+
+impl Area {
+    const ALL: [Area; 6] = [
+        Area::Messages,
+        Area::Contacts,
+        Area::Maps,
+        Area::Fonts,
+        Area::Logs,
+        Area::System,
+    ];
+
+    fn dir(self) -> &'static str {
+        match self {
+            Area::Messages => "messages",
+            Area::Contacts => "contacts",
+            Area::Maps => "maps",
+            Area::Fonts => "fonts",
+            Area::Logs => "logs",
+            Area::System => "system",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageStatus {
+    NoCard,
+    Mounted,
+    // a card answers but has no readable FAT filesystem
+    Unreadable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    NotMounted,
+    NotFound(String),
+    Io(String),
+}
+
+impl StorageError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        StorageError::Io(format!("{:?}", e))
+    }
+}
+
+fn io_error(e: io::Error, name: &str) -> StorageError {
+    match e.kind() {
+        io::ErrorKind::NotFound => StorageError::NotFound(name.to_string()),
+        _ => StorageError::from_debug(e),
+    }
+}
+
+// Files on the card by area. `root` is where the card is mounted, any directory on the host.
+pub struct Storage {
+    root: PathBuf,
+    status: StorageStatus,
+}
+
+impl Storage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            status: StorageStatus::NoCard,
+        }
+    }
+
+    pub fn status(&self) -> StorageStatus {
+        self.status
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.status == StorageStatus::Mounted
+    }
+
+    // Set by the storage task; a newly mounted card gets the area directories.
+    pub fn set_status(&mut self, status: StorageStatus) {
+        self.status = status;
+        if status == StorageStatus::Mounted {
+            for area in Area::ALL {
+                if let Err(e) = fs::create_dir_all(self.dir(area)) {
+                    log::warn!("creating {:?} directory failed: {e:?}", area);
+                }
+            }
+        }
+    }
+
+    // where an area lives, whether or not a card is mounted (e.g. for `TileStore`)
+    pub fn dir(&self, area: Area) -> PathBuf {
+        self.root.join(area.dir())
+    }
+
+    pub fn path(&self, area: Area, name: &str) -> Result<PathBuf, StorageError> {
+        if !self.is_mounted() {
+            return Err(StorageError::NotMounted);
+        }
+        Ok(self.dir(area).join(name))
+    }
+
+    pub fn read(&self, area: Area, name: &str) -> Result<Vec<u8>, StorageError> {
+        fs::read(self.path(area, name)?).map_err(|e| io_error(e, name))
+    }
+
+    // Replaces the file as a whole: written next to it, then renamed over it, so pulling the
+    // card mid-write leaves the old contents.
+    pub fn write(&self, area: Area, name: &str, data: &[u8]) -> Result<(), StorageError> {
+        let path = self.path(area, name)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data).map_err(StorageError::from_debug)?;
+        // FAT does not rename over an existing file
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(StorageError::from_debug(e)),
+        }
+        fs::rename(&tmp, &path).map_err(StorageError::from_debug)
+    }
+
+    // adds to the end of the file, creating it if needed (logs)
+    pub fn append(&self, area: Area, name: &str, data: &[u8]) -> Result<(), StorageError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(area, name)?)
+            .map_err(StorageError::from_debug)?;
+        file.write_all(data).map_err(StorageError::from_debug)
+    }
+
+    pub fn remove(&self, area: Area, name: &str) -> Result<(), StorageError> {
+        fs::remove_file(self.path(area, name)?).map_err(|e| io_error(e, name))
+    }
+
+    // names of the files in an area, sorted
+    pub fn list(&self, area: Area) -> Result<Vec<String>, StorageError> {
+        if !self.is_mounted() {
+            return Err(StorageError::NotMounted);
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(self.dir(area)).map_err(StorageError::from_debug)? {
+            let entry = entry.map_err(StorageError::from_debug)?;
+            if entry.file_type().is_ok_and(|t| t.is_file()) {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+// Mounts the card at MOUNT_POINT, unmounted again when the returned value is dropped. Fails
+// with NoCard when no card answers and Unreadable when its filesystem can't be mounted.
+fn mount<'d>(
+    spi: Arc<SpiDriver<'d>>,
+    cs: impl Peripheral<P = impl OutputPin> + 'd,
+) -> Result<impl Sized + 'd, StorageStatus> {
+    let no_card = |e: sys::EspError| {
+        log::debug!("no SD card: {e:?}");
+        StorageStatus::NoCard
+    };
+    let unreadable = |e: sys::EspError| {
+        log::warn!("SD card filesystem: {e:?}");
+        StorageStatus::Unreadable
+    };
+    let host = SdSpiHostDriver::new(
+        spi,
+        Some(cs),
+        AnyIOPin::none(),
+        AnyIOPin::none(),
+        AnyIOPin::none(),
+        None,
+    )
+    .map_err(no_card)?;
+    let card = SdCardDriver::new_spi(host, &SdCardConfiguration::new()).map_err(no_card)?;
+    let fatfs = Fatfs::new_sdcard(DRIVE, card).map_err(unreadable)?;
+    // every access starts with a status command, so a card taken out fails with an error
+    // rather than answering from FatFs' cache
+    unsafe { sys::ff_sdmmc_set_disk_status_check(DRIVE, true) };
+    MountedFatfs::mount(fatfs, MOUNT_POINT, MAX_OPEN_FILES).map_err(unreadable)
+}
+
+// the card is there and its filesystem readable
+fn probe() -> bool {
+    fs::read_dir(MOUNT_POINT).is_ok()
+}
+
+// Storage task: keeps the card mounted while there is one and reports changes with
+// `Event::Storage`.
+pub fn run<CS: OutputPin>(
+    spi: Arc<SpiDriver<'static>>,
+    mut cs: CS,
+    storage: Arc<Mutex<Storage>>,
+    events: Sender<Event>,
+) -> anyhow::Result<()> {
+    let report = |status: StorageStatus| -> anyhow::Result<()> {
+        let mut storage = storage.lock().unwrap();
+        if storage.status() == status {
+            return Ok(());
+        }
+        log::info!("SD card: {status:?}");
+        storage.set_status(status);
+        drop(storage);
+        events
+            .send(Event::Storage(status))
+            .map_err(|_| anyhow::anyhow!("display task is gone"))
+    };
+    loop {
+        // the card stays off MISO while the SPI driver does not drive its CS
+        unsafe { sys::gpio_pullup_en(cs.pin()) };
+        let status = match mount(spi.clone(), &mut cs) {
+            // the filesystem is only read on first access
+            Ok(mounted) if probe() => {
+                report(StorageStatus::Mounted)?;
+                while probe() {
+                    thread::sleep(CHECK_PERIOD);
+                }
+                drop(mounted);
+                StorageStatus::NoCard
+            }
+            Ok(_) => StorageStatus::Unreadable,
+            Err(status) => status,
+        };
+        report(status)?;
+        thread::sleep(RETRY_PERIOD);
+    }
+}
@@ -0,0 +1,540 @@
+// Semtech SX1262 LoRa transceiver datasheet: https://www.semtech.com/products/wireless-rf/lora-connect/sx1262
+//
+// Only the LoRa packet engine is supported. Every command waits for BUSY to go low first, so
+// the driver works on a shared bus device without holding the bus while the radio is busy.
+
+use std::fmt::Debug;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::spi::{Operation, SpiDevice};
+
+// commands
+const SET_SLEEP: u8 = 0x84;
+const SET_STANDBY: u8 = 0x80;
+const SET_TX: u8 = 0x83;
+const SET_RX: u8 = 0x82;
+const SET_CAD: u8 = 0xC5;
+const SET_REGULATOR_MODE: u8 = 0x96;
+const CALIBRATE: u8 = 0x89;
+const CALIBRATE_IMAGE: u8 = 0x98;
+const SET_PA_CONFIG: u8 = 0x95;
+const WRITE_REGISTER: u8 = 0x0D;
+const READ_REGISTER: u8 = 0x1D;
+const WRITE_BUFFER: u8 = 0x0E;
+const READ_BUFFER: u8 = 0x1E;
+const SET_DIO_IRQ_PARAMS: u8 = 0x08;
+const GET_IRQ_STATUS: u8 = 0x12;
+const CLEAR_IRQ_STATUS: u8 = 0x02;
+const SET_DIO2_AS_RF_SWITCH: u8 = 0x9D;
+const SET_DIO3_AS_TCXO: u8 = 0x97;
+const SET_RF_FREQUENCY: u8 = 0x86;
+const SET_PACKET_TYPE: u8 = 0x8A;
+const SET_TX_PARAMS: u8 = 0x8E;
+const SET_MODULATION_PARAMS: u8 = 0x8B;
+const SET_PACKET_PARAMS: u8 = 0x8C;
+const SET_CAD_PARAMS: u8 = 0x88;
+const SET_BUFFER_BASE_ADDRESS: u8 = 0x8F;
+const GET_RX_BUFFER_STATUS: u8 = 0x13;
+const GET_PACKET_STATUS: u8 = 0x14;
+const GET_DEVICE_ERRORS: u8 = 0x17;
+const CLEAR_DEVICE_ERRORS: u8 = 0x07;
+
+// registers
+const REG_SYNC_WORD: u16 = 0x0740;
+const REG_IQ_POLARITY: u16 = 0x0736;
+const REG_TX_CLAMP: u16 = 0x08D8;
+const REG_RX_GAIN: u16 = 0x08AC;
+const REG_OCP: u16 = 0x08E7;
+
+// IRQ bits, routed to DIO1
+pub const IRQ_TX_DONE: u16 = 1 << 0;
+pub const IRQ_RX_DONE: u16 = 1 << 1;
+pub const IRQ_HEADER_ERROR: u16 = 1 << 5;
+pub const IRQ_CRC_ERROR: u16 = 1 << 6;
+pub const IRQ_CAD_DONE: u16 = 1 << 7;
+pub const IRQ_CAD_DETECTED: u16 = 1 << 8;
+pub const IRQ_TIMEOUT: u16 = 1 << 9;
+pub const IRQ_ALL: u16 = 0x03FF;
+
+const PACKET_TYPE_LORA: u8 = 0x01;
+const STANDBY_RC: u8 = 0x00;
+const REGULATOR_DC_DC: u8 = 0x01;
+const CALIBRATE_ALL: u8 = 0x7F;
+// 0xFFFFFF keeps the receiver on until a packet arrives or it is stopped
+const RX_CONTINUOUS: u32 = 0xFF_FFFF;
+const MAX_PAYLOAD: usize = 255;
+const XTAL_HZ: u64 = 32_000_000;
+const BUSY_TIMEOUT_US: u32 = 100_000;
+
+#[derive(Debug)]
+pub enum RadioError {
+    Spi(String),
+    Pin(String),
+    // BUSY stayed high, the chip is stuck or not powered
+    Busy,
+    PayloadTooLong,
+}
+impl RadioError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        RadioError::Spi(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bandwidth {
+    Khz7_8,
+    Khz10_4,
+    Khz15_6,
+    Khz20_8,
+    Khz31_25,
+    Khz41_7,
+    Khz62_5,
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    fn code(self) -> u8 {
+        match self {
+            Bandwidth::Khz7_8 => 0x00,
+            Bandwidth::Khz10_4 => 0x08,
+            Bandwidth::Khz15_6 => 0x01,
+            Bandwidth::Khz20_8 => 0x09,
+            Bandwidth::Khz31_25 => 0x02,
+            Bandwidth::Khz41_7 => 0x0A,
+            Bandwidth::Khz62_5 => 0x03,
+            Bandwidth::Khz125 => 0x04,
+            Bandwidth::Khz250 => 0x05,
+            Bandwidth::Khz500 => 0x06,
+        }
+    }
+
+    pub fn hz(self) -> u32 {
+        match self {
+            Bandwidth::Khz7_8 => 7_810,
+            Bandwidth::Khz10_4 => 10_420,
+            Bandwidth::Khz15_6 => 15_630,
+            Bandwidth::Khz20_8 => 20_830,
+            Bandwidth::Khz31_25 => 31_250,
+            Bandwidth::Khz41_7 => 41_670,
+            Bandwidth::Khz62_5 => 62_500,
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+}
+
+// 4/5 to 4/8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodingRate {
+    Cr4_5 = 1,
+    Cr4_6 = 2,
+    Cr4_7 = 3,
+    Cr4_8 = 4,
+}
+
+// Supply for a TCXO driven from DIO3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcxoVoltage {
+    V1_6 = 0,
+    V1_7 = 1,
+    V1_8 = 2,
+    V2_2 = 3,
+    V2_4 = 4,
+    V2_7 = 5,
+    V3_0 = 6,
+    V3_3 = 7,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoraConfig {
+    pub frequency_hz: u32,
+    // 5 to 12
+    pub spreading_factor: u8,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    pub preamble_len: u16,
+    // one byte sync word as used by other LoRa stacks, 0x12 private, 0x34 LoRaWAN
+    pub sync_word: u8,
+    // -9 to 22 dBm
+    pub tx_power_dbm: i8,
+    pub crc: bool,
+    pub tcxo: Option<TcxoVoltage>,
+    // the antenna switch is driven by DIO2
+    pub dio2_rf_switch: bool,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            frequency_hz: 868_000_000,
+            spreading_factor: 11,
+            bandwidth: Bandwidth::Khz250,
+            coding_rate: CodingRate::Cr4_5,
+            preamble_len: 16,
+            sync_word: 0x12,
+            tx_power_dbm: 14,
+            crc: true,
+            tcxo: None,
+            dio2_rf_switch: true,
+        }
+    }
+}
+
+impl LoraConfig {
+    // low data rate optimisation is required once a symbol takes longer than 16 ms
+    fn low_data_rate(&self) -> bool {
+        let symbol_us = (1u64 << self.spreading_factor) * 1_000_000 / self.bandwidth.hz() as u64;
+        symbol_us >= 16_380
+    }
+
+    // Time on air of a packet with `len` payload bytes and an explicit header.
+    pub fn time_on_air_ms(&self, len: usize) -> u32 {
+        let sf = self.spreading_factor as i64;
+        let de = self.low_data_rate() as i64;
+        let crc = self.crc as i64;
+        let bits = 8 * len as i64 - 4 * sf + 28 + 16 * crc;
+        let symbols = if bits > 0 {
+            let per = 4 * (sf - 2 * de);
+            (bits + per - 1) / per * (self.coding_rate as i64 + 4)
+        } else {
+            0
+        };
+        let total = self.preamble_len as i64 * 4 + 17 + 32 + symbols * 4; // quarter symbols
+        let symbol_us = (1i64 << sf) * 1_000_000 / self.bandwidth.hz() as i64;
+        (total * symbol_us / 4 / 1000) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketStatus {
+    pub len: usize,
+    pub rssi_dbm: i16,
+    // in quarter dB steps
+    pub snr_quarter_db: i8,
+}
+
+impl PacketStatus {
+    pub fn snr_db(&self) -> f32 {
+        self.snr_quarter_db as f32 / 4.0
+    }
+}
+
+pub struct Sx1262<SPI, BUSY, RST, DELAY> {
+    spi: SPI,
+    busy: BUSY,
+    reset: RST,
+    delay: DELAY,
+    config: LoraConfig,
+    // the packet params are sent again whenever the payload length changes
+    payload_len: u8,
+}
+
+impl<SPI, BUSY, RST, DELAY> Sx1262<SPI, BUSY, RST, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    RST: OutputPin,
+    DELAY: DelayNs,
+{
+    pub fn new(spi: SPI, busy: BUSY, reset: RST, delay: DELAY) -> Self {
+        Self {
+            spi,
+            busy,
+            reset,
+            delay,
+            config: LoraConfig::default(),
+            payload_len: MAX_PAYLOAD as u8,
+        }
+    }
+
+    pub fn config(&self) -> &LoraConfig {
+        &self.config
+    }
+
+    pub fn reset(&mut self) -> Result<(), RadioError> {
+        self.reset
+            .set_low()
+            .map_err(|e| RadioError::Pin(format!("{e:?}")))?;
+        self.delay.delay_ms(1);
+        self.reset
+            .set_high()
+            .map_err(|e| RadioError::Pin(format!("{e:?}")))?;
+        self.delay.delay_ms(10);
+        self.wait_busy()
+    }
+
+    // Resets the chip and sets it up for LoRa with `config`, leaving it in standby.
+    pub fn init(&mut self, config: &LoraConfig) -> Result<(), RadioError> {
+        self.reset()?;
+        self.standby()?;
+        if let Some(voltage) = config.tcxo {
+            // 5 ms start-up, in 15.625 us steps
+            let delay = 320u32.to_be_bytes();
+            self.command(
+                SET_DIO3_AS_TCXO,
+                &[voltage as u8, delay[1], delay[2], delay[3]],
+            )?;
+            self.command(CALIBRATE, &[CALIBRATE_ALL])?;
+            self.delay.delay_ms(5);
+            self.wait_busy()?;
+            self.command(CLEAR_DEVICE_ERRORS, &[0, 0])?;
+        }
+        if config.dio2_rf_switch {
+            self.command(SET_DIO2_AS_RF_SWITCH, &[0x01])?;
+        }
+        self.command(SET_REGULATOR_MODE, &[REGULATOR_DC_DC])?;
+        self.command(SET_PACKET_TYPE, &[PACKET_TYPE_LORA])?;
+        self.command(SET_BUFFER_BASE_ADDRESS, &[0x00, 0x00])?;
+        // boosted receive gain
+        self.write_register(REG_RX_GAIN, &[0x96])?;
+        self.command(
+            SET_DIO_IRQ_PARAMS,
+            &[
+                (IRQ_ALL >> 8) as u8,
+                IRQ_ALL as u8,
+                (IRQ_ALL >> 8) as u8,
+                IRQ_ALL as u8,
+                0,
+                0,
+                0,
+                0,
+            ],
+        )?;
+        self.configure(config)
+    }
+
+    // Applies frequency, modulation, power and packet settings; call from standby.
+    pub fn configure(&mut self, config: &LoraConfig) -> Result<(), RadioError> {
+        self.config = *config;
+        self.set_frequency(config.frequency_hz)?;
+        self.set_tx_power(config.tx_power_dbm)?;
+        self.command(
+            SET_MODULATION_PARAMS,
+            &[
+                config.spreading_factor.clamp(5, 12),
+                config.bandwidth.code(),
+                config.coding_rate as u8,
+                config.low_data_rate() as u8,
+            ],
+        )?;
+        self.set_packet_params(MAX_PAYLOAD as u8)?;
+        // the one byte sync word is spread over the high nibbles of the two register bytes
+        let sync = config.sync_word;
+        self.write_register(
+            REG_SYNC_WORD,
+            &[(sync & 0xF0) | 0x04, ((sync & 0x0F) << 4) | 0x04],
+        )?;
+        // datasheet 15.4: IQ polarity fix for standard (non-inverted) IQ
+        let mut iq = [0u8];
+        self.read_register(REG_IQ_POLARITY, &mut iq)?;
+        self.write_register(REG_IQ_POLARITY, &[iq[0] | 0x04])?;
+        // datasheet 15.2: better antenna mismatch tolerance
+        let mut clamp = [0u8];
+        self.read_register(REG_TX_CLAMP, &mut clamp)?;
+        self.write_register(REG_TX_CLAMP, &[clamp[0] | 0x1E])
+    }
+
+    pub fn set_frequency(&mut self, hz: u32) -> Result<(), RadioError> {
+        // image calibration for the band, in 4 MHz steps
+        let band: [u8; 2] = match hz {
+            0..=446_000_000 => [0x6B, 0x6F],
+            446_000_001..=734_000_000 => [0x75, 0x81],
+            734_000_001..=828_000_000 => [0xC1, 0xC5],
+            828_000_001..=902_000_000 => [0xD7, 0xDB],
+            _ => [0xE1, 0xE9],
+        };
+        self.command(CALIBRATE_IMAGE, &band)?;
+        let steps = ((hz as u64) << 25) / XTAL_HZ;
+        self.command(SET_RF_FREQUENCY, &(steps as u32).to_be_bytes())?;
+        self.config.frequency_hz = hz;
+        Ok(())
+    }
+
+    pub fn set_tx_power(&mut self, dbm: i8) -> Result<(), RadioError> {
+        let dbm = dbm.clamp(-9, 22);
+        // high power PA at full size, the power is set with SetTxParams
+        self.command(SET_PA_CONFIG, &[0x04, 0x07, 0x00, 0x01])?;
+        // 140 mA over current protection
+        self.write_register(REG_OCP, &[0x38])?;
+        // 200 us ramp
+        self.command(SET_TX_PARAMS, &[dbm as u8, 0x04])?;
+        self.config.tx_power_dbm = dbm;
+        Ok(())
+    }
+
+    fn set_packet_params(&mut self, payload_len: u8) -> Result<(), RadioError> {
+        let preamble = self.config.preamble_len.to_be_bytes();
+        self.command(
+            SET_PACKET_PARAMS,
+            &[
+                preamble[0],
+                preamble[1],
+                0x00, // explicit header
+                payload_len,
+                self.config.crc as u8,
+                0x00, // standard IQ
+            ],
+        )?;
+        self.payload_len = payload_len;
+        Ok(())
+    }
+
+    pub fn standby(&mut self) -> Result<(), RadioError> {
+        self.command(SET_STANDBY, &[STANDBY_RC])
+    }
+
+    // Warm sleep, the configuration is kept. Any command (after BUSY drops) wakes the chip.
+    pub fn sleep(&mut self) -> Result<(), RadioError> {
+        self.command(SET_SLEEP, &[0x04])?;
+        self.delay.delay_us(500);
+        Ok(())
+    }
+
+    // Loads `data` and starts transmitting, IRQ_TX_DONE (or IRQ_TIMEOUT) follows on DIO1.
+    pub fn start_transmit(&mut self, data: &[u8]) -> Result<(), RadioError> {
+        if data.len() > MAX_PAYLOAD {
+            return Err(RadioError::PayloadTooLong);
+        }
+        self.standby()?;
+        if self.payload_len != data.len() as u8 {
+            self.set_packet_params(data.len() as u8)?;
+        }
+        self.write_buffer(0, data)?;
+        self.clear_irq(IRQ_ALL)?;
+        // time out at twice the time on air, in 15.625 us steps
+        let timeout_ms = 2 * self.config.time_on_air_ms(data.len()) + 100;
+        let timeout = (timeout_ms * 64).min(RX_CONTINUOUS - 1).to_be_bytes();
+        self.command(SET_TX, &timeout[1..])
+    }
+
+    // Continuous receive, every packet raises IRQ_RX_DONE.
+    pub fn start_receive(&mut self) -> Result<(), RadioError> {
+        self.standby()?;
+        if self.payload_len != MAX_PAYLOAD as u8 {
+            self.set_packet_params(MAX_PAYLOAD as u8)?;
+        }
+        self.clear_irq(IRQ_ALL)?;
+        let timeout = RX_CONTINUOUS.to_be_bytes();
+        self.command(SET_RX, &timeout[1..])
+    }
+
+    // Channel activity detection over a few symbols. IRQ_CAD_DONE follows, together with
+    // IRQ_CAD_DETECTED if a LoRa preamble was heard.
+    pub fn start_cad(&mut self) -> Result<(), RadioError> {
+        self.standby()?;
+        let sf = self.config.spreading_factor;
+        // detection peak recommended by Semtech AN1200.48 for 4 symbols
+        let peak = if sf < 9 { 22 } else { sf + 13 };
+        self.command(SET_CAD_PARAMS, &[0x02, peak, 10, 0x00, 0, 0, 0])?;
+        self.clear_irq(IRQ_ALL)?;
+        self.command(SET_CAD, &[])
+    }
+
+    pub fn irq_status(&mut self) -> Result<u16, RadioError> {
+        let mut buf = [0u8; 2];
+        self.read_command(GET_IRQ_STATUS, &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    pub fn clear_irq(&mut self, mask: u16) -> Result<(), RadioError> {
+        self.command(CLEAR_IRQ_STATUS, &mask.to_be_bytes())
+    }
+
+    // Copies the last received packet into `buf`, after IRQ_RX_DONE without IRQ_CRC_ERROR.
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> Result<PacketStatus, RadioError> {
+        let mut rx = [0u8; 2];
+        self.read_command(GET_RX_BUFFER_STATUS, &mut rx)?;
+        let len = (rx[0] as usize).min(buf.len());
+        self.read_buffer(rx[1], &mut buf[..len])?;
+        let mut status = [0u8; 3];
+        self.read_command(GET_PACKET_STATUS, &mut status)?;
+        Ok(PacketStatus {
+            len,
+            rssi_dbm: -(status[0] as i16) / 2,
+            snr_quarter_db: status[1] as i8,
+        })
+    }
+
+    pub fn device_errors(&mut self) -> Result<u16, RadioError> {
+        let mut buf = [0u8; 2];
+        self.read_command(GET_DEVICE_ERRORS, &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn wait_busy(&mut self) -> Result<(), RadioError> {
+        let mut waited = 0;
+        while self
+            .busy
+            .is_high()
+            .map_err(|e| RadioError::Pin(format!("{e:?}")))?
+        {
+            if waited >= BUSY_TIMEOUT_US {
+                return Err(RadioError::Busy);
+            }
+            self.delay.delay_us(10);
+            waited += 10;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, opcode: u8, params: &[u8]) -> Result<(), RadioError> {
+        self.wait_busy()?;
+        self.spi
+            .transaction(&mut [Operation::Write(&[opcode]), Operation::Write(params)])
+            .map_err(RadioError::from_debug)
+    }
+
+    // Commands that return data: the byte after the opcode clocks out the status.
+    fn read_command(&mut self, opcode: u8, buf: &mut [u8]) -> Result<(), RadioError> {
+        self.wait_busy()?;
+        self.spi
+            .transaction(&mut [Operation::Write(&[opcode, 0x00]), Operation::Read(buf)])
+            .map_err(RadioError::from_debug)
+    }
+
+    fn write_register(&mut self, address: u16, data: &[u8]) -> Result<(), RadioError> {
+        let address = address.to_be_bytes();
+        self.wait_busy()?;
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[WRITE_REGISTER, address[0], address[1]]),
+                Operation::Write(data),
+            ])
+            .map_err(RadioError::from_debug)
+    }
+
+    fn read_register(&mut self, address: u16, buf: &mut [u8]) -> Result<(), RadioError> {
+        let address = address.to_be_bytes();
+        self.wait_busy()?;
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[READ_REGISTER, address[0], address[1], 0x00]),
+                Operation::Read(buf),
+            ])
+            .map_err(RadioError::from_debug)
+    }
+
+    fn write_buffer(&mut self, offset: u8, data: &[u8]) -> Result<(), RadioError> {
+        self.wait_busy()?;
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[WRITE_BUFFER, offset]),
+                Operation::Write(data),
+            ])
+            .map_err(RadioError::from_debug)
+    }
+
+    fn read_buffer(&mut self, offset: u8, buf: &mut [u8]) -> Result<(), RadioError> {
+        self.wait_busy()?;
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[READ_BUFFER, offset, 0x00]),
+                Operation::Read(buf),
+            ])
+            .map_err(RadioError::from_debug)
+    }
+}
@@ -0,0 +1,129 @@
+// TCA8418 I2C keypad scan controller datasheet: https://www.ti.com/lit/ds/symlink/tca8418.pdf
+
+use embedded_hal::i2c::I2c;
+
+pub const DEFAULT_ADDRESS: u8 = 0x34;
+
+const REG_CFG: u8 = 0x01;
+const REG_INT_STAT: u8 = 0x02;
+const REG_KEY_LCK_EC: u8 = 0x03;
+const REG_KEY_EVENT_A: u8 = 0x04;
+const REG_KP_GPIO1: u8 = 0x1D;
+const REG_KP_GPIO2: u8 = 0x1E;
+const REG_KP_GPIO3: u8 = 0x1F;
+
+// CFG bits
+const CFG_AI: u8 = 0x80; // auto increment
+const CFG_OVR_FLOW_M: u8 = 0x20; // keep newest events when the FIFO overflows
+const CFG_INT_CFG: u8 = 0x10; // deassert INT for 50us if an interrupt is still pending
+const CFG_OVR_FLOW_IEN: u8 = 0x08;
+const CFG_KE_IEN: u8 = 0x01;
+
+// INT_STAT bits, write 1 to clear
+const INT_K: u8 = 0x01;
+const INT_GPI: u8 = 0x02;
+const INT_K_LCK: u8 = 0x04;
+const INT_OVR_FLOW: u8 = 0x08;
+const INT_CAD: u8 = 0x10;
+
+const MAX_ROWS: u8 = 8;
+const MAX_COLS: u8 = 10;
+const FIFO_DEPTH: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub row: u8,
+    pub col: u8,
+    pub pressed: bool,
+}
+
+pub struct Tca8418<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Tca8418<I2C>
+where
+    I2C: I2c,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    // Puts `rows` x `cols` of the matrix into keypad scan mode and enables key event
+    // interrupts on the INT pin (active low).
+    pub fn configure(&mut self, rows: u8, cols: u8) -> Result<(), I2C::Error> {
+        let rows = rows.min(MAX_ROWS);
+        let cols = cols.min(MAX_COLS);
+        let row_mask = ((1u16 << rows) - 1) as u8;
+        let col_mask = (1u16 << cols) - 1;
+        self.write_register(REG_KP_GPIO1, row_mask)?;
+        self.write_register(REG_KP_GPIO2, col_mask as u8)?;
+        self.write_register(REG_KP_GPIO3, (col_mask >> 8) as u8)?;
+        self.write_register(
+            REG_CFG,
+            CFG_AI | CFG_OVR_FLOW_M | CFG_INT_CFG | CFG_OVR_FLOW_IEN | CFG_KE_IEN,
+        )?;
+        // drop anything captured before configuration
+        self.flush()?;
+        Ok(())
+    }
+
+    pub fn pending(&mut self) -> Result<u8, I2C::Error> {
+        Ok(self.read_register(REG_KEY_LCK_EC)? & 0x0F)
+    }
+
+    // Pops the oldest event from the FIFO. GPI events are skipped, only the keypad
+    // matrix is in use.
+    pub fn read_event(&mut self) -> Result<Option<KeyEvent>, I2C::Error> {
+        loop {
+            let raw = self.read_register(REG_KEY_EVENT_A)?;
+            if raw == 0 {
+                return Ok(None);
+            }
+            let code = raw & 0x7F;
+            if (1..=80).contains(&code) {
+                return Ok(Some(KeyEvent {
+                    row: (code - 1) / 10,
+                    col: (code - 1) % 10,
+                    pressed: raw & 0x80 != 0,
+                }));
+            }
+        }
+    }
+
+    // Drains the FIFO and clears the interrupt so INT is released.
+    pub fn read_events(&mut self, events: &mut Vec<KeyEvent>) -> Result<(), I2C::Error> {
+        for _ in 0..FIFO_DEPTH {
+            match self.read_event()? {
+                Some(event) => events.push(event),
+                None => break,
+            }
+        }
+        self.clear_interrupts()
+    }
+
+    pub fn clear_interrupts(&mut self) -> Result<(), I2C::Error> {
+        self.write_register(
+            REG_INT_STAT,
+            INT_K | INT_GPI | INT_K_LCK | INT_OVR_FLOW | INT_CAD,
+        )
+    }
+
+    pub fn flush(&mut self) -> Result<(), I2C::Error> {
+        while self.pending()? > 0 {
+            self.read_register(REG_KEY_EVENT_A)?;
+        }
+        self.clear_interrupts()
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(self.address, &[register, value])
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, I2C::Error> {
+        let mut buf = [0u8];
+        self.i2c.write_read(self.address, &[register], &mut buf)?;
+        Ok(buf[0])
+    }
+}
@@ -0,0 +1,175 @@
+// Offline map tiles: Web Mercator ("slippy map") projection and 1-bit tiles stored as
+// binary PBM (P4) files under `<root>/<zoom>/<x>/<y>.pbm`, the usual XYZ layout. Any tile
+// renderer can produce them, e.g. `convert tile.png -dither FloydSteinberg -monochrome
+// tile.pbm` on each PNG tile.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::nmea::Position;
+
+pub const TILE_SIZE: i64 = 256;
+pub const MAX_ZOOM: u8 = 18;
+const TILE_BYTES: usize = (TILE_SIZE * TILE_SIZE / 8) as usize;
+// Web Mercator stops short of the poles
+const MAX_LATITUDE: f64 = 85.051_128_78;
+const EARTH_CIRCUMFERENCE_M: f64 = 40_075_016.686;
+// a few tiles cover the screen; misses are cached too so absent tiles cost one lookup
+const CACHE_TILES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TileError {
+    Io(String),
+    Format(String),
+}
+impl TileError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        TileError::Io(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileKey {
+    pub zoom: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+// TILE_SIZE x TILE_SIZE pixels, rows packed MSB first, a set bit is black
+pub struct Tile {
+    bits: Vec<u8>,
+}
+
+impl Tile {
+    pub fn decode_pbm(data: &[u8]) -> Result<Self, TileError> {
+        // header: "P4", width and height as decimal, separated by whitespace and comments,
+        // then a single whitespace byte before the raster
+        let mut fields = Vec::new();
+        let mut i = 0;
+        while fields.len() < 3 {
+            while i < data.len() && (data[i].is_ascii_whitespace() || data[i] == b'#') {
+                if data[i] == b'#' {
+                    while i < data.len() && data[i] != b'\n' {
+                        i += 1;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+            let start = i;
+            while i < data.len() && !data[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if start == i {
+                return Err(TileError::Format("truncated header".to_string()));
+            }
+            fields.push(&data[start..i]);
+        }
+        if fields[0] != b"P4" {
+            return Err(TileError::Format("not a binary PBM".to_string()));
+        }
+        let size = format!("{}x{}", ascii(fields[1]), ascii(fields[2]));
+        if size != format!("{TILE_SIZE}x{TILE_SIZE}") {
+            return Err(TileError::Format(format!("tile is {size}")));
+        }
+        let raster = data
+            .get(i + 1..i + 1 + TILE_BYTES)
+            .ok_or_else(|| TileError::Format("truncated raster".to_string()))?;
+        Ok(Self {
+            bits: raster.to_vec(),
+        })
+    }
+
+    pub fn is_black(&self, x: i64, y: i64) -> bool {
+        let i = (y * TILE_SIZE + x) as usize;
+        self.bits[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+}
+
+fn ascii(field: &[u8]) -> String {
+    String::from_utf8_lossy(field).into_owned()
+}
+
+// Tiles from a directory, with a small cache of the ones used last.
+pub struct TileStore {
+    root: PathBuf,
+    cache: VecDeque<(TileKey, Option<Tile>)>,
+}
+
+impl TileStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            cache: VecDeque::new(),
+        }
+    }
+
+    // None when the tile is not on the card
+    pub fn get(&mut self, key: TileKey) -> Option<&Tile> {
+        match self.cache.iter().position(|(k, _)| *k == key) {
+            Some(i) => {
+                let entry = self.cache.remove(i).unwrap();
+                self.cache.push_back(entry);
+            }
+            None => {
+                let tile = self.load(key);
+                if self.cache.len() == CACHE_TILES {
+                    self.cache.pop_front();
+                }
+                self.cache.push_back((key, tile));
+            }
+        }
+        self.cache.back().and_then(|(_, tile)| tile.as_ref())
+    }
+
+    // forget cached misses, e.g. after a card was inserted
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    fn load(&self, key: TileKey) -> Option<Tile> {
+        let path = self
+            .root
+            .join(key.zoom.to_string())
+            .join(key.x.to_string())
+            .join(format!("{}.pbm", key.y));
+        let data = fs::read(&path).ok()?;
+        match Tile::decode_pbm(&data) {
+            Ok(tile) => Some(tile),
+            Err(e) => {
+                log::warn!("bad tile {}: {e:?}", path.display());
+                None
+            }
+        }
+    }
+}
+
+// Position in pixels of the whole world map at `zoom`, x east and y south from the top
+// left corner.
+pub fn world_pixel(position: Position, zoom: u8) -> (f64, f64) {
+    let size = (TILE_SIZE << zoom) as f64;
+    let lat = position
+        .latitude
+        .clamp(-MAX_LATITUDE, MAX_LATITUDE)
+        .to_radians();
+    let x = (position.longitude + 180.0) / 360.0 * size;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * size;
+    (x, y)
+}
+
+pub fn position_at(x: f64, y: f64, zoom: u8) -> Position {
+    let size = (TILE_SIZE << zoom) as f64;
+    let n = PI * (1.0 - 2.0 * y / size);
+    Position {
+        latitude: n.sinh().atan().to_degrees(),
+        longitude: x / size * 360.0 - 180.0,
+    }
+}
+
+// ground distance covered by one pixel at `latitude`
+pub fn meters_per_pixel(latitude: f64, zoom: u8) -> f64 {
+    EARTH_CIRCUMFERENCE_M * latitude.to_radians().cos() / (TILE_SIZE << zoom) as f64
+}
@@ -0,0 +1,202 @@
+// Time sources, and which of their readings the clock is set from, and the calendar
+// arithmetic for the UTC times they report.
+
+use std::time::Duration;
+
+// assumed drift of the clock since it was last set, generous for the RTC oscillator in deep
+// sleep
+const DRIFT_PPM: u64 = 500;
+// a reading is only taken when it improves the clock by at least this much
+const MIN_GAIN: Duration = Duration::from_millis(500);
+
+// In order of accuracy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    // NITZ, read back from the modem's clock
+    Network,
+    Gnss,
+    Sntp,
+}
+
+impl TimeSource {
+    // error of a fresh reading
+    pub fn error(self) -> Duration {
+        match self {
+            // NITZ is in whole seconds and the modem's clock is only read now and then
+            TimeSource::Network => Duration::from_secs(2),
+            // NMEA arrives a few hundred ms after the epoch it describes
+            TimeSource::Gnss => Duration::from_millis(500),
+            TimeSource::Sntp => Duration::from_millis(100),
+        }
+    }
+}
+
+// the reading the clock was last set from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    pub source: TimeSource,
+    // Unix time it was set to
+    pub at: u64,
+}
+
+// Decides which readings to believe. The clock's error is that of the source it was last
+// set from, growing by DRIFT_PPM since; a reading is taken when its own error is smaller by
+// MIN_GAIN. So GNSS and SNTP win over the network, but a stale GNSS time is refreshed from
+// the network once the phone has been indoors for an hour or so.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceSelector {
+    last: Option<Reading>,
+}
+
+impl SourceSelector {
+    pub fn new(last: Option<Reading>) -> Self {
+        Self { last }
+    }
+
+    pub fn last(&self) -> Option<Reading> {
+        self.last
+    }
+
+    // error of the clock at Unix time `now`, None if it was never set
+    pub fn error(&self, now: u64) -> Option<Duration> {
+        let last = self.last?;
+        let age = now.saturating_sub(last.at);
+        Some(last.source.error() + Duration::from_micros(age * DRIFT_PPM))
+    }
+
+    pub fn wants(&self, source: TimeSource, now: u64) -> bool {
+        match self.error(now) {
+            Some(error) => error >= source.error() + MIN_GAIN,
+            None => true,
+        }
+    }
+
+    pub fn synced(&mut self, source: TimeSource, now: u64) {
+        self.last = Some(Reading { source, at: now });
+    }
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's days_from_civil).
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let y = year as i64 - (month <= 2) as i64;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// seconds since the Unix epoch
+pub fn utc_seconds(year: i32, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> i64 {
+    days_from_civil(year, month, day) * 86_400
+        + hour as i64 * 3600
+        + minute as i64 * 60
+        + second as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T: u64 = 1_760_000_000;
+
+    fn synced(source: TimeSource, at: u64) -> SourceSelector {
+        let mut selector = SourceSelector::default();
+        selector.synced(source, at);
+        selector
+    }
+
+    #[test]
+    fn unset_clock_takes_anything() {
+        let selector = SourceSelector::default();
+        assert_eq!(selector.error(T), None);
+        assert!(selector.wants(TimeSource::Network, T));
+        assert!(selector.wants(TimeSource::Gnss, T));
+        assert!(selector.wants(TimeSource::Sntp, T));
+    }
+
+    #[test]
+    fn priority() {
+        // the network is the least accurate, anything else improves on it at once
+        let network = synced(TimeSource::Network, T);
+        assert!(!network.wants(TimeSource::Network, T));
+        assert!(network.wants(TimeSource::Gnss, T));
+        assert!(network.wants(TimeSource::Sntp, T));
+
+        let gnss = synced(TimeSource::Gnss, T);
+        assert!(!gnss.wants(TimeSource::Network, T));
+        assert!(!gnss.wants(TimeSource::Gnss, T));
+        // SNTP is better, but not by MIN_GAIN until the clock has drifted a little
+        assert!(!gnss.wants(TimeSource::Sntp, T));
+        assert!(gnss.wants(TimeSource::Sntp, T + 200));
+
+        let sntp = synced(TimeSource::Sntp, T);
+        assert!(!sntp.wants(TimeSource::Network, T));
+        assert!(!sntp.wants(TimeSource::Gnss, T));
+        assert!(!sntp.wants(TimeSource::Gnss, T + 1799));
+        assert!(sntp.wants(TimeSource::Gnss, T + 1800));
+    }
+
+    #[test]
+    fn staleness() {
+        let gnss = synced(TimeSource::Gnss, T);
+        assert_eq!(gnss.error(T), Some(Duration::from_millis(500)));
+        // 500 ppm: a second every 2000 s
+        assert_eq!(gnss.error(T + 2000), Some(Duration::from_millis(1500)));
+        // the network takes over once the GNSS time is 2.5 s out, a bit over an hour later
+        assert!(!gnss.wants(TimeSource::Network, T + 3999));
+        assert!(gnss.wants(TimeSource::Network, T + 4000));
+
+        // a source refreshes itself once the clock has drifted by MIN_GAIN
+        let network = synced(TimeSource::Network, T);
+        assert!(!network.wants(TimeSource::Network, T + 999));
+        assert!(network.wants(TimeSource::Network, T + 1000));
+    }
+
+    #[test]
+    fn restored_from_rtc() {
+        // a reading kept through deep sleep counts like one just taken
+        let last = Reading {
+            source: TimeSource::Gnss,
+            at: T,
+        };
+        let selector = SourceSelector::new(Some(last));
+        assert_eq!(selector.last(), Some(last));
+        assert!(!selector.wants(TimeSource::Network, T + 600));
+        assert!(selector.wants(TimeSource::Network, T + 6 * 3600));
+        // a clock behind the reading has not drifted
+        assert_eq!(selector.error(T - 100), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn last_reading_replaced() {
+        let mut selector = synced(TimeSource::Sntp, T);
+        selector.synced(TimeSource::Network, T + 10);
+        assert_eq!(
+            selector.last(),
+            Some(Reading {
+                source: TimeSource::Network,
+                at: T + 10,
+            })
+        );
+        assert!(selector.wants(TimeSource::Gnss, T + 10));
+    }
+
+    #[test]
+    fn calendar() {
+        assert_eq!(utc_seconds(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(utc_seconds(2024, 1, 1, 0, 0, 0), 1_704_067_200);
+        // leap day, and the day after
+        assert_eq!(utc_seconds(2024, 2, 29, 12, 30, 15), 1_709_209_815);
+        assert_eq!(
+            days_from_civil(2024, 3, 1) - days_from_civil(2024, 2, 28),
+            2
+        );
+        assert_eq!(
+            days_from_civil(2100, 3, 1) - days_from_civil(2100, 2, 28),
+            1
+        );
+        assert_eq!(utc_seconds(1969, 12, 31, 23, 59, 59), -1);
+    }
+}
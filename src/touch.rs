@@ -0,0 +1,173 @@
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use embedded_hal::i2c::I2c;
+use esp_idf_hal::gpio::{Input, InputPin, InterruptType, PinDriver};
+use esp_idf_hal::task::notification::Notification;
+
+use crate::cst328::{Cst328, TouchPoint};
+use crate::event::{Direction, Event, Gesture};
+
+// touch panel resolution, the same as the e-paper panel in its native orientation
+const PANEL_WIDTH: i16 = 240;
+const PANEL_HEIGHT: i16 = 320;
+
+const TAP_MAX_MOVE: i16 = 12;
+const SWIPE_MIN_MOVE: i16 = 40;
+const LONG_PRESS: Duration = Duration::from_millis(600);
+
+// poll interval while a finger is down, the controller does not interrupt on a still finger
+const TOUCHING_POLL_TICKS: u32 = 20;
+const IDLE_POLL_TICKS: u32 = 1000;
+
+// Inverse of the rotation applied by `Epd310Gdeq031t10::draw_pixel`.
+pub fn to_logical(x: u16, y: u16, rotation: u8) -> (i16, i16) {
+    let (x, y) = (x as i16, y as i16);
+    match rotation % 4 {
+        1 => (y, PANEL_WIDTH - 1 - x),
+        2 => (PANEL_WIDTH - 1 - x, PANEL_HEIGHT - 1 - y),
+        3 => (PANEL_HEIGHT - 1 - y, x),
+        _ => (x, y),
+    }
+}
+
+struct Stroke {
+    start: Instant,
+    origin: (i16, i16),
+    last: (i16, i16),
+    long_press_sent: bool,
+}
+
+// Turns a stream of single finger positions into gestures. Only the first finger is
+// tracked, additional fingers are ignored.
+#[derive(Default)]
+pub struct GestureRecognizer {
+    stroke: Option<Stroke>,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn touching(&self) -> bool {
+        self.stroke.is_some()
+    }
+
+    // `position` is None once the finger has lifted
+    pub fn update(&mut self, position: Option<(i16, i16)>, now: Instant) -> Option<Gesture> {
+        match (position, self.stroke.as_mut()) {
+            (Some(p), None) => {
+                self.stroke = Some(Stroke {
+                    start: now,
+                    origin: p,
+                    last: p,
+                    long_press_sent: false,
+                });
+                None
+            }
+            (Some(p), Some(stroke)) => {
+                stroke.last = p;
+                let (x, y) = stroke.origin;
+                if !stroke.long_press_sent
+                    && distance(stroke.origin, p) <= TAP_MAX_MOVE
+                    && now.duration_since(stroke.start) >= LONG_PRESS
+                {
+                    stroke.long_press_sent = true;
+                    return Some(Gesture::LongPress { x, y });
+                }
+                None
+            }
+            (None, Some(_)) => {
+                let stroke = self.stroke.take()?;
+                let (x, y) = stroke.origin;
+                let dx = stroke.last.0 - x;
+                let dy = stroke.last.1 - y;
+                if stroke.long_press_sent {
+                    None
+                } else if dx.abs().max(dy.abs()) >= SWIPE_MIN_MOVE {
+                    let direction = if dx.abs() > dy.abs() {
+                        if dx > 0 {
+                            Direction::Right
+                        } else {
+                            Direction::Left
+                        }
+                    } else if dy > 0 {
+                        Direction::Down
+                    } else {
+                        Direction::Up
+                    };
+                    Some(Gesture::Swipe { x, y, direction })
+                } else if distance(stroke.origin, stroke.last) <= TAP_MAX_MOVE {
+                    Some(Gesture::Tap { x, y })
+                } else {
+                    None
+                }
+            }
+            (None, None) => None,
+        }
+    }
+}
+
+fn distance(a: (i16, i16), b: (i16, i16)) -> i16 {
+    (a.0 - b.0).abs().max((a.1 - b.1).abs())
+}
+
+// Touch task: reads the controller on interrupt (and periodically while a finger is down
+// so long presses are detected) and forwards gestures to the display task. `rotation` is
+// shared with the display task so points land in framebuffer coordinates.
+pub fn run<I2C, INT>(
+    mut touch: Cst328<I2C>,
+    mut int_pin: PinDriver<'static, INT, Input>,
+    rotation: Arc<AtomicU8>,
+    events: Sender<Event>,
+) -> anyhow::Result<()>
+where
+    I2C: I2c,
+    INT: InputPin,
+{
+    // points are mapped assuming the controller covers the e-paper panel pixel for pixel
+    match touch.resolution() {
+        Ok((w, h)) if (w as i16, h as i16) == (PANEL_WIDTH, PANEL_HEIGHT) => {}
+        Ok((w, h)) => log::warn!(
+            "touch panel reports {w}x{h}, taps are mapped to {PANEL_WIDTH}x{PANEL_HEIGHT}"
+        ),
+        Err(e) => log::warn!("touch resolution read error: {e:?}"),
+    }
+
+    let notification = Notification::new();
+    let notifier = notification.notifier();
+    int_pin.set_interrupt_type(InterruptType::NegEdge)?;
+    unsafe {
+        int_pin.subscribe(move || {
+            notifier.notify_and_yield(NonZeroU32::new(1).unwrap());
+        })?;
+    }
+
+    let mut recognizer = GestureRecognizer::new();
+    let mut points: Vec<TouchPoint> = Vec::new();
+    loop {
+        int_pin.enable_interrupt()?;
+        let timeout = if recognizer.touching() {
+            TOUCHING_POLL_TICKS
+        } else {
+            IDLE_POLL_TICKS
+        };
+        notification.wait(timeout);
+
+        if let Err(e) = touch.read_points(&mut points) {
+            log::warn!("touch read error: {e:?}");
+            continue;
+        }
+        let rotation = rotation.load(Ordering::Relaxed);
+        let position = points.first().map(|p| to_logical(p.x, p.y, rotation));
+        if let Some(gesture) = recognizer.update(position, Instant::now()) {
+            if events.send(Event::Touch(gesture)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
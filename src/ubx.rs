@@ -0,0 +1,390 @@
+// u-blox UBX protocol: frames, the M10 configuration interface (CFG-VALSET) and the messages
+// the GNSS task reads back (ACK, NAV-PVT).
+// Interface description: u-blox M10 SPG 5.10 Interface Description (UBX-21035062)
+
+use std::fmt::Debug;
+
+use crate::nmea::{Date, Position, Time};
+
+pub const SYNC: [u8; 2] = [0xB5, 0x62];
+// sync, class, id and length before the payload, checksum after it
+pub const OVERHEAD: usize = 8;
+pub const MAX_PAYLOAD: usize = 1024;
+
+pub const CLASS_NAV: u8 = 0x01;
+pub const CLASS_ACK: u8 = 0x05;
+pub const CLASS_CFG: u8 = 0x06;
+pub const NAV_PVT: u8 = 0x07;
+pub const ACK_NAK: u8 = 0x00;
+pub const ACK_ACK: u8 = 0x01;
+pub const CFG_VALSET: u8 = 0x8A;
+
+// configuration layers of CFG-VALSET
+pub const LAYER_RAM: u8 = 0x01;
+pub const LAYER_BBR: u8 = 0x02;
+
+// configuration keys, the value size is encoded in bits 28..31
+pub const CFG_RATE_MEAS: u32 = 0x3021_0001;
+pub const CFG_RATE_NAV: u32 = 0x3021_0002;
+pub const CFG_PM_OPERATEMODE: u32 = 0x20D0_0001;
+pub const CFG_PM_POSUPDATEPERIOD: u32 = 0x40D0_0002;
+pub const CFG_PM_ACQPERIOD: u32 = 0x40D0_0003;
+pub const CFG_PM_ONTIME: u32 = 0x30D0_0005;
+pub const CFG_MSGOUT_UBX_NAV_PVT_UART1: u32 = 0x2091_0007;
+pub const CFG_MSGOUT_NMEA_GGA_UART1: u32 = 0x2091_00BB;
+pub const CFG_MSGOUT_NMEA_RMC_UART1: u32 = 0x2091_00AC;
+pub const CFG_MSGOUT_NMEA_GSA_UART1: u32 = 0x2091_00C0;
+pub const CFG_MSGOUT_NMEA_GSV_UART1: u32 = 0x2091_00C5;
+
+// values of CFG-PM-OPERATEMODE
+pub const PM_FULL: u8 = 0;
+pub const PM_PSMOO: u8 = 1;
+pub const PM_PSMCT: u8 = 2;
+
+const NAV_PVT_LEN: usize = 92;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UbxError {
+    Checksum,
+    TooLong,
+    Invalid(String),
+}
+impl UbxError {
+    pub fn from_debug<T: Debug>(e: T) -> Self {
+        UbxError::Invalid(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub class: u8,
+    pub id: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(class: u8, id: u8, payload: Vec<u8>) -> Self {
+        Self { class, id, payload }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(OVERHEAD + self.payload.len());
+        out.extend(SYNC);
+        out.extend([self.class, self.id]);
+        out.extend((self.payload.len() as u16).to_le_bytes());
+        out.extend(&self.payload);
+        let (a, b) = checksum(&out[2..]);
+        out.extend([a, b]);
+        out
+    }
+
+    // Decodes one complete frame, sync bytes included.
+    pub fn decode(data: &[u8]) -> Result<Self, UbxError> {
+        if data.len() < OVERHEAD || data[..2] != SYNC {
+            return Err(UbxError::Invalid("not a UBX frame".to_string()));
+        }
+        let len = u16::from_le_bytes([data[4], data[5]]) as usize;
+        if len > MAX_PAYLOAD {
+            return Err(UbxError::TooLong);
+        }
+        if data.len() != OVERHEAD + len {
+            return Err(UbxError::Invalid(format!(
+                "length {} for {len}",
+                data.len()
+            )));
+        }
+        if checksum(&data[2..6 + len]) != (data[6 + len], data[7 + len]) {
+            return Err(UbxError::Checksum);
+        }
+        Ok(Self::new(data[2], data[3], data[6..6 + len].to_vec()))
+    }
+}
+
+// 8-bit Fletcher checksum over class, id, length and payload
+pub fn checksum(data: &[u8]) -> (u8, u8) {
+    data.iter().fold((0u8, 0u8), |(a, b), &byte| {
+        let a = a.wrapping_add(byte);
+        (a, b.wrapping_add(a))
+    })
+}
+
+// CFG-VALSET with the given (key, value) items on `layers`. Values are truncated to the size
+// the key declares.
+pub fn valset(layers: u8, items: &[(u32, u64)]) -> Frame {
+    let mut payload = vec![0x00, layers, 0x00, 0x00];
+    for &(key, value) in items {
+        payload.extend(key.to_le_bytes());
+        let size = match (key >> 28) & 0x7 {
+            0x1 | 0x2 => 1,
+            0x3 => 2,
+            0x4 => 4,
+            _ => 8,
+        };
+        payload.extend(&value.to_le_bytes()[..size]);
+    }
+    Frame::new(CLASS_CFG, CFG_VALSET, payload)
+}
+
+// ACK-ACK or ACK-NAK for the message (class, id)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ack {
+    pub class: u8,
+    pub id: u8,
+    pub accepted: bool,
+}
+
+impl Ack {
+    pub fn decode(frame: &Frame) -> Option<Self> {
+        if frame.class != CLASS_ACK || frame.payload.len() != 2 {
+            return None;
+        }
+        let accepted = match frame.id {
+            ACK_ACK => true,
+            ACK_NAK => false,
+            _ => return None,
+        };
+        Some(Self {
+            class: frame.payload[0],
+            id: frame.payload[1],
+            accepted,
+        })
+    }
+}
+
+// NAV-PVT fix types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PvtFix {
+    None,
+    DeadReckoning,
+    TwoD,
+    ThreeD,
+    GnssDeadReckoning,
+    TimeOnly,
+}
+
+// navigation solution, one per epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavPvt {
+    // GPS time of week of the epoch, ms
+    pub itow: u32,
+    pub date: Option<Date>,
+    pub time: Option<Time>,
+    pub fix: PvtFix,
+    // the fix is within the receiver's accuracy limits
+    pub fix_ok: bool,
+    pub satellites: u8,
+    pub position: Position,
+    // above mean sea level
+    pub altitude_m: f32,
+    pub horizontal_accuracy_m: f32,
+    pub speed_mps: f32,
+    pub heading_deg: f32,
+    pub pdop: f32,
+}
+
+impl NavPvt {
+    pub fn decode(frame: &Frame) -> Option<Self> {
+        if frame.class != CLASS_NAV || frame.id != NAV_PVT || frame.payload.len() < NAV_PVT_LEN {
+            return None;
+        }
+        let p = &frame.payload;
+        let u16_at = |i: usize| u16::from_le_bytes([p[i], p[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([p[i], p[i + 1], p[i + 2], p[i + 3]]);
+        let i32_at = |i: usize| u32_at(i) as i32;
+        let valid = p[11];
+        let date = (valid & 0x01 != 0).then(|| Date {
+            year: u16_at(4),
+            month: p[6],
+            day: p[7],
+        });
+        // nano is the signed fraction to add to the whole second, it may be negative
+        let nano = i32_at(16);
+        let time = (valid & 0x02 != 0).then(|| Time {
+            hour: p[8],
+            minute: p[9],
+            second: p[10],
+            millis: (nano.max(0) / 1_000_000) as u16,
+        });
+        let fix = match p[20] {
+            1 => PvtFix::DeadReckoning,
+            2 => PvtFix::TwoD,
+            3 => PvtFix::ThreeD,
+            4 => PvtFix::GnssDeadReckoning,
+            5 => PvtFix::TimeOnly,
+            _ => PvtFix::None,
+        };
+        Some(Self {
+            itow: u32_at(0),
+            date,
+            time,
+            fix,
+            fix_ok: p[21] & 0x01 != 0,
+            satellites: p[23],
+            position: Position {
+                latitude: i32_at(28) as f64 * 1e-7,
+                longitude: i32_at(24) as f64 * 1e-7,
+            },
+            altitude_m: i32_at(36) as f32 / 1000.0,
+            horizontal_accuracy_m: u32_at(40) as f32 / 1000.0,
+            speed_mps: i32_at(60) as f32 / 1000.0,
+            heading_deg: i32_at(64) as f32 * 1e-5,
+            pdop: u16_at(76) as f32 * 0.01,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_frames() {
+        // MON-VER poll
+        assert_eq!(
+            Frame::new(0x0A, 0x04, Vec::new()).encode(),
+            [0xB5, 0x62, 0x0A, 0x04, 0x00, 0x00, 0x0E, 0x34]
+        );
+        // ACK-ACK of a CFG-VALSET
+        let ack = [0xB5, 0x62, 0x05, 0x01, 0x02, 0x00, 0x06, 0x8A, 0x98, 0xC1];
+        let frame = Frame::decode(&ack).unwrap();
+        assert_eq!(frame.encode(), ack);
+        assert_eq!(
+            Ack::decode(&frame),
+            Some(Ack {
+                class: CLASS_CFG,
+                id: CFG_VALSET,
+                accepted: true
+            })
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let frame = Frame::new(CLASS_NAV, NAV_PVT, (0..=255).collect());
+        let data = frame.encode();
+        assert_eq!(data.len(), OVERHEAD + 256);
+        assert_eq!(Frame::decode(&data), Ok(frame));
+    }
+
+    #[test]
+    fn bad_frames() {
+        let mut data = valset(LAYER_RAM, &[(CFG_RATE_MEAS, 1000)]).encode();
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        assert_eq!(Frame::decode(&data), Err(UbxError::Checksum));
+        data[last] ^= 0x01;
+        data[7] ^= 0x10;
+        assert_eq!(Frame::decode(&data), Err(UbxError::Checksum));
+        data[7] ^= 0x10;
+        // truncated, or trailing bytes
+        assert!(matches!(
+            Frame::decode(&data[..last]),
+            Err(UbxError::Invalid(_))
+        ));
+        assert!(matches!(
+            Frame::decode(&data[..4]),
+            Err(UbxError::Invalid(_))
+        ));
+        let mut long = data.clone();
+        long.push(0);
+        assert!(matches!(Frame::decode(&long), Err(UbxError::Invalid(_))));
+        // no sync
+        data[0] = 0x24;
+        assert!(matches!(Frame::decode(&data), Err(UbxError::Invalid(_))));
+        let too_long = [0xB5, 0x62, 0x01, 0x07, 0x01, 0x04, 0x00, 0x00];
+        assert_eq!(Frame::decode(&too_long), Err(UbxError::TooLong));
+    }
+
+    #[test]
+    fn valset_sizes() {
+        let frame = valset(
+            LAYER_RAM | LAYER_BBR,
+            &[
+                (CFG_PM_OPERATEMODE, PM_PSMCT as u64),
+                (CFG_RATE_MEAS, 1000),
+                (CFG_PM_POSUPDATEPERIOD, 0x1_0000_0010),
+            ],
+        );
+        assert_eq!((frame.class, frame.id), (CLASS_CFG, CFG_VALSET));
+        assert_eq!(
+            frame.payload,
+            [
+                0x00, 0x03, 0x00, 0x00, // version, layers, reserved
+                0x01, 0x00, 0xD0, 0x20, 0x02, // one byte
+                0x01, 0x00, 0x21, 0x30, 0xE8, 0x03, // two bytes
+                0x02, 0x00, 0xD0, 0x40, 0x10, 0x00, 0x00, 0x00, // four bytes, truncated
+            ]
+        );
+    }
+
+    #[test]
+    fn nak() {
+        let frame = Frame::new(CLASS_ACK, ACK_NAK, vec![CLASS_CFG, CFG_VALSET]);
+        assert!(!Ack::decode(&frame).unwrap().accepted);
+        assert_eq!(Ack::decode(&Frame::new(CLASS_ACK, 0x02, vec![0, 0])), None);
+        assert_eq!(Ack::decode(&Frame::new(CLASS_ACK, ACK_ACK, vec![0])), None);
+    }
+
+    #[test]
+    fn nav_pvt() {
+        let mut p = vec![0u8; NAV_PVT_LEN];
+        p[0..4].copy_from_slice(&123_456_000u32.to_le_bytes());
+        p[4..6].copy_from_slice(&2026u16.to_le_bytes());
+        p[6..11].copy_from_slice(&[10, 19, 8, 35, 59]);
+        // date and time valid
+        p[11] = 0x03;
+        p[16..20].copy_from_slice(&250_000_000i32.to_le_bytes());
+        p[20] = 3;
+        p[21] = 0x01;
+        p[23] = 12;
+        p[24..28].copy_from_slice(&134_019_703i32.to_le_bytes());
+        p[28..32].copy_from_slice(&(-524_758_016i32).to_le_bytes());
+        p[36..40].copy_from_slice(&48_300i32.to_le_bytes());
+        p[40..44].copy_from_slice(&2_500u32.to_le_bytes());
+        p[60..64].copy_from_slice(&1_250i32.to_le_bytes());
+        p[64..68].copy_from_slice(&9_000_000i32.to_le_bytes());
+        p[76..78].copy_from_slice(&132u16.to_le_bytes());
+        let pvt = NavPvt::decode(&Frame::new(CLASS_NAV, NAV_PVT, p.clone())).unwrap();
+        assert_eq!(pvt.itow, 123_456_000);
+        assert_eq!(
+            pvt.date,
+            Some(Date {
+                year: 2026,
+                month: 10,
+                day: 19
+            })
+        );
+        assert_eq!(
+            pvt.time,
+            Some(Time {
+                hour: 8,
+                minute: 35,
+                second: 59,
+                millis: 250
+            })
+        );
+        assert_eq!(pvt.fix, PvtFix::ThreeD);
+        assert!(pvt.fix_ok);
+        assert_eq!(pvt.satellites, 12);
+        assert!((pvt.position.latitude + 52.4758016).abs() < 1e-9);
+        assert!((pvt.position.longitude - 13.4019703).abs() < 1e-9);
+        assert_eq!(pvt.altitude_m, 48.3);
+        assert_eq!(pvt.horizontal_accuracy_m, 2.5);
+        assert_eq!(pvt.speed_mps, 1.25);
+        assert_eq!(pvt.heading_deg, 90.0);
+        assert!((pvt.pdop - 1.32).abs() < 1e-6);
+
+        // no valid date or time yet, the fraction may be negative
+        p[11] = 0x02;
+        p[16..20].copy_from_slice(&(-1_000i32).to_le_bytes());
+        let pvt = NavPvt::decode(&Frame::new(CLASS_NAV, NAV_PVT, p.clone())).unwrap();
+        assert_eq!(pvt.date, None);
+        assert_eq!(pvt.time.unwrap().millis, 0);
+        // short payload, or another message
+        assert_eq!(
+            NavPvt::decode(&Frame::new(CLASS_NAV, NAV_PVT, p[..91].to_vec())),
+            None
+        );
+        assert_eq!(NavPvt::decode(&Frame::new(CLASS_NAV, 0x03, p)), None);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::canvas::{Canvas, Rect, Refresh};
+use crate::epdisplay::Colour;
+use crate::font::FontStack;
+use crate::widget::{Modal, Widget};
+
+const PAGE_PADDING: i16 = 6;
+const SPACING: i16 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WidgetId(usize);
+
+struct Entry {
+    widget: Box<dyn Widget>,
+    bounds: Rect,
+}
+
+// A page of widgets stacked top to bottom. `render` redraws whatever changed since the
+// last call and returns the area the panel has to refresh.
+pub struct Ui {
+    entries: Vec<Entry>,
+    modal: Option<(Modal, Rect)>,
+    screen: Rect,
+    needs_layout: bool,
+    needs_full: bool,
+    // areas uncovered since the last render (e.g. by closing the modal)
+    damage: Rect,
+}
+
+impl Default for Ui {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ui {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            modal: None,
+            screen: Rect::default(),
+            needs_layout: true,
+            needs_full: true,
+            damage: Rect::default(),
+        }
+    }
+
+    pub fn add<W: Widget>(&mut self, widget: W) -> WidgetId {
+        self.entries.push(Entry {
+            widget: Box::new(widget),
+            bounds: Rect::default(),
+        });
+        self.needs_layout = true;
+        WidgetId(self.entries.len() - 1)
+    }
+
+    pub fn get<W: Widget>(&self, id: WidgetId) -> Option<&W> {
+        self.entries
+            .get(id.0)
+            .and_then(|e| e.widget.as_any().downcast_ref::<W>())
+    }
+
+    pub fn get_mut<W: Widget>(&mut self, id: WidgetId) -> Option<&mut W> {
+        self.entries
+            .get_mut(id.0)
+            .and_then(|e| e.widget.as_any_mut().downcast_mut::<W>())
+    }
+
+    // the first widget of type W on the page
+    pub fn find_mut<W: Widget>(&mut self) -> Option<&mut W> {
+        self.entries
+            .iter_mut()
+            .find_map(|e| e.widget.as_any_mut().downcast_mut::<W>())
+    }
+
+    pub fn bounds(&self, id: WidgetId) -> Option<Rect> {
+        self.entries.get(id.0).map(|e| e.bounds)
+    }
+
+    // widget under a touch point, as laid out by the last render
+    pub fn hit(&self, x: i16, y: i16) -> Option<WidgetId> {
+        self.entries
+            .iter()
+            .position(|e| e.bounds.contains(x, y))
+            .map(WidgetId)
+    }
+
+    pub fn show_modal(&mut self, modal: Modal) {
+        if let Some((_, bounds)) = self.modal.take() {
+            self.damage = self.damage.union(&bounds);
+        }
+        self.modal = Some((modal, Rect::default()));
+    }
+
+    pub fn close_modal(&mut self) -> Option<Modal> {
+        let (modal, bounds) = self.modal.take()?;
+        self.damage = self.damage.union(&bounds);
+        Some(modal)
+    }
+
+    pub fn modal_mut(&mut self) -> Option<&mut Modal> {
+        self.modal.as_mut().map(|(m, _)| m)
+    }
+
+    pub fn has_modal(&self) -> bool {
+        self.modal.is_some()
+    }
+
+    // redraw `area` on the next render, e.g. after something drawn over the page went away
+    pub fn damage(&mut self, area: Rect) {
+        self.damage = self.damage.union(&area);
+    }
+
+    // redraw and refresh the whole screen on the next render, e.g. to clear ghosting
+    pub fn invalidate(&mut self) {
+        self.needs_full = true;
+    }
+
+    fn layout(&mut self, fonts: &FontStack) {
+        let mut heights: Vec<i16> = self
+            .entries
+            .iter()
+            .map(|e| e.widget.height(fonts))
+            .collect();
+        let bottom = self.place(&heights);
+        // an expanding widget takes whatever the page has left
+        let spare = self.screen.bottom() - bottom;
+        if let Some(i) = self.entries.iter().position(|e| e.widget.expands()) {
+            if spare > 0 {
+                heights[i] += spare;
+                self.place(&heights);
+            }
+        }
+        self.needs_layout = false;
+    }
+
+    // stacks the widgets with the given heights, returns where the page content ends
+    fn place(&mut self, heights: &[i16]) -> i16 {
+        let mut y = self.screen.y;
+        let mut at_edge = true;
+        for (entry, &h) in self.entries.iter_mut().zip(heights) {
+            if entry.widget.full_bleed() {
+                entry.bounds = Rect::new(self.screen.x, y, self.screen.w, h);
+                at_edge = true;
+            } else {
+                y += if at_edge { PAGE_PADDING } else { SPACING };
+                let w = self.screen.w - 2 * PAGE_PADDING;
+                entry.bounds = Rect::new(self.screen.x + PAGE_PADDING, y, w, h);
+                at_edge = false;
+            }
+            y += h;
+        }
+        if at_edge {
+            y
+        } else {
+            y + PAGE_PADDING
+        }
+    }
+
+    pub fn render(&mut self, canvas: &mut dyn Canvas, fonts: &mut FontStack) -> Refresh {
+        let screen = Rect::new(0, 0, canvas.width(), canvas.height());
+        if screen != self.screen {
+            self.screen = screen;
+            self.needs_layout = true;
+        }
+        if self.needs_layout {
+            self.layout(fonts);
+            self.needs_full = true;
+        }
+        if let Some((modal, bounds)) = self.modal.as_mut() {
+            let new_bounds = modal.bounds(fonts, screen);
+            if new_bounds != *bounds {
+                self.damage = self.damage.union(bounds).union(&new_bounds);
+                *bounds = new_bounds;
+            }
+        }
+
+        let mut area = std::mem::take(&mut self.damage);
+        for entry in self.entries.iter() {
+            if entry.widget.is_dirty() {
+                area = area.union(&entry.bounds);
+            }
+        }
+        if let Some((modal, bounds)) = self.modal.as_ref() {
+            if modal.is_dirty() {
+                area = area.union(bounds);
+            }
+        }
+        let refresh = if self.needs_full {
+            area = screen;
+            Refresh::Full
+        } else if area.is_empty() {
+            return Refresh::None;
+        } else {
+            Refresh::Partial(area)
+        };
+
+        // everything overlapping the damaged area is redrawn, back to front
+        canvas.fill_rect(area.x, area.y, area.w, area.h, Colour::WHITE);
+        for entry in self.entries.iter_mut() {
+            if entry.bounds.intersects(&area) {
+                entry.widget.draw(canvas, fonts, entry.bounds);
+            }
+            entry.widget.set_dirty(false);
+        }
+        if let Some((modal, bounds)) = self.modal.as_mut() {
+            if bounds.intersects(&area) {
+                modal.draw(canvas, fonts, *bounds);
+            }
+            modal.set_dirty(false);
+        }
+        self.needs_full = false;
+        refresh
+    }
+}
@@ -0,0 +1,450 @@
+// vCard 3.0 (RFC 2426) and 4.0 (RFC 6350), as much as an address book of names, phone
+// numbers, Meshtastic nodes and notes needs. Reading is lenient: groups, parameters and
+// properties it doesn't know are skipped, and the quoted-printable values of 2.1 cards are
+// decoded (as UTF-8), so cards exported by other phones import fine. Meshtastic nodes have
+// no standard property and go in X-MESHTASTIC-NODE as "!a1b2c3d4".
+
+use crate::meshtastic;
+
+// lines longer than this many octets are folded
+const MAX_LINE: usize = 75;
+const NODE_PROPERTY: &str = "X-MESHTASTIC-NODE";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Contact {
+    // assigned by `Contacts`, 0 until added
+    pub id: u32,
+    pub name: String,
+    // as entered, e.g. "+44 7700 900123"
+    pub numbers: Vec<String>,
+    // Meshtastic node numbers
+    pub nodes: Vec<u32>,
+    pub note: String,
+}
+
+impl Contact {
+    // Whether the name, a word of it or (for a query of digits) a number starts with `query`.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return true;
+        }
+        let name = self.name.to_lowercase();
+        if name.starts_with(&query) || name.split_whitespace().any(|w| w.starts_with(&query)) {
+            return true;
+        }
+        let is_number = query
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | ' ' | '-'));
+        let digits = digits_of(&query);
+        is_number
+            && !digits.is_empty()
+            && self
+                .numbers
+                .iter()
+                .any(|n| digits_of(n).starts_with(&digits))
+    }
+}
+
+// the digits of a phone number, without '+', spaces or dashes
+pub fn digits_of(number: &str) -> String {
+    number.chars().filter(char::is_ascii_digit).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V3,
+    V4,
+}
+
+// Cards in `text`, skipping those with neither a name nor a number.
+pub fn parse(text: &str) -> Vec<Contact> {
+    let mut contacts = Vec::new();
+    let mut card: Option<Contact> = None;
+    // N is only used when there is no FN
+    let mut structured_name = String::new();
+    for line in unfold(text) {
+        let Some((name, value)) = split_line(&line) else {
+            continue;
+        };
+        match name.as_str() {
+            "BEGIN" if value.eq_ignore_ascii_case("VCARD") => {
+                card = Some(Contact::default());
+                structured_name.clear();
+            }
+            "END" if value.eq_ignore_ascii_case("VCARD") => {
+                let Some(mut contact) = card.take() else {
+                    continue;
+                };
+                if contact.name.is_empty() {
+                    contact.name = std::mem::take(&mut structured_name);
+                }
+                if contact.name.is_empty() {
+                    contact.name = contact.numbers.first().cloned().unwrap_or_default();
+                }
+                if !contact.name.is_empty() {
+                    contacts.push(contact);
+                }
+            }
+            _ => {
+                let Some(contact) = card.as_mut() else {
+                    continue;
+                };
+                read_property(contact, &mut structured_name, &name, &value);
+            }
+        }
+    }
+    contacts
+}
+
+fn read_property(contact: &mut Contact, structured_name: &mut String, name: &str, value: &str) {
+    match name {
+        "FN" => contact.name = unescape(value).trim().to_string(),
+        // family;given;additional;prefixes;suffixes
+        "N" => {
+            let parts: Vec<String> = split_escaped(value, ';')
+                .iter()
+                .map(|p| unescape(p))
+                .collect();
+            let order = [3, 1, 2, 0, 4];
+            let words: Vec<&str> = order
+                .iter()
+                .filter_map(|&i| parts.get(i))
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .collect();
+            *structured_name = words.join(" ");
+        }
+        "TEL" => {
+            // 4.0 has them as URIs, "tel:+44-7700-900123;ext=2"
+            let value = unescape(value);
+            let value = value
+                .strip_prefix("tel:")
+                .map_or(value.as_str(), |v| v.split(';').next().unwrap_or(v));
+            let number = value.trim();
+            if !number.is_empty() && !contact.numbers.iter().any(|n| n == number) {
+                contact.numbers.push(number.to_string());
+            }
+        }
+        "NOTE" => {
+            if !contact.note.is_empty() {
+                contact.note.push('\n');
+            }
+            contact.note.push_str(&unescape(value));
+        }
+        NODE_PROPERTY => match parse_node(&unescape(value)) {
+            Some(node) if !contact.nodes.contains(&node) => contact.nodes.push(node),
+            Some(_) => {}
+            None => log::warn!("ignoring Meshtastic node {value:?}"),
+        },
+        _ => {}
+    }
+}
+
+// "!a1b2c3d4" as shown by Meshtastic apps, or the node number in hex
+pub fn parse_node(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let hex = text.strip_prefix('!').unwrap_or(text);
+    if hex.is_empty() || hex.len() > 8 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+pub fn write(contacts: &[Contact], version: Version) -> String {
+    let mut out = String::new();
+    for contact in contacts {
+        push_line(&mut out, "BEGIN:VCARD");
+        push_line(
+            &mut out,
+            match version {
+                Version::V3 => "VERSION:3.0",
+                Version::V4 => "VERSION:4.0",
+            },
+        );
+        push_line(&mut out, &format!("FN:{}", escape(&contact.name)));
+        // required in 3.0; the last word is taken as the family name
+        if version == Version::V3 {
+            let name = contact.name.trim();
+            let (given, family) = match name.rsplit_once(' ') {
+                Some((given, family)) => (given.trim(), family),
+                None => ("", name),
+            };
+            push_line(
+                &mut out,
+                &format!("N:{};{};;;", escape(family), escape(given)),
+            );
+        }
+        for number in contact.numbers.iter() {
+            let line = match version {
+                Version::V3 => format!("TEL;TYPE=CELL:{}", escape(number)),
+                Version::V4 => format!("TEL;VALUE=uri;TYPE=cell:tel:{}", number.replace(' ', "-")),
+            };
+            push_line(&mut out, &line);
+        }
+        for &node in contact.nodes.iter() {
+            push_line(
+                &mut out,
+                &format!("{NODE_PROPERTY}:{}", meshtastic::node_id(node)),
+            );
+        }
+        if !contact.note.is_empty() {
+            push_line(&mut out, &format!("NOTE:{}", escape(&contact.note)));
+        }
+        push_line(&mut out, "END:VCARD");
+    }
+    out
+}
+
+// Folds at MAX_LINE octets, continuation lines start with a space.
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+// Joins folded lines: a line break followed by a space or tab continues the line, as does
+// one after the "=" of a quoted-printable soft line break.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        if let Some(last) = lines.last_mut() {
+            if last.ends_with('=') && quoted_printable(last) {
+                last.pop();
+                last.push_str(line);
+                continue;
+            }
+        }
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+// "item1.TEL;TYPE=CELL:+44..." -> ("TEL", "+44..."); the value starts at the first colon
+// outside a quoted parameter
+fn split_line(line: &str) -> Option<(String, String)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let name = head.split(';').next().unwrap_or(head);
+    let name = name.rsplit('.').next().unwrap_or(name);
+    let value = if quoted_printable(head) {
+        decode_quoted_printable(value)
+    } else {
+        value.to_string()
+    };
+    Some((name.trim().to_ascii_uppercase(), value))
+}
+
+// 2.1 parameters "ENCODING=QUOTED-PRINTABLE" or just "QUOTED-PRINTABLE", before the value
+fn quoted_printable(line: &str) -> bool {
+    let head = line.split(':').next().unwrap_or(line);
+    head.split(';').skip(1).any(|p| {
+        let p = p.trim();
+        p.eq_ignore_ascii_case("QUOTED-PRINTABLE")
+            || p.eq_ignore_ascii_case("ENCODING=QUOTED-PRINTABLE")
+    })
+}
+
+// "=C3=A9" -> "é"; an "=" not followed by two hex digits is kept
+fn decode_quoted_printable(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'=' {
+            if let Some(byte) = value
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// splits at separators that are not escaped with a backslash, escapes are kept
+fn split_escaped(value: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            part.push(c);
+            if let Some(next) = chars.next() {
+                part.push(next);
+            }
+        } else if c == separator {
+            parts.push(std::mem::take(&mut part));
+        } else {
+            part.push(c);
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | ',' | ';' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(name: &str, numbers: &[&str]) -> Contact {
+        Contact {
+            name: name.to_string(),
+            numbers: numbers.iter().map(|n| n.to_string()).collect(),
+            ..Contact::default()
+        }
+    }
+
+    #[test]
+    fn folding() {
+        let mut long = contact("Zoë Ångström", &["+44 7700 900123"]);
+        long.note = "Ünïcode ".repeat(20);
+        let text = write(&[long.clone()], Version::V3);
+        assert!(text.lines().all(|line| line.len() <= MAX_LINE));
+        assert!(text.contains("\r\n "));
+        assert_eq!(parse(&text), [long]);
+
+        let text = "BEGIN:VCARD\r\nFN:Ada\r\n  Lovelace\r\nNOTE:first\r\n\tsecond\r\nEND:VCARD\r\n";
+        let parsed = parse(text);
+        assert_eq!(parsed[0].name, "Ada Lovelace");
+        assert_eq!(parsed[0].note, "firstsecond");
+    }
+
+    #[test]
+    fn structured_name() {
+        let text = "BEGIN:VCARD\nVERSION:3.0\nN:Doe;John;Quincy;Dr.;Jr.\nTEL:123\nEND:VCARD\n";
+        assert_eq!(parse(text)[0].name, "Dr. John Quincy Doe Jr.");
+        // FN wins, wherever it is
+        let text = "BEGIN:VCARD\nN:Doe;John;;;\nFN:Johnny\nEND:VCARD\n";
+        assert_eq!(parse(text)[0].name, "Johnny");
+        // the number stands in for a name, a card with neither is dropped
+        let text = "BEGIN:VCARD\nTEL:+44 7700 900123\nEND:VCARD\nBEGIN:VCARD\nNOTE:x\nEND:VCARD\n";
+        assert_eq!(parse(text), [contact("+44 7700 900123", &["+44 7700 900123"])]);
+    }
+
+    #[test]
+    fn tel_uri() {
+        let text = "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:Ann\r\n\
+            TEL;VALUE=uri;TYPE=\"voice,cell\";PREF=1:tel:+44-7700-900123;ext=2\r\n\
+            item1.TEL;TYPE=home:0114 496 0999\r\n\
+            TEL:tel:+44-7700-900123\r\nEND:VCARD\r\n";
+        assert_eq!(
+            parse(text)[0].numbers,
+            ["+44-7700-900123", "0114 496 0999"]
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let text = "BEGIN:VCARD\nFN:Smith\\, Jane\nNOTE:a\\;b\\\\c\\nnext\nEND:VCARD\n";
+        let parsed = parse(text);
+        assert_eq!(parsed[0].name, "Smith, Jane");
+        assert_eq!(parsed[0].note, "a;b\\c\nnext");
+
+        let written = write(&parsed, Version::V4);
+        assert!(written.contains("FN:Smith\\, Jane\r\n"));
+        assert!(written.contains("NOTE:a\\;b\\\\c\\nnext\r\n"));
+        assert_eq!(parse(&written), parsed);
+    }
+
+    #[test]
+    fn meshtastic_nodes() {
+        let text = "BEGIN:VCARD\nFN:Base\nX-MESHTASTIC-NODE:!a1b2c3d4\n\
+            item2.x-meshtastic-node:A1B2C3D5\nX-MESHTASTIC-NODE:!a1b2c3d4\n\
+            X-MESHTASTIC-NODE:!nothex\nEND:VCARD\n";
+        assert_eq!(parse(text)[0].nodes, [0xa1b2_c3d4, 0xa1b2_c3d5]);
+        assert_eq!(parse_node(" !0000002a "), Some(42));
+        assert_eq!(parse_node("!123456789"), None);
+        assert_eq!(parse_node("!"), None);
+    }
+
+    #[test]
+    fn quoted_printable_2_1() {
+        let text = "BEGIN:VCARD\r\nVERSION:2.1\r\n\
+            N;CHARSET=UTF-8;ENCODING=QUOTED-PRINTABLE:M=C3=BCller;J=C3=BCrgen;;;\r\n\
+            TEL;CELL:+49 151 23456789\r\n\
+            NOTE;QUOTED-PRINTABLE:Line one=0ALine two, =\r\nwrapped; 2+2=4 =3D 4\r\n\
+            END:VCARD\r\n";
+        let parsed = parse(text);
+        assert_eq!(parsed[0].name, "Jürgen Müller");
+        assert_eq!(parsed[0].numbers, ["+49 151 23456789"]);
+        assert_eq!(parsed[0].note, "Line one\nLine two, wrapped; 2+2=4 = 4");
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut card = contact("Jane van der Berg", &["+44 7700 900123", "*100#"]);
+        card.nodes = vec![0x0000_002a, 0xdead_beef];
+        card.note = "Met at the meetup\nbring cable".to_string();
+        let plain = contact("Bob", &["112"]);
+
+        let v3 = write(&[card.clone(), plain.clone()], Version::V3);
+        assert!(v3.contains("VERSION:3.0\r\n"));
+        assert!(v3.contains("N:Berg;Jane van der;;;\r\n"));
+        assert_eq!(parse(&v3), [card.clone(), plain.clone()]);
+
+        let v4 = write(&[card.clone(), plain.clone()], Version::V4);
+        assert!(v4.contains("VERSION:4.0\r\n"));
+        assert!(!v4.contains("\r\nN:"));
+        // spaces are not allowed in tel: URIs
+        card.numbers[0] = "+44-7700-900123".to_string();
+        assert_eq!(parse(&v4), [card, plain]);
+    }
+}
@@ -0,0 +1,906 @@
+use std::any::Any;
+use std::cell::Cell;
+
+use crate::canvas::{Canvas, Rect};
+use crate::epdisplay::Colour;
+use crate::font::FontStack;
+
+const PADDING: i16 = 4;
+const BATTERY_ICON_W: i16 = 18;
+const SIGNAL_BARS: u8 = 4;
+const SIGNAL_ICON_W: i16 = 4 * SIGNAL_BARS as i16 - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+// A retained widget. Setters mark the widget dirty when its content actually changes, the
+// `Ui` then redraws only dirty widgets and reports their bounds for a partial refresh.
+pub trait Widget: Any + Send {
+    fn height(&self, fonts: &FontStack) -> i16;
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect);
+    fn is_dirty(&self) -> bool;
+    fn set_dirty(&mut self, dirty: bool);
+
+    // laid out edge to edge rather than inside the page padding
+    fn full_bleed(&self) -> bool {
+        false
+    }
+
+    // grows to fill the rest of the page, `height` is then the minimum
+    fn expands(&self) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+macro_rules! impl_widget_common {
+    () => {
+        fn is_dirty(&self) -> bool {
+            self.dirty
+        }
+
+        fn set_dirty(&mut self, dirty: bool) {
+            self.dirty = dirty;
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    };
+}
+
+fn ink(inverted: bool) -> (Colour, Colour) {
+    if inverted {
+        (Colour::WHITE, Colour::BLACK)
+    } else {
+        (Colour::BLACK, Colour::WHITE)
+    }
+}
+
+fn draw_aligned(
+    canvas: &mut dyn Canvas,
+    fonts: &mut FontStack,
+    bounds: Rect,
+    y: i16,
+    text: &str,
+    align: Align,
+    colour: Colour,
+) {
+    let width = fonts.text_width(text);
+    let x = match align {
+        Align::Left => bounds.x,
+        Align::Center => bounds.x + (bounds.w - width) / 2,
+        Align::Right => bounds.right() - width,
+    };
+    fonts.draw_text(canvas, x, y, text, colour);
+}
+
+pub struct Label {
+    text: String,
+    align: Align,
+    dirty: bool,
+}
+
+impl Label {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            align: Align::Left,
+            dirty: true,
+        }
+    }
+
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = text.to_string();
+            self.dirty = true;
+        }
+    }
+}
+
+impl Widget for Label {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        fonts.line_height()
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        let (fg, bg) = ink(false);
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, bg);
+        draw_aligned(canvas, fonts, bounds, bounds.y, &self.text, self.align, fg);
+    }
+
+    impl_widget_common!();
+}
+
+// A line of centred text drawn `scale` times the font size, e.g. the time on the home screen.
+pub struct LargeLabel {
+    text: String,
+    scale: i16,
+    dirty: bool,
+}
+
+impl LargeLabel {
+    pub fn new(text: &str, scale: i16) -> Self {
+        Self {
+            text: text.to_string(),
+            scale,
+            dirty: true,
+        }
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = text.to_string();
+            self.dirty = true;
+        }
+    }
+}
+
+impl Widget for LargeLabel {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        fonts.line_height() * self.scale
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::WHITE);
+        let width = fonts.text_width(&self.text) * self.scale;
+        let x = bounds.x + (bounds.w - width) / 2;
+        fonts.draw_text_scaled(canvas, x, bounds.y, &self.text, self.scale, Colour::BLACK);
+    }
+
+    impl_widget_common!();
+}
+
+pub struct Button {
+    text: String,
+    focused: bool,
+    dirty: bool,
+}
+
+impl Button {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            focused: false,
+            dirty: true,
+        }
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = text.to_string();
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        if self.focused != focused {
+            self.focused = focused;
+            self.dirty = true;
+        }
+    }
+}
+
+impl Widget for Button {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        fonts.line_height() + 2 * PADDING
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        let (fg, bg) = ink(self.focused);
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, bg);
+        canvas.draw_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::BLACK);
+        let y = bounds.y + PADDING;
+        draw_aligned(canvas, fonts, bounds, y, &self.text, Align::Center, fg);
+    }
+
+    impl_widget_common!();
+}
+
+// Scrolling list with a single selected row, `rows` sets how many rows are visible.
+pub struct List {
+    items: Vec<String>,
+    selected: usize,
+    scroll: usize,
+    rows: usize,
+    // row height of the last draw, for touch hit testing
+    row_height: Cell<i16>,
+    dirty: bool,
+}
+
+impl List {
+    pub fn new(rows: usize) -> Self {
+        Self {
+            items: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            rows: rows.max(1),
+            row_height: Cell::new(0),
+            dirty: true,
+        }
+    }
+
+    pub fn set_items(&mut self, items: Vec<String>) {
+        if self.items != items {
+            self.items = items;
+            self.selected = self.selected.min(self.items.len().saturating_sub(1));
+            self.scroll_to_selected();
+            self.dirty = true;
+        }
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        if self.items.is_empty() {
+            None
+        } else {
+            Some(self.selected)
+        }
+    }
+
+    pub fn select(&mut self, index: usize) {
+        let index = index.min(self.items.len().saturating_sub(1));
+        if index != self.selected {
+            self.selected = index;
+            self.scroll_to_selected();
+            self.dirty = true;
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        self.select(self.selected + 1);
+    }
+
+    pub fn select_previous(&mut self) {
+        self.select(self.selected.saturating_sub(1));
+    }
+
+    // item at `y` pixels below the top of the list
+    pub fn index_at(&self, y: i16) -> Option<usize> {
+        let row_height = self.row_height.get();
+        if y < 0 || row_height <= 0 {
+            return None;
+        }
+        let index = self.scroll + (y / row_height) as usize;
+        (index < self.items.len() && (y / row_height) < self.rows as i16).then_some(index)
+    }
+
+    fn scroll_to_selected(&mut self) {
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+    }
+}
+
+impl Widget for List {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        (fonts.line_height() + 2) * self.rows as i16
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::WHITE);
+        let row_height = fonts.line_height() + 2;
+        self.row_height.set(row_height);
+        let visible = self.items.iter().enumerate().skip(self.scroll);
+        for (row, (i, item)) in visible.take(self.rows).enumerate() {
+            let y = bounds.y + row as i16 * row_height;
+            let (fg, bg) = ink(i == self.selected);
+            canvas.fill_rect(bounds.x, y, bounds.w, row_height, bg);
+            fonts.draw_text(canvas, bounds.x + 2, y + 1, item, fg);
+        }
+    }
+
+    impl_widget_common!();
+}
+
+pub struct TextInput {
+    text: String,
+    placeholder: String,
+    focused: bool,
+    dirty: bool,
+}
+
+impl TextInput {
+    pub fn new(placeholder: &str) -> Self {
+        Self {
+            text: String::new(),
+            placeholder: placeholder.to_string(),
+            focused: false,
+            dirty: true,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = text.to_string();
+            self.dirty = true;
+        }
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.push(c);
+        self.dirty = true;
+    }
+
+    pub fn backspace(&mut self) {
+        if self.text.pop().is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub fn take(&mut self) -> String {
+        self.dirty = true;
+        std::mem::take(&mut self.text)
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        if self.focused != focused {
+            self.focused = focused;
+            self.dirty = true;
+        }
+    }
+}
+
+impl Widget for TextInput {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        fonts.line_height() + 2 * PADDING
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::WHITE);
+        canvas.draw_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::BLACK);
+        if self.focused {
+            canvas.draw_rect(
+                bounds.x + 1,
+                bounds.y + 1,
+                bounds.w - 2,
+                bounds.h - 2,
+                Colour::BLACK,
+            );
+        }
+        let inner = bounds.inset(PADDING);
+        if self.text.is_empty() && !self.focused {
+            fonts.draw_text(canvas, inner.x, inner.y, &self.placeholder, Colour::BLACK);
+            return;
+        }
+        // keep the end of the text (and the cursor) visible
+        let mut start = 0;
+        while start < self.text.len() && fonts.text_width(&self.text[start..]) > inner.w - 2 {
+            start += self.text[start..].chars().next().map_or(1, char::len_utf8);
+        }
+        let end = fonts.draw_text(canvas, inner.x, inner.y, &self.text[start..], Colour::BLACK);
+        if self.focused {
+            canvas.draw_vline(end + 1, inner.y, fonts.line_height(), Colour::BLACK);
+        }
+    }
+
+    impl_widget_common!();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bubble {
+    pub text: String,
+    // small print under the bubble, e.g. the time and delivery
+    pub caption: String,
+    // ours, drawn on the right in reverse
+    pub outgoing: bool,
+}
+
+// Message history as bubbles, the newest at the bottom. Each bubble is word-wrapped to three
+// quarters of the width; scrolling moves a whole bubble at a time.
+pub struct Conversation {
+    bubbles: Vec<Bubble>,
+    // bubbles hidden below the bottom edge
+    scroll: usize,
+    dirty: bool,
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Self {
+            bubbles: Vec::new(),
+            scroll: 0,
+            dirty: true,
+        }
+    }
+
+    // Keeps the view where it was: at the newest bubble, or on the same ones when scrolled
+    // back.
+    pub fn set_bubbles(&mut self, bubbles: Vec<Bubble>) {
+        if self.bubbles != bubbles {
+            if self.scroll > 0 {
+                self.scroll += bubbles.len().saturating_sub(self.bubbles.len());
+            }
+            self.bubbles = bubbles;
+            self.scroll = self.scroll.min(self.bubbles.len().saturating_sub(1));
+            self.dirty = true;
+        }
+    }
+
+    pub fn scroll_older(&mut self) {
+        if self.scroll + 1 < self.bubbles.len() {
+            self.scroll += 1;
+            self.dirty = true;
+        }
+    }
+
+    pub fn scroll_newer(&mut self) {
+        if self.scroll > 0 {
+            self.scroll -= 1;
+            self.dirty = true;
+        }
+    }
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Widget for Conversation {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        fonts.line_height() * 4
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::WHITE);
+        let line_height = fonts.line_height();
+        let max_w = bounds.w * 3 / 4;
+        // bottom up, from the newest bubble in view
+        let mut bottom = bounds.bottom();
+        for (n, bubble) in self.bubbles.iter().rev().skip(self.scroll).enumerate() {
+            let caption_y = bottom - line_height;
+            let mut lines = fonts.wrap(&bubble.text, max_w - 2 * PADDING);
+            let room = ((caption_y - bounds.y - 2 * PADDING) / line_height).max(0) as usize;
+            if lines.len() > room {
+                // only the end of a bubble taller than the page shows
+                if n > 0 || room == 0 {
+                    break;
+                }
+                lines.drain(..lines.len() - room);
+            }
+            let text_w = lines.iter().map(|l| fonts.text_width(l)).max().unwrap_or(0);
+            let w = text_w + 2 * PADDING;
+            let h = lines.len() as i16 * line_height + 2 * PADDING;
+            let top = caption_y - h;
+            let (x, align) = if bubble.outgoing {
+                (bounds.right() - w, Align::Right)
+            } else {
+                (bounds.x, Align::Left)
+            };
+            let (fg, bg) = ink(bubble.outgoing);
+            canvas.fill_rect(x, top, w, h, bg);
+            canvas.draw_rect(x, top, w, h, Colour::BLACK);
+            for (i, line) in lines.iter().enumerate() {
+                let y = top + PADDING + i as i16 * line_height;
+                fonts.draw_text(canvas, x + PADDING, y, line, fg);
+            }
+            let caption = &bubble.caption;
+            draw_aligned(
+                canvas,
+                fonts,
+                bounds,
+                caption_y,
+                caption,
+                align,
+                Colour::BLACK,
+            );
+            bottom = top - PADDING;
+        }
+    }
+
+    fn expands(&self) -> bool {
+        true
+    }
+
+    impl_widget_common!();
+}
+
+pub struct ProgressBar {
+    percent: u8,
+    dirty: bool,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        Self {
+            percent: 0,
+            dirty: true,
+        }
+    }
+
+    pub fn set_percent(&mut self, percent: u8) {
+        let percent = percent.min(100);
+        if self.percent != percent {
+            self.percent = percent;
+            self.dirty = true;
+        }
+    }
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Widget for ProgressBar {
+    fn height(&self, _fonts: &FontStack) -> i16 {
+        12
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, _fonts: &mut FontStack, bounds: Rect) {
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::WHITE);
+        canvas.draw_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::BLACK);
+        let inner = bounds.inset(2);
+        let filled = inner.w * self.percent as i16 / 100;
+        canvas.fill_rect(inner.x, inner.y, filled, inner.h, Colour::BLACK);
+    }
+
+    impl_widget_common!();
+}
+
+// Inverted bar across the top of the screen with a title on the left and indicators
+// (time, satellites...) on the right, followed by the SD card, the cellular signal and the
+// battery level when they are known.
+pub struct StatusBar {
+    title: String,
+    indicators: String,
+    // None without a card, false when it can't be read
+    card: Option<bool>,
+    // bars, 0 to SIGNAL_BARS
+    signal: Option<u8>,
+    // percent and charging
+    battery: Option<(u8, bool)>,
+    dirty: bool,
+}
+
+impl StatusBar {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            indicators: String::new(),
+            card: None,
+            signal: None,
+            battery: None,
+            dirty: true,
+        }
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        if self.title != title {
+            self.title = title.to_string();
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_indicators(&mut self, indicators: &str) {
+        if self.indicators != indicators {
+            self.indicators = indicators.to_string();
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_card(&mut self, card: Option<bool>) {
+        if self.card != card {
+            self.card = card;
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_signal(&mut self, signal: Option<u8>) {
+        if self.signal != signal {
+            self.signal = signal;
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_battery(&mut self, battery: Option<(u8, bool)>) {
+        if self.battery != battery {
+            self.battery = battery;
+            self.dirty = true;
+        }
+    }
+}
+
+// battery outline with a fill for the level, `y` is the top of the text line
+fn draw_battery(canvas: &mut dyn Canvas, right: i16, y: i16, percent: u8, colour: Colour) {
+    const H: i16 = 9;
+    let x = right - BATTERY_ICON_W;
+    // body and the terminal nub on the right
+    canvas.draw_rect(x, y, BATTERY_ICON_W - 2, H, colour);
+    canvas.fill_rect(right - 2, y + 2, 2, H - 4, colour);
+    let filled = (BATTERY_ICON_W - 6) * percent.min(100) as i16 / 100;
+    canvas.fill_rect(x + 2, y + 2, filled, H - 4, colour);
+}
+
+// rising bars, outlined when not reached; `y` is the top of the text line
+fn draw_signal(canvas: &mut dyn Canvas, right: i16, y: i16, bars: u8, colour: Colour) {
+    const H: i16 = 9;
+    let x = right - SIGNAL_ICON_W;
+    for bar in 0..SIGNAL_BARS {
+        let h = 3 + 2 * bar as i16;
+        let left = x + 4 * bar as i16;
+        if bar < bars {
+            canvas.fill_rect(left, y + H - h, 3, h, colour);
+        } else {
+            canvas.draw_rect(left, y + H - h, 3, h, colour);
+        }
+    }
+}
+
+impl Widget for StatusBar {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        fonts.line_height() + 4
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::BLACK);
+        let mut inner = Rect::new(
+            bounds.x + PADDING,
+            bounds.y + 2,
+            bounds.w - 2 * PADDING,
+            bounds.h,
+        );
+        fonts.draw_text(canvas, inner.x, inner.y, &self.title, Colour::WHITE);
+        if let Some((percent, charging)) = self.battery {
+            draw_battery(canvas, inner.right(), inner.y, percent, Colour::WHITE);
+            inner.w -= BATTERY_ICON_W + PADDING;
+            let level = if charging {
+                format!("+{percent}%")
+            } else {
+                format!("{percent}%")
+            };
+            draw_aligned(
+                canvas,
+                fonts,
+                inner,
+                inner.y,
+                &level,
+                Align::Right,
+                Colour::WHITE,
+            );
+            inner.w -= fonts.text_width(&level) + PADDING;
+        }
+        if let Some(bars) = self.signal {
+            draw_signal(canvas, inner.right(), inner.y, bars, Colour::WHITE);
+            inner.w -= SIGNAL_ICON_W + PADDING;
+        }
+        if let Some(readable) = self.card {
+            let card = if readable { "SD" } else { "SD!" };
+            draw_aligned(
+                canvas,
+                fonts,
+                inner,
+                inner.y,
+                card,
+                Align::Right,
+                Colour::WHITE,
+            );
+            inner.w -= fonts.text_width(card) + PADDING;
+        }
+        let indicators = &self.indicators;
+        draw_aligned(
+            canvas,
+            fonts,
+            inner,
+            inner.y,
+            indicators,
+            Align::Right,
+            Colour::WHITE,
+        );
+    }
+
+    fn full_bleed(&self) -> bool {
+        true
+    }
+
+    impl_widget_common!();
+}
+
+// Dialog drawn centred over the page, with a row of buttons of which one is selected.
+pub struct Modal {
+    title: String,
+    message: String,
+    buttons: Vec<String>,
+    selected: usize,
+    dirty: bool,
+}
+
+impl Modal {
+    pub fn new(title: &str, message: &str, buttons: &[&str]) -> Self {
+        Self {
+            title: title.to_string(),
+            message: message.to_string(),
+            buttons: buttons.iter().map(|b| b.to_string()).collect(),
+            selected: 0,
+            dirty: true,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.buttons.len() {
+            self.selected += 1;
+            self.dirty = true;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+            self.dirty = true;
+        }
+    }
+
+    // the dialog sizes itself to its message inside the given screen area
+    pub fn bounds(&self, fonts: &mut FontStack, screen: Rect) -> Rect {
+        let w = screen.w - 8 * PADDING;
+        let lines = fonts.wrap(&self.message, w - 2 * PADDING).len() as i16;
+        let line_height = fonts.line_height();
+        let h = (lines + 1) * line_height + (line_height + 2 * PADDING) + 5 * PADDING;
+        Rect::new(
+            screen.x + (screen.w - w) / 2,
+            screen.y + (screen.h - h) / 2,
+            w,
+            h,
+        )
+    }
+}
+
+impl Widget for Modal {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        fonts.line_height() * 4
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        let line_height = fonts.line_height();
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::WHITE);
+        canvas.draw_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::BLACK);
+        canvas.draw_rect(
+            bounds.x + 1,
+            bounds.y + 1,
+            bounds.w - 2,
+            bounds.h - 2,
+            Colour::BLACK,
+        );
+
+        let title_bar = Rect::new(bounds.x, bounds.y, bounds.w, line_height + PADDING);
+        canvas.fill_rect(
+            title_bar.x,
+            title_bar.y,
+            title_bar.w,
+            title_bar.h,
+            Colour::BLACK,
+        );
+        let y = title_bar.y + PADDING / 2;
+        draw_aligned(
+            canvas,
+            fonts,
+            title_bar,
+            y,
+            &self.title,
+            Align::Center,
+            Colour::WHITE,
+        );
+
+        let inner = bounds.inset(PADDING);
+        let mut y = title_bar.bottom() + PADDING;
+        for line in fonts.wrap(&self.message, inner.w - PADDING) {
+            fonts.draw_text(canvas, inner.x + PADDING / 2, y, &line, Colour::BLACK);
+            y += line_height;
+        }
+
+        if self.buttons.is_empty() {
+            return;
+        }
+        let button_h = line_height + 2 * PADDING;
+        let button_w = inner.w / self.buttons.len() as i16;
+        let y = inner.bottom() - button_h;
+        for (i, text) in self.buttons.iter().enumerate() {
+            let r = Rect::new(
+                inner.x + i as i16 * button_w,
+                y,
+                button_w - PADDING,
+                button_h,
+            );
+            let (fg, bg) = ink(i == self.selected);
+            canvas.fill_rect(r.x, r.y, r.w, r.h, bg);
+            canvas.draw_rect(r.x, r.y, r.w, r.h, Colour::BLACK);
+            draw_aligned(canvas, fonts, r, r.y + PADDING, text, Align::Center, fg);
+        }
+    }
+
+    impl_widget_common!();
+}
+
+// Notification drawn over the bottom of the page: a heading and the first line of its text.
+pub struct Toast {
+    heading: String,
+    text: String,
+    dirty: bool,
+}
+
+impl Toast {
+    pub fn new(heading: &str, text: &str) -> Self {
+        Self {
+            heading: heading.to_string(),
+            text: text.to_string(),
+            dirty: true,
+        }
+    }
+
+    pub fn bounds(&self, fonts: &mut FontStack, screen: Rect) -> Rect {
+        let h = self.height(fonts);
+        Rect::new(
+            screen.x + PADDING,
+            screen.bottom() - h - PADDING,
+            screen.w - 2 * PADDING,
+            h,
+        )
+    }
+}
+
+impl Widget for Toast {
+    fn height(&self, fonts: &FontStack) -> i16 {
+        2 * fonts.line_height() + 3 * PADDING
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas, fonts: &mut FontStack, bounds: Rect) {
+        let line_height = fonts.line_height();
+        canvas.fill_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::WHITE);
+        canvas.draw_rect(bounds.x, bounds.y, bounds.w, bounds.h, Colour::BLACK);
+        canvas.draw_rect(
+            bounds.x + 1,
+            bounds.y + 1,
+            bounds.w - 2,
+            bounds.h - 2,
+            Colour::BLACK,
+        );
+        let inner = bounds.inset(PADDING);
+        let heading = Rect::new(bounds.x, bounds.y, bounds.w, line_height + PADDING);
+        canvas.fill_rect(heading.x, heading.y, heading.w, heading.h, Colour::BLACK);
+        fonts.draw_text(
+            canvas,
+            inner.x,
+            heading.y + PADDING / 2,
+            &self.heading,
+            Colour::WHITE,
+        );
+        let first_line = fonts.wrap(&self.text, inner.w).into_iter().next();
+        if let Some(line) = first_line {
+            let y = heading.bottom() + PADDING;
+            fonts.draw_text(canvas, inner.x, y, &line, Colour::BLACK);
+        }
+    }
+
+    impl_widget_common!();
+}
@@ -0,0 +1,355 @@
+// Builds a DFNT font file (the format is described in src/font.rs and README.md) for the
+// "fonts" partition from one or more BDF fonts:
+//
+//   mkfont [--range FIRST-LAST]... OUT.dfnt FONT.bdf [MORE.bdf ...]
+//
+// Where fonts overlap the glyph of the earlier one is kept, so a hand tuned font can go first
+// and a large fallback such as GNU Unifont last. Ranges (hex code points, e.g. 0400-04FF)
+// limit which glyphs are kept. Outline fonts (TTF, OTF) are rendered to BDF at the wanted
+// pixel size first, e.g. with `otf2bdf -p 16 -r 72 font.ttf -o font.bdf`.
+
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+use std::{env, fs};
+
+const MAGIC: &[u8; 4] = b"DFNT";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 16;
+const INDEX_ENTRY_SIZE: usize = 16;
+// size of the "fonts" partition in partitions.csv
+const PARTITION_SIZE: usize = 0x40_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Glyph {
+    width: u8,
+    height: u8,
+    // offset of the top left pixel from the pen position on the baseline
+    x_offset: i8,
+    y_offset: i8,
+    advance: u8,
+    // rows padded to whole bytes, MSB is the leftmost pixel
+    bitmap: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Font {
+    ascent: u8,
+    descent: u8,
+    glyphs: BTreeMap<u32, Glyph>,
+}
+
+fn main() -> ExitCode {
+    match run(env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("mkfont: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut ranges = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--range" {
+            let range = args.next().ok_or("--range needs FIRST-LAST")?;
+            ranges.push(parse_range(&range)?);
+        } else {
+            paths.push(arg);
+        }
+    }
+    if paths.len() < 2 {
+        return Err("usage: mkfont [--range FIRST-LAST]... OUT.dfnt FONT.bdf...".to_string());
+    }
+    let out = paths.remove(0);
+
+    let mut merged = Font::default();
+    for path in paths.iter() {
+        let text = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        let font =
+            parse_bdf(&String::from_utf8_lossy(&text)).map_err(|e| format!("{path}: {e}"))?;
+        merged.ascent = merged.ascent.max(font.ascent);
+        merged.descent = merged.descent.max(font.descent);
+        let count = merged.glyphs.len();
+        for (code, glyph) in font.glyphs {
+            let wanted = ranges.is_empty() || ranges.iter().any(|(a, b)| (*a..=*b).contains(&code));
+            if wanted {
+                merged.glyphs.entry(code).or_insert(glyph);
+            }
+        }
+        println!("{path}: {} glyphs added", merged.glyphs.len() - count);
+    }
+
+    let line_height = merged.ascent as u16 + merged.descent as u16;
+    let line_height = u8::try_from(line_height).map_err(|_| "line height over 255")?;
+    let data = encode(line_height, merged.ascent, &merged.glyphs);
+    if data.len() > PARTITION_SIZE {
+        eprintln!(
+            "mkfont: warning: {} bytes do not fit the {PARTITION_SIZE} byte fonts partition",
+            data.len()
+        );
+    }
+    fs::write(&out, &data).map_err(|e| format!("{out}: {e}"))?;
+    println!(
+        "{out}: {} glyphs, line height {line_height}, ascent {}, {} bytes",
+        merged.glyphs.len(),
+        merged.ascent,
+        data.len()
+    );
+    Ok(())
+}
+
+// "0400-04FF" or a single "20AC", hex with an optional "U+" or "0x"
+fn parse_range(text: &str) -> Result<(u32, u32), String> {
+    let code = |s: &str| {
+        let s = s.trim();
+        let s = s.strip_prefix("U+").or(s.strip_prefix("0x")).unwrap_or(s);
+        u32::from_str_radix(s, 16).map_err(|_| format!("bad code point {s:?}"))
+    };
+    match text.split_once('-') {
+        Some((first, last)) => Ok((code(first)?, code(last)?)),
+        None => code(text).map(|c| (c, c)),
+    }
+}
+
+// The glyphs with a Unicode encoding; BDF y offsets count up from the baseline to the bottom
+// row, DFNT ones down to the top row.
+fn parse_bdf(text: &str) -> Result<Font, String> {
+    let mut font = Font::default();
+    let mut bounding_box: Option<(i32, i32)> = None;
+    let mut ascent = None;
+    let mut descent = None;
+    let mut lines = text.lines().enumerate();
+    while let Some((n, line)) = lines.next() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            // height and y offset, for fonts without FONT_ASCENT and FONT_DESCENT
+            Some("FONTBOUNDINGBOX") => {
+                let numbers = numbers(words, 4, n)?;
+                bounding_box = Some((numbers[1], numbers[3]));
+            }
+            Some("FONT_ASCENT") => ascent = Some(numbers(words, 1, n)?[0]),
+            Some("FONT_DESCENT") => descent = Some(numbers(words, 1, n)?[0]),
+            Some("STARTCHAR") => {
+                if let Some((code, glyph)) = parse_char(&mut lines)? {
+                    font.glyphs.insert(code, glyph);
+                }
+            }
+            _ => {}
+        }
+    }
+    let (ascent, descent) = match (ascent, descent, bounding_box) {
+        (Some(a), Some(d), _) => (a, d),
+        (_, _, Some((height, y_offset))) => (height + y_offset, -y_offset),
+        _ => return Err("no FONT_ASCENT/FONT_DESCENT or FONTBOUNDINGBOX".to_string()),
+    };
+    font.ascent = u8::try_from(ascent).map_err(|_| format!("ascent {ascent} out of range"))?;
+    font.descent = u8::try_from(descent).map_err(|_| format!("descent {descent} out of range"))?;
+    Ok(font)
+}
+
+// From after STARTCHAR to ENDCHAR; None for glyphs without a Unicode code point.
+fn parse_char<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<Option<(u32, Glyph)>, String> {
+    let mut code = None;
+    let mut advance = 0;
+    let mut bbx = [0; 4];
+    let mut bitmap = Vec::new();
+    let mut in_bitmap = false;
+    for (n, line) in lines.by_ref() {
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        match keyword {
+            "ENDCHAR" => {
+                let Some(code) = code else {
+                    return Ok(None);
+                };
+                let [w, h, x, y] = bbx;
+                let glyph = Glyph {
+                    width: u8::try_from(w).map_err(|_| format!("{code:#x}: width {w}"))?,
+                    height: u8::try_from(h).map_err(|_| format!("{code:#x}: height {h}"))?,
+                    x_offset: i8::try_from(x).map_err(|_| format!("{code:#x}: x offset {x}"))?,
+                    y_offset: i8::try_from(-(y + h))
+                        .map_err(|_| format!("{code:#x}: y offset {y}"))?,
+                    advance: u8::try_from(advance)
+                        .map_err(|_| format!("{code:#x}: advance {advance}"))?,
+                    bitmap,
+                };
+                if glyph.bitmap.len() != (w as usize).div_ceil(8) * h as usize {
+                    return Err(format!("{code:#x}: {h} bitmap rows expected"));
+                }
+                return Ok(Some((code, glyph)));
+            }
+            _ if in_bitmap => {
+                // rows may be padded beyond the glyph width
+                let row_bytes = (bbx[0] as usize).div_ceil(8);
+                let row = keyword
+                    .get(..row_bytes * 2)
+                    .ok_or(format!("line {}: short bitmap row", n + 1))?;
+                for i in 0..row_bytes {
+                    let byte = u8::from_str_radix(&row[2 * i..2 * i + 2], 16)
+                        .map_err(|_| format!("line {}: bad bitmap row", n + 1))?;
+                    bitmap.push(byte);
+                }
+            }
+            // "ENCODING -1 n" gives a non-standard code, not Unicode
+            "ENCODING" => code = numbers(words, 1, n)?[0].try_into().ok(),
+            "DWIDTH" => advance = numbers(words, 1, n)?[0],
+            "BBX" => bbx.copy_from_slice(&numbers(words, 4, n)?),
+            "BITMAP" => in_bitmap = true,
+            _ => {}
+        }
+    }
+    Err("STARTCHAR without ENDCHAR".to_string())
+}
+
+fn numbers<'a>(
+    words: impl Iterator<Item = &'a str>,
+    count: usize,
+    line: usize,
+) -> Result<Vec<i32>, String> {
+    let numbers: Vec<i32> = words
+        .take(count)
+        .map(|w| {
+            w.parse()
+                .map_err(|_| format!("line {}: bad number {w:?}", line + 1))
+        })
+        .collect::<Result<_, _>>()?;
+    if numbers.len() < count {
+        return Err(format!("line {}: {count} numbers expected", line + 1));
+    }
+    Ok(numbers)
+}
+
+// header, index sorted by code point, bitmaps
+fn encode(line_height: u8, ascent: u8, glyphs: &BTreeMap<u32, Glyph>) -> Vec<u8> {
+    let bitmap_offset = HEADER_SIZE + glyphs.len() * INDEX_ENTRY_SIZE;
+    let mut out = Vec::with_capacity(bitmap_offset);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&[VERSION, line_height, ascent, 0]);
+    out.extend_from_slice(&(glyphs.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(bitmap_offset as u32).to_le_bytes());
+    let mut bitmaps = Vec::new();
+    for (&code, glyph) in glyphs {
+        out.extend_from_slice(&code.to_le_bytes());
+        out.extend_from_slice(&(bitmaps.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[
+            glyph.width,
+            glyph.height,
+            glyph.x_offset as u8,
+            glyph.y_offset as u8,
+            glyph.advance,
+            0,
+            0,
+            0,
+        ]);
+        bitmaps.extend_from_slice(&glyph.bitmap);
+    }
+    out.extend_from_slice(&bitmaps);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BDF: &str = "STARTFONT 2.1
+FONT -test-fixed-medium-r-normal--8-80-75-75-c-80-iso10646-1
+SIZE 8 75 75
+FONTBOUNDINGBOX 6 8 0 -2
+STARTPROPERTIES 2
+FONT_ASCENT 6
+FONT_DESCENT 2
+ENDPROPERTIES
+CHARS 3
+STARTCHAR space
+ENCODING 32
+SWIDTH 500 0
+DWIDTH 6 0
+BBX 0 0 0 0
+BITMAP
+ENDCHAR
+STARTCHAR Cyrillic_zhe
+ENCODING 1078
+SWIDTH 500 0
+DWIDTH 6 0
+BBX 5 4 0 0
+BITMAP
+A8
+70
+70
+A8
+ENDCHAR
+STARTCHAR private
+ENCODING -1 57344
+DWIDTH 6 0
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+STARTCHAR wide
+ENCODING 12354
+DWIDTH 10 0
+BBX 9 2 1 -2
+BITMAP
+FF8000
+0080
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn bdf() {
+        let font = parse_bdf(BDF).unwrap();
+        assert_eq!((font.ascent, font.descent), (6, 2));
+        assert_eq!(
+            font.glyphs.keys().copied().collect::<Vec<_>>(),
+            [32, 1078, 12354]
+        );
+        assert_eq!(
+            font.glyphs[&1078],
+            Glyph {
+                width: 5,
+                height: 4,
+                x_offset: 0,
+                y_offset: -4,
+                advance: 6,
+                bitmap: vec![0xA8, 0x70, 0x70, 0xA8],
+            }
+        );
+        // below the baseline, rows of two bytes
+        let wide = &font.glyphs[&12354];
+        assert_eq!((wide.x_offset, wide.y_offset), (1, 0));
+        assert_eq!(wide.bitmap, [0xFF, 0x80, 0x00, 0x80]);
+        assert!(font.glyphs[&32].bitmap.is_empty());
+    }
+
+    #[test]
+    fn dfnt() {
+        let font = parse_bdf(BDF).unwrap();
+        let data = encode(8, font.ascent, &font.glyphs);
+        assert_eq!(&data[..8], b"DFNT\x01\x08\x06\x00");
+        assert_eq!(&data[8..16], [3, 0, 0, 0, 64, 0, 0, 0]);
+        // the second index entry, Cyrillic zhe after the empty space
+        assert_eq!(
+            &data[32..48],
+            [0x36, 0x04, 0, 0, 0, 0, 0, 0, 5, 4, 0, 0xFC, 6, 0, 0, 0]
+        );
+        assert_eq!(
+            &data[64..],
+            [0xA8, 0x70, 0x70, 0xA8, 0xFF, 0x80, 0x00, 0x80]
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("0400-04FF"), Ok((0x400, 0x4FF)));
+        assert_eq!(parse_range("U+20AC"), Ok((0x20AC, 0x20AC)));
+        assert!(parse_range("cyrillic").is_err());
+    }
+}